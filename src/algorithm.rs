@@ -0,0 +1,141 @@
+//! # algorithm
+//!
+//! `algorithm` crate names the chained PoW algorithms of this crate so they can be selected at runtime.
+use crate::blake256::Midstate;
+use crate::chain::HashChain;
+use crate::lyra2::Matrix;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// A chained PoW algorithm implemented by this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Algorithm {
+    Lyra2RE,
+    Lyra2REv2,
+    Lyra2REv3,
+    Lyra2Z,
+}
+
+impl Algorithm {
+    /// Every algorithm, in the order they are declared.
+    pub const ALL: [Algorithm; 4] = [
+        Algorithm::Lyra2RE,
+        Algorithm::Lyra2REv2,
+        Algorithm::Lyra2REv3,
+        Algorithm::Lyra2Z,
+    ];
+
+    /// Returns the lower-case name of the algorithm, which is also its module name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Lyra2RE => "lyra2re",
+            Algorithm::Lyra2REv2 => "lyra2rev2",
+            Algorithm::Lyra2REv3 => "lyra2rev3",
+            Algorithm::Lyra2Z => "lyra2z",
+        }
+    }
+
+    /// Returns the calculation result of the algorithm.
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::algorithm::Algorithm;
+    ///
+    /// let base1 = "abc".as_bytes().to_vec();
+    /// assert_eq!(Algorithm::Lyra2REv2.sum(base1.clone()), lyra2::lyra2rev2::sum(base1));
+    /// ```
+    pub fn sum(&self, input: Vec<u8>) -> Vec<u8> {
+        match self {
+            Algorithm::Lyra2RE => crate::lyra2re::sum(input),
+            Algorithm::Lyra2REv2 => crate::lyra2rev2::sum(input),
+            Algorithm::Lyra2REv3 => crate::lyra2rev3::sum(input),
+            Algorithm::Lyra2Z => crate::lyra2z::sum(input),
+        }
+    }
+
+    /// Returns the calculation result of the algorithm for an 80-byte message from the Blake-256
+    /// [`Midstate`] of its first 64 bytes and its last 16 bytes.
+    pub fn sum_midstate(&self, midstate: &Midstate, tail: &[u8; 16]) -> Vec<u8> {
+        match self {
+            Algorithm::Lyra2RE => crate::lyra2re::sum_midstate(midstate, tail),
+            Algorithm::Lyra2REv2 => crate::lyra2rev2::sum_midstate(midstate, tail),
+            Algorithm::Lyra2REv3 => crate::lyra2rev3::sum_midstate(midstate, tail),
+            Algorithm::Lyra2Z => crate::lyra2z::sum_midstate(midstate, tail),
+        }
+    }
+
+    /// Same as [`Algorithm::sum_midstate`], but reuses the Lyra2 memory matrix in `matrix`.
+    pub fn sum_midstate_with(
+        &self,
+        matrix: &mut Matrix,
+        midstate: &Midstate,
+        tail: &[u8; 16],
+    ) -> Vec<u8> {
+        let result_blake = midstate.finish(tail).to_vec();
+        match self {
+            Algorithm::Lyra2RE => crate::lyra2re::finish_with(matrix, result_blake),
+            Algorithm::Lyra2REv2 => crate::lyra2rev2::finish_with(matrix, result_blake),
+            Algorithm::Lyra2REv3 => crate::lyra2rev3::finish_with(matrix, result_blake),
+            Algorithm::Lyra2Z => crate::lyra2z::finish_with(matrix, result_blake),
+        }
+    }
+
+    /// Returns the algorithm as a [`HashChain`].
+    pub fn chain(&self) -> HashChain {
+        match self {
+            Algorithm::Lyra2RE => HashChain::lyra2re(),
+            Algorithm::Lyra2REv2 => HashChain::lyra2rev2(),
+            Algorithm::Lyra2REv3 => HashChain::lyra2rev3(),
+            Algorithm::Lyra2Z => HashChain::lyra2z(),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = ParseAlgorithmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase();
+        Algorithm::ALL
+            .iter()
+            .find(|a| a.name() == name)
+            .copied()
+            .ok_or_else(|| ParseAlgorithmError(s.to_string()))
+    }
+}
+
+/// An error returned when an algorithm name is not one of this crate's algorithms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseAlgorithmError(pub String);
+
+impl fmt::Display for ParseAlgorithmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unsupported algorithm: {}", self.0)
+    }
+}
+
+impl error::Error for ParseAlgorithmError {}
+
+#[test]
+fn algorithm_cal() {
+    let base = "脇山珠美ちゃんかわいい！".as_bytes().to_vec();
+    for algorithm in Algorithm::ALL.iter() {
+        assert_eq!(algorithm.to_string().parse::<Algorithm>(), Ok(*algorithm));
+        assert_eq!(
+            algorithm.sum(base.clone()),
+            algorithm.chain().sum(base.clone())
+        );
+    }
+    assert_eq!("Lyra2REv3".parse::<Algorithm>(), Ok(Algorithm::Lyra2REv3));
+    assert_eq!(
+        "scrypt".parse::<Algorithm>(),
+        Err(ParseAlgorithmError("scrypt".to_string()))
+    );
+}
@@ -0,0 +1,358 @@
+//! # batch
+//!
+//! `batch` crate checks many shares at once for pool backends: each [`Item`] is a header, the
+//! target it must meet and its algorithm, and each gets an [`Outcome`] with its verdict and PoW
+//! hash.
+//!
+//! A [`BatchVerifier`] keeps its worker threads and their Lyra2 memory matrices across batches,
+//! so a batch costs the hashing and not the setup. It can deduplicate identical headers and stop
+//! at the first failing item.
+use crate::algorithm::Algorithm;
+use crate::header::{BlockHeader, Hash256};
+use crate::lyra2::Matrix;
+use crate::target::Target;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// A header to check against a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Item {
+    pub header: BlockHeader,
+    pub target: Target,
+    pub algorithm: Algorithm,
+}
+
+impl Item {
+    pub fn new(header: BlockHeader, target: Target, algorithm: Algorithm) -> Item {
+        Item {
+            header,
+            target,
+            algorithm,
+        }
+    }
+}
+
+/// The verdict on an [`Item`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The PoW hash meets the target.
+    Valid,
+    /// The PoW hash is above the target.
+    HighHash,
+    /// The header is the same as that of the item at this earlier index.
+    Duplicate(usize),
+    /// The item comes after the first failing item of a batch that stops on failure.
+    Skipped,
+}
+
+/// The verdict on an [`Item`] and its PoW hash, if it was hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    pub verdict: Verdict,
+    /// The PoW hash of the header. A duplicate carries the hash of its first occurrence; a
+    /// skipped item has none.
+    pub hash: Option<Hash256>,
+}
+
+impl Outcome {
+    /// Returns whether the item is valid.
+    pub fn is_valid(&self) -> bool {
+        self.verdict == Verdict::Valid
+    }
+}
+
+/// A pool of threads checking batches of [`Item`]s. Dropping it stops the threads.
+/// # Examples
+///
+/// ```
+/// use lyra2::algorithm::Algorithm;
+/// use lyra2::batch::{BatchVerifier, Item, Verdict};
+/// use lyra2::header::BlockHeader;
+///
+/// let header = BlockHeader::from_hex("700000005d385ba114d079971b29a9418fd0549e7d68a95c7f168621a314201000000000578586d149fd07b22f3a8a347c516de7052f034d2b76ff68e0d6ecff9b77a45489e3fd511732011df0731000").unwrap();
+/// let item = Item::new(header, header.target().unwrap(), Algorithm::Lyra2REv2);
+///
+/// let verifier = BatchVerifier::new(2).dedup(true);
+/// let outcomes = verifier.verify(&[item, item]);
+/// assert_eq!(outcomes[1].verdict, Verdict::Duplicate(0));
+/// assert_eq!(outcomes[0].hash, outcomes[1].hash);
+/// ```
+pub struct BatchVerifier {
+    threads: usize,
+    stop_on_failure: bool,
+    dedup: bool,
+    tasks: Option<Sender<Arc<Batch>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl BatchVerifier {
+    /// Starts a pool of `threads` threads, or as many as there are cores if 0, that checks every
+    /// item of a batch and does not deduplicate.
+    pub fn new(threads: usize) -> BatchVerifier {
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            threads => threads,
+        };
+        let (tasks, receiver) = mpsc::channel::<Arc<Batch>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || work(&receiver))
+            })
+            .collect();
+        BatchVerifier {
+            threads,
+            stop_on_failure: false,
+            dedup: false,
+            tasks: Some(tasks),
+            workers,
+        }
+    }
+
+    /// Stops hashing at the first item that is not valid. Every item before it is checked and
+    /// every item after it is [`Verdict::Skipped`].
+    pub fn stop_on_failure(mut self, stop: bool) -> BatchVerifier {
+        self.stop_on_failure = stop;
+        self
+    }
+
+    /// Hashes identical headers of the same algorithm once; every occurrence after the first
+    /// is a [`Verdict::Duplicate`], whatever its target.
+    pub fn dedup(mut self, dedup: bool) -> BatchVerifier {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Returns the number of threads.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Checks `items` and returns their outcomes, in the same order.
+    pub fn verify(&self, items: &[Item]) -> Vec<Outcome> {
+        let mut outcomes: Vec<Option<Outcome>> = vec![None; items.len()];
+        // The index of the first failing item found so far.
+        let mut failed_at = u64::MAX;
+        let mut todo = Vec::with_capacity(items.len());
+        if self.dedup {
+            let mut seen = HashMap::with_capacity(items.len());
+            for (index, item) in items.iter().enumerate() {
+                match seen.get(&(item.header, item.algorithm)) {
+                    Some(&first) => {
+                        outcomes[index] = Some(Outcome {
+                            verdict: Verdict::Duplicate(first),
+                            hash: None,
+                        });
+                        failed_at = failed_at.min(index as u64);
+                    }
+                    None => {
+                        seen.insert((item.header, item.algorithm), index);
+                        todo.push(index);
+                    }
+                }
+            }
+        } else {
+            todo.extend(0..items.len());
+        }
+
+        if !todo.is_empty() {
+            let (results, finished) = mpsc::channel();
+            let batch = Arc::new(Batch {
+                items: todo.iter().map(|&index| (index, items[index])).collect(),
+                next: AtomicUsize::new(0),
+                stop_on_failure: self.stop_on_failure,
+                failed_at: AtomicU64::new(failed_at),
+                results: Mutex::new(results),
+            });
+            let tasks = self.tasks.as_ref().expect("batch verifier is stopped");
+            for _ in 0..self.threads.min(todo.len()) {
+                tasks
+                    .send(Arc::clone(&batch))
+                    .expect("batch verifier threads exited");
+            }
+            // The workers drop their clones of the batch when they are done with it.
+            drop(batch);
+            for (index, outcome) in finished {
+                if outcome.verdict != Verdict::Valid {
+                    failed_at = failed_at.min(index as u64);
+                }
+                outcomes[index] = Some(outcome);
+            }
+        }
+
+        (0..items.len())
+            .map(|index| match outcomes[index] {
+                _ if self.stop_on_failure && index as u64 > failed_at => Outcome {
+                    verdict: Verdict::Skipped,
+                    hash: None,
+                },
+                Some(Outcome {
+                    verdict: Verdict::Duplicate(first),
+                    ..
+                }) => Outcome {
+                    verdict: Verdict::Duplicate(first),
+                    hash: outcomes[first].and_then(|outcome| outcome.hash),
+                },
+                Some(outcome) => outcome,
+                None => panic!("batch verifier thread panicked"),
+            })
+            .collect()
+    }
+}
+
+impl Drop for BatchVerifier {
+    fn drop(&mut self) {
+        // Closing the task queue ends the workers.
+        self.tasks.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl fmt::Debug for BatchVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchVerifier")
+            .field("threads", &self.threads)
+            .field("stop_on_failure", &self.stop_on_failure)
+            .field("dedup", &self.dedup)
+            .finish()
+    }
+}
+
+// Batch is a batch shared by the workers, which take its items one at a time.
+struct Batch {
+    // The items to hash with their indexes in the batch.
+    items: Vec<(usize, Item)>,
+    next: AtomicUsize,
+    stop_on_failure: bool,
+    failed_at: AtomicU64,
+    results: Mutex<Sender<(usize, Outcome)>>,
+}
+
+// work runs a worker: it takes batches from `receiver` until the verifier is dropped, hashing
+// with the same matrix throughout.
+fn work(receiver: &Mutex<Receiver<Arc<Batch>>>) {
+    let mut matrix = Matrix::new();
+    loop {
+        let batch = match receiver.lock().unwrap().recv() {
+            Ok(batch) => batch,
+            Err(_) => return,
+        };
+        let results = batch.results.lock().unwrap().clone();
+        loop {
+            let next = batch.next.fetch_add(1, Ordering::Relaxed);
+            let Some(&(index, item)) = batch.items.get(next) else {
+                break;
+            };
+            if batch.stop_on_failure && index as u64 > batch.failed_at.load(Ordering::Relaxed) {
+                break;
+            }
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(&item.algorithm.sum_midstate_with(
+                &mut matrix,
+                &item.header.midstate(),
+                &item.header.tail(),
+            ));
+            let verdict = if item.target.is_met_by(&hash) {
+                Verdict::Valid
+            } else {
+                batch.failed_at.fetch_min(index as u64, Ordering::Relaxed);
+                Verdict::HighHash
+            };
+            let outcome = Outcome {
+                verdict,
+                hash: Some(Hash256(hash)),
+            };
+            if results.send((index, outcome)).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn batch_cal() {
+    use crate::scan::Scanner;
+
+    // Headers at the regtest target, each mined with its algorithm.
+    let easy = Target::from_compact(0x207fffff).unwrap();
+    let hard = Target::from_compact(0x1d00ffff).unwrap();
+    let items: Vec<Item> = (0..8u8)
+        .map(|i| {
+            let algorithm = Algorithm::ALL[i as usize % Algorithm::ALL.len()];
+            let mut header = BlockHeader {
+                version: 0x20000000,
+                prev_block: Hash256([i; 32]),
+                merkle_root: Hash256::sha256d(&[i]),
+                time: 1_600_000_000,
+                bits: 0x207fffff,
+                nonce: 0,
+            };
+            header.nonce = Scanner::new().scan(&header, 0..=63, &easy, algorithm, 1)[0].nonce;
+            Item::new(header, easy, algorithm)
+        })
+        .collect();
+    let hash = |item: &Item| item.header.pow_hash(item.algorithm);
+
+    // Every item is valid and carries its hash, on one thread or several.
+    for threads in [1, 3] {
+        let outcomes = BatchVerifier::new(threads).verify(&items);
+        assert_eq!(outcomes.len(), items.len());
+        for (item, outcome) in items.iter().zip(&outcomes) {
+            assert!(outcome.is_valid());
+            assert_eq!(outcome.hash, Some(hash(item)));
+        }
+    }
+    assert!(BatchVerifier::new(2).verify(&[]).is_empty());
+
+    // A batch with a high hash at index 2 and a duplicate of index 0 at index 5.
+    let mut mixed = items.clone();
+    mixed[2].target = hard;
+    mixed[5] = mixed[0];
+    let verifier = BatchVerifier::new(3);
+    let outcomes = verifier.verify(&mixed);
+    assert_eq!(outcomes[2].verdict, Verdict::HighHash);
+    assert_eq!(outcomes[2].hash, Some(hash(&mixed[2])));
+    assert_eq!(outcomes[5].verdict, Verdict::Valid);
+    assert_eq!(outcomes.iter().filter(|o| o.is_valid()).count(), 7);
+
+    // The pool and its matrices are reused across batches and settings.
+    let verifier = verifier.dedup(true);
+    let outcomes = verifier.verify(&mixed);
+    assert_eq!(outcomes[2].verdict, Verdict::HighHash);
+    assert_eq!(outcomes[5].verdict, Verdict::Duplicate(0));
+    assert_eq!(outcomes[5].hash, Some(hash(&mixed[0])));
+    assert_eq!(outcomes.iter().filter(|o| o.is_valid()).count(), 6);
+
+    // The same header under another algorithm is hashed on its own.
+    let mut other = mixed.clone();
+    other[5].algorithm = Algorithm::Lyra2Z;
+    assert_ne!(other[5].algorithm, other[0].algorithm);
+    let outcomes = verifier.verify(&other);
+    assert_ne!(outcomes[5].verdict, Verdict::Duplicate(0));
+    assert_eq!(outcomes[5].hash, Some(hash(&other[5])));
+
+    let verifier = verifier.stop_on_failure(true);
+    for _ in 0..4 {
+        let outcomes = verifier.verify(&mixed);
+        assert!(outcomes[0].is_valid() && outcomes[1].is_valid());
+        assert_eq!(outcomes[2].verdict, Verdict::HighHash);
+        assert!(outcomes[3..]
+            .iter()
+            .all(|o| o.verdict == Verdict::Skipped && o.hash.is_none()));
+    }
+
+    // A duplicate is a failure too.
+    let mut dup_first = items.clone();
+    dup_first[1] = dup_first[0];
+    let outcomes = verifier.verify(&dup_first);
+    assert!(outcomes[0].is_valid());
+    assert_eq!(outcomes[1].verdict, Verdict::Duplicate(0));
+    assert!(outcomes[2..].iter().all(|o| o.verdict == Verdict::Skipped));
+}
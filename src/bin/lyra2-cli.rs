@@ -0,0 +1,493 @@
+//! # lyra2-cli
+//!
+//! `lyra2-cli` is the command-line front end of the `lyra2` crate.
+//!
+//! ```text
+//! lyra2-cli verify-headers [options] <file>
+//! lyra2-cli check-header [options] [<file>]
+//! lyra2-cli genesis [options] --message <text>
+//! lyra2-cli regtest-blocks [options] <prev>
+//! lyra2-cli solo [options] --script <hex>
+//! ```
+use lyra2::algorithm::{Algorithm, ParseAlgorithmError};
+use lyra2::block::Block;
+use lyra2::coin::{ChainParams, Registry};
+use lyra2::genesis::{self, Generator, Genesis, COIN};
+use lyra2::header::BlockHeader;
+use lyra2::retarget::Retarget;
+use lyra2::rpc::{RpcClient, RpcHeader};
+use lyra2::solo::SoloMiner;
+use lyra2::verify::{read_headers, Verifier};
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+
+const USAGE: &str = "usage: lyra2-cli <command> [options]
+
+commands:
+  verify-headers [options] <file>
+      Verifies a file of concatenated 80-byte headers, such as an Electrum
+      blockchain_headers file.
+      --coin <name>           chain of the headers (default: monacoin)
+      --chain-config <file>   loads custom chains from a JSON config file
+      --start-height <n>      height of the first header (default: 0)
+      --retarget <rule>       also checks nBits: kgw, dgw3, digishield[:interval]
+                              or lwma[:window]
+      --threads <n>           number of threads (default: number of cores)
+      --skip-unsupported      skips the PoW of heights not hashed with Lyra2
+
+  check-header [options] [<file>]
+      Rebuilds a header from `getblockheader <hash> true` JSON, read from the
+      file or standard input, and checks its PoW against its bits.
+      --coin <name>           chain of the header (default: monacoin)
+      --chain-config <file>   loads custom chains from a JSON config file
+      --algorithm <name>      hashes with this algorithm instead of the one of
+                              the chain at the header's height
+
+  genesis [options] --message <text>
+      Mines a genesis block and prints it in chainparams.cpp form.
+      --message <text>        timestamp message of the coinbase
+      --pubkey <hex>          pays the coinbase to this pubkey (P2PK)
+      --script <hex>          pays the coinbase to this output script
+      --algorithm <name>      PoW algorithm (default: lyra2rev2)
+      --bits <hex>            compact target (default: 1e0ffff0)
+      --time <unix time>      block time (default: now)
+      --version <n>           block version (default: 1)
+      --reward <coins>        coinbase value (default: 50)
+      --threads <n>           number of threads (default: 1)
+
+  regtest-blocks [options] <prev>
+      Mines blocks on top of <prev>, a header or block in hex, and prints
+      each block in hex, ready for submitblock.
+      --height <n>            height of the first block (default: 1)
+      --count <n>             number of blocks (default: 1)
+      --script <hex>          coinbase output script (default: 51, OP_TRUE)
+      --algorithm <name>      PoW algorithm (default: lyra2rev2)
+      --bits <hex>            compact target (default: 207fffff)
+      --time <unix time>      time of the first block (default: one second
+                              after <prev>)
+      --version <n>           block version (default: 536870912)
+      --reward <coins>        coinbase value (default: 50)
+      --threads <n>           number of threads (default: 1)
+
+  solo [options] --script <hex>
+      Mines blocks from the getblocktemplate of a node and submits them, and
+      prints the hash of each accepted block.
+      --script <hex>          coinbase output script to pay to
+      --rpc <host:port>       JSON-RPC address of the node
+                              (default: 127.0.0.1:9402)
+      --user <name>           rpcuser of the node
+      --password <text>       rpcpassword of the node
+      --algorithm <name>      PoW algorithm (default: lyra2rev2)
+      --tag <text>            text pushed at the end of the coinbase script
+      --count <n>             number of blocks (default: 1)
+      --threads <n>           number of threads (default: 1)";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("verify-headers") => verify_headers(&args[1..]),
+        Some("check-header") => check_header(&args[1..]),
+        Some("genesis") => genesis(&args[1..]),
+        Some("regtest-blocks") => regtest_blocks(&args[1..]),
+        Some("solo") => solo(&args[1..]),
+        Some("help") | Some("-h") | Some("--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(command) => Err(Error::Usage(format!("unknown command: {}", command))),
+        None => Err(Error::Usage("missing command".to_string())),
+    };
+    match result {
+        Ok(()) => {}
+        Err(Error::Usage(msg)) => {
+            eprintln!("error: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+        Err(Error::Failed(msg)) => {
+            eprintln!("error: {}", msg);
+            process::exit(1);
+        }
+    }
+}
+
+enum Error {
+    // Bad arguments; prints the usage.
+    Usage(String),
+    // The command ran and failed.
+    Failed(String),
+}
+
+// Options splits `--name value` options and flags from positional arguments.
+struct Options {
+    options: Vec<(String, Option<String>)>,
+    positional: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String], flags: &[&str]) -> Result<Options, Error> {
+        let mut options = Vec::new();
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if let Some(name) = arg.strip_prefix("--") {
+                if flags.contains(&name) {
+                    options.push((name.to_string(), None));
+                } else {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| Error::Usage(format!("missing value for --{}", name)))?;
+                    options.push((name.to_string(), Some(value.clone())));
+                }
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+        Ok(Options {
+            options,
+            positional,
+        })
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(n, _)| n == name)
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, Error> {
+        self.value(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| Error::Usage(format!("invalid --{}: {}", name, value)))
+            })
+            .transpose()
+    }
+
+    fn check(&self, known: &[&str]) -> Result<(), Error> {
+        match self
+            .options
+            .iter()
+            .find(|(n, _)| !known.contains(&n.as_str()))
+        {
+            Some((name, _)) => Err(Error::Usage(format!("unknown option: --{}", name))),
+            None => Ok(()),
+        }
+    }
+}
+
+// chain_params looks up `--coin` among the built-in chains and those of `--chain-config`.
+fn chain_params(options: &Options) -> Result<ChainParams, Error> {
+    let mut registry = Registry::new();
+    if let Some(path) = options.value("chain-config") {
+        registry
+            .load(path)
+            .map_err(|err| Error::Failed(err.to_string()))?;
+    }
+    registry
+        .get(options.value("coin").unwrap_or("monacoin"))
+        .cloned()
+        .map_err(|err| Error::Usage(err.to_string()))
+}
+
+fn parse_retarget(rule: &str, params: &ChainParams) -> Result<Retarget, Error> {
+    let (name, arg) = match rule.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (rule, None),
+    };
+    let arg = |default: u32| -> Result<u32, Error> {
+        arg.map_or(Ok(default), |arg| {
+            arg.parse()
+                .map_err(|_| Error::Usage(format!("invalid --retarget: {}", rule)))
+        })
+    };
+    match name {
+        "kgw" => Ok(Retarget::kgw(params.target_spacing)),
+        "dgw3" => Ok(Retarget::DGW3),
+        "digishield" => Ok(Retarget::DigiShield { interval: arg(1)? }),
+        "lwma" => Ok(Retarget::Lwma { window: arg(45)? }),
+        _ => Err(Error::Usage(format!("invalid --retarget: {}", rule))),
+    }
+}
+
+fn parse_algorithm(options: &Options) -> Result<Algorithm, Error> {
+    options
+        .value("algorithm")
+        .unwrap_or("lyra2rev2")
+        .parse()
+        .map_err(|err: ParseAlgorithmError| Error::Usage(err.to_string()))
+}
+
+fn parse_hex(options: &Options, name: &str) -> Result<Option<Vec<u8>>, Error> {
+    options
+        .value(name)
+        .map(|hex| {
+            let hex = hex.trim_start_matches("0x");
+            if hex.len() % 2 != 0 {
+                return Err(Error::Usage(format!("invalid --{}: {}", name, hex)));
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or("zz"), 16))
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| Error::Usage(format!("invalid --{}: {}", name, hex)))
+        })
+        .transpose()
+}
+
+// generator configures a generator from the options shared by genesis and regtest-blocks.
+fn generator(options: &Options, default_bits: u32) -> Result<Generator, Error> {
+    let bits = match options.value("bits") {
+        Some(bits) => u32::from_str_radix(bits.trim_start_matches("0x"), 16)
+            .map_err(|_| Error::Usage(format!("invalid --bits: {}", bits)))?,
+        None => default_bits,
+    };
+    let mut generator = Generator::new(parse_algorithm(options)?, bits)
+        .threads(options.number("threads")?.unwrap_or(1));
+    if let Some(time) = options.number("time")? {
+        generator = generator.time(time);
+    }
+    if let Some(version) = options.number("version")? {
+        generator = generator.version(version);
+    }
+    Ok(generator)
+}
+
+fn reward(options: &Options) -> Result<u64, Error> {
+    let coins: u64 = options.number("reward")?.unwrap_or(50);
+    coins
+        .checked_mul(COIN)
+        .ok_or_else(|| Error::Usage(format!("invalid --reward: {}", coins)))
+}
+
+fn verify_headers(args: &[String]) -> Result<(), Error> {
+    let options = Options::parse(args, &["skip-unsupported"])?;
+    options.check(&[
+        "coin",
+        "chain-config",
+        "start-height",
+        "retarget",
+        "threads",
+        "skip-unsupported",
+    ])?;
+    let path = match options.positional.as_slice() {
+        [path] => path,
+        _ => return Err(Error::Usage("expected one header file".to_string())),
+    };
+    let params = chain_params(&options)?;
+    let start_height = options.number("start-height")?.unwrap_or(0);
+
+    let mut verifier = Verifier::new(&params)
+        .start_height(start_height)
+        .skip_unsupported(options.flag("skip-unsupported"));
+    if let Some(rule) = options.value("retarget") {
+        verifier = verifier.retarget(parse_retarget(rule, &params)?);
+    }
+    if let Some(threads) = options.number("threads")? {
+        verifier = verifier.threads(threads);
+    }
+
+    let headers = read_headers(path).map_err(|err| Error::Failed(format!("{}: {}", path, err)))?;
+    let summary = verifier
+        .verify(&headers)
+        .map_err(|err| Error::Failed(err.to_string()))?;
+    println!(
+        "{} headers ok ({} heights {}..{}): {} PoW checked, {} PoW skipped, {} retargets checked",
+        summary.headers,
+        params.name,
+        start_height,
+        start_height as usize + summary.headers,
+        summary.pow_checked,
+        summary.pow_skipped,
+        summary.retarget_checked
+    );
+    Ok(())
+}
+
+fn check_header(args: &[String]) -> Result<(), Error> {
+    let options = Options::parse(args, &[])?;
+    options.check(&["coin", "chain-config", "algorithm"])?;
+    let path = match options.positional.as_slice() {
+        [] => "-",
+        [path] => path.as_str(),
+        _ => return Err(Error::Usage("expected at most one JSON file".to_string())),
+    };
+    let json = if path == "-" {
+        let mut json = String::new();
+        io::stdin().read_to_string(&mut json).map(|_| json)
+    } else {
+        fs::read_to_string(path)
+    }
+    .map_err(|err| Error::Failed(format!("{}: {}", path, err)))?;
+
+    let rpc = RpcHeader::from_json(&json).map_err(|err| Error::Failed(err.to_string()))?;
+    let check = match options.value("algorithm") {
+        Some(name) => {
+            let algorithm: Algorithm = name
+                .parse()
+                .map_err(|err: ParseAlgorithmError| Error::Usage(err.to_string()))?;
+            rpc.check_with(algorithm)
+        }
+        None => rpc.check(&chain_params(&options)?),
+    }
+    .map_err(|err| Error::Failed(err.to_string()))?;
+
+    println!("header:    {}", rpc.header.to_hex());
+    println!("hash:      {}", check.block_hash);
+    match check.hash_matches {
+        Some(true) => println!("           matches the reported hash"),
+        Some(false) => println!(
+            "           DOES NOT match the reported hash {}",
+            rpc.hash.unwrap()
+        ),
+        None => {}
+    }
+    println!("algorithm: {}", check.algorithm);
+    println!("pow hash:  {}", check.pow_hash);
+    println!("target:    {} (bits {:08x})", check.target, rpc.header.bits);
+    println!(
+        "pow:       {}",
+        if check.meets_target {
+            "meets target"
+        } else {
+            "DOES NOT meet target"
+        }
+    );
+    if check.is_valid() {
+        Ok(())
+    } else {
+        Err(Error::Failed("header is not valid".to_string()))
+    }
+}
+
+fn genesis(args: &[String]) -> Result<(), Error> {
+    let options = Options::parse(args, &[])?;
+    options.check(&[
+        "message",
+        "pubkey",
+        "script",
+        "algorithm",
+        "bits",
+        "time",
+        "version",
+        "reward",
+        "threads",
+    ])?;
+    if !options.positional.is_empty() {
+        return Err(Error::Usage("unexpected argument".to_string()));
+    }
+    let message = options
+        .value("message")
+        .ok_or_else(|| Error::Usage("missing --message".to_string()))?;
+    let output_script = match (
+        parse_hex(&options, "pubkey")?,
+        parse_hex(&options, "script")?,
+    ) {
+        (Some(pubkey), None) => genesis::p2pk_script(&pubkey),
+        (None, Some(script)) => script,
+        _ => {
+            return Err(Error::Usage(
+                "expected one of --pubkey and --script".to_string(),
+            ))
+        }
+    };
+    let mut genesis = Genesis::new(message, &output_script);
+    genesis.reward = reward(&options)?;
+    let algorithm = parse_algorithm(&options)?;
+
+    let block = generator(&options, 0x1e0ffff0)?
+        .genesis(&genesis)
+        .map_err(|err| Error::Failed(err.to_string()))?;
+    print!("{}", genesis.chainparams(&block, algorithm));
+    println!("// block: {}", block.to_hex());
+    Ok(())
+}
+
+fn regtest_blocks(args: &[String]) -> Result<(), Error> {
+    let options = Options::parse(args, &[])?;
+    options.check(&[
+        "height",
+        "count",
+        "script",
+        "algorithm",
+        "bits",
+        "time",
+        "version",
+        "reward",
+        "threads",
+    ])?;
+    let prev = match options.positional.as_slice() {
+        [prev] => prev.trim(),
+        _ => {
+            return Err(Error::Usage(
+                "expected the previous header or block".to_string(),
+            ))
+        }
+    };
+    let mut prev = if prev.len() == BlockHeader::SIZE * 2 {
+        BlockHeader::from_hex(prev).map_err(|err| Error::Usage(err.to_string()))?
+    } else {
+        Block::from_hex(prev)
+            .map_err(|err| Error::Usage(err.to_string()))?
+            .header
+    };
+    let height: u32 = options.number("height")?.unwrap_or(1);
+    let count: u32 = options.number("count")?.unwrap_or(1);
+    let script = parse_hex(&options, "script")?.unwrap_or_else(|| vec![0x51]);
+    let value = reward(&options)?;
+
+    let mut generator = generator(&options, 0x207fffff)?;
+    for i in 0..count {
+        let coinbase = genesis::coinbase(height + i, &script, value);
+        let block = generator
+            .next_block(&prev, coinbase, Vec::new())
+            .map_err(|err| Error::Failed(err.to_string()))?;
+        println!("{}", block.to_hex());
+        prev = block.header;
+        // Only the first block takes --time; the others follow it.
+        generator = generator.time(prev.time + 1);
+    }
+    Ok(())
+}
+
+fn solo(args: &[String]) -> Result<(), Error> {
+    let options = Options::parse(args, &[])?;
+    options.check(&[
+        "script",
+        "rpc",
+        "user",
+        "password",
+        "algorithm",
+        "tag",
+        "count",
+        "threads",
+    ])?;
+    if !options.positional.is_empty() {
+        return Err(Error::Usage("unexpected argument".to_string()));
+    }
+    let script = parse_hex(&options, "script")?
+        .ok_or_else(|| Error::Usage("missing --script".to_string()))?;
+    let mut client = RpcClient::new(options.value("rpc").unwrap_or("127.0.0.1:9402"));
+    if let Some(user) = options.value("user") {
+        client = client.auth(user, options.value("password").unwrap_or(""));
+    }
+    let count: u32 = options.number("count")?.unwrap_or(1);
+    let miner = SoloMiner::new(client, parse_algorithm(&options)?, &script)
+        .coinbase_tag(options.value("tag").unwrap_or("").as_bytes())
+        .threads(options.number("threads")?.unwrap_or(1));
+    for _ in 0..count {
+        let mined = miner
+            .mine_block()
+            .map_err(|err| Error::Failed(err.to_string()))?;
+        println!("block {} at height {}", mined.block_hash, mined.height);
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! # lyra2-hashd
+//!
+//! `lyra2-hashd` serves the hash functions of the `lyra2` crate over HTTP JSON-RPC on a local
+//! port; see the `hashd` module for the methods.
+//!
+//! ```text
+//! lyra2-hashd [options]
+//! ```
+use lyra2::hashd::{HashServer, HashServerConfig};
+use std::env;
+use std::process;
+use std::thread;
+
+const USAGE: &str = "usage: lyra2-hashd [options]
+
+Serves hash, verify_header and lyra2 over HTTP JSON-RPC.
+  --listen <host:port>    address to listen on (default: 127.0.0.1:9430)
+  --workers <n>           number of worker threads (default: number of cores)
+  --queue <n>             connections waiting for a worker before new ones
+                          are refused with 503 (default: 64)
+  --max-body <bytes>      largest request body (default: 65536)
+  --max-param <bytes>     largest decoded hex param (default: 4096)
+  --max-memory <bytes>    largest lyra2 memory matrix (default: 16777216)
+  --max-time-cost <n>     largest lyra2 t_cost (default: 16)";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args
+        .iter()
+        .any(|arg| matches!(arg.as_str(), "help" | "-h" | "--help"))
+    {
+        println!("{}", USAGE);
+        return;
+    }
+    let (listen, config) = match parse(&args) {
+        Ok(parsed) => parsed,
+        Err(msg) => {
+            eprintln!("error: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+    let server = match HashServer::new(config).listen(&listen) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("error: cannot listen on {}: {}", listen, err);
+            process::exit(1);
+        }
+    };
+    eprintln!("listening on {}", server.local_addr());
+    loop {
+        thread::park();
+    }
+}
+
+fn parse(args: &[String]) -> Result<(String, HashServerConfig), String> {
+    let mut listen = "127.0.0.1:9430".to_string();
+    let mut config = HashServerConfig::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .ok_or_else(|| format!("unexpected argument: {}", arg))?;
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for --{}", name))?;
+        let number = || {
+            value
+                .parse::<u64>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("invalid --{}: {}", name, value))
+        };
+        match name {
+            "listen" => listen = value.clone(),
+            "workers" => config.workers = number()? as usize,
+            "queue" => config.queue = number()? as usize,
+            "max-body" => config.max_body = number()? as usize,
+            "max-param" => config.max_param = number()? as usize,
+            "max-memory" => config.max_lyra2_memory = number()?,
+            "max-time-cost" => config.max_time_cost = number()?,
+            _ => return Err(format!("unknown option: --{}", name)),
+        }
+    }
+    Ok((listen, config))
+}
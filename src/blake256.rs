@@ -0,0 +1,328 @@
+//! # blake256
+//!
+//! `blake256` crate has necessary formulas to calculate Blake-256 (14 rounds), the first stage of
+//! every chained algorithm in this crate.
+//!
+//! [`Midstate`] holds the chaining value after the first 64 bytes of an 80-byte block header, so
+//! a miner iterating the nonce only needs to compress the final 16 bytes per attempt.
+use digest::block_buffer::Eager;
+use digest::core_api::{
+    AlgorithmName, Block, BlockSizeUser, Buffer, BufferKindUser, CoreWrapper, FixedOutputCore,
+    OutputSizeUser, UpdateCore,
+};
+use digest::typenum::{U32, U64};
+use digest::{HashMarker, Output, Reset};
+use std::fmt;
+
+const IV: [u32; 8] = [
+    0x6A09_E667,
+    0xBB67_AE85,
+    0x3C6E_F372,
+    0xA54F_F53A,
+    0x510E_527F,
+    0x9B05_688C,
+    0x1F83_D9AB,
+    0x5BE0_CD19,
+];
+
+const U: [u32; 16] = [
+    0x243F_6A88,
+    0x85A3_08D3,
+    0x1319_8A2E,
+    0x0370_7344,
+    0xA409_3822,
+    0x299F_31D0,
+    0x082E_FA98,
+    0xEC4E_6C89,
+    0x4528_21E6,
+    0x38D0_1377,
+    0xBE54_66CF,
+    0x34E9_0C6C,
+    0xC0AC_29B7,
+    0xC97C_50DD,
+    0x3F84_D5B5,
+    0xB547_0917,
+];
+
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+const ROUNDS: usize = 14;
+
+// g is the G function applied to the words `abcd` of column or diagonal `i`, with the
+// message permutation `s` of the current round.
+#[inline(always)]
+fn g(v: &mut [u32; 16], m: &[u32; 16], s: &[usize; 16], i: usize, abcd: [usize; 4]) {
+    let [a, b, c, d] = abcd;
+    v[a] = v[a]
+        .wrapping_add(v[b])
+        .wrapping_add(m[s[2 * i]] ^ U[s[2 * i + 1]]);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(12);
+    v[a] = v[a]
+        .wrapping_add(v[b])
+        .wrapping_add(m[s[2 * i + 1]] ^ U[s[2 * i]]);
+    v[d] = (v[d] ^ v[a]).rotate_right(8);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(7);
+}
+
+// compress_words runs the compression function over message words `m` with bit counter `t`.
+fn compress_words(h: &mut [u32; 8], m: &[u32; 16], t: u64) {
+    let mut v = [0u32; 16];
+    v[..8].copy_from_slice(h);
+    v[8..].copy_from_slice(&U[..8]);
+    v[12] ^= t as u32;
+    v[13] ^= t as u32;
+    v[14] ^= (t >> 32) as u32;
+    v[15] ^= (t >> 32) as u32;
+
+    for r in 0..ROUNDS {
+        let s = &SIGMA[r % 10];
+        g(&mut v, m, s, 0, [0, 4, 8, 12]);
+        g(&mut v, m, s, 1, [1, 5, 9, 13]);
+        g(&mut v, m, s, 2, [2, 6, 10, 14]);
+        g(&mut v, m, s, 3, [3, 7, 11, 15]);
+        g(&mut v, m, s, 4, [0, 5, 10, 15]);
+        g(&mut v, m, s, 5, [1, 6, 11, 12]);
+        g(&mut v, m, s, 6, [2, 7, 8, 13]);
+        g(&mut v, m, s, 7, [3, 4, 9, 14]);
+    }
+
+    for i in 0..8 {
+        h[i] ^= v[i] ^ v[i + 8];
+    }
+}
+
+fn compress(h: &mut [u32; 8], block: &[u8], t: u64) {
+    let mut m = [0u32; 16];
+    for (word, chunk) in m.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    compress_words(h, &m, t);
+}
+
+fn output(h: &[u32; 8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    for (chunk, word) in out.chunks_exact_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+// finalize pads the trailing `data` (less than one block) of a message of `bit_len` bits and
+// compresses the last one or two blocks. A block holding no message bits uses a zero counter.
+fn finalize(h: &mut [u32; 8], data: &[u8], bit_len: u64) {
+    let mut block = [0u8; 64];
+    block[..data.len()].copy_from_slice(data);
+    block[data.len()] = 0x80;
+    let counter = if data.is_empty() { 0 } else { bit_len };
+    if data.len() < 56 {
+        block[55] |= 0x01;
+        block[56..].copy_from_slice(&bit_len.to_be_bytes());
+        compress(h, &block, counter);
+    } else {
+        compress(h, &block, counter);
+        let mut block = [0u8; 64];
+        block[55] = 0x01;
+        block[56..].copy_from_slice(&bit_len.to_be_bytes());
+        compress(h, &block, 0);
+    }
+}
+
+/// Core block-level state of Blake-256.
+#[derive(Clone)]
+pub struct Blake256Core {
+    h: [u32; 8],
+    block_len: u64,
+}
+
+/// Blake-256 hasher implementing the `digest` traits.
+pub type Blake256 = CoreWrapper<Blake256Core>;
+
+impl HashMarker for Blake256Core {}
+
+impl BlockSizeUser for Blake256Core {
+    type BlockSize = U64;
+}
+
+impl BufferKindUser for Blake256Core {
+    type BufferKind = Eager;
+}
+
+impl OutputSizeUser for Blake256Core {
+    type OutputSize = U32;
+}
+
+impl UpdateCore for Blake256Core {
+    fn update_blocks(&mut self, blocks: &[Block<Self>]) {
+        for block in blocks {
+            self.block_len += 1;
+            compress(&mut self.h, block, 512 * self.block_len);
+        }
+    }
+}
+
+impl FixedOutputCore for Blake256Core {
+    fn finalize_fixed_core(&mut self, buffer: &mut Buffer<Self>, out: &mut Output<Self>) {
+        let bit_len = 8 * (buffer.get_pos() as u64 + 64 * self.block_len);
+        finalize(&mut self.h, buffer.get_data(), bit_len);
+        out.copy_from_slice(&output(&self.h));
+    }
+}
+
+impl Default for Blake256Core {
+    fn default() -> Self {
+        Self {
+            h: IV,
+            block_len: 0,
+        }
+    }
+}
+
+impl Reset for Blake256Core {
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl AlgorithmName for Blake256Core {
+    fn write_alg_name(f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BLAKE-256")
+    }
+}
+
+impl fmt::Debug for Blake256Core {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Blake256Core { ... }")
+    }
+}
+
+/// The Blake-256 chaining value after the first 64-byte block of an 80-byte message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Midstate {
+    h: [u32; 8],
+}
+
+impl Midstate {
+    /// Compresses the first 64 bytes of an 80-byte message, e.g. a block header up to and
+    /// including the first 28 bytes of the merkle root.
+    pub fn new(prefix: &[u8; 64]) -> Midstate {
+        let mut h = IV;
+        compress(&mut h, prefix, 512);
+        Midstate { h }
+    }
+
+    /// Returns Blake-256 of `prefix || tail`, where `prefix` is the block this midstate was
+    /// made from. Only the final block is compressed.
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::blake256::Midstate;
+    ///
+    /// let header = [7u8; 80];
+    /// let midstate = Midstate::new(header[..64].try_into().unwrap());
+    /// assert_eq!(
+    ///     midstate.finish(header[64..].try_into().unwrap()).to_vec(),
+    ///     lyra2::blake256::sum(header.to_vec())
+    /// );
+    /// ```
+    pub fn finish(&self, tail: &[u8; 16]) -> [u8; 32] {
+        let mut m = [0u32; 16];
+        for (word, chunk) in m.iter_mut().zip(tail.chunks_exact(4)) {
+            *word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        m[4] = 0x8000_0000;
+        m[13] = 0x0000_0001;
+        m[15] = 640;
+        let mut h = self.h;
+        compress_words(&mut h, &m, 640);
+        output(&h)
+    }
+}
+
+/// Returns the calculation result of Blake-256.
+/// # Examples
+///
+/// ```
+/// let base1 = "abc".as_bytes().to_vec();
+/// let blake256_result1 = lyra2::blake256::sum(base1);
+/// assert_eq!(
+///     "1833a9fa7cf4086bd5fda73da32e5a1d75b4c3f89d5c436369f9d78bb2da5c28",
+///     blake256_result1
+///         .iter()
+///         .map(|n| format!("{:02x}", n))
+///         .collect::<String>()
+/// );
+/// ```
+pub fn sum(input: Vec<u8>) -> Vec<u8> {
+    let mut h = IV;
+    let mut blocks = input.chunks_exact(64);
+    for (i, block) in blocks.by_ref().enumerate() {
+        compress(&mut h, block, 512 * (i as u64 + 1));
+    }
+    finalize(&mut h, blocks.remainder(), 8 * input.len() as u64);
+    output(&h).to_vec()
+}
+
+#[test]
+fn blake256_hash_cal() {
+    use blake_hash::Digest as BlakeDigest;
+    use digest::Digest;
+
+    // Test vectors from the BLAKE submission: the empty message, one zero byte and 72 zero bytes.
+    let base1 = Vec::new();
+    assert_eq!(
+        "716f6e863f744b9ac22c97ec7b76ea5f5908bc5b2f67c61510bfc4751384ea7a",
+        sum(base1)
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+    let base2 = vec![0u8];
+    assert_eq!(
+        "0ce8d4ef4dd7cd8d62dfded9d4edb0a774ae6a41929a74da23109e8f11139c87",
+        sum(base2)
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+    let base3 = vec![0u8; 72];
+    assert_eq!(
+        "d419bad32d504fb7d44d460c42c5593fe544fa4c135dec31e21bd9abdcc22d41",
+        sum(base3)
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+
+    // Every padding case, one-shot and streamed, against the blake-hash crate.
+    let base4 = "脇山珠美ちゃんかわいい！".repeat(8).into_bytes();
+    for len in 0..base4.len() {
+        let input = &base4[..len];
+        let expected = blake_hash::Blake256::digest(input).to_vec();
+        assert_eq!(sum(input.to_vec()), expected);
+        let mut hasher = Blake256::new();
+        for chunk in input.chunks(13) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finalize().to_vec(), expected);
+    }
+
+    let midstate = Midstate::new(base4[..64].try_into().unwrap());
+    assert_eq!(
+        midstate.finish(base4[64..80].try_into().unwrap()).to_vec(),
+        sum(base4[..80].to_vec())
+    );
+}
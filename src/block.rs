@@ -0,0 +1,697 @@
+//! # block
+//!
+//! `block` crate parses serialized blocks, such as the result of `getblock <hash> 0`: the
+//! header, and transactions with or without segwit witnesses. It recomputes txids, the merkle
+//! root and the witness commitment, and checks the PoW with the algorithms of this crate.
+use crate::algorithm::Algorithm;
+use crate::coin::{ChainParams, CoinError};
+use crate::header::{BlockHeader, Hash256};
+use crate::merkle;
+use crate::target::{Target, TargetError};
+use crate::utils::{from_hex, read_u32_le, read_u64_le, to_hex};
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// The script prefix of the witness commitment output of a coinbase: `OP_RETURN`, a 36-byte
+/// push and the commitment header `aa21a9ed`.
+pub const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// A transaction input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxIn {
+    /// The txid of the spent output, in internal byte order.
+    pub prev_txid: Hash256,
+    pub prev_index: u32,
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+    /// The witness stack; empty for inputs without a witness.
+    pub witness: Vec<Vec<u8>>,
+}
+
+/// A transaction output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxOut {
+    /// The value in the smallest unit of the coin.
+    pub value: u64,
+    pub script_pubkey: Vec<u8>,
+}
+
+/// A transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub version: i32,
+    pub inputs: Vec<TxIn>,
+    pub outputs: Vec<TxOut>,
+    pub lock_time: u32,
+}
+
+impl Transaction {
+    /// Parses one serialized transaction, which must fill `data` exactly.
+    pub fn parse(data: &[u8]) -> Result<Transaction, BlockError> {
+        let mut reader = Reader::new(data);
+        let tx = Transaction::read(&mut reader)?;
+        reader.finish()?;
+        Ok(tx)
+    }
+
+    fn read(reader: &mut Reader) -> Result<Transaction, BlockError> {
+        let version = reader.u32()? as i32;
+        let mut inputs_len = reader.var_int()?;
+        // A zero input count is the segwit marker when followed by the flag 0x01.
+        let segwit = inputs_len == 0 && reader.peek() == Some(1);
+        if segwit {
+            reader.u8()?;
+            inputs_len = reader.var_int()?;
+        }
+        let mut inputs = Vec::with_capacity(reader.capacity(inputs_len, 41));
+        for _ in 0..inputs_len {
+            inputs.push(TxIn {
+                prev_txid: Hash256::from_slice(reader.bytes(32)?).expect("32 bytes"),
+                prev_index: reader.u32()?,
+                script_sig: reader.var_bytes()?.to_vec(),
+                sequence: reader.u32()?,
+                witness: Vec::new(),
+            });
+        }
+        let outputs_len = reader.var_int()?;
+        let mut outputs = Vec::with_capacity(reader.capacity(outputs_len, 9));
+        for _ in 0..outputs_len {
+            outputs.push(TxOut {
+                value: reader.u64()?,
+                script_pubkey: reader.var_bytes()?.to_vec(),
+            });
+        }
+        if segwit {
+            for input in inputs.iter_mut() {
+                let items = reader.var_int()?;
+                input.witness = Vec::with_capacity(reader.capacity(items, 1));
+                for _ in 0..items {
+                    input.witness.push(reader.var_bytes()?.to_vec());
+                }
+            }
+            if inputs.iter().all(|input| input.witness.is_empty()) {
+                return Err(BlockError::Malformed(
+                    "superfluous witness flag".to_string(),
+                ));
+            }
+        }
+        Ok(Transaction {
+            version,
+            inputs,
+            outputs,
+            lock_time: reader.u32()?,
+        })
+    }
+
+    /// Returns true if any input has a witness.
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
+    /// Returns true if the transaction is a coinbase: one input spending the null outpoint.
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1
+            && self.inputs[0].prev_txid == Hash256::default()
+            && self.inputs[0].prev_index == u32::MAX
+    }
+
+    /// Returns the serialization, with witnesses if there are any.
+    pub fn serialize(&self) -> Vec<u8> {
+        self.serialize_with(self.has_witness())
+    }
+
+    /// Returns the serialization without witnesses, the one txids are computed from.
+    pub fn serialize_no_witness(&self) -> Vec<u8> {
+        self.serialize_with(false)
+    }
+
+    fn serialize_with(&self, witness: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&self.version.to_le_bytes());
+        if witness {
+            out.extend_from_slice(&[0, 1]);
+        }
+        write_var_int(&mut out, self.inputs.len() as u64);
+        for input in &self.inputs {
+            out.extend_from_slice(input.prev_txid.as_bytes());
+            out.extend_from_slice(&input.prev_index.to_le_bytes());
+            write_var_bytes(&mut out, &input.script_sig);
+            out.extend_from_slice(&input.sequence.to_le_bytes());
+        }
+        write_var_int(&mut out, self.outputs.len() as u64);
+        for output in &self.outputs {
+            out.extend_from_slice(&output.value.to_le_bytes());
+            write_var_bytes(&mut out, &output.script_pubkey);
+        }
+        if witness {
+            for input in &self.inputs {
+                write_var_int(&mut out, input.witness.len() as u64);
+                for item in &input.witness {
+                    write_var_bytes(&mut out, item);
+                }
+            }
+        }
+        out.extend_from_slice(&self.lock_time.to_le_bytes());
+        out
+    }
+
+    /// Returns the txid, the double SHA-256 of the serialization without witnesses.
+    pub fn txid(&self) -> Hash256 {
+        Hash256::sha256d(&self.serialize_no_witness())
+    }
+
+    /// Returns the wtxid, the double SHA-256 of the serialization with witnesses.
+    pub fn wtxid(&self) -> Hash256 {
+        Hash256::sha256d(&self.serialize())
+    }
+}
+
+/// A block: a header and its transactions, the coinbase first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub transactions: Vec<Transaction>,
+}
+
+impl Block {
+    /// Parses a serialized block, which must fill `data` exactly.
+    pub fn parse(data: &[u8]) -> Result<Block, BlockError> {
+        let mut reader = Reader::new(data);
+        let header = BlockHeader::parse(reader.bytes(BlockHeader::SIZE)?).expect("80 bytes");
+        let count = reader.var_int()?;
+        let mut transactions = Vec::with_capacity(reader.capacity(count, 60));
+        for _ in 0..count {
+            transactions.push(Transaction::read(&mut reader)?);
+        }
+        reader.finish()?;
+        Ok(Block {
+            header,
+            transactions,
+        })
+    }
+
+    /// Parses a hex-encoded serialized block, as returned by `getblock <hash> 0`.
+    pub fn from_hex(hex: &str) -> Result<Block, BlockError> {
+        Block::parse(&from_hex(hex.trim()).ok_or(BlockError::InvalidHex)?)
+    }
+
+    /// Returns the serialization of the block.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = self.header.serialize().to_vec();
+        write_var_int(&mut out, self.transactions.len() as u64);
+        for tx in &self.transactions {
+            out.extend_from_slice(&tx.serialize());
+        }
+        out
+    }
+
+    /// Returns the hex encoding of the serialization.
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.serialize())
+    }
+
+    /// Returns the txids of the transactions, in block order.
+    pub fn txids(&self) -> Vec<Hash256> {
+        self.transactions.iter().map(Transaction::txid).collect()
+    }
+
+    /// Returns the merkle root of the txids.
+    pub fn merkle_root(&self) -> Hash256 {
+        merkle::merkle_root(&self.txids())
+    }
+
+    /// Returns the merkle root of the wtxids, the coinbase counting as zero.
+    pub fn witness_root(&self) -> Hash256 {
+        let wtxids: Vec<Hash256> = self
+            .transactions
+            .iter()
+            .enumerate()
+            .map(|(i, tx)| {
+                if i == 0 {
+                    Hash256::default()
+                } else {
+                    tx.wtxid()
+                }
+            })
+            .collect();
+        merkle::merkle_root(&wtxids)
+    }
+
+    /// Returns the witness commitment of the coinbase: the last output starting with
+    /// [`WITNESS_COMMITMENT_PREFIX`].
+    pub fn witness_commitment(&self) -> Option<Hash256> {
+        self.transactions
+            .first()?
+            .outputs
+            .iter()
+            .rev()
+            .find(|output| {
+                output.script_pubkey.len() >= 38
+                    && output.script_pubkey.starts_with(&WITNESS_COMMITMENT_PREFIX)
+            })
+            .map(|output| Hash256::from_slice(&output.script_pubkey[6..38]).expect("32 bytes"))
+    }
+
+    /// Returns the height pushed at the start of the coinbase script (BIP 34), if any.
+    pub fn coinbase_height(&self) -> Option<u32> {
+        let script = &self.transactions.first()?.inputs.first()?.script_sig;
+        match *script.first()? {
+            // OP_1 .. OP_16
+            op @ 0x51..=0x60 => Some(u32::from(op - 0x50)),
+            len @ 1..=4 => {
+                let bytes = script.get(1..1 + len as usize)?;
+                if bytes[bytes.len() - 1] & 0x80 != 0 {
+                    return None;
+                }
+                Some(
+                    bytes
+                        .iter()
+                        .rev()
+                        .fold(0u32, |acc, &b| acc << 8 | u32::from(b)),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks the structure of the block, its merkle root, its witness commitment and its PoW
+    /// hash under `algorithm` against its `nBits`.
+    pub fn validate(&self, algorithm: Algorithm) -> Result<(), BlockError> {
+        match self.transactions.first() {
+            Some(coinbase) if coinbase.is_coinbase() => {}
+            _ => {
+                return Err(BlockError::Malformed(
+                    "first transaction is not a coinbase".to_string(),
+                ))
+            }
+        }
+        if let Some(tx) = self.transactions[1..].iter().find(|tx| tx.is_coinbase()) {
+            return Err(BlockError::Malformed(format!(
+                "second coinbase {}",
+                tx.txid()
+            )));
+        }
+        let merkle_root = self.merkle_root();
+        if merkle_root != self.header.merkle_root {
+            return Err(BlockError::BadMerkleRoot {
+                expected: self.header.merkle_root,
+                found: merkle_root,
+            });
+        }
+        if self.transactions.iter().any(Transaction::has_witness) {
+            let commitment = self
+                .witness_commitment()
+                .ok_or(BlockError::BadWitnessCommitment)?;
+            let nonce = match self.transactions[0].inputs[0].witness.as_slice() {
+                [nonce] if nonce.len() == 32 => nonce.as_slice(),
+                _ => return Err(BlockError::BadWitnessCommitment),
+            };
+            let root = self.witness_root();
+            if Hash256::sha256d(&[root.as_bytes().as_slice(), nonce].concat()) != commitment {
+                return Err(BlockError::BadWitnessCommitment);
+            }
+        }
+        let target = self.header.target()?;
+        let hash = self.header.pow_hash(algorithm);
+        if !target.is_met_by(hash.as_bytes()) {
+            return Err(BlockError::HighHash { hash, target });
+        }
+        Ok(())
+    }
+
+    /// Same as [`Block::validate`], with the algorithm of `params` at `height`. Without a
+    /// height, the BIP 34 height of the coinbase is used.
+    pub fn validate_for(
+        &self,
+        params: &ChainParams,
+        height: Option<u32>,
+    ) -> Result<(), BlockError> {
+        let height = height
+            .or_else(|| self.coinbase_height())
+            .ok_or(BlockError::UnknownHeight)?;
+        self.validate(params.algorithm_at(height)?)
+    }
+}
+
+impl FromStr for Block {
+    type Err = BlockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Block::from_hex(s)
+    }
+}
+
+/// Returns the script of a witness commitment output for `witness_root` and the witness
+/// reserved value `reserved` of the coinbase input.
+pub fn commitment_script(witness_root: &Hash256, reserved: &[u8; 32]) -> Vec<u8> {
+    let commitment = Hash256::sha256d(&[witness_root.0, *reserved].concat());
+    [&WITNESS_COMMITMENT_PREFIX[..], commitment.as_bytes()].concat()
+}
+
+/// Appends a script push of `data`.
+pub(crate) fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    match data.len() {
+        0..=0x4b => script.push(data.len() as u8),
+        0x4c..=0xff => script.extend_from_slice(&[0x4c, data.len() as u8]),
+        0x100..=0xffff => {
+            script.push(0x4d);
+            script.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        }
+        _ => {
+            script.push(0x4e);
+            script.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        }
+    }
+    script.extend_from_slice(data);
+}
+
+/// Appends a script push of the number `n`, as `CScript() << n` of nodes does.
+pub(crate) fn push_int(script: &mut Vec<u8>, n: i64) {
+    match n {
+        0 => script.push(0x00),
+        -1 | 1..=16 => script.push((n + 0x50) as u8),
+        _ => {
+            // Minimal little-endian magnitude with the sign in the top bit.
+            let mut bytes = Vec::new();
+            let mut abs = n.unsigned_abs();
+            while abs > 0 {
+                bytes.push(abs as u8);
+                abs >>= 8;
+            }
+            if bytes[bytes.len() - 1] & 0x80 != 0 {
+                bytes.push(if n < 0 { 0x80 } else { 0 });
+            } else if n < 0 {
+                let last = bytes.len() - 1;
+                bytes[last] |= 0x80;
+            }
+            push_data(script, &bytes);
+        }
+    }
+}
+
+/// Appends `value` as a compact size (var int).
+pub(crate) fn write_var_int(out: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0xfc => out.push(value as u8),
+        0xfd..=0xffff => {
+            out.push(0xfd);
+            out.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(0xfe);
+            out.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        _ => {
+            out.push(0xff);
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+/// Appends `bytes` prefixed with their length.
+pub(crate) fn write_var_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_var_int(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+// Reader reads consensus-serialized fields from a byte slice.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], BlockError> {
+        if self.data.len() - self.pos < len {
+            return Err(BlockError::Truncated(self.data.len()));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn u8(&mut self) -> Result<u8, BlockError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, BlockError> {
+        Ok(read_u32_le(self.bytes(4)?))
+    }
+
+    fn u64(&mut self) -> Result<u64, BlockError> {
+        Ok(read_u64_le(self.bytes(8)?))
+    }
+
+    fn var_int(&mut self) -> Result<u64, BlockError> {
+        let (value, min) = match self.u8()? {
+            0xfd => {
+                let b = self.bytes(2)?;
+                (u64::from(u16::from_le_bytes([b[0], b[1]])), 0xfd)
+            }
+            0xfe => (u64::from(self.u32()?), 0x1_0000),
+            0xff => (self.u64()?, 0x1_0000_0000),
+            n => return Ok(u64::from(n)),
+        };
+        if value < min {
+            return Err(BlockError::Malformed(format!(
+                "non-canonical var int at offset {}",
+                self.pos
+            )));
+        }
+        Ok(value)
+    }
+
+    fn var_bytes(&mut self) -> Result<&'a [u8], BlockError> {
+        let len = self.var_int()?;
+        if len > (self.data.len() - self.pos) as u64 {
+            return Err(BlockError::Truncated(self.data.len()));
+        }
+        self.bytes(len as usize)
+    }
+
+    // capacity bounds a count read from the data by what the remaining bytes can hold, so
+    // that a bogus count cannot make us allocate.
+    fn capacity(&self, count: u64, min_size: usize) -> usize {
+        count.min(((self.data.len() - self.pos) / min_size) as u64) as usize
+    }
+
+    fn finish(&self) -> Result<(), BlockError> {
+        if self.pos != self.data.len() {
+            return Err(BlockError::TrailingData(self.data.len() - self.pos));
+        }
+        Ok(())
+    }
+}
+
+/// An error returned when a block cannot be parsed or is invalid.
+#[derive(Debug)]
+pub enum BlockError {
+    /// The hex is malformed.
+    InvalidHex,
+    /// The data ends early; holds the length of the data.
+    Truncated(usize),
+    /// Bytes are left after the block; holds their count.
+    TrailingData(usize),
+    /// The serialization or structure is invalid.
+    Malformed(String),
+    /// The merkle root of the txids differs from the header's.
+    BadMerkleRoot { expected: Hash256, found: Hash256 },
+    /// The block has witnesses but no matching witness commitment.
+    BadWitnessCommitment,
+    /// `nBits` does not decode to a target.
+    InvalidBits(TargetError),
+    /// The PoW hash does not meet the target.
+    HighHash { hash: Hash256, target: Target },
+    /// No height was given and the coinbase does not start with one.
+    UnknownHeight,
+    /// The block is hashed with an algorithm this crate does not implement.
+    Coin(CoinError),
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockError::InvalidHex => write!(f, "invalid block hex"),
+            BlockError::Truncated(len) => write!(f, "block data ends early at {} bytes", len),
+            BlockError::TrailingData(len) => write!(f, "{} bytes after the block", len),
+            BlockError::Malformed(msg) => write!(f, "malformed block: {}", msg),
+            BlockError::BadMerkleRoot { expected, found } => {
+                write!(f, "merkle root is {}, header has {}", found, expected)
+            }
+            BlockError::BadWitnessCommitment => write!(f, "witness commitment does not match"),
+            BlockError::InvalidBits(err) => write!(f, "{}", err),
+            BlockError::HighHash { hash, target } => {
+                write!(f, "PoW hash {} is above target {}", hash, target)
+            }
+            BlockError::UnknownHeight => write!(f, "block height is unknown"),
+            BlockError::Coin(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for BlockError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BlockError::Coin(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<TargetError> for BlockError {
+    fn from(err: TargetError) -> Self {
+        BlockError::InvalidBits(err)
+    }
+}
+
+impl From<CoinError> for BlockError {
+    fn from(err: CoinError) -> Self {
+        BlockError::Coin(err)
+    }
+}
+
+#[test]
+fn block_cal() {
+    use crate::coin::{Coin, Epoch};
+
+    // The Monacoin genesis block.
+    let genesis = "010000000000000000000000000000000000000000000000000000000000000000000000a64bac07fe31877f31d03252953b3c32398933af7a724119bc4d6fa4a805e435f083c252f0ff0f1e66d612000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff5f04ffff001d01044c564465632e20333174682032303133204a6170616e2c205468652077696e6e696e67206e756d62657273206f6620746865203230313320596561722d456e64204a756d626f204c6f74746572793a32332d313330393136ffffffff0100f2052a010000004341040184710fa689ad5023690c80f3a49c8f13f8d45b8c857fbcbc8bc4a8e4d3eb4b10f4d4604fa08dce601aaf0f470216fe1b51850b4acf21b179c45070ac7b03a9ac00000000";
+    let block = Block::from_hex(genesis).unwrap();
+    assert_eq!(block.to_hex(), genesis);
+    assert_eq!(
+        block.header.block_hash().to_string(),
+        "ff9f1c0116d19de7c9963845e129f9ed1bfc0b376eb54fd7afa42e0d418c8bb6"
+    );
+    assert_eq!(block.transactions.len(), 1);
+    let coinbase = &block.transactions[0];
+    assert!(coinbase.is_coinbase());
+    assert!(!coinbase.has_witness());
+    assert_eq!(coinbase.outputs[0].value, 50 * 100_000_000);
+    assert_eq!(coinbase.txid(), block.header.merkle_root);
+    assert_eq!(coinbase.wtxid(), coinbase.txid());
+    assert_eq!(block.merkle_root(), block.header.merkle_root);
+    assert_eq!(block.witness_commitment(), None);
+    // The genesis coinbase predates BIP 34 and starts with a push of the bits instead, so a
+    // height read from it is meaningless.
+    assert_eq!(block.coinbase_height(), Some(0x1d00ffff));
+    // It was mined with scrypt, so no Lyra2 variant accepts its PoW.
+    assert!(matches!(
+        block.validate(Algorithm::Lyra2REv2),
+        Err(BlockError::HighHash { .. })
+    ));
+    assert!(matches!(
+        block.validate_for(&Coin::Monacoin.params(), Some(0)),
+        Err(BlockError::Coin(CoinError::UnsupportedAlgorithm { .. }))
+    ));
+
+    assert!(matches!(
+        Block::from_hex(&genesis[..genesis.len() - 2]),
+        Err(BlockError::Truncated(_))
+    ));
+    assert!(matches!(
+        Block::from_hex(&format!("{}00", genesis)),
+        Err(BlockError::TrailingData(1))
+    ));
+    assert!(matches!(Block::from_hex("zz"), Err(BlockError::InvalidHex)));
+    let mut bad = block.clone();
+    bad.transactions[0].lock_time = 1;
+    assert!(matches!(
+        bad.validate(Algorithm::Lyra2REv2),
+        Err(BlockError::BadMerkleRoot { .. })
+    ));
+
+    // A segwit block at height 300 mined with Lyra2REv2 at minimal difficulty.
+    let spend = Transaction {
+        version: 2,
+        inputs: vec![TxIn {
+            prev_txid: coinbase.txid(),
+            prev_index: 0,
+            script_sig: Vec::new(),
+            sequence: 0xffff_fffe,
+            witness: vec![vec![0x30; 71], vec![0x02; 33]],
+        }],
+        outputs: vec![TxOut {
+            value: 49 * 100_000_000,
+            script_pubkey: [&[0x00, 0x14][..], &[0x11; 20]].concat(),
+        }],
+        lock_time: 299,
+    };
+    let mut coinbase = Transaction {
+        version: 1,
+        inputs: vec![TxIn {
+            prev_txid: Hash256::default(),
+            prev_index: u32::MAX,
+            script_sig: vec![0x02, 0x2c, 0x01, 0x00],
+            sequence: u32::MAX,
+            witness: vec![vec![0; 32]],
+        }],
+        outputs: vec![TxOut {
+            value: 50 * 100_000_000,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    };
+    let mut block = Block {
+        header: BlockHeader {
+            version: 0x20000000,
+            prev_block: Hash256::sha256d(b"parent"),
+            merkle_root: Hash256::default(),
+            time: 1500000000,
+            bits: 0x207fffff,
+            nonce: 0,
+        },
+        transactions: vec![coinbase.clone(), spend.clone()],
+    };
+    let script = commitment_script(&block.witness_root(), &[0; 32]);
+    let commitment = Hash256::from_slice(&script[6..]).unwrap();
+    coinbase.outputs.push(TxOut {
+        value: 0,
+        script_pubkey: script,
+    });
+    block.transactions[0] = coinbase;
+    block.header.merkle_root = block.merkle_root();
+    while !block.header.meets_target(Algorithm::Lyra2REv2) {
+        block.header.nonce += 1;
+    }
+
+    let parsed = Block::from_hex(&block.to_hex()).unwrap();
+    assert_eq!(parsed, block);
+    assert!(spend.has_witness());
+    assert_ne!(spend.txid(), spend.wtxid());
+    assert_eq!(
+        Transaction::parse(&spend.serialize_no_witness())
+            .unwrap()
+            .txid(),
+        spend.txid()
+    );
+    assert_eq!(parsed.coinbase_height(), Some(300));
+    assert_eq!(parsed.witness_commitment(), Some(commitment));
+    parsed.validate(Algorithm::Lyra2REv2).unwrap();
+    let mut params = Coin::Vertcoin.params();
+    params.schedule = vec![Epoch::new(0, "lyra2rev2")];
+    parsed.validate_for(&params, None).unwrap();
+
+    let mut bad = parsed.clone();
+    bad.transactions[1].inputs[0].witness[0][0] = 0x31;
+    assert!(matches!(
+        bad.validate(Algorithm::Lyra2REv2),
+        Err(BlockError::BadWitnessCommitment)
+    ));
+    // The marker and flag without any witness are rejected, as nodes do.
+    let mut stripped = spend.serialize_no_witness();
+    stripped.splice(4..4, [0, 1]);
+    stripped.splice(stripped.len() - 4..stripped.len() - 4, [0]);
+    assert!(matches!(
+        Transaction::parse(&stripped),
+        Err(BlockError::Malformed(_))
+    ));
+}
@@ -0,0 +1,358 @@
+use crate::utils::read_u64_le;
+use digest::block_buffer::Eager;
+use digest::core_api::{
+    AlgorithmName, Block, BlockSizeUser, Buffer, BufferKindUser, CoreWrapper, FixedOutputCore,
+    OutputSizeUser, UpdateCore,
+};
+use digest::typenum::{U128, U48, U64};
+use digest::{HashMarker, Output, Reset};
+use std::fmt;
+
+const INITVAL384: [u64; 16] = [
+    0x0001_0203_0405_0607,
+    0x0809_0A0B_0C0D_0E0F,
+    0x1011_1213_1415_1617,
+    0x1819_1A1B_1C1D_1E1F,
+    0x2021_2223_2425_2627,
+    0x2829_2A2B_2C2D_2E2F,
+    0x3031_3233_3435_3637,
+    0x3839_3A3B_3C3D_3E3F,
+    0x4041_4243_4445_4647,
+    0x4849_4A4B_4C4D_4E4F,
+    0x5051_5253_5455_5657,
+    0x5859_5A5B_5C5D_5E5F,
+    0x6061_6263_6465_6667,
+    0x6869_6A6B_6C6D_6E6F,
+    0x7071_7273_7475_7677,
+    0x7879_7A7B_7C7D_7E7F,
+];
+
+const INITVAL512: [u64; 16] = [
+    0x8081_8283_8485_8687,
+    0x8889_8A8B_8C8D_8E8F,
+    0x9091_9293_9495_9697,
+    0x9899_9A9B_9C9D_9E9F,
+    0xA0A1_A2A3_A4A5_A6A7,
+    0xA8A9_AAAB_ACAD_AEAF,
+    0xB0B1_B2B3_B4B5_B6B7,
+    0xB8B9_BABB_BCBD_BEBF,
+    0xC0C1_C2C3_C4C5_C6C7,
+    0xC8C9_CACB_CCCD_CECF,
+    0xD0D1_D2D3_D4D5_D6D7,
+    0xD8D9_DADB_DCDD_DEDF,
+    0xE0E1_E2E3_E4E5_E6E7,
+    0xE8E9_EAEB_ECED_EEEF,
+    0xF0F1_F2F3_F4F5_F6F7,
+    0xF8F9_FAFB_FCFD_FEFF,
+];
+
+const FINAL: [u64; 16] = [
+    0xaaaa_aaaa_aaaa_aaa0,
+    0xaaaa_aaaa_aaaa_aaa1,
+    0xaaaa_aaaa_aaaa_aaa2,
+    0xaaaa_aaaa_aaaa_aaa3,
+    0xaaaa_aaaa_aaaa_aaa4,
+    0xaaaa_aaaa_aaaa_aaa5,
+    0xaaaa_aaaa_aaaa_aaa6,
+    0xaaaa_aaaa_aaaa_aaa7,
+    0xaaaa_aaaa_aaaa_aaa8,
+    0xaaaa_aaaa_aaaa_aaa9,
+    0xaaaa_aaaa_aaaa_aaaa,
+    0xaaaa_aaaa_aaaa_aaab,
+    0xaaaa_aaaa_aaaa_aaac,
+    0xaaaa_aaaa_aaaa_aaad,
+    0xaaaa_aaaa_aaaa_aaae,
+    0xaaaa_aaaa_aaaa_aaaf,
+];
+
+// Rotation amounts r1..r7 used by expand2.
+const ROT: [u32; 7] = [5, 11, 27, 32, 37, 43, 53];
+
+fn s0(x: u64) -> u64 {
+    (x >> 1) ^ (x << 3) ^ x.rotate_left(4) ^ x.rotate_left(37)
+}
+
+fn s1(x: u64) -> u64 {
+    (x >> 1) ^ (x << 2) ^ x.rotate_left(13) ^ x.rotate_left(43)
+}
+
+fn s2(x: u64) -> u64 {
+    (x >> 2) ^ (x << 1) ^ x.rotate_left(19) ^ x.rotate_left(53)
+}
+
+fn s3(x: u64) -> u64 {
+    (x >> 2) ^ (x << 2) ^ x.rotate_left(28) ^ x.rotate_left(59)
+}
+
+fn s4(x: u64) -> u64 {
+    (x >> 1) ^ x
+}
+
+fn s5(x: u64) -> u64 {
+    (x >> 2) ^ x
+}
+
+// compress is the BMW-384/512 compression function f2(f1(f0(h, m))).
+fn compress(h: &[u64; 16], m: &[u64; 16]) -> [u64; 16] {
+    let mut t = [0u64; 16];
+    for i in 0..16 {
+        t[i] = m[i] ^ h[i];
+    }
+
+    //f0: bijective transform of m ^ h
+    let w = [
+        t[5].wrapping_sub(t[7]).wrapping_add(t[10]).wrapping_add(t[13]).wrapping_add(t[14]),
+        t[6].wrapping_sub(t[8]).wrapping_add(t[11]).wrapping_add(t[14]).wrapping_sub(t[15]),
+        t[0].wrapping_add(t[7]).wrapping_add(t[9]).wrapping_sub(t[12]).wrapping_add(t[15]),
+        t[0].wrapping_sub(t[1]).wrapping_add(t[8]).wrapping_sub(t[10]).wrapping_add(t[13]),
+        t[1].wrapping_add(t[2]).wrapping_add(t[9]).wrapping_sub(t[11]).wrapping_sub(t[14]),
+        t[3].wrapping_sub(t[2]).wrapping_add(t[10]).wrapping_sub(t[12]).wrapping_add(t[15]),
+        t[4].wrapping_sub(t[0]).wrapping_sub(t[3]).wrapping_sub(t[11]).wrapping_add(t[13]),
+        t[1].wrapping_sub(t[4]).wrapping_sub(t[5]).wrapping_sub(t[12]).wrapping_sub(t[14]),
+        t[2].wrapping_sub(t[5]).wrapping_sub(t[6]).wrapping_add(t[13]).wrapping_sub(t[15]),
+        t[0].wrapping_sub(t[3]).wrapping_add(t[6]).wrapping_sub(t[7]).wrapping_add(t[14]),
+        t[8].wrapping_sub(t[1]).wrapping_sub(t[4]).wrapping_sub(t[7]).wrapping_add(t[15]),
+        t[8].wrapping_sub(t[0]).wrapping_sub(t[2]).wrapping_sub(t[5]).wrapping_add(t[9]),
+        t[1].wrapping_add(t[3]).wrapping_sub(t[6]).wrapping_sub(t[9]).wrapping_add(t[10]),
+        t[2].wrapping_add(t[4]).wrapping_add(t[7]).wrapping_add(t[10]).wrapping_add(t[11]),
+        t[3].wrapping_sub(t[5]).wrapping_add(t[8]).wrapping_sub(t[11]).wrapping_sub(t[12]),
+        t[12].wrapping_sub(t[4]).wrapping_sub(t[6]).wrapping_sub(t[9]).wrapping_add(t[13]),
+    ];
+    let mut q = [0u64; 32];
+    for j in 0..16 {
+        let s = match j % 5 {
+            0 => s0(w[j]),
+            1 => s1(w[j]),
+            2 => s2(w[j]),
+            3 => s3(w[j]),
+            _ => s4(w[j]),
+        };
+        q[j] = s.wrapping_add(h[(j + 1) % 16]);
+    }
+
+    //f1: expand1 for the first two rounds, expand2 for the remaining fourteen
+    for j in 16..32 {
+        let add_element = (m[j % 16]
+            .rotate_left((j % 16 + 1) as u32)
+            .wrapping_add(m[(j + 3) % 16].rotate_left(((j + 3) % 16 + 1) as u32))
+            .wrapping_sub(m[(j + 10) % 16].rotate_left(((j + 10) % 16 + 1) as u32))
+            .wrapping_add((j as u64).wrapping_mul(0x0555_5555_5555_5555)))
+            ^ h[(j + 7) % 16];
+        let mut acc = add_element;
+        if j < 18 {
+            for i in 0..16 {
+                let x = q[j - 16 + i];
+                acc = acc.wrapping_add(match i % 4 {
+                    0 => s1(x),
+                    1 => s2(x),
+                    2 => s3(x),
+                    _ => s0(x),
+                });
+            }
+        } else {
+            for i in 0..14 {
+                let x = q[j - 16 + i];
+                acc = acc.wrapping_add(if i % 2 == 0 { x } else { x.rotate_left(ROT[i / 2]) });
+            }
+            acc = acc.wrapping_add(s4(q[j - 2])).wrapping_add(s5(q[j - 1]));
+        }
+        q[j] = acc;
+    }
+
+    //f2: fold q and m into the new chaining value
+    let xl = q[16] ^ q[17] ^ q[18] ^ q[19] ^ q[20] ^ q[21] ^ q[22] ^ q[23];
+    let xh = xl ^ q[24] ^ q[25] ^ q[26] ^ q[27] ^ q[28] ^ q[29] ^ q[30] ^ q[31];
+    let mut out = [0u64; 16];
+    out[0] = ((xh << 5) ^ (q[16] >> 5) ^ m[0]).wrapping_add(xl ^ q[24] ^ q[0]);
+    out[1] = ((xh >> 7) ^ (q[17] << 8) ^ m[1]).wrapping_add(xl ^ q[25] ^ q[1]);
+    out[2] = ((xh >> 5) ^ (q[18] << 5) ^ m[2]).wrapping_add(xl ^ q[26] ^ q[2]);
+    out[3] = ((xh >> 1) ^ (q[19] << 5) ^ m[3]).wrapping_add(xl ^ q[27] ^ q[3]);
+    out[4] = ((xh >> 3) ^ q[20] ^ m[4]).wrapping_add(xl ^ q[28] ^ q[4]);
+    out[5] = ((xh << 6) ^ (q[21] >> 6) ^ m[5]).wrapping_add(xl ^ q[29] ^ q[5]);
+    out[6] = ((xh >> 4) ^ (q[22] << 6) ^ m[6]).wrapping_add(xl ^ q[30] ^ q[6]);
+    out[7] = ((xh >> 11) ^ (q[23] << 2) ^ m[7]).wrapping_add(xl ^ q[31] ^ q[7]);
+    out[8] = out[4]
+        .rotate_left(9)
+        .wrapping_add(xh ^ q[24] ^ m[8])
+        .wrapping_add((xl << 8) ^ q[23] ^ q[8]);
+    out[9] = out[5]
+        .rotate_left(10)
+        .wrapping_add(xh ^ q[25] ^ m[9])
+        .wrapping_add((xl >> 6) ^ q[16] ^ q[9]);
+    out[10] = out[6]
+        .rotate_left(11)
+        .wrapping_add(xh ^ q[26] ^ m[10])
+        .wrapping_add((xl << 6) ^ q[17] ^ q[10]);
+    out[11] = out[7]
+        .rotate_left(12)
+        .wrapping_add(xh ^ q[27] ^ m[11])
+        .wrapping_add((xl << 4) ^ q[18] ^ q[11]);
+    out[12] = out[0]
+        .rotate_left(13)
+        .wrapping_add(xh ^ q[28] ^ m[12])
+        .wrapping_add((xl >> 3) ^ q[19] ^ q[12]);
+    out[13] = out[1]
+        .rotate_left(14)
+        .wrapping_add(xh ^ q[29] ^ m[13])
+        .wrapping_add((xl >> 4) ^ q[20] ^ q[13]);
+    out[14] = out[2]
+        .rotate_left(15)
+        .wrapping_add(xh ^ q[30] ^ m[14])
+        .wrapping_add((xl >> 7) ^ q[21] ^ q[14]);
+    out[15] = out[3]
+        .rotate_left(16)
+        .wrapping_add(xh ^ q[31] ^ m[15])
+        .wrapping_add((xl >> 2) ^ q[22] ^ q[15]);
+    out
+}
+
+// compress_block runs one BMW-384/512 compression over a 128-byte block.
+fn compress_block(h: &mut [u64; 16], block: &[u8]) {
+    let mut m = [0u64; 16];
+    for (i, word) in m.iter_mut().enumerate() {
+        *word = read_u64_le(&block[8 * i..8 * i + 8]);
+    }
+    *h = compress(h, &m);
+}
+
+macro_rules! big_core {
+    ($core:ident, $hasher:ident, $iv:ident, $out_size:ty, $out_words:expr, $alg:expr) => {
+        #[doc = concat!("Core block-level state of ", $alg, ".")]
+        #[derive(Clone)]
+        pub struct $core {
+            h: [u64; 16],
+            block_len: u64,
+        }
+
+        #[doc = concat!($alg, " hasher implementing the `digest` traits.")]
+        pub type $hasher = CoreWrapper<$core>;
+
+        impl HashMarker for $core {}
+
+        impl BlockSizeUser for $core {
+            type BlockSize = U128;
+        }
+
+        impl BufferKindUser for $core {
+            type BufferKind = Eager;
+        }
+
+        impl OutputSizeUser for $core {
+            type OutputSize = $out_size;
+        }
+
+        impl UpdateCore for $core {
+            fn update_blocks(&mut self, blocks: &[Block<Self>]) {
+                self.block_len += blocks.len() as u64;
+                for block in blocks {
+                    compress_block(&mut self.h, block);
+                }
+            }
+        }
+
+        impl FixedOutputCore for $core {
+            fn finalize_fixed_core(&mut self, buffer: &mut Buffer<Self>, out: &mut Output<Self>) {
+                let bit_len = 8 * (buffer.get_pos() as u64 + 128 * self.block_len);
+                let h = &mut self.h;
+                buffer.len64_padding_le(bit_len, |block| compress_block(h, block));
+                let h = compress(&FINAL, &self.h);
+                for (chunk, word) in out.chunks_exact_mut(8).zip(&h[16 - $out_words..]) {
+                    chunk.copy_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+
+        impl Default for $core {
+            fn default() -> Self {
+                Self {
+                    h: $iv,
+                    block_len: 0,
+                }
+            }
+        }
+
+        impl Reset for $core {
+            fn reset(&mut self) {
+                *self = Self::default();
+            }
+        }
+
+        impl AlgorithmName for $core {
+            fn write_alg_name(f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str($alg)
+            }
+        }
+
+        impl fmt::Debug for $core {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(concat!(stringify!($core), " { ... }"))
+            }
+        }
+    };
+}
+
+big_core!(Bmw384Core, Bmw384, INITVAL384, U48, 6, "BMW-384");
+big_core!(Bmw512Core, Bmw512, INITVAL512, U64, 8, "BMW-512");
+
+#[test]
+fn bmw_big_digest_cal() {
+    use digest::Digest;
+
+    // Vectors of the independent bmw-hash crate.
+    let bmw512_result1 = Bmw512::digest(b"");
+    assert_eq!(
+        "6a725655c42bc8a2a20549dd5a233a6a2beb01616975851fd122504e604b46af7d96697d0b6333db1d1709d6df328d2a6c786551b0cce2255e8c7332b4819c0e",
+        bmw512_result1
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+
+    let bmw512_result2 = Bmw512::digest(b"abc");
+    assert_eq!(
+        "8f37bef264289f61f3d713944d394a7ac1dd95d3fe5787b5d325a310bc9cd18783852bfee12fbdeaab3ad9a67f2b654e348714aed3acf7d7548e95591af68046",
+        bmw512_result2
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+
+    let bmw512_result3 = Bmw512::digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq");
+    assert_eq!(
+        "75a51657d4bbe74c434c444718f00e4a88d18b234533661e6c79462907b350d08e6b115506844e2d570f1e510357e70faa751f72689d16ea102c3277fe4c712e",
+        bmw512_result3
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+
+    let mut hasher = Bmw512::new();
+    hasher.update([0x61u8; 100]);
+    hasher.update([0x61u8; 29]);
+    assert_eq!(
+        "1d1d9696ee95513733340ed58e8cb544c812352cdbe26c7f65fafe19ad8ef53182d96e2dcb2eea1f5e0c4b2c105f8000c8d725d14821c3a16b01b46bf704e4f0",
+        hasher
+            .finalize()
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+
+    let bmw512_result4 = Bmw512::digest([0u8; 128]);
+    assert_eq!(
+        "893e5d2dbfd328ab40f655f11e2300ccbde691f32259b7eae222b20c8490fe8ad1880bf5cbc84411f8f3bdc003f9e814a56f039948ca97c9a115bf74addac3a1",
+        bmw512_result4
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+
+    let bmw384_result1 = Bmw384::digest(b"abc");
+    assert_eq!(
+        "411e84c41bd59e1376fc905fe96d2ef58fd59970abba02ca53a7a662f9e6cedb4e7e43bd63717215cd86ea20282f2b36",
+        bmw384_result1
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+}
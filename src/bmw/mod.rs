@@ -1,4 +1,39 @@
+//! # bmw
+//!
+//! `bmw` crate has necessary formulas to calculate Blue Midnight Wish (BMW-224, BMW-256,
+//! BMW-384 and BMW-512).
 use crate::utils::read_u32_le;
+use digest::block_buffer::Eager;
+use digest::core_api::{
+    AlgorithmName, Block, BlockSizeUser, Buffer, BufferKindUser, CoreWrapper, FixedOutputCore,
+    OutputSizeUser, UpdateCore,
+};
+use digest::typenum::{U28, U32, U64};
+use digest::{HashMarker, Output, Reset};
+use std::fmt;
+
+mod big;
+
+pub use big::{Bmw384, Bmw384Core, Bmw512, Bmw512Core};
+
+const INITVAL224: [u32; 16] = [
+    0x0001_0203,
+    0x0405_0607,
+    0x0809_0A0B,
+    0x0C0D_0E0F,
+    0x1011_1213,
+    0x1415_1617,
+    0x1819_1A1B,
+    0x1C1D_1E1F,
+    0x2021_2223,
+    0x2425_2627,
+    0x2829_2A2B,
+    0x2C2D_2E2F,
+    0x3031_3233,
+    0x3435_3637,
+    0x3839_3A3B,
+    0x3C3D_3E3F,
+];
 
 const INITVAL: [u32; 16] = [
     0x4041_4243,
@@ -39,7 +74,7 @@ const FINAL: [u32; 16] = [
 ];
 
 fn circular_left(x: u32, n: u32) -> u32 {
-    (x << n) | (x >> (32 - n))
+    x.rotate_left(n)
 }
 
 struct Bmw {
@@ -971,6 +1006,112 @@ pub fn sum(input: Vec<u8>) -> Vec<u8> {
     out
 }
 
+// compress_block runs one BMW-224/256 compression over a 64-byte block.
+fn compress_block(h: &mut [u32; 16], block: &[u8]) {
+    let mut m = [0u32; 16];
+    for (i, word) in m.iter_mut().enumerate() {
+        *word = read_u32_le(&block[4 * i..4 * i + 4]);
+    }
+    let b = Bmw {
+        m,
+        h: *h,
+        h2: [0; 16],
+        q: [0; 32],
+    };
+    *h = compress(b, m).h;
+}
+
+// final_compress runs the finalization compression: the chaining value becomes
+// the message and FINAL becomes the chaining value.
+fn final_compress(h: &[u32; 16]) -> [u32; 16] {
+    let b = Bmw {
+        m: *h,
+        h: FINAL,
+        h2: [0; 16],
+        q: [0; 32],
+    };
+    compress(b, *h).h
+}
+
+macro_rules! small_core {
+    ($core:ident, $hasher:ident, $iv:ident, $out_size:ty, $out_words:expr, $alg:expr) => {
+        #[doc = concat!("Core block-level state of ", $alg, ".")]
+        #[derive(Clone)]
+        pub struct $core {
+            h: [u32; 16],
+            block_len: u64,
+        }
+
+        #[doc = concat!($alg, " hasher implementing the `digest` traits.")]
+        pub type $hasher = CoreWrapper<$core>;
+
+        impl HashMarker for $core {}
+
+        impl BlockSizeUser for $core {
+            type BlockSize = U64;
+        }
+
+        impl BufferKindUser for $core {
+            type BufferKind = Eager;
+        }
+
+        impl OutputSizeUser for $core {
+            type OutputSize = $out_size;
+        }
+
+        impl UpdateCore for $core {
+            fn update_blocks(&mut self, blocks: &[Block<Self>]) {
+                self.block_len += blocks.len() as u64;
+                for block in blocks {
+                    compress_block(&mut self.h, block);
+                }
+            }
+        }
+
+        impl FixedOutputCore for $core {
+            fn finalize_fixed_core(&mut self, buffer: &mut Buffer<Self>, out: &mut Output<Self>) {
+                let bit_len = 8 * (buffer.get_pos() as u64 + 64 * self.block_len);
+                let h = &mut self.h;
+                buffer.len64_padding_le(bit_len, |block| compress_block(h, block));
+                let h = final_compress(&self.h);
+                for (chunk, word) in out.chunks_exact_mut(4).zip(&h[16 - $out_words..]) {
+                    chunk.copy_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+
+        impl Default for $core {
+            fn default() -> Self {
+                Self {
+                    h: $iv,
+                    block_len: 0,
+                }
+            }
+        }
+
+        impl Reset for $core {
+            fn reset(&mut self) {
+                *self = Self::default();
+            }
+        }
+
+        impl AlgorithmName for $core {
+            fn write_alg_name(f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str($alg)
+            }
+        }
+
+        impl fmt::Debug for $core {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(concat!(stringify!($core), " { ... }"))
+            }
+        }
+    };
+}
+
+small_core!(Bmw224Core, Bmw224, INITVAL224, U28, 7, "BMW-224");
+small_core!(Bmw256Core, Bmw256, INITVAL, U32, 8, "BMW-256");
+
 #[test]
 fn bmw_hash_cal() {
     let base1 = "abc".as_bytes().to_vec();
@@ -1003,3 +1144,42 @@ fn bmw_hash_cal() {
             .collect::<String>()
     );
 }
+
+#[test]
+fn bmw_digest_cal() {
+    use digest::Digest;
+
+    let bmw256_result1 = Bmw256::digest(b"");
+    assert_eq!(
+        "82cac4bf6f4c2b41fbcc0e0984e9d8b76d7662f8e1789cdfbd85682acc55577a",
+        bmw256_result1
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+
+    // The single-block fast path and the streaming hasher must agree.
+    let base2 = "脇山珠美ちゃんかわいい！".as_bytes().to_vec();
+    let mut hasher = Bmw256::new();
+    hasher.update(&base2[..5]);
+    hasher.update(&base2[5..]);
+    assert_eq!(sum(base2), hasher.finalize().to_vec());
+
+    let bmw256_result3 = Bmw256::digest([0x61u8; 200]);
+    assert_eq!(
+        "484b7edca6a28639607601c2101d852957e64037449b583705b529733ba6a4d1",
+        bmw256_result3
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+
+    let bmw224_result1 = Bmw224::digest(b"abc");
+    assert_eq!(
+        "246607792ad2625430c81e2c4ea1380add5b08fb8075daed4f401dbc",
+        bmw224_result1
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+}
@@ -0,0 +1,564 @@
+//! # chain
+//!
+//! `chain` crate composes the hash functions of this crate into new Lyra2-based PoW algorithms.
+use crate::blake256;
+use crate::bmw;
+use crate::cubehash;
+use crate::lyra2::{self, Matrix};
+use crate::lyra2mod;
+use crate::utils::{from_hex, to_hex};
+use digest::generic_array::typenum::U32;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha3::Digest;
+use skein_hash::Digest as SkeinDigest;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Largest Lyra2 `time_cost` of a parsed stage.
+pub const MAX_LYRA2_TIME_COST: u64 = 16;
+
+/// Largest Lyra2 memory matrix of a parsed stage in bytes, 96 bytes per cell of
+/// `n_rows * n_cols`.
+pub const MAX_LYRA2_MEMORY: u64 = 16 * 1024 * 1024;
+
+// The stages of the built-in algorithms. The algorithm modules hash with these too.
+pub(crate) const LYRA2RE: [Stage; 5] = [
+    Stage::Blake256,
+    Stage::Keccak256,
+    Stage::Lyra2 {
+        time_cost: 1,
+        n_rows: 8,
+        n_cols: 8,
+    },
+    Stage::Skein256,
+    Stage::Groestl256,
+];
+pub(crate) const LYRA2REV2: [Stage; 7] = [
+    Stage::Blake256,
+    Stage::Keccak256,
+    Stage::CubeHash256,
+    Stage::Lyra2 {
+        time_cost: 1,
+        n_rows: 4,
+        n_cols: 4,
+    },
+    Stage::Skein256,
+    Stage::CubeHash256,
+    Stage::Bmw256,
+];
+pub(crate) const LYRA2REV3: [Stage; 5] = [
+    Stage::Blake256,
+    Stage::Lyra2Mod {
+        time_cost: 1,
+        n_rows: 4,
+        n_cols: 4,
+    },
+    Stage::CubeHash256,
+    Stage::Lyra2Mod {
+        time_cost: 1,
+        n_rows: 4,
+        n_cols: 4,
+    },
+    Stage::Bmw256,
+];
+pub(crate) const LYRA2Z: [Stage; 2] = [
+    Stage::Blake256,
+    Stage::Lyra2 {
+        time_cost: 8,
+        n_rows: 8,
+        n_cols: 8,
+    },
+];
+
+// finish_with runs `stages` after their leading Blake-256 stage on its output `result_blake`,
+// reusing the Lyra2 memory matrix in `matrix`.
+pub(crate) fn finish_with(stages: &[Stage], matrix: &mut Matrix, result_blake: Vec<u8>) -> Vec<u8> {
+    debug_assert_eq!(stages.first(), Some(&Stage::Blake256));
+    stages[1..]
+        .iter()
+        .fold(result_blake, |data, stage| stage.sum_with(matrix, data))
+}
+
+/// One step of a [`HashChain`].
+///
+/// Every stage outputs 32 bytes. Stages after the first therefore always see 32 bytes of input;
+/// `CubeHash256` requires exactly 32 bytes, so a [`HashChain`] rejects it as the first stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Blake256,
+    Keccak256,
+    CubeHash256,
+    /// Lyra2 with the input used as both password and salt.
+    Lyra2 {
+        time_cost: u64,
+        n_rows: u64,
+        n_cols: u64,
+    },
+    /// The Lyra2REv3 variant of Lyra2 with the input used as both password and salt.
+    Lyra2Mod {
+        time_cost: u64,
+        n_rows: u64,
+        n_cols: u64,
+    },
+    /// Skein-512 with a 256-bit output.
+    Skein256,
+    Bmw256,
+    Groestl256,
+}
+
+impl Stage {
+    /// Returns the name of the stage without its parameters.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Blake256 => "blake256",
+            Stage::Keccak256 => "keccak256",
+            Stage::CubeHash256 => "cubehash256",
+            Stage::Lyra2 { .. } => "lyra2",
+            Stage::Lyra2Mod { .. } => "lyra2mod",
+            Stage::Skein256 => "skein256",
+            Stage::Bmw256 => "bmw256",
+            Stage::Groestl256 => "groestl256",
+        }
+    }
+
+    /// Checks the Lyra2 parameters of the stage against the limits [`Stage::from_str`] applies.
+    pub fn check(&self) -> Result<(), ParseChainError> {
+        match *self {
+            Stage::Lyra2 {
+                time_cost,
+                n_rows,
+                n_cols,
+            }
+            | Stage::Lyra2Mod {
+                time_cost,
+                n_rows,
+                n_cols,
+            } if !lyra2_params_ok(time_cost, n_rows, n_cols) => {
+                Err(ParseChainError::InvalidParams(self.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the calculation result of this stage alone.
+    ///
+    /// # Panics
+    ///
+    /// A stage that [`Stage::check`] rejects, or `CubeHash256` with an input shorter than
+    /// 32 bytes.
+    pub fn sum(&self, input: Vec<u8>) -> Vec<u8> {
+        self.sum_with(&mut Matrix::new(), input)
+    }
+
+    // sum_with is sum reusing the Lyra2 memory matrix in `matrix`.
+    fn sum_with(&self, matrix: &mut Matrix, input: Vec<u8>) -> Vec<u8> {
+        match *self {
+            Stage::Blake256 => blake256::sum(input),
+            Stage::Keccak256 => sha3::Keccak256::digest(input).to_vec(),
+            Stage::CubeHash256 => cubehash::sum(input),
+            Stage::Lyra2 {
+                time_cost,
+                n_rows,
+                n_cols,
+            } => lyra2::lyra2_with_matrix(matrix, 32, &input, &input, time_cost, n_rows, n_cols),
+            Stage::Lyra2Mod {
+                time_cost,
+                n_rows,
+                n_cols,
+            } => lyra2mod::lyra2mod_with_matrix(
+                matrix, 32, &input, &input, time_cost, n_rows, n_cols,
+            ),
+            Stage::Skein256 => skein_hash::Skein512::<U32>::digest(&input).to_vec(),
+            Stage::Bmw256 => bmw::Bmw256::digest(input).to_vec(),
+            Stage::Groestl256 => groestl::Groestl256::digest(input).to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Lyra2 {
+                time_cost,
+                n_rows,
+                n_cols,
+            }
+            | Stage::Lyra2Mod {
+                time_cost,
+                n_rows,
+                n_cols,
+            } => write!(f, "{}({},{},{})", self.name(), time_cost, n_rows, n_cols),
+            _ => f.write_str(self.name()),
+        }
+    }
+}
+
+impl FromStr for Stage {
+    type Err = ParseChainError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, params) = match s.find('(') {
+            Some(open) => {
+                let close = s
+                    .strip_suffix(')')
+                    .ok_or_else(|| ParseChainError::InvalidParams(s.to_string()))?;
+                (s[..open].trim(), Some(&close[open + 1..]))
+            }
+            None => (s, None),
+        };
+        let name = name.to_ascii_lowercase();
+        let stage = match (name.as_str(), params) {
+            ("blake256", None) => Stage::Blake256,
+            ("keccak256", None) => Stage::Keccak256,
+            ("cubehash256", None) => Stage::CubeHash256,
+            ("skein256", None) => Stage::Skein256,
+            ("bmw256", None) => Stage::Bmw256,
+            ("groestl256", None) => Stage::Groestl256,
+            ("lyra2", Some(params)) => {
+                let (time_cost, n_rows, n_cols) = parse_lyra2_params(s, params)?;
+                Stage::Lyra2 {
+                    time_cost,
+                    n_rows,
+                    n_cols,
+                }
+            }
+            ("lyra2mod", Some(params)) => {
+                let (time_cost, n_rows, n_cols) = parse_lyra2_params(s, params)?;
+                Stage::Lyra2Mod {
+                    time_cost,
+                    n_rows,
+                    n_cols,
+                }
+            }
+            ("lyra2", None) | ("lyra2mod", None) => {
+                return Err(ParseChainError::InvalidParams(s.to_string()))
+            }
+            ("", _) => return Err(ParseChainError::Empty),
+            _ => return Err(ParseChainError::UnknownStage(s.to_string())),
+        };
+        Ok(stage)
+    }
+}
+
+// parse_lyra2_params parses "time_cost,n_rows,n_cols" and rejects values lyra2 would panic on,
+// a time cost over MAX_LYRA2_TIME_COST and a matrix over MAX_LYRA2_MEMORY.
+fn parse_lyra2_params(stage: &str, params: &str) -> Result<(u64, u64, u64), ParseChainError> {
+    let invalid = || ParseChainError::InvalidParams(stage.to_string());
+    let values = params
+        .split(',')
+        .map(|p| p.trim().parse::<u64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<u64>, _>>()?;
+    match values[..] {
+        [time_cost, n_rows, n_cols] if lyra2_params_ok(time_cost, n_rows, n_cols) => {
+            Ok((time_cost, n_rows, n_cols))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn lyra2_params_ok(time_cost: u64, n_rows: u64, n_cols: u64) -> bool {
+    (1..=MAX_LYRA2_TIME_COST).contains(&time_cost)
+        && n_rows >= 3
+        && n_cols >= 1
+        && n_rows
+            .checked_mul(n_cols)
+            .and_then(|cells| cells.checked_mul(96))
+            .is_some_and(|memory| memory <= MAX_LYRA2_MEMORY)
+}
+
+/// An error returned when parsing a [`Stage`] or [`HashChain`] fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseChainError {
+    /// The description, or one of its stages, is empty.
+    Empty,
+    /// The stage name is not known.
+    UnknownStage(String),
+    /// The Lyra2 parameters are missing, malformed or out of range.
+    InvalidParams(String),
+    /// The chain starts with `cubehash256`, which needs exactly 32 bytes of input.
+    CubeHashFirst,
+}
+
+impl fmt::Display for ParseChainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseChainError::Empty => f.write_str("empty hash chain stage"),
+            ParseChainError::UnknownStage(s) => write!(f, "unknown hash chain stage: {}", s),
+            ParseChainError::InvalidParams(s) => write!(f, "invalid lyra2 parameters: {}", s),
+            ParseChainError::CubeHashFirst => {
+                f.write_str("cubehash256 cannot be the first hash chain stage")
+            }
+        }
+    }
+}
+
+impl error::Error for ParseChainError {}
+
+/// A sequence of [`Stage`]s, each hashing the output of the previous one.
+///
+/// # Examples
+///
+/// ```
+/// use lyra2::chain::HashChain;
+///
+/// let chain: HashChain = "blake256>keccak256>cubehash256>lyra2(1,4,4)>skein256>cubehash256>bmw256"
+///     .parse()
+///     .unwrap();
+/// let base1 = "abc".as_bytes().to_vec();
+/// assert_eq!(chain.sum(base1.clone()), lyra2::lyra2rev2::sum(base1));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct HashChain {
+    stages: Vec<Stage>,
+}
+
+impl HashChain {
+    /// Returns an empty chain. An empty chain returns its input unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a stage to the chain, rejecting it if [`Stage::check`] fails or if it is a
+    /// `CubeHash256` stage starting the chain.
+    pub fn stage(mut self, stage: Stage) -> Result<Self, ParseChainError> {
+        stage.check()?;
+        if self.stages.is_empty() && stage == Stage::CubeHash256 {
+            return Err(ParseChainError::CubeHashFirst);
+        }
+        self.stages.push(stage);
+        Ok(self)
+    }
+
+    /// Returns the stages in the order they are applied.
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// Returns the calculation result of the whole chain.
+    pub fn sum(&self, input: Vec<u8>) -> Vec<u8> {
+        self.stages
+            .iter()
+            .fold(input, |data, stage| stage.sum(data))
+    }
+
+    /// Returns the output of every stage of the chain, in order. The last entry is the
+    /// calculation result of the whole chain.
+    ///
+    /// # Panics
+    ///
+    /// A stage output that is not 32 bytes long.
+    pub fn sum_with_trace(&self, input: Vec<u8>) -> Vec<StageOutput> {
+        let mut trace = Vec::with_capacity(self.stages.len());
+        let mut data = input;
+        for stage in self.stages.iter() {
+            data = stage.sum(data);
+            let mut output = [0u8; 32];
+            output.copy_from_slice(&data);
+            trace.push(StageOutput {
+                name: stage.to_string(),
+                output,
+            });
+        }
+        trace
+    }
+
+    /// Returns the chain of [`crate::lyra2re::sum`].
+    pub fn lyra2re() -> Self {
+        HashChain {
+            stages: LYRA2RE.to_vec(),
+        }
+    }
+
+    /// Returns the chain of [`crate::lyra2rev2::sum`].
+    pub fn lyra2rev2() -> Self {
+        HashChain {
+            stages: LYRA2REV2.to_vec(),
+        }
+    }
+
+    /// Returns the chain of [`crate::lyra2rev3::sum`].
+    pub fn lyra2rev3() -> Self {
+        HashChain {
+            stages: LYRA2REV3.to_vec(),
+        }
+    }
+
+    /// Returns the chain of [`crate::lyra2z::sum`].
+    pub fn lyra2z() -> Self {
+        HashChain {
+            stages: LYRA2Z.to_vec(),
+        }
+    }
+}
+
+/// The output of one stage of a [`HashChain`], as returned by [`HashChain::sum_with_trace`].
+///
+/// Serializes as `{"name":"lyra2(1,4,4)","output":"<64 hex digits>"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StageOutput {
+    /// The stage in the textual form accepted by [`Stage::from_str`].
+    pub name: String,
+    #[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
+    pub output: [u8; 32],
+}
+
+fn serialize_hex<S: Serializer>(output: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_hex(output))
+}
+
+fn deserialize_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    let bytes = from_hex(&hex).ok_or_else(|| de::Error::custom("invalid hex string"))?;
+    <[u8; 32]>::try_from(bytes.as_slice())
+        .map_err(|_| de::Error::invalid_length(bytes.len(), &"32 bytes"))
+}
+
+impl fmt::Display for HashChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if i > 0 {
+                f.write_str(">")?;
+            }
+            write!(f, "{}", stage)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for HashChain {
+    type Err = ParseChainError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let stages = s
+            .split('>')
+            .map(str::parse)
+            .collect::<Result<Vec<Stage>, _>>()?;
+        if stages.first() == Some(&Stage::CubeHash256) {
+            return Err(ParseChainError::CubeHashFirst);
+        }
+        Ok(HashChain { stages })
+    }
+}
+
+#[test]
+fn hash_chain_cal() {
+    let bases = [
+        "abc".as_bytes().to_vec(),
+        "脇山珠美ちゃんかわいい！".as_bytes().to_vec(),
+        "😀😁😂".as_bytes().to_vec(),
+    ];
+    for base in bases.iter() {
+        assert_eq!(
+            HashChain::lyra2re().sum(base.clone()),
+            crate::lyra2re::sum(base.clone())
+        );
+        assert_eq!(
+            HashChain::lyra2rev2().sum(base.clone()),
+            crate::lyra2rev2::sum(base.clone())
+        );
+        assert_eq!(
+            HashChain::lyra2rev3().sum(base.clone()),
+            crate::lyra2rev3::sum(base.clone())
+        );
+        assert_eq!(
+            HashChain::lyra2z().sum(base.clone()),
+            crate::lyra2z::sum(base.clone())
+        );
+    }
+
+    let trace = HashChain::lyra2rev2().sum_with_trace(bases[0].clone());
+    assert_eq!(
+        trace.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+        [
+            "blake256",
+            "keccak256",
+            "cubehash256",
+            "lyra2(1,4,4)",
+            "skein256",
+            "cubehash256",
+            "bmw256"
+        ]
+    );
+    assert_eq!(
+        trace[6].output.to_vec(),
+        crate::lyra2rev2::sum(bases[0].clone())
+    );
+    let json = serde_json::to_string(&trace[6]).unwrap();
+    assert_eq!(
+        json,
+        r#"{"name":"bmw256","output":"80ec5344227c5d0bfd63038f00c3fe5aecddd1a1122043b0a90b5fd67b1e8f32"}"#
+    );
+    assert_eq!(
+        serde_json::from_str::<StageOutput>(&json).unwrap(),
+        trace[6]
+    );
+
+    let text = "blake256>keccak256>cubehash256>lyra2(1,4,4)>skein256>cubehash256>bmw256";
+    let chain: HashChain = text.parse().unwrap();
+    assert_eq!(chain, HashChain::lyra2rev2());
+    assert_eq!(chain.to_string(), text);
+    assert_eq!(
+        " Blake256 > LYRA2MOD( 1, 4, 4 )>cubehash256>lyra2mod(1,4,4)>bmw256".parse::<HashChain>(),
+        Ok(HashChain::lyra2rev3())
+    );
+
+    assert_eq!("".parse::<HashChain>(), Err(ParseChainError::Empty));
+    assert_eq!(
+        "blake256>>bmw256".parse::<HashChain>(),
+        Err(ParseChainError::Empty)
+    );
+    assert_eq!(
+        "blake256>sha256".parse::<HashChain>(),
+        Err(ParseChainError::UnknownStage("sha256".to_string()))
+    );
+    for bad in [
+        "lyra2",
+        "lyra2(1,4)",
+        "lyra2(0,4,4)",
+        "lyra2(1,2,4)",
+        "lyra2(1,4,x)",
+        "lyra2(1,4,4",
+        "lyra2(17,4,4)",
+        "lyra2(1,4096,4096)",
+        "lyra2mod(1,18446744073709551615,2)",
+    ] {
+        assert_eq!(
+            bad.parse::<Stage>(),
+            Err(ParseChainError::InvalidParams(bad.to_string()))
+        );
+    }
+    assert!("lyra2(16,1024,170)".parse::<Stage>().is_ok());
+
+    assert_eq!(
+        "cubehash256>bmw256".parse::<HashChain>(),
+        Err(ParseChainError::CubeHashFirst)
+    );
+    assert_eq!(
+        HashChain::new().stage(Stage::CubeHash256),
+        Err(ParseChainError::CubeHashFirst)
+    );
+    for bad in [
+        Stage::Lyra2 {
+            time_cost: 0,
+            n_rows: 4,
+            n_cols: 4,
+        },
+        Stage::Lyra2Mod {
+            time_cost: 1,
+            n_rows: 2,
+            n_cols: 4,
+        },
+    ] {
+        assert_eq!(
+            HashChain::new().stage(Stage::Blake256).unwrap().stage(bad),
+            Err(ParseChainError::InvalidParams(bad.to_string()))
+        );
+    }
+    let built = LYRA2REV2
+        .iter()
+        .try_fold(HashChain::new(), |chain, &stage| chain.stage(stage))
+        .unwrap();
+    assert_eq!(built, HashChain::lyra2rev2());
+}
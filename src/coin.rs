@@ -0,0 +1,472 @@
+//! # coin
+//!
+//! `coin` crate describes the chains that hash their blocks with the algorithms of this crate:
+//! which PoW algorithm is in force at a block height, and the targets to check blocks against.
+//!
+//! Monacoin and Vertcoin are built in as [`Coin`]s; other chains can be loaded from JSON
+//! config files into a [`Registry`].
+use crate::algorithm::Algorithm;
+use crate::header::Hash256;
+use crate::target::Target;
+use serde::Deserialize;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A chain built into this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Coin {
+    Monacoin,
+    Vertcoin,
+}
+
+impl Coin {
+    /// Every built-in chain, in the order they are declared.
+    pub const ALL: [Coin; 2] = [Coin::Monacoin, Coin::Vertcoin];
+
+    /// Returns the parameters of the chain's main network.
+    pub fn params(&self) -> ChainParams {
+        match self {
+            Coin::Monacoin => ChainParams {
+                name: "monacoin".to_string(),
+                ticker: "MONA".to_string(),
+                genesis_hash: hash(
+                    "ff9f1c0116d19de7c9963845e129f9ed1bfc0b376eb54fd7afa42e0d418c8bb6",
+                ),
+                pow_limit: pow_limit(),
+                diff1: Target::DIFF1_NODE,
+                target_spacing: 90,
+                schedule: vec![Epoch::new(0, "scrypt"), Epoch::new(450000, "lyra2rev2")],
+            },
+            Coin::Vertcoin => ChainParams {
+                name: "vertcoin".to_string(),
+                ticker: "VTC".to_string(),
+                genesis_hash: hash(
+                    "4d96a915f49d40b1e5c2844d1ee2dccb90013a990ccea12c492d22110489f0c4",
+                ),
+                pow_limit: pow_limit(),
+                diff1: Target::DIFF1_NODE,
+                target_spacing: 150,
+                schedule: vec![
+                    Epoch::new(0, "scrypt-n"),
+                    Epoch::new(208301, "lyra2re"),
+                    Epoch::new(347000, "lyra2rev2"),
+                    Epoch::new(1080000, "lyra2rev3"),
+                    Epoch::new(1500000, "verthash"),
+                ],
+            },
+        }
+    }
+}
+
+impl FromStr for Coin {
+    type Err = CoinError;
+
+    /// Parses a chain name or ticker, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Coin::ALL
+            .into_iter()
+            .find(|coin| {
+                let params = coin.params();
+                params.name.eq_ignore_ascii_case(s) || params.ticker.eq_ignore_ascii_case(s)
+            })
+            .ok_or_else(|| CoinError::UnknownCoin(s.to_string()))
+    }
+}
+
+fn hash(hex: &str) -> Hash256 {
+    hex.parse().expect("built-in hash")
+}
+
+fn pow_limit() -> Target {
+    "00000fffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"
+        .parse()
+        .expect("built-in target")
+}
+
+/// A range of block heights hashed with one PoW algorithm, from `height` up to the start of the
+/// next epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Epoch {
+    /// The first height of the epoch.
+    pub height: u32,
+    /// The name of the PoW algorithm. Algorithms this crate does not implement, such as
+    /// `scrypt`, are kept by name so that their heights can be reported.
+    pub algorithm: String,
+}
+
+impl Epoch {
+    /// Returns an epoch starting at `height`.
+    pub fn new(height: u32, algorithm: &str) -> Epoch {
+        Epoch {
+            height,
+            algorithm: algorithm.to_string(),
+        }
+    }
+}
+
+/// The consensus parameters of a chain that this crate needs to check its blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainParams {
+    /// The lower-case name of the chain.
+    pub name: String,
+    pub ticker: String,
+    pub genesis_hash: Hash256,
+    /// The easiest target a block may have.
+    pub pow_limit: Target,
+    /// The target of difficulty 1, as the node reports difficulty.
+    pub diff1: Target,
+    /// The intended time between blocks, in seconds.
+    pub target_spacing: u32,
+    /// The algorithm epochs, ordered by height and starting at height 0.
+    pub schedule: Vec<Epoch>,
+}
+
+impl ChainParams {
+    /// Parses chain parameters from JSON:
+    ///
+    /// ```json
+    /// {
+    ///     "name": "examplecoin",
+    ///     "ticker": "EXC",
+    ///     "genesis_hash": "<block hash hex>",
+    ///     "pow_limit": "00000fffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+    ///     "diff1": "00000000ffff0000000000000000000000000000000000000000000000000000",
+    ///     "target_spacing": 150,
+    ///     "schedule": [{ "height": 0, "algorithm": "lyra2rev2" }]
+    /// }
+    /// ```
+    ///
+    /// `diff1` is optional and defaults to [`Target::DIFF1_NODE`].
+    pub fn from_json(json: &str) -> Result<ChainParams, CoinError> {
+        let raw: RawChainParams =
+            serde_json::from_str(json).map_err(|err| CoinError::InvalidConfig(err.to_string()))?;
+        raw.into_params()
+    }
+
+    /// Returns the epoch of `height`, or `None` if the schedule starts above it.
+    pub fn epoch_at(&self, height: u32) -> Option<&Epoch> {
+        self.schedule
+            .iter()
+            .rev()
+            .find(|epoch| epoch.height <= height)
+    }
+
+    /// Returns the PoW algorithm of the block at `height`, or
+    /// [`CoinError::UnsupportedAlgorithm`] if this crate does not implement it.
+    /// [`CoinError::NoEpoch`] is returned if the schedule starts above `height`.
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::algorithm::Algorithm;
+    /// use lyra2::coin::Coin;
+    ///
+    /// let vtc = Coin::Vertcoin.params();
+    /// assert_eq!(vtc.algorithm_at(1080000).unwrap(), Algorithm::Lyra2REv3);
+    /// assert_eq!(
+    ///     vtc.algorithm_at(100).unwrap_err().to_string(),
+    ///     "unsupported algorithm: scrypt-n (vertcoin at height 100)"
+    /// );
+    /// ```
+    pub fn algorithm_at(&self, height: u32) -> Result<Algorithm, CoinError> {
+        let epoch = self.epoch_at(height).ok_or_else(|| CoinError::NoEpoch {
+            chain: self.name.clone(),
+            height,
+        })?;
+        epoch
+            .algorithm
+            .parse()
+            .map_err(|_| CoinError::UnsupportedAlgorithm {
+                chain: self.name.clone(),
+                height,
+                algorithm: epoch.algorithm.clone(),
+            })
+    }
+
+    /// Returns the first height hashed with `algorithm`, if the chain ever used it.
+    pub fn activation_height(&self, algorithm: Algorithm) -> Option<u32> {
+        self.schedule
+            .iter()
+            .find(|epoch| epoch.algorithm == algorithm.name())
+            .map(|epoch| epoch.height)
+    }
+
+    /// Returns the diff-1 target of the block at `height`.
+    pub fn diff1_at(&self, height: u32) -> Result<Target, CoinError> {
+        self.algorithm_at(height).map(|_| self.diff1)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawChainParams {
+    name: String,
+    ticker: String,
+    genesis_hash: String,
+    pow_limit: String,
+    diff1: Option<String>,
+    target_spacing: u32,
+    schedule: Vec<Epoch>,
+}
+
+impl RawChainParams {
+    fn into_params(self) -> Result<ChainParams, CoinError> {
+        let invalid = |field: &str, value: &str| {
+            CoinError::InvalidConfig(format!("invalid {}: {}", field, value))
+        };
+        let target =
+            |field: &str, value: &str| value.parse::<Target>().map_err(|_| invalid(field, value));
+        if self.schedule.first().map(|epoch| epoch.height) != Some(0) {
+            return Err(CoinError::InvalidConfig(
+                "schedule must start at height 0".to_string(),
+            ));
+        }
+        if self
+            .schedule
+            .windows(2)
+            .any(|pair| pair[0].height >= pair[1].height)
+        {
+            return Err(CoinError::InvalidConfig(
+                "schedule heights must increase".to_string(),
+            ));
+        }
+        Ok(ChainParams {
+            genesis_hash: self
+                .genesis_hash
+                .parse()
+                .map_err(|_| invalid("genesis_hash", &self.genesis_hash))?,
+            pow_limit: target("pow_limit", &self.pow_limit)?,
+            diff1: match &self.diff1 {
+                Some(diff1) => target("diff1", diff1)?,
+                None => Target::DIFF1_NODE,
+            },
+            name: self.name.to_lowercase(),
+            ticker: self.ticker,
+            target_spacing: self.target_spacing,
+            schedule: self.schedule,
+        })
+    }
+}
+
+/// Chain parameters looked up by name or ticker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registry {
+    chains: Vec<ChainParams>,
+}
+
+impl Registry {
+    /// Returns a registry of the built-in [`Coin`]s.
+    pub fn new() -> Registry {
+        Registry {
+            chains: Coin::ALL.iter().map(Coin::params).collect(),
+        }
+    }
+
+    /// Adds `params`, replacing a chain of the same name.
+    pub fn insert(&mut self, params: ChainParams) {
+        self.chains.retain(|chain| chain.name != params.name);
+        self.chains.push(params);
+    }
+
+    /// Loads a JSON config file holding one chain, or an array of chains, in the format of
+    /// [`ChainParams::from_json`], and adds them. Returns the number of chains loaded.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<usize, CoinError> {
+        let json = fs::read_to_string(path)?;
+        let chains = match serde_json::from_str::<serde_json::Value>(&json)
+            .map_err(|err| CoinError::InvalidConfig(err.to_string()))?
+        {
+            serde_json::Value::Array(chains) => chains,
+            chain => vec![chain],
+        };
+        let chains = chains
+            .into_iter()
+            .map(|chain| {
+                serde_json::from_value::<RawChainParams>(chain)
+                    .map_err(|err| CoinError::InvalidConfig(err.to_string()))?
+                    .into_params()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let count = chains.len();
+        for chain in chains {
+            self.insert(chain);
+        }
+        Ok(count)
+    }
+
+    /// Returns the chain named `name` or with ticker `name`, ignoring case.
+    pub fn get(&self, name: &str) -> Result<&ChainParams, CoinError> {
+        self.chains
+            .iter()
+            .find(|chain| {
+                chain.name.eq_ignore_ascii_case(name) || chain.ticker.eq_ignore_ascii_case(name)
+            })
+            .ok_or_else(|| CoinError::UnknownCoin(name.to_string()))
+    }
+
+    /// Returns every chain of the registry.
+    pub fn chains(&self) -> &[ChainParams] {
+        &self.chains
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+/// An error of looking up chain parameters.
+#[derive(Debug)]
+pub enum CoinError {
+    /// The block at `height` is hashed with an algorithm this crate does not implement.
+    UnsupportedAlgorithm {
+        chain: String,
+        height: u32,
+        algorithm: String,
+    },
+    /// The schedule has no epoch covering `height`.
+    NoEpoch { chain: String, height: u32 },
+    /// No chain has the given name or ticker.
+    UnknownCoin(String),
+    /// A config file could not be read.
+    Io(io::Error),
+    /// A config file is malformed.
+    InvalidConfig(String),
+}
+
+impl fmt::Display for CoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoinError::UnsupportedAlgorithm {
+                chain,
+                height,
+                algorithm,
+            } => write!(
+                f,
+                "unsupported algorithm: {} ({} at height {})",
+                algorithm, chain, height
+            ),
+            CoinError::NoEpoch { chain, height } => {
+                write!(f, "{} has no algorithm epoch at height {}", chain, height)
+            }
+            CoinError::UnknownCoin(name) => write!(f, "unknown coin: {}", name),
+            CoinError::Io(err) => write!(f, "failed to read chain config: {}", err),
+            CoinError::InvalidConfig(msg) => write!(f, "invalid chain config: {}", msg),
+        }
+    }
+}
+
+impl error::Error for CoinError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            CoinError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CoinError {
+    fn from(err: io::Error) -> Self {
+        CoinError::Io(err)
+    }
+}
+
+#[test]
+fn coin_cal() {
+    use crate::header::BlockHeader;
+
+    let mona = Coin::Monacoin.params();
+    assert!(matches!(
+        mona.algorithm_at(449999),
+        Err(CoinError::UnsupportedAlgorithm { height: 449999, .. })
+    ));
+    assert_eq!(mona.algorithm_at(450000).unwrap(), Algorithm::Lyra2REv2);
+    assert_eq!(mona.algorithm_at(u32::MAX).unwrap(), Algorithm::Lyra2REv2);
+    assert_eq!(mona.activation_height(Algorithm::Lyra2REv2), Some(450000));
+    assert_eq!(mona.activation_height(Algorithm::Lyra2REv3), None);
+    assert_eq!(mona.diff1_at(450000).unwrap(), Target::DIFF1_NODE);
+    assert!(mona.diff1_at(0).is_err());
+
+    let vtc = Coin::Vertcoin.params();
+    for (height, algorithm) in [
+        (208301, Algorithm::Lyra2RE),
+        (346999, Algorithm::Lyra2RE),
+        (347000, Algorithm::Lyra2REv2),
+        (1079999, Algorithm::Lyra2REv2),
+        (1080000, Algorithm::Lyra2REv3),
+        (1499999, Algorithm::Lyra2REv3),
+    ] {
+        assert_eq!(vtc.algorithm_at(height).unwrap(), algorithm);
+    }
+    assert_eq!(
+        vtc.algorithm_at(1500000).unwrap_err().to_string(),
+        "unsupported algorithm: verthash (vertcoin at height 1500000)"
+    );
+
+    // The genesis blocks match the built-in hashes and limits.
+    let genesis = BlockHeader::from_hex("010000000000000000000000000000000000000000000000000000000000000000000000a64bac07fe31877f31d03252953b3c32398933af7a724119bc4d6fa4a805e435f083c252f0ff0f1e66d61200").unwrap();
+    assert_eq!(genesis.block_hash(), mona.genesis_hash);
+    assert!(genesis.target().unwrap() <= mona.pow_limit);
+    let genesis = BlockHeader::from_hex("010000000000000000000000000000000000000000000000000000000000000000000000e72301fc49323ee151cf1048230f032ca589753ba7086222a5c023e3a08cf34a8b35cf52f0ff0f1e0eba5700").unwrap();
+    assert_eq!(genesis.block_hash(), vtc.genesis_hash);
+
+    assert_eq!("mona".parse::<Coin>().unwrap(), Coin::Monacoin);
+    assert_eq!("Vertcoin".parse::<Coin>().unwrap(), Coin::Vertcoin);
+    assert!("btc".parse::<Coin>().is_err());
+
+    let json = r#"{
+        "name": "Examplecoin",
+        "ticker": "EXC",
+        "genesis_hash": "0000000000000000000000000000000000000000000000000000000000000001",
+        "pow_limit": "00000fffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+        "target_spacing": 60,
+        "schedule": [
+            { "height": 0, "algorithm": "lyra2rev2" },
+            { "height": 1000, "algorithm": "lyra2z" }
+        ]
+    }"#;
+    let exc = ChainParams::from_json(json).unwrap();
+    assert_eq!(exc.name, "examplecoin");
+    assert_eq!(exc.diff1, Target::DIFF1_NODE);
+    assert_eq!(exc.algorithm_at(999).unwrap(), Algorithm::Lyra2REv2);
+    assert_eq!(exc.algorithm_at(1000).unwrap(), Algorithm::Lyra2Z);
+
+    // The schedule is public, so a hand-edited one may leave low heights uncovered.
+    let mut late = exc.clone();
+    late.schedule.remove(0);
+    assert_eq!(late.epoch_at(999), None);
+    assert_eq!(
+        late.algorithm_at(999).unwrap_err().to_string(),
+        "examplecoin has no algorithm epoch at height 999"
+    );
+    assert_eq!(late.epoch_at(1000), Some(&Epoch::new(1000, "lyra2z")));
+
+    let path = std::env::temp_dir().join(format!("lyra2-coin-{}.json", std::process::id()));
+    fs::write(&path, format!("[{}]", json)).unwrap();
+    let mut registry = Registry::new();
+    assert_eq!(registry.load(&path).unwrap(), 1);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(registry.get("exc").unwrap(), &exc);
+    assert_eq!(registry.get("MONA").unwrap(), &mona);
+    assert_eq!(registry.chains().len(), 3);
+    assert!(matches!(
+        registry.get("btc"),
+        Err(CoinError::UnknownCoin(_))
+    ));
+    assert!(matches!(registry.load(&path), Err(CoinError::Io(_))));
+
+    for bad in [
+        json.replace("\"height\": 0", "\"height\": 1"),
+        json.replace("\"height\": 1000", "\"height\": 0"),
+        json.replace("00000fff", "zz"),
+        json.replace("\"target_spacing\"", "\"spacing\""),
+    ] {
+        assert!(matches!(
+            ChainParams::from_json(&bad),
+            Err(CoinError::InvalidConfig(_))
+        ));
+    }
+}
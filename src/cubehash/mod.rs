@@ -123,37 +123,37 @@ fn sixteen_rounds(mut c: CubeHash) -> CubeHash {
     let mut _i = 0;
     for _i in 0..8 {
         c.xg = c.x0.wrapping_add(c.xg);
-        c.x0 = (c.x0 << 7) | (c.x0 >> (32 - 7));
+        c.x0 = c.x0.rotate_left(7);
         c.xh = c.x1.wrapping_add(c.xh);
-        c.x1 = (c.x1 << 7) | (c.x1 >> (32 - 7));
+        c.x1 = c.x1.rotate_left(7);
         c.xi = c.x2.wrapping_add(c.xi);
-        c.x2 = (c.x2 << 7) | (c.x2 >> (32 - 7));
+        c.x2 = c.x2.rotate_left(7);
         c.xj = c.x3.wrapping_add(c.xj);
-        c.x3 = (c.x3 << 7) | (c.x3 >> (32 - 7));
+        c.x3 = c.x3.rotate_left(7);
         c.xk = c.x4.wrapping_add(c.xk);
-        c.x4 = (c.x4 << 7) | (c.x4 >> (32 - 7));
+        c.x4 = c.x4.rotate_left(7);
         c.xl = c.x5.wrapping_add(c.xl);
-        c.x5 = (c.x5 << 7) | (c.x5 >> (32 - 7));
+        c.x5 = c.x5.rotate_left(7);
         c.xm = c.x6.wrapping_add(c.xm);
-        c.x6 = (c.x6 << 7) | (c.x6 >> (32 - 7));
+        c.x6 = c.x6.rotate_left(7);
         c.xn = c.x7.wrapping_add(c.xn);
-        c.x7 = (c.x7 << 7) | (c.x7 >> (32 - 7));
+        c.x7 = c.x7.rotate_left(7);
         c.xo = c.x8.wrapping_add(c.xo);
-        c.x8 = (c.x8 << 7) | (c.x8 >> (32 - 7));
+        c.x8 = c.x8.rotate_left(7);
         c.xp = c.x9.wrapping_add(c.xp);
-        c.x9 = (c.x9 << 7) | (c.x9 >> (32 - 7));
+        c.x9 = c.x9.rotate_left(7);
         c.xq = c.xa.wrapping_add(c.xq);
-        c.xa = (c.xa << 7) | (c.xa >> (32 - 7));
+        c.xa = c.xa.rotate_left(7);
         c.xr = c.xb.wrapping_add(c.xr);
-        c.xb = (c.xb << 7) | (c.xb >> (32 - 7));
+        c.xb = c.xb.rotate_left(7);
         c.xs = c.xc.wrapping_add(c.xs);
-        c.xc = (c.xc << 7) | (c.xc >> (32 - 7));
+        c.xc = c.xc.rotate_left(7);
         c.xt = c.xd.wrapping_add(c.xt);
-        c.xd = (c.xd << 7) | (c.xd >> (32 - 7));
+        c.xd = c.xd.rotate_left(7);
         c.xu = c.xe.wrapping_add(c.xu);
-        c.xe = (c.xe << 7) | (c.xe >> (32 - 7));
+        c.xe = c.xe.rotate_left(7);
         c.xv = c.xf.wrapping_add(c.xv);
-        c.xf = (c.xf << 7) | (c.xf >> (32 - 7));
+        c.xf = c.xf.rotate_left(7);
         c.x8 ^= c.xg;
         c.x9 ^= c.xh;
         c.xa ^= c.xi;
@@ -171,37 +171,37 @@ fn sixteen_rounds(mut c: CubeHash) -> CubeHash {
         c.x6 ^= c.xu;
         c.x7 ^= c.xv;
         c.xi = c.x8.wrapping_add(c.xi);
-        c.x8 = (c.x8 << 11) | (c.x8 >> (32 - 11));
+        c.x8 = c.x8.rotate_left(11);
         c.xj = c.x9.wrapping_add(c.xj);
-        c.x9 = (c.x9 << 11) | (c.x9 >> (32 - 11));
+        c.x9 = c.x9.rotate_left(11);
         c.xg = c.xa.wrapping_add(c.xg);
-        c.xa = (c.xa << 11) | (c.xa >> (32 - 11));
+        c.xa = c.xa.rotate_left(11);
         c.xh = c.xb.wrapping_add(c.xh);
-        c.xb = (c.xb << 11) | (c.xb >> (32 - 11));
+        c.xb = c.xb.rotate_left(11);
         c.xm = c.xc.wrapping_add(c.xm);
-        c.xc = (c.xc << 11) | (c.xc >> (32 - 11));
+        c.xc = c.xc.rotate_left(11);
         c.xn = c.xd.wrapping_add(c.xn);
-        c.xd = (c.xd << 11) | (c.xd >> (32 - 11));
+        c.xd = c.xd.rotate_left(11);
         c.xk = c.xe.wrapping_add(c.xk);
-        c.xe = (c.xe << 11) | (c.xe >> (32 - 11));
+        c.xe = c.xe.rotate_left(11);
         c.xl = c.xf.wrapping_add(c.xl);
-        c.xf = (c.xf << 11) | (c.xf >> (32 - 11));
+        c.xf = c.xf.rotate_left(11);
         c.xq = c.x0.wrapping_add(c.xq);
-        c.x0 = (c.x0 << 11) | (c.x0 >> (32 - 11));
+        c.x0 = c.x0.rotate_left(11);
         c.xr = c.x1.wrapping_add(c.xr);
-        c.x1 = (c.x1 << 11) | (c.x1 >> (32 - 11));
+        c.x1 = c.x1.rotate_left(11);
         c.xo = c.x2.wrapping_add(c.xo);
-        c.x2 = (c.x2 << 11) | (c.x2 >> (32 - 11));
+        c.x2 = c.x2.rotate_left(11);
         c.xp = c.x3.wrapping_add(c.xp);
-        c.x3 = (c.x3 << 11) | (c.x3 >> (32 - 11));
+        c.x3 = c.x3.rotate_left(11);
         c.xu = c.x4.wrapping_add(c.xu);
-        c.x4 = (c.x4 << 11) | (c.x4 >> (32 - 11));
+        c.x4 = c.x4.rotate_left(11);
         c.xv = c.x5.wrapping_add(c.xv);
-        c.x5 = (c.x5 << 11) | (c.x5 >> (32 - 11));
+        c.x5 = c.x5.rotate_left(11);
         c.xs = c.x6.wrapping_add(c.xs);
-        c.x6 = (c.x6 << 11) | (c.x6 >> (32 - 11));
+        c.x6 = c.x6.rotate_left(11);
         c.xt = c.x7.wrapping_add(c.xt);
-        c.x7 = (c.x7 << 11) | (c.x7 >> (32 - 11));
+        c.x7 = c.x7.rotate_left(11);
         c.xc ^= c.xi;
         c.xd ^= c.xj;
         c.xe ^= c.xg;
@@ -220,37 +220,37 @@ fn sixteen_rounds(mut c: CubeHash) -> CubeHash {
         c.x3 ^= c.xt;
 
         c.xj = c.xc.wrapping_add(c.xj);
-        c.xc = (c.xc << 7) | (c.xc >> (32 - 7));
+        c.xc = c.xc.rotate_left(7);
         c.xi = c.xd.wrapping_add(c.xi);
-        c.xd = (c.xd << 7) | (c.xd >> (32 - 7));
+        c.xd = c.xd.rotate_left(7);
         c.xh = c.xe.wrapping_add(c.xh);
-        c.xe = (c.xe << 7) | (c.xe >> (32 - 7));
+        c.xe = c.xe.rotate_left(7);
         c.xg = c.xf.wrapping_add(c.xg);
-        c.xf = (c.xf << 7) | (c.xf >> (32 - 7));
+        c.xf = c.xf.rotate_left(7);
         c.xn = c.x8.wrapping_add(c.xn);
-        c.x8 = (c.x8 << 7) | (c.x8 >> (32 - 7));
+        c.x8 = c.x8.rotate_left(7);
         c.xm = c.x9.wrapping_add(c.xm);
-        c.x9 = (c.x9 << 7) | (c.x9 >> (32 - 7));
+        c.x9 = c.x9.rotate_left(7);
         c.xl = c.xa.wrapping_add(c.xl);
-        c.xa = (c.xa << 7) | (c.xa >> (32 - 7));
+        c.xa = c.xa.rotate_left(7);
         c.xk = c.xb.wrapping_add(c.xk);
-        c.xb = (c.xb << 7) | (c.xb >> (32 - 7));
+        c.xb = c.xb.rotate_left(7);
         c.xr = c.x4.wrapping_add(c.xr);
-        c.x4 = (c.x4 << 7) | (c.x4 >> (32 - 7));
+        c.x4 = c.x4.rotate_left(7);
         c.xq = c.x5.wrapping_add(c.xq);
-        c.x5 = (c.x5 << 7) | (c.x5 >> (32 - 7));
+        c.x5 = c.x5.rotate_left(7);
         c.xp = c.x6.wrapping_add(c.xp);
-        c.x6 = (c.x6 << 7) | (c.x6 >> (32 - 7));
+        c.x6 = c.x6.rotate_left(7);
         c.xo = c.x7.wrapping_add(c.xo);
-        c.x7 = (c.x7 << 7) | (c.x7 >> (32 - 7));
+        c.x7 = c.x7.rotate_left(7);
         c.xv = c.x0.wrapping_add(c.xv);
-        c.x0 = (c.x0 << 7) | (c.x0 >> (32 - 7));
+        c.x0 = c.x0.rotate_left(7);
         c.xu = c.x1.wrapping_add(c.xu);
-        c.x1 = (c.x1 << 7) | (c.x1 >> (32 - 7));
+        c.x1 = c.x1.rotate_left(7);
         c.xt = c.x2.wrapping_add(c.xt);
-        c.x2 = (c.x2 << 7) | (c.x2 >> (32 - 7));
+        c.x2 = c.x2.rotate_left(7);
         c.xs = c.x3.wrapping_add(c.xs);
-        c.x3 = (c.x3 << 7) | (c.x3 >> (32 - 7));
+        c.x3 = c.x3.rotate_left(7);
         c.x4 ^= c.xj;
         c.x5 ^= c.xi;
         c.x6 ^= c.xh;
@@ -268,37 +268,37 @@ fn sixteen_rounds(mut c: CubeHash) -> CubeHash {
         c.xa ^= c.xt;
         c.xb ^= c.xs;
         c.xh = c.x4.wrapping_add(c.xh);
-        c.x4 = (c.x4 << 11) | (c.x4 >> (32 - 11));
+        c.x4 = c.x4.rotate_left(11);
         c.xg = c.x5.wrapping_add(c.xg);
-        c.x5 = (c.x5 << 11) | (c.x5 >> (32 - 11));
+        c.x5 = c.x5.rotate_left(11);
         c.xj = c.x6.wrapping_add(c.xj);
-        c.x6 = (c.x6 << 11) | (c.x6 >> (32 - 11));
+        c.x6 = c.x6.rotate_left(11);
         c.xi = c.x7.wrapping_add(c.xi);
-        c.x7 = (c.x7 << 11) | (c.x7 >> (32 - 11));
+        c.x7 = c.x7.rotate_left(11);
         c.xl = c.x0.wrapping_add(c.xl);
-        c.x0 = (c.x0 << 11) | (c.x0 >> (32 - 11));
+        c.x0 = c.x0.rotate_left(11);
         c.xk = c.x1.wrapping_add(c.xk);
-        c.x1 = (c.x1 << 11) | (c.x1 >> (32 - 11));
+        c.x1 = c.x1.rotate_left(11);
         c.xn = c.x2.wrapping_add(c.xn);
-        c.x2 = (c.x2 << 11) | (c.x2 >> (32 - 11));
+        c.x2 = c.x2.rotate_left(11);
         c.xm = c.x3.wrapping_add(c.xm);
-        c.x3 = (c.x3 << 11) | (c.x3 >> (32 - 11));
+        c.x3 = c.x3.rotate_left(11);
         c.xp = c.xc.wrapping_add(c.xp);
-        c.xc = (c.xc << 11) | (c.xc >> (32 - 11));
+        c.xc = c.xc.rotate_left(11);
         c.xo = c.xd.wrapping_add(c.xo);
-        c.xd = (c.xd << 11) | (c.xd >> (32 - 11));
+        c.xd = c.xd.rotate_left(11);
         c.xr = c.xe.wrapping_add(c.xr);
-        c.xe = (c.xe << 11) | (c.xe >> (32 - 11));
+        c.xe = c.xe.rotate_left(11);
         c.xq = c.xf.wrapping_add(c.xq);
-        c.xf = (c.xf << 11) | (c.xf >> (32 - 11));
+        c.xf = c.xf.rotate_left(11);
         c.xt = c.x8.wrapping_add(c.xt);
-        c.x8 = (c.x8 << 11) | (c.x8 >> (32 - 11));
+        c.x8 = c.x8.rotate_left(11);
         c.xs = c.x9.wrapping_add(c.xs);
-        c.x9 = (c.x9 << 11) | (c.x9 >> (32 - 11));
+        c.x9 = c.x9.rotate_left(11);
         c.xv = c.xa.wrapping_add(c.xv);
-        c.xa = (c.xa << 11) | (c.xa >> (32 - 11));
+        c.xa = c.xa.rotate_left(11);
         c.xu = c.xb.wrapping_add(c.xu);
-        c.xb = (c.xb << 11) | (c.xb >> (32 - 11));
+        c.xb = c.xb.rotate_left(11);
         c.x0 ^= c.xh;
         c.x1 ^= c.xg;
         c.x2 ^= c.xj;
@@ -0,0 +1,334 @@
+//! # difficulty
+//!
+//! `difficulty` crate converts between pool share difficulty and share targets the way common
+//! pool software does for each algorithm of this crate.
+//!
+//! Pools scale share difficulty per algorithm so that difficulty 1 stays a practical amount of
+//! work: a Lyra2REv2 share of difficulty 1 is 256 times easier than a Bitcoin difficulty-1
+//! share. The conventions differ in the difficulty-1 target and in how precisely a difficulty
+//! becomes a target, so a share can be valid for one pool and low for another.
+use crate::algorithm::Algorithm;
+use crate::header::Hash256;
+use crate::target::Target;
+use crate::work::Work;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// A pool's interpretation of share difficulty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Convention {
+    /// NOMP (node-stratum-pool) and its forks: difficulty 1 is the node difficulty-1 target
+    /// `0x00000000ffff0000...` times the algorithm multiplier, at full precision.
+    Nomp,
+    /// yiimp: the difficulty is divided by the algorithm multiplier and turned into a target as
+    /// cpuminer's `diff_to_target` does, which keeps only 64 significant bits.
+    Yiimp,
+    /// MPOS with stratum-mining: difficulty 1 is `2^(256 - target_bits) - 1`, the target whose
+    /// shares MPOS counts as `2^target_bits` hashes for its hashrate. `target_bits` is the
+    /// pool's setting, parsed from `mpos(24)`; `None` uses the usual value of the algorithm,
+    /// see [`Convention::target_bits`].
+    Mpos { target_bits: Option<u32> },
+}
+
+impl Convention {
+    /// Every convention, in the order they are declared, MPOS with its usual `target_bits`.
+    pub const ALL: [Convention; 3] = [
+        Convention::Nomp,
+        Convention::Yiimp,
+        Convention::Mpos { target_bits: None },
+    ];
+
+    /// Largest MPOS `target_bits`, that of SHA-256d.
+    pub const MAX_TARGET_BITS: u32 = 32;
+
+    /// Returns the lower-case name of the convention.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Convention::Nomp => "nomp",
+            Convention::Yiimp => "yiimp",
+            Convention::Mpos { .. } => "mpos",
+        }
+    }
+
+    /// Returns how many times easier a share of difficulty 1 is than a Bitcoin difficulty-1
+    /// share, as each pool software sets it per algorithm:
+    ///
+    /// - NOMP: the `multiplier` of `algoProperties.js`, `2^7` for `lyra2re` and `2^8` for
+    ///   `lyra2re2`, which forks reuse for `lyra2rev3` and `lyra2z`.
+    /// - yiimp: the difficulty multiplier of the stratum algorithm table, `0x80` for `lyra2`
+    ///   and `0x100` for `lyra2v2`, `lyra2v3` and `lyra2z`.
+    /// - MPOS: `2^(32 - target_bits)`, see [`Convention::target_bits`].
+    pub fn multiplier(&self, algorithm: Algorithm) -> u64 {
+        match self {
+            Convention::Nomp => match algorithm {
+                Algorithm::Lyra2RE => 1 << 7,
+                Algorithm::Lyra2REv2 | Algorithm::Lyra2REv3 | Algorithm::Lyra2Z => 1 << 8,
+            },
+            Convention::Yiimp => match algorithm {
+                Algorithm::Lyra2RE => 0x80,
+                Algorithm::Lyra2REv2 | Algorithm::Lyra2REv3 | Algorithm::Lyra2Z => 0x100,
+            },
+            Convention::Mpos { .. } => 1 << (32 - self.target_bits(algorithm)),
+        }
+    }
+
+    /// Returns the MPOS `target_bits` of `algorithm`: the configured value, at most
+    /// [`Convention::MAX_TARGET_BITS`], or else 25 for Lyra2RE and 24 for the later
+    /// algorithms, scaled from the 32 of SHA-256d as the 16 of scrypt is. The other
+    /// conventions have no such setting and return the bits of their own multiplier.
+    pub fn target_bits(&self, algorithm: Algorithm) -> u32 {
+        match *self {
+            Convention::Mpos {
+                target_bits: Some(bits),
+            } => bits.min(Convention::MAX_TARGET_BITS),
+            Convention::Mpos { target_bits: None } => match algorithm {
+                Algorithm::Lyra2RE => 25,
+                Algorithm::Lyra2REv2 | Algorithm::Lyra2REv3 | Algorithm::Lyra2Z => 24,
+            },
+            _ => 32 - self.multiplier(algorithm).trailing_zeros(),
+        }
+    }
+
+    /// Returns the share target of difficulty 1.
+    pub fn diff1(&self, algorithm: Algorithm) -> Target {
+        match self {
+            Convention::Nomp | Convention::Yiimp => {
+                Target::DIFF1_NODE.saturating_mul(self.multiplier(algorithm))
+            }
+            Convention::Mpos { .. } => Target::MAX.div(1 << self.target_bits(algorithm)),
+        }
+    }
+
+    /// Returns the share target of `difficulty`. Non-positive difficulties give
+    /// [`Target::MAX`].
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::algorithm::Algorithm;
+    /// use lyra2::difficulty::Convention;
+    ///
+    /// let target = Convention::Nomp.share_target(Algorithm::Lyra2REv2, 1.0);
+    /// assert_eq!(
+    ///     "000000ffff000000000000000000000000000000000000000000000000000000",
+    ///     target.to_string()
+    /// );
+    /// assert_eq!(1.0, Convention::Nomp.difficulty(Algorithm::Lyra2REv2, &target));
+    /// ```
+    pub fn share_target(&self, algorithm: Algorithm, difficulty: f64) -> Target {
+        match self {
+            Convention::Yiimp => miner_target(difficulty / self.multiplier(algorithm) as f64),
+            _ => Target::from_difficulty(difficulty, &self.diff1(algorithm)),
+        }
+    }
+
+    /// Returns the difficulty of the share target `target`.
+    pub fn difficulty(&self, algorithm: Algorithm, target: &Target) -> f64 {
+        target.difficulty(&self.diff1(algorithm))
+    }
+
+    /// Returns the difficulty a share with PoW hash `hash` reaches, as pools log it.
+    pub fn hash_difficulty(&self, algorithm: Algorithm, hash: &Hash256) -> f64 {
+        self.difficulty(algorithm, &Target::from_le_bytes(hash.0))
+    }
+
+    /// Returns the hashrate, in hashes per second, that finds shares adding up to
+    /// `difficulty` in `seconds` on average.
+    pub fn hashrate(&self, algorithm: Algorithm, difficulty: f64, seconds: f64) -> f64 {
+        difficulty * Work::from_target(&self.diff1(algorithm)).to_f64() / seconds
+    }
+}
+
+// miner_target is `diff_to_target` of cpuminer: the target of `difficulty` relative to the node
+// difficulty-1 target, as one 64-bit word at a 32-bit word offset.
+fn miner_target(mut difficulty: f64) -> Target {
+    if difficulty.is_nan() || difficulty <= 0.0 {
+        return Target::MAX;
+    }
+    let mut k = 6;
+    while k > 0 && difficulty > 1.0 {
+        difficulty /= 4294967296.0;
+        k -= 1;
+    }
+    let m = (4294901760.0 / difficulty) as u64;
+    if m == 0 && k == 6 {
+        return Target::MAX;
+    }
+    let mut bytes = [0u8; 32];
+    bytes[k * 4..k * 4 + 8].copy_from_slice(&m.to_le_bytes());
+    Target::from_le_bytes(bytes)
+}
+
+impl fmt::Display for Convention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Convention::Mpos {
+                target_bits: Some(bits),
+            } => write!(f, "{}({})", self.name(), bits),
+            _ => f.write_str(self.name()),
+        }
+    }
+}
+
+impl FromStr for Convention {
+    type Err = ParseConventionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseConventionError(s.to_string());
+        let (name, target_bits) = match s.strip_suffix(')').and_then(|s| s.split_once('(')) {
+            Some((name, bits)) => {
+                let bits = bits.trim().parse::<u32>().map_err(|_| invalid())?;
+                if !(1..=Convention::MAX_TARGET_BITS).contains(&bits) {
+                    return Err(invalid());
+                }
+                (name.trim(), Some(bits))
+            }
+            None => (s, None),
+        };
+        match Convention::ALL
+            .iter()
+            .find(|convention| convention.name().eq_ignore_ascii_case(name))
+            .copied()
+        {
+            Some(Convention::Mpos { .. }) => Ok(Convention::Mpos { target_bits }),
+            Some(convention) if target_bits.is_none() => Ok(convention),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// An error returned when a string names no known convention.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseConventionError(pub String);
+
+impl fmt::Display for ParseConventionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown difficulty convention: {}", self.0)
+    }
+}
+
+impl error::Error for ParseConventionError {}
+
+#[test]
+fn difficulty_cal() {
+    let target = |hex: &str| hex.parse::<Target>().unwrap();
+
+    // Difficulty 1 of each convention.
+    assert_eq!(
+        Convention::Nomp.diff1(Algorithm::Lyra2RE),
+        target("0000007fff800000000000000000000000000000000000000000000000000000")
+    );
+    assert_eq!(
+        Convention::Yiimp.diff1(Algorithm::Lyra2Z),
+        target("000000ffff000000000000000000000000000000000000000000000000000000")
+    );
+    let mpos = Convention::Mpos { target_bits: None };
+    assert_eq!(
+        mpos.diff1(Algorithm::Lyra2REv3),
+        target("000000ffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
+    );
+    assert_eq!(
+        mpos.diff1(Algorithm::Lyra2RE),
+        target("0000007fffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
+    );
+    // A pool configured with its own target_bits; values past 32 are taken as 32.
+    let mpos20 = Convention::Mpos {
+        target_bits: Some(20),
+    };
+    assert_eq!(
+        mpos20.diff1(Algorithm::Lyra2REv2),
+        target("00000fffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
+    );
+    assert_eq!(mpos20.multiplier(Algorithm::Lyra2REv2), 1 << 12);
+    assert_eq!(
+        Convention::Mpos {
+            target_bits: Some(40)
+        }
+        .diff1(Algorithm::Lyra2Z),
+        target("00000000ffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
+    );
+
+    // NOMP and yiimp share their difficulty-1 targets; they differ in precision only.
+    for algorithm in Algorithm::ALL {
+        let expected = match algorithm {
+            Algorithm::Lyra2RE => {
+                "0000007fff800000000000000000000000000000000000000000000000000000"
+            }
+            _ => "000000ffff000000000000000000000000000000000000000000000000000000",
+        };
+        assert_eq!(Convention::Nomp.diff1(algorithm), target(expected));
+        assert_eq!(Convention::Yiimp.diff1(algorithm), target(expected));
+    }
+    for convention in Convention::ALL {
+        for algorithm in Algorithm::ALL {
+            assert_eq!(
+                convention.share_target(algorithm, 1.0),
+                convention.diff1(algorithm)
+            );
+        }
+    }
+
+    // Lyra2RE at difficulty 1000: yiimp keeps 64 significant bits, MPOS counts all-ones.
+    assert_eq!(
+        Convention::Nomp.share_target(Algorithm::Lyra2RE, 1000.0),
+        target("0000000020c47ae147ae147ae147ae147ae147ae147ae147ae147ae147ae147a")
+    );
+    assert_eq!(
+        Convention::Yiimp.share_target(Algorithm::Lyra2RE, 1000.0),
+        target("0000000020c47ae147ae14000000000000000000000000000000000000000000")
+    );
+    assert_eq!(
+        mpos.share_target(Algorithm::Lyra2RE, 1000.0),
+        target("0000000020c49ba5e353f7ced916872b020c49ba5e353f7ced916872b020c49b")
+    );
+    // Lyra2REv2 at difficulty 3.
+    assert_eq!(
+        Convention::Nomp.share_target(Algorithm::Lyra2REv2, 3.0),
+        target("0000005555000000000000000000000000000000000000000000000000000000")
+    );
+    assert_eq!(
+        Convention::Yiimp.share_target(Algorithm::Lyra2REv2, 3.0),
+        target("0000005555000000000000000000000000000000000000000000000000000000")
+    );
+    // Sub-1 difficulties, as set for CPU miners.
+    assert_eq!(
+        Convention::Yiimp.share_target(Algorithm::Lyra2REv2, 0.5),
+        target("000001fffe000000000000000000000000000000000000000000000000000000")
+    );
+    assert_eq!(
+        Convention::Nomp.share_target(Algorithm::Lyra2REv2, 0.5),
+        target("000001fffe000000000000000000000000000000000000000000000000000000")
+    );
+    assert_eq!(
+        Convention::Yiimp.share_target(Algorithm::Lyra2REv2, 0.0),
+        Target::MAX
+    );
+
+    for convention in Convention::ALL {
+        let target = convention.share_target(Algorithm::Lyra2REv3, 64.0);
+        let difficulty = convention.difficulty(Algorithm::Lyra2REv3, &target);
+        assert!((difficulty - 64.0).abs() < 1e-9, "{}", convention);
+        assert_eq!(convention.to_string().parse(), Ok(convention));
+    }
+    assert_eq!(
+        Convention::Nomp.hash_difficulty(
+            Algorithm::Lyra2REv2,
+            &Hash256(Convention::Nomp.diff1(Algorithm::Lyra2REv2).to_le_bytes())
+        ),
+        1.0
+    );
+    // A Lyra2REv2 share of difficulty 1 takes 2^40 / 0xffff hashes, so one per second
+    // is about 16.8 MH/s.
+    let hashrate = Convention::Nomp.hashrate(Algorithm::Lyra2REv2, 6000.0, 600.0);
+    assert_eq!(hashrate, 167_774_720.0);
+    assert_eq!("MPOS".parse::<Convention>(), Ok(mpos));
+    assert_eq!("mpos( 20 )".parse::<Convention>(), Ok(mpos20));
+    assert_eq!(mpos20.to_string(), "mpos(20)");
+    assert_eq!(mpos20.to_string().parse::<Convention>(), Ok(mpos20));
+    for bad in ["p2pool", "mpos(0)", "mpos(33)", "mpos(x)", "nomp(24)"] {
+        assert_eq!(
+            bad.parse::<Convention>(),
+            Err(ParseConventionError(bad.to_string()))
+        );
+    }
+}
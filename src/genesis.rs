@@ -0,0 +1,485 @@
+//! # genesis
+//!
+//! `genesis` crate builds and mines the genesis block of a new Lyra2-based chain, printed in
+//! the form `chainparams.cpp` expects, and follow-up blocks for regtest networks.
+use crate::algorithm::Algorithm;
+use crate::block::{self, Block, Transaction, TxIn, TxOut};
+use crate::header::{BlockHeader, Hash256};
+use crate::scan::Scanner;
+use crate::target::TargetError;
+use crate::utils::to_hex;
+use std::error;
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of base units in one coin.
+pub const COIN: u64 = 100_000_000;
+
+// Nonces scanned per thread before the stop flag is checked again.
+const CHUNK: u32 = 4096;
+
+/// The contents of a genesis coinbase, as `CreateGenesisBlock()` of nodes takes them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Genesis {
+    /// The timestamp message, e.g. a newspaper headline.
+    pub message: String,
+    /// The script of the only output.
+    pub output_script: Vec<u8>,
+    /// The value of the only output, in base units.
+    pub reward: u64,
+}
+
+impl Genesis {
+    /// Returns a genesis paying 50 coins to `output_script`.
+    pub fn new(message: &str, output_script: &[u8]) -> Genesis {
+        Genesis {
+            message: message.to_string(),
+            output_script: output_script.to_vec(),
+            reward: 50 * COIN,
+        }
+    }
+
+    /// Returns the coinbase. Its script is `486604799 4 <message>` as in Bitcoin's genesis.
+    pub fn coinbase(&self) -> Transaction {
+        let mut script_sig = Vec::new();
+        block::push_int(&mut script_sig, 486604799);
+        // CScriptNum(4) is pushed as data, not as OP_4.
+        block::push_data(&mut script_sig, &[4]);
+        block::push_data(&mut script_sig, self.message.as_bytes());
+        Transaction {
+            version: 1,
+            inputs: vec![TxIn {
+                prev_txid: Hash256::default(),
+                prev_index: u32::MAX,
+                script_sig,
+                sequence: u32::MAX,
+                witness: Vec::new(),
+            }],
+            outputs: vec![TxOut {
+                value: self.reward,
+                script_pubkey: self.output_script.clone(),
+            }],
+            lock_time: 0,
+        }
+    }
+
+    /// Returns the unmined genesis block, with a zero nonce.
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::genesis::{p2pk_script, Genesis};
+    ///
+    /// let message = "Dec. 31th 2013 Japan, The winning numbers of the 2013 Year-End Jumbo Lottery:23-130916";
+    /// let hex = "040184710fa689ad5023690c80f3a49c8f13f8d45b8c857fbcbc8bc4a8e4d3eb4b10f4d4604fa08dce601aaf0f470216fe1b51850b4acf21b179c45070ac7b03a9";
+    /// let pubkey: Vec<u8> = (0..hex.len())
+    ///     .step_by(2)
+    ///     .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+    ///     .collect();
+    /// let mut block = Genesis::new(message, &p2pk_script(&pubkey)).block(1, 1388479472, 0x1e0ffff0);
+    /// block.header.nonce = 1234534;
+    /// assert_eq!(block.header.block_hash().to_string(), "ff9f1c0116d19de7c9963845e129f9ed1bfc0b376eb54fd7afa42e0d418c8bb6");
+    /// ```
+    pub fn block(&self, version: i32, time: u32, bits: u32) -> Block {
+        let coinbase = self.coinbase();
+        Block {
+            header: BlockHeader {
+                version,
+                prev_block: Hash256::default(),
+                merkle_root: coinbase.txid(),
+                time,
+                bits,
+                nonce: 0,
+            },
+            transactions: vec![coinbase],
+        }
+    }
+
+    /// Returns the `chainparams.cpp` lines that recreate the mined genesis `block`, with its
+    /// PoW hash under `algorithm` as a comment.
+    pub fn chainparams(&self, block: &Block, algorithm: Algorithm) -> String {
+        let header = &block.header;
+        let script = &self.output_script;
+        let mut out = format!(
+            "const char* pszTimestamp = \"{}\";\n",
+            escape(&self.message)
+        );
+        if script.len() == 67 && script[0] == 65 && script[66] == 0xac {
+            out += &format!(
+                "const CScript genesisOutputScript = CScript() << ParseHex(\"{}\") << OP_CHECKSIG;\n",
+                to_hex(&script[1..66])
+            );
+        } else {
+            out += &format!(
+                "const std::vector<unsigned char> genesisScript = ParseHex(\"{}\");\n\
+                 const CScript genesisOutputScript(genesisScript.begin(), genesisScript.end());\n",
+                to_hex(script)
+            );
+        }
+        let reward = if self.reward % COIN == 0 {
+            format!("{} * COIN", self.reward / COIN)
+        } else {
+            self.reward.to_string()
+        };
+        out += &format!(
+            "genesis = CreateGenesisBlock(pszTimestamp, genesisOutputScript, {}, {}, 0x{:08x}, {}, {});\n\
+             consensus.hashGenesisBlock = genesis.GetHash();\n\
+             assert(consensus.hashGenesisBlock == uint256S(\"0x{}\"));\n\
+             assert(genesis.hashMerkleRoot == uint256S(\"0x{}\"));\n\
+             // {} PoW hash: {}\n\
+             // header: {}\n",
+            header.time,
+            header.nonce,
+            header.bits,
+            header.version,
+            reward,
+            header.block_hash(),
+            header.merkle_root,
+            algorithm,
+            header.pow_hash(algorithm),
+            header.to_hex()
+        );
+        out
+    }
+}
+
+// escape escapes `s` for a C++ string literal.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Returns the pay-to-pubkey script `<pubkey> OP_CHECKSIG`.
+pub fn p2pk_script(pubkey: &[u8]) -> Vec<u8> {
+    let mut script = Vec::with_capacity(pubkey.len() + 2);
+    block::push_data(&mut script, pubkey);
+    script.push(0xac);
+    script
+}
+
+/// Returns a coinbase for the block at `height` paying `value` to `output_script`. Its script
+/// is `<height> OP_0` as the miner of nodes builds it, which satisfies BIP 34.
+pub fn coinbase(height: u32, output_script: &[u8], value: u64) -> Transaction {
+    let mut script_sig = Vec::new();
+    block::push_int(&mut script_sig, i64::from(height));
+    script_sig.push(0x00);
+    Transaction {
+        version: 2,
+        inputs: vec![TxIn {
+            prev_txid: Hash256::default(),
+            prev_index: u32::MAX,
+            script_sig,
+            sequence: u32::MAX,
+            witness: Vec::new(),
+        }],
+        outputs: vec![TxOut {
+            value,
+            script_pubkey: output_script.to_vec(),
+        }],
+        lock_time: 0,
+    }
+}
+
+/// Builds and mines blocks with one algorithm and `nBits`.
+pub struct Generator {
+    algorithm: Algorithm,
+    bits: u32,
+    version: Option<i32>,
+    time: Option<u32>,
+    threads: usize,
+    scanner: Scanner,
+}
+
+impl Generator {
+    /// Returns a generator mining with `algorithm` at compact target `bits` on one thread.
+    pub fn new(algorithm: Algorithm, bits: u32) -> Generator {
+        Generator {
+            algorithm,
+            bits,
+            version: None,
+            time: None,
+            threads: 1,
+            scanner: Scanner::new(),
+        }
+    }
+
+    /// Sets the block version. Defaults to 1 for genesis blocks and `0x20000000` for others.
+    pub fn version(mut self, version: i32) -> Generator {
+        self.version = Some(version);
+        self
+    }
+
+    /// Sets the block time. Defaults to now for genesis blocks and one second after the
+    /// previous block for others.
+    pub fn time(mut self, time: u32) -> Generator {
+        self.time = Some(time);
+        self
+    }
+
+    /// Sets the number of mining threads.
+    pub fn threads(mut self, threads: usize) -> Generator {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Mines with `scanner`, e.g. to stop mining or report the hashrate.
+    pub fn scanner(mut self, scanner: Scanner) -> Generator {
+        self.scanner = scanner;
+        self
+    }
+
+    /// Builds and mines the genesis block of `genesis`.
+    pub fn genesis(&self, genesis: &Genesis) -> Result<Block, GenerateError> {
+        let time = self.time.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as u32)
+        });
+        let mut block = genesis.block(self.version.unwrap_or(1), time, self.bits);
+        self.mine(&mut block.header)?;
+        Ok(block)
+    }
+
+    /// Builds and mines a block on top of `prev` with `coinbase` and `transactions`. When a
+    /// transaction has witnesses, the coinbase gets a zero witness reserved value and the
+    /// witness commitment output.
+    pub fn next_block(
+        &self,
+        prev: &BlockHeader,
+        mut coinbase: Transaction,
+        transactions: Vec<Transaction>,
+    ) -> Result<Block, GenerateError> {
+        if coinbase.inputs.is_empty() {
+            return Err(GenerateError::NoCoinbaseInput);
+        }
+        let time = match self.time {
+            Some(time) => time,
+            None => prev
+                .time
+                .checked_add(1)
+                .ok_or(GenerateError::TimeOverflow)?,
+        };
+        let segwit = transactions.iter().any(Transaction::has_witness);
+        if segwit {
+            coinbase.inputs[0].witness = vec![vec![0; 32]];
+        }
+        let mut transactions = [vec![coinbase], transactions].concat();
+        let mut block = Block {
+            header: BlockHeader {
+                version: self.version.unwrap_or(0x20000000),
+                prev_block: prev.block_hash(),
+                merkle_root: Hash256::default(),
+                time,
+                bits: self.bits,
+                nonce: 0,
+            },
+            transactions: Vec::new(),
+        };
+        if segwit {
+            block.transactions = transactions;
+            let script = block::commitment_script(&block.witness_root(), &[0; 32]);
+            block.transactions[0].outputs.push(TxOut {
+                value: 0,
+                script_pubkey: script,
+            });
+        } else {
+            block.transactions.append(&mut transactions);
+        }
+        block.header.merkle_root = block.merkle_root();
+        self.mine(&mut block.header)?;
+        Ok(block)
+    }
+
+    /// Searches for a nonce of `header` whose PoW hash meets its `nBits`, stores it and
+    /// returns the hash. When every nonce fails, the time is increased by one second and the
+    /// search starts over, until the time runs out.
+    pub fn mine(&self, header: &mut BlockHeader) -> Result<Hash256, GenerateError> {
+        let target = header.target()?;
+        let stop = self.scanner.stop();
+        let chunk = CHUNK.saturating_mul(self.threads as u32);
+        loop {
+            let mut start = 0u32;
+            loop {
+                if stop.load(Ordering::Relaxed) {
+                    return Err(GenerateError::Stopped);
+                }
+                let end = start.saturating_add(chunk - 1);
+                let solutions =
+                    self.scanner
+                        .scan(header, start..=end, &target, self.algorithm, self.threads);
+                if let Some(solution) = solutions.first() {
+                    header.nonce = solution.nonce;
+                    return Ok(solution.hash);
+                }
+                if end == u32::MAX {
+                    break;
+                }
+                start = end + 1;
+            }
+            header.time = header
+                .time
+                .checked_add(1)
+                .ok_or(GenerateError::TimeOverflow)?;
+        }
+    }
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Generator")
+            .field("algorithm", &self.algorithm)
+            .field("bits", &self.bits)
+            .field("version", &self.version)
+            .field("time", &self.time)
+            .field("threads", &self.threads)
+            .finish()
+    }
+}
+
+/// An error returned when a block cannot be mined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenerateError {
+    /// `nBits` does not decode to a target.
+    InvalidBits(TargetError),
+    /// The stop flag of the scanner was set.
+    Stopped,
+    /// The coinbase transaction has no input.
+    NoCoinbaseInput,
+    /// The block time would pass `u32::MAX`.
+    TimeOverflow,
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenerateError::InvalidBits(err) => write!(f, "{}", err),
+            GenerateError::Stopped => write!(f, "mining was stopped"),
+            GenerateError::NoCoinbaseInput => write!(f, "coinbase has no input"),
+            GenerateError::TimeOverflow => write!(f, "block time overflows"),
+        }
+    }
+}
+
+impl error::Error for GenerateError {}
+
+impl From<TargetError> for GenerateError {
+    fn from(err: TargetError) -> Self {
+        GenerateError::InvalidBits(err)
+    }
+}
+
+#[test]
+fn genesis_cal() {
+    use crate::utils::from_hex;
+
+    // The Monacoin genesis block is rebuilt from its message and pubkey.
+    let mona = Genesis::new(
+        "Dec. 31th 2013 Japan, The winning numbers of the 2013 Year-End Jumbo Lottery:23-130916",
+        &p2pk_script(&from_hex("040184710fa689ad5023690c80f3a49c8f13f8d45b8c857fbcbc8bc4a8e4d3eb4b10f4d4604fa08dce601aaf0f470216fe1b51850b4acf21b179c45070ac7b03a9").unwrap()),
+    );
+    let mut block = mona.block(1, 1388479472, 0x1e0ffff0);
+    block.header.nonce = 1234534;
+    assert_eq!(block.to_hex(), "010000000000000000000000000000000000000000000000000000000000000000000000a64bac07fe31877f31d03252953b3c32398933af7a724119bc4d6fa4a805e435f083c252f0ff0f1e66d612000101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff5f04ffff001d01044c564465632e20333174682032303133204a6170616e2c205468652077696e6e696e67206e756d62657273206f6620746865203230313320596561722d456e64204a756d626f204c6f74746572793a32332d313330393136ffffffff0100f2052a010000004341040184710fa689ad5023690c80f3a49c8f13f8d45b8c857fbcbc8bc4a8e4d3eb4b10f4d4604fa08dce601aaf0f470216fe1b51850b4acf21b179c45070ac7b03a9ac00000000");
+    let params = mona.chainparams(&block, Algorithm::Lyra2REv2);
+    assert!(params.contains("CreateGenesisBlock(pszTimestamp, genesisOutputScript, 1388479472, 1234534, 0x1e0ffff0, 1, 50 * COIN);"));
+    assert!(params.contains(
+        "uint256S(\"0xff9f1c0116d19de7c9963845e129f9ed1bfc0b376eb54fd7afa42e0d418c8bb6\")"
+    ));
+    assert!(params.contains(
+        "uint256S(\"0x35e405a8a46f4dbc1941727aaf338939323c3b955232d0317f8731fe07ac4ba6\")"
+    ));
+    assert!(params.contains("<< OP_CHECKSIG;"));
+
+    // A new Lyra2REv3 chain at minimal difficulty.
+    let genesis = Genesis {
+        message: "a \"test\" chain".to_string(),
+        output_script: vec![0x51],
+        reward: 12_345,
+    };
+    let generator = Generator::new(Algorithm::Lyra2REv3, 0x207fffff)
+        .time(1600000000)
+        .threads(2);
+    let block = generator.genesis(&genesis).unwrap();
+    block.validate(Algorithm::Lyra2REv3).unwrap();
+    assert_eq!(block.header.version, 1);
+    assert_eq!(block.header.time, 1600000000);
+    let params = genesis.chainparams(&block, Algorithm::Lyra2REv3);
+    assert!(params.contains("pszTimestamp = \"a \\\"test\\\" chain\";"));
+    assert!(params.contains("ParseHex(\"51\");"));
+    assert!(params.contains(&format!(
+        "1600000000, {}, 0x207fffff, 1, 12345);",
+        block.header.nonce
+    )));
+
+    // Regtest blocks on top of it, the second one with a segwit spend.
+    let generator = Generator::new(Algorithm::Lyra2REv3, 0x207fffff);
+    let b1 = generator
+        .next_block(&block.header, coinbase(1, &[0x51], 50 * COIN), Vec::new())
+        .unwrap();
+    let spend = Transaction {
+        version: 2,
+        inputs: vec![TxIn {
+            prev_txid: b1.transactions[0].txid(),
+            prev_index: 0,
+            script_sig: Vec::new(),
+            sequence: u32::MAX,
+            witness: vec![vec![0x51]],
+        }],
+        outputs: vec![TxOut {
+            value: 50 * COIN - 1000,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    };
+    let b2 = generator
+        .next_block(
+            &b1.header,
+            coinbase(2, &[0x51], 50 * COIN + 1000),
+            vec![spend],
+        )
+        .unwrap();
+    for (height, b) in [(1, &b1), (2, &b2)] {
+        b.validate(Algorithm::Lyra2REv3).unwrap();
+        assert_eq!(b.coinbase_height(), Some(height));
+        assert_eq!(b.header.version, 0x20000000);
+        assert_eq!(Block::from_hex(&b.to_hex()).unwrap(), *b);
+    }
+    assert_eq!(b1.header.prev_block, block.header.block_hash());
+    assert_eq!(b2.header.prev_block, b1.header.block_hash());
+    assert_eq!(b2.header.time, b1.header.time + 1);
+    assert!(b1.witness_commitment().is_none());
+    assert!(b2.witness_commitment().is_some());
+
+    let mut empty = coinbase(3, &[0x51], 50 * COIN);
+    empty.inputs.clear();
+    assert_eq!(
+        generator.next_block(&b2.header, empty, Vec::new()),
+        Err(GenerateError::NoCoinbaseInput)
+    );
+    let mut last = b2.header;
+    last.time = u32::MAX;
+    assert_eq!(
+        generator.next_block(&last, coinbase(3, &[0x51], 50 * COIN), Vec::new()),
+        Err(GenerateError::TimeOverflow)
+    );
+
+    let scanner = Scanner::new();
+    scanner.stop().store(true, Ordering::Relaxed);
+    let mut header = block.header;
+    assert_eq!(
+        Generator::new(Algorithm::Lyra2REv2, 0x207fffff)
+            .scanner(scanner)
+            .mine(&mut header),
+        Err(GenerateError::Stopped)
+    );
+}
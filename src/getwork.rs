@@ -0,0 +1,644 @@
+//! # getwork
+//!
+//! `getwork` crate speaks the legacy `getwork` mining protocol of early Lyra2RE coin daemons
+//! and proxies: the node hands out a header as a 128-byte data blob with a target, and takes
+//! the blob back with the nonce filled in.
+//!
+//! The blob is the 80-byte header followed by its SHA-256 padding, with every 4-byte word
+//! byte-swapped. The target is 32 bytes in little-endian order. The `midstate` and `hash1`
+//! fields of Bitcoin's getwork are SHA-256 specific; they are neither sent nor needed.
+use crate::algorithm::Algorithm;
+use crate::header::{BlockHeader, Hash256};
+use crate::http;
+use crate::job::{swap_words, Template};
+use crate::rpc::{RpcCallError, RpcClient};
+use crate::scan::Scanner;
+use crate::stratum::FoundBlock;
+use crate::target::Target;
+use crate::utils::{from_hex, to_hex};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error;
+use std::fmt;
+use std::io::{self, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Size in bytes of a getwork data blob.
+pub const DATA_SIZE: usize = 128;
+
+/// Returns the getwork data blob of `header`.
+pub fn encode_data(header: &BlockHeader) -> [u8; DATA_SIZE] {
+    let mut data = [0u8; DATA_SIZE];
+    data[..BlockHeader::SIZE].copy_from_slice(&header.serialize());
+    data[BlockHeader::SIZE] = 0x80;
+    // The length of the header in bits.
+    data[DATA_SIZE - 8..].copy_from_slice(&(BlockHeader::SIZE as u64 * 8).to_be_bytes());
+    swap_words(&mut data);
+    data
+}
+
+/// Returns the header of a getwork data blob. The padding is not checked, as miners and
+/// proxies do not agree on it.
+/// # Examples
+///
+/// ```
+/// use lyra2::getwork;
+///
+/// let data = "00000001000000000000000000000000000000000000000000000000000000000000000007ac4ba67f8731fe5232d031323c3b95af3389391941727aa46f4dbc35e405a852c283f01e0ffff00012d666000000800000000000000000000000000000000000000000000000000000000000000000000000000000000080020000";
+/// let header = getwork::decode_data(data).unwrap();
+/// assert_eq!(header.nonce, 1234534);
+/// ```
+pub fn decode_data(hex: &str) -> Result<BlockHeader, GetworkError> {
+    let mut data = from_hex(hex)
+        .filter(|data| data.len() == DATA_SIZE)
+        .ok_or_else(|| GetworkError::Malformed(format!("invalid data: {}", hex)))?;
+    swap_words(&mut data);
+    Ok(BlockHeader::parse(&data[..BlockHeader::SIZE]).expect("80 bytes parse"))
+}
+
+/// Work handed out by `getwork`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Getwork {
+    pub header: BlockHeader,
+    /// The target a solved header's PoW hash must meet, which may be a share target above
+    /// the one of the header's bits.
+    pub target: Target,
+}
+
+impl Getwork {
+    /// Reads the result of `getwork` without params: `{"data": ..., "target": ...}`.
+    pub fn from_json(value: &Value) -> Result<Getwork, GetworkError> {
+        let field = |name: &str| {
+            value[name]
+                .as_str()
+                .ok_or_else(|| GetworkError::Malformed(format!("missing {}", name)))
+        };
+        let header = decode_data(field("data")?)?;
+        let target = field("target")?;
+        let target = from_hex(target)
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .ok_or_else(|| GetworkError::Malformed(format!("invalid target: {}", target)))?;
+        Ok(Getwork {
+            header,
+            target: Target::from_le_bytes(target),
+        })
+    }
+
+    /// Returns the result of `getwork` without params for this work.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "data": to_hex(&encode_data(&self.header)),
+            "target": to_hex(&self.target.to_le_bytes()),
+        })
+    }
+
+    /// Returns true if the PoW hash of `header` with `algorithm` meets the target of the work.
+    pub fn is_solved_by(&self, header: &BlockHeader, algorithm: Algorithm) -> bool {
+        self.target.is_met_by(header.pow_hash(algorithm).as_bytes())
+    }
+}
+
+/// Mines getwork work from a node.
+/// # Examples
+///
+/// ```no_run
+/// use lyra2::algorithm::Algorithm;
+/// use lyra2::getwork::GetworkClient;
+/// use lyra2::rpc::RpcClient;
+///
+/// let client = RpcClient::new("127.0.0.1:9332").auth("user", "password");
+/// let miner = GetworkClient::new(client, Algorithm::Lyra2RE).threads(4);
+/// loop {
+///     let work = miner.get_work().unwrap();
+///     if let Some(header) = miner.mine(&work, 0..=u32::MAX) {
+///         println!("accepted: {}", miner.submit(&header).unwrap());
+///     }
+/// }
+/// ```
+pub struct GetworkClient {
+    client: RpcClient,
+    algorithm: Algorithm,
+    threads: usize,
+    scanner: Scanner,
+}
+
+impl GetworkClient {
+    /// Returns a client that mines on one thread.
+    pub fn new(client: RpcClient, algorithm: Algorithm) -> GetworkClient {
+        GetworkClient {
+            client,
+            algorithm,
+            threads: 1,
+            scanner: Scanner::new(),
+        }
+    }
+
+    /// Scans on `threads` threads.
+    pub fn threads(mut self, threads: usize) -> GetworkClient {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Scans with `scanner`, e.g. for its stop flag or hashrate reports.
+    pub fn scanner(mut self, scanner: Scanner) -> GetworkClient {
+        self.scanner = scanner;
+        self
+    }
+
+    /// Fetches work with `getwork`.
+    pub fn get_work(&self) -> Result<Getwork, GetworkError> {
+        Getwork::from_json(&self.client.call("getwork", json!([]))?)
+    }
+
+    /// Scans `nonces` of `work` and returns the header with the first nonce that solves it.
+    pub fn mine(&self, work: &Getwork, nonces: RangeInclusive<u32>) -> Option<BlockHeader> {
+        let solutions = self.scanner.scan(
+            &work.header,
+            nonces,
+            &work.target,
+            self.algorithm,
+            self.threads,
+        );
+        solutions.first().map(|solution| BlockHeader {
+            nonce: solution.nonce,
+            ..work.header
+        })
+    }
+
+    /// Submits a solved header with `getwork <data>` and returns whether the node accepted it.
+    pub fn submit(&self, header: &BlockHeader) -> Result<bool, GetworkError> {
+        let data = to_hex(&encode_data(header));
+        self.client
+            .call("getwork", json!([data]))?
+            .as_bool()
+            .ok_or_else(|| GetworkError::Malformed("getwork did not return a bool".to_string()))
+    }
+}
+
+impl fmt::Debug for GetworkClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GetworkClient")
+            .field("client", &self.client)
+            .field("algorithm", &self.algorithm)
+            .field("threads", &self.threads)
+            .finish()
+    }
+}
+
+type BlockFn = dyn Fn(&FoundBlock) + Send + Sync;
+
+// Work handed out per previous block that submissions are accepted for.
+const MAX_WORKS: usize = 4096;
+// How far past the template time a solved header's time may be, as nodes allow for blocks.
+const MAX_TIME_ROLL: u32 = 7200;
+// Largest request body: a getwork call with its data blob and room to spare.
+const MAX_BODY: usize = 4096;
+
+/// Serves `getwork` over HTTP JSON-RPC to legacy miners from block templates, e.g. to put
+/// them on a node that only speaks `getblocktemplate`. Every call gets its own extranonce in
+/// the coinbase, so miners never scan the same header twice. [`GetworkServer::listen`] starts
+/// it.
+/// # Examples
+///
+/// ```no_run
+/// use lyra2::algorithm::Algorithm;
+/// use lyra2::getwork::GetworkServer;
+/// # fn template() -> lyra2::job::Template { unimplemented!() }
+///
+/// let server = GetworkServer::new(Algorithm::Lyra2RE)
+///     .on_block(|found| println!("block {}: {}", found.block_hash, found.block.to_hex()))
+///     .listen("127.0.0.1:9332")
+///     .unwrap();
+/// server.set_template(template());
+/// ```
+pub struct GetworkServer {
+    algorithm: Algorithm,
+    on_block: Option<Box<BlockFn>>,
+}
+
+impl GetworkServer {
+    /// Returns a server hashing with `algorithm`, without a block callback.
+    pub fn new(algorithm: Algorithm) -> GetworkServer {
+        GetworkServer {
+            algorithm,
+            on_block: None,
+        }
+    }
+
+    /// Calls `on_block` with every submitted header that solves its block, on the thread of
+    /// the submitting connection. The worker is the basic auth user name.
+    pub fn on_block<F>(mut self, on_block: F) -> GetworkServer
+    where
+        F: Fn(&FoundBlock) + Send + Sync + 'static,
+    {
+        self.on_block = Some(Box::new(on_block));
+        self
+    }
+
+    /// Listens on `addr` and serves each connection on its own thread. Calls fail until a
+    /// template is set with [`GetworkHandle::set_template`].
+    pub fn listen<A: ToSocketAddrs>(self, addr: A) -> io::Result<GetworkHandle> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            algorithm: self.algorithm,
+            on_block: self.on_block,
+            stop: AtomicBool::new(false),
+            state: Mutex::new(State::default()),
+        });
+        let accept = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shared.stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let shared = Arc::clone(&shared);
+                        thread::spawn(move || serve(&shared, stream));
+                    }
+                }
+            })
+        };
+        Ok(GetworkHandle {
+            shared,
+            local_addr,
+            accept: Some(accept),
+        })
+    }
+}
+
+impl fmt::Debug for GetworkServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GetworkServer")
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+/// A running [`GetworkServer`]. Dropping it shuts the server down.
+pub struct GetworkHandle {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl GetworkHandle {
+    /// Returns the address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Hands out work of `template` from now on. When the previous block changes, work
+    /// handed out before is stale.
+    pub fn set_template(&self, template: Template) {
+        let mut state = self.shared.state.lock().unwrap();
+        let same_tip = state
+            .template
+            .as_ref()
+            .is_some_and(|current| current.prev_hash == template.prev_hash);
+        if !same_tip {
+            state.works.clear();
+            state.order.clear();
+            state.solved.clear();
+        }
+        state.template = Some(Arc::new(template));
+    }
+
+    /// Stops accepting connections. Open connections end after their current request.
+    pub fn shutdown(&mut self) {
+        if let Some(accept) = self.accept.take() {
+            self.shared.stop.store(true, Ordering::SeqCst);
+            // Wake the accept loop up.
+            let _ = TcpStream::connect(self.local_addr);
+            let _ = accept.join();
+        }
+    }
+}
+
+impl Drop for GetworkHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl fmt::Debug for GetworkHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GetworkHandle")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+struct Shared {
+    algorithm: Algorithm,
+    on_block: Option<Box<BlockFn>>,
+    stop: AtomicBool,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    template: Option<Arc<Template>>,
+    next_extranonce: u64,
+    // The template and extranonce of the work handed out, by merkle root.
+    works: HashMap<Hash256, (Arc<Template>, u64)>,
+    // Merkle roots of `works`, oldest first.
+    order: VecDeque<Hash256>,
+    // Block hashes of accepted blocks.
+    solved: HashSet<Hash256>,
+}
+
+// serve answers the requests of one connection until it closes.
+fn serve(shared: &Shared, stream: TcpStream) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(60)));
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    while !shared.stop.load(Ordering::SeqCst) {
+        let request = match http::read_request(&mut reader, MAX_BODY) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(err) => {
+                let status = http::too_large_status(&err).unwrap_or(400);
+                let _ = http::write_response(&mut writer, status, "", false);
+                break;
+            }
+        };
+        let worker = request
+            .basic_auth()
+            .map(|(user, _)| user)
+            .unwrap_or_default();
+        let call: Value = serde_json::from_slice(&request.body).unwrap_or(Value::Null);
+        let mut found = None;
+        let result = match call["method"].as_str() {
+            _ if request.method != "POST" => Err((-32600, "Invalid Request".to_string())),
+            Some("getwork") => match call["params"].as_array().map(Vec::as_slice) {
+                None | Some([]) => get_work(shared),
+                Some([Value::String(data)]) => submit(shared, data, worker).map(|block| {
+                    let accepted = block.is_some();
+                    found = block;
+                    json!(accepted)
+                }),
+                Some(_) => Err((-1, "Invalid params".to_string())),
+            },
+            Some(_) => Err((-32601, "Method not found".to_string())),
+            None => Err((-32700, "Parse error".to_string())),
+        };
+        let (status, reply) = match result {
+            Ok(result) => (
+                200,
+                json!({"result": result, "error": null, "id": call["id"]}),
+            ),
+            Err((code, message)) => (
+                if code == -32601 { 404 } else { 500 },
+                json!({"result": null, "error": {"code": code, "message": message}, "id": call["id"]}),
+            ),
+        };
+        let keep_alive = request.keep_alive();
+        if http::write_response(&mut writer, status, &reply.to_string(), keep_alive).is_err() {
+            break;
+        }
+        if let (Some(found), Some(on_block)) = (found, &shared.on_block) {
+            on_block(&found);
+        }
+        if !keep_alive {
+            break;
+        }
+    }
+}
+
+type Reply = Result<Value, (i64, String)>;
+
+// get_work hands out the current template with a new extranonce.
+fn get_work(shared: &Shared) -> Reply {
+    let mut state = shared.state.lock().unwrap();
+    let template = state
+        .template
+        .clone()
+        .ok_or((-9, "No block template".to_string()))?;
+    let extranonce = state.next_extranonce;
+    state.next_extranonce += 1;
+    let header = template
+        .block(&extranonce.to_le_bytes(), &[], template.time, 0)
+        .header;
+    let target = header
+        .target()
+        .map_err(|err| (-1, format!("invalid template: {}", err)))?;
+    state
+        .works
+        .insert(header.merkle_root, (template, extranonce));
+    state.order.push_back(header.merkle_root);
+    while state.order.len() > MAX_WORKS {
+        if let Some(root) = state.order.pop_front() {
+            state.works.remove(&root);
+        }
+    }
+    Ok(Getwork { header, target }.to_json())
+}
+
+// submit checks a solved blob and returns its block, or None if it is stale or invalid.
+fn submit(
+    shared: &Shared,
+    data: &str,
+    worker: String,
+) -> Result<Option<FoundBlock>, (i64, String)> {
+    let header = decode_data(data).map_err(|err| (-1, err.to_string()))?;
+    let mut state = shared.state.lock().unwrap();
+    let (template, extranonce) = match state.works.get(&header.merkle_root) {
+        Some(work) => work.clone(),
+        None => return Ok(None),
+    };
+    if header.time < template.time || header.time > template.time.saturating_add(MAX_TIME_ROLL) {
+        return Ok(None);
+    }
+    let block = template.block(&extranonce.to_le_bytes(), &[], header.time, header.nonce);
+    // Only the time and nonce are the miner's to change.
+    if block.header != header || !header.meets_target(shared.algorithm) {
+        return Ok(None);
+    }
+    let block_hash = header.block_hash();
+    if !state.solved.insert(block_hash) {
+        return Ok(None);
+    }
+    Ok(Some(FoundBlock {
+        block,
+        block_hash,
+        pow_hash: header.pow_hash(shared.algorithm),
+        worker,
+    }))
+}
+
+/// An error of a getwork client.
+#[derive(Debug)]
+pub enum GetworkError {
+    /// The call to the node failed.
+    Rpc(RpcCallError),
+    /// The node's answer is not getwork.
+    Malformed(String),
+}
+
+impl fmt::Display for GetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GetworkError::Rpc(err) => write!(f, "{}", err),
+            GetworkError::Malformed(msg) => write!(f, "malformed getwork: {}", msg),
+        }
+    }
+}
+
+impl error::Error for GetworkError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            GetworkError::Rpc(err) => Some(err),
+            GetworkError::Malformed(_) => None,
+        }
+    }
+}
+
+impl From<RpcCallError> for GetworkError {
+    fn from(err: RpcCallError) -> Self {
+        GetworkError::Rpc(err)
+    }
+}
+
+#[test]
+fn getwork_cal() {
+    use crate::block::Block;
+    use std::io::Write;
+
+    // The Monacoin genesis header as a getwork blob.
+    let genesis = BlockHeader::from_hex("010000000000000000000000000000000000000000000000000000000000000000000000a64bac07fe31877f31d03252953b3c32398933af7a724119bc4d6fa4a805e435f083c252f0ff0f1e66d61200").unwrap();
+    let data = "00000001000000000000000000000000000000000000000000000000000000000000000007ac4ba67f8731fe5232d031323c3b95af3389391941727aa46f4dbc35e405a852c283f01e0ffff00012d666000000800000000000000000000000000000000000000000000000000000000000000000000000000000000080020000";
+    assert_eq!(to_hex(&encode_data(&genesis)), data);
+    assert_eq!(decode_data(data).unwrap(), genesis);
+    assert!(decode_data(&data[..160]).is_err());
+    // Decoded headers hash like any other, e.g. with lyra2re::sum.
+    for (algorithm, sum) in [
+        (
+            Algorithm::Lyra2RE,
+            crate::lyra2re::sum as fn(Vec<u8>) -> Vec<u8>,
+        ),
+        (Algorithm::Lyra2REv2, crate::lyra2rev2::sum),
+        (Algorithm::Lyra2REv3, crate::lyra2rev3::sum),
+        (Algorithm::Lyra2Z, crate::lyra2z::sum),
+    ] {
+        let header = decode_data(data).unwrap();
+        assert_eq!(
+            sum(header.serialize().to_vec()),
+            header.pow_hash(algorithm).as_bytes()
+        );
+    }
+
+    // A mock node serving the blob, recording what is submitted and refusing it.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let submitted = Arc::new(Mutex::new(Vec::new()));
+    {
+        let submitted = Arc::clone(&submitted);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let request = http::read_request(&mut reader, MAX_BODY).unwrap().unwrap();
+                let call: Value = serde_json::from_slice(&request.body).unwrap();
+                let result = match call["params"][0].as_str() {
+                    None => json!({
+                        "data": data,
+                        "target": "000000000000000000000000000000000000000000000000000000f0ff0f0000",
+                    }),
+                    Some(data) => {
+                        submitted.lock().unwrap().push(data.to_string());
+                        json!(false)
+                    }
+                };
+                let reply = json!({"result": result, "error": null, "id": call["id"]});
+                http::write_response(&mut stream, 200, &reply.to_string(), false).unwrap();
+                stream.flush().unwrap();
+            }
+        });
+    }
+    let client = GetworkClient::new(RpcClient::new(&addr), Algorithm::Lyra2RE);
+    let work = client.get_work().unwrap();
+    assert_eq!(work.header, genesis);
+    assert_eq!(work.target, genesis.target().unwrap());
+    assert_eq!(Getwork::from_json(&work.to_json()).unwrap(), work);
+    // The genesis block was mined with scrypt, so its Lyra2RE hash is far off the target.
+    assert!(!work.is_solved_by(&genesis, Algorithm::Lyra2RE));
+    assert!(!client.submit(&genesis).unwrap());
+    assert_eq!(*submitted.lock().unwrap(), vec![data.to_string()]);
+
+    // A getwork server on a regtest template, mined by the client.
+    let template = Template {
+        prev_hash: Hash256::sha256d(b"tip 99"),
+        height: 100,
+        version: 2,
+        bits: 0x207fffff,
+        time: 1_400_000_000,
+        coinbase_value: 50 * 100_000_000,
+        payout_script: vec![0x51],
+        coinbase_tag: b"/getwork/".to_vec(),
+        transactions: Vec::new(),
+    };
+    let found = Arc::new(Mutex::new(Vec::new()));
+    let mut server = {
+        let found = Arc::clone(&found);
+        GetworkServer::new(Algorithm::Lyra2RE)
+            .on_block(move |block| found.lock().unwrap().push(block.clone()))
+            .listen("127.0.0.1:0")
+            .unwrap()
+    };
+    let client = GetworkClient::new(
+        RpcClient::new(&server.local_addr().to_string()).auth("miner", "x"),
+        Algorithm::Lyra2RE,
+    );
+    assert!(matches!(
+        client.get_work(),
+        Err(GetworkError::Rpc(RpcCallError::Rpc { code: -9, .. }))
+    ));
+    server.set_template(template.clone());
+    let work = client.get_work().unwrap();
+    let other = client.get_work().unwrap();
+    assert_ne!(work.header.merkle_root, other.header.merkle_root);
+    assert_eq!(work.target, Target::from_compact(0x207fffff).unwrap());
+    let header = client.mine(&work, 0..=255).unwrap();
+    assert!(work.is_solved_by(&header, Algorithm::Lyra2RE));
+
+    // Tampering with the version or submitting twice is refused.
+    let tampered = BlockHeader {
+        version: 3,
+        ..header
+    };
+    assert!(!client.submit(&tampered).unwrap());
+    assert!(client.submit(&header).unwrap());
+    assert!(!client.submit(&header).unwrap());
+    let blocks = found.lock().unwrap().clone();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].worker, "miner");
+    assert_eq!(blocks[0].block.header, header);
+    assert_eq!(blocks[0].block.coinbase_height(), Some(100));
+    let block = Block::from_hex(&blocks[0].block.to_hex()).unwrap();
+    block.validate(Algorithm::Lyra2RE).unwrap();
+
+    // A new tip makes earlier work stale.
+    let other_header = client.mine(&other, 0..=255).unwrap();
+    server.set_template(Template {
+        prev_hash: block.header.block_hash(),
+        height: 101,
+        ..template
+    });
+    assert!(!client.submit(&other_header).unwrap());
+    assert_eq!(found.lock().unwrap().len(), 1);
+    assert!(matches!(
+        client.client.call("getblockcount", json!([])),
+        Err(RpcCallError::Rpc { code: -32601, .. })
+    ));
+    server.shutdown();
+}
@@ -0,0 +1,616 @@
+//! # hashd
+//!
+//! `hashd` crate serves the hash functions of this crate over HTTP JSON-RPC, so that programs
+//! in other languages can hash without linking Rust code or spawning a process per hash. The
+//! `lyra2-hashd` binary runs it on a local port.
+//!
+//! Params may be positional or named:
+//!
+//! - `hash(algorithm, hex)` returns the hash of the data in hex, in the byte order the algorithm
+//!   outputs it.
+//! - `verify_header(hex, bits)` checks the PoW of an 80-byte header against the target of
+//!   `bits`, a hex string or number, or the header's own bits if null. An optional `algorithm`
+//!   defaults to lyra2rev2. It returns `{"valid", "pow_hash", "block_hash", "target"}`.
+//! - `lyra2(params, pwd, salt)` runs bare Lyra2 with `params` `{"klen", "t_cost", "rows",
+//!   "cols"}` and returns the `klen`-byte key in hex.
+//!
+//! A fixed number of workers serve connections; a kept-alive connection gives its worker up
+//! when another connection is waiting, and connections beyond the queue are answered with
+//! 503, headers over 16 KiB with 431, bodies over the limit with 413 and parameters over the
+//! limits with an RPC error.
+use crate::algorithm::Algorithm;
+use crate::chain;
+use crate::header::BlockHeader;
+use crate::http;
+use crate::lyra2::{lyra2_with_matrix, Matrix};
+use crate::target::Target;
+use crate::utils::{from_hex, to_hex};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// How often a kept-alive connection checks for connections waiting for its worker.
+const KEEP_ALIVE_POLL: Duration = Duration::from_millis(50);
+
+// Lyra2 squeezes its output from a single sponge block.
+const MAX_LYRA2_KLEN: u64 = 96;
+// Bytes of a Lyra2 matrix column: one 12-word block.
+const LYRA2_COLUMN_SIZE: u64 = 96;
+
+/// Settings of a [`HashServer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashServerConfig {
+    /// Number of threads serving connections, each one connection at a time.
+    pub workers: usize,
+    /// Connections waiting for a worker; more are answered with 503.
+    pub queue: usize,
+    /// Largest request body in bytes.
+    pub max_body: usize,
+    /// Largest decoded byte param (`hex`, `pwd`, `salt`) in bytes.
+    pub max_param: usize,
+    /// Largest Lyra2 memory matrix in bytes, 96 bytes per cell of `rows * cols`.
+    pub max_lyra2_memory: u64,
+    /// Largest Lyra2 `t_cost`.
+    pub max_time_cost: u64,
+    /// Time a connection may stay idle before it is closed. Between requests, a kept-alive
+    /// connection is closed as soon as another connection is waiting for a worker.
+    pub idle_timeout: Duration,
+}
+
+impl HashServerConfig {
+    /// Returns a config with a worker per core, a queue of 64 connections, 64 KiB bodies,
+    /// 4 KiB params and Lyra2 up to 16 MiB of memory and a time cost of 16.
+    pub fn new() -> HashServerConfig {
+        HashServerConfig {
+            workers: thread::available_parallelism().map_or(1, |n| n.get()),
+            queue: 64,
+            max_body: 64 * 1024,
+            max_param: 4096,
+            max_lyra2_memory: chain::MAX_LYRA2_MEMORY,
+            max_time_cost: chain::MAX_LYRA2_TIME_COST,
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Default for HashServerConfig {
+    fn default() -> Self {
+        HashServerConfig::new()
+    }
+}
+
+/// Serves the hash functions over HTTP JSON-RPC.
+/// # Examples
+///
+/// ```no_run
+/// use lyra2::hashd::{HashServer, HashServerConfig};
+///
+/// let server = HashServer::new(HashServerConfig::new())
+///     .listen("127.0.0.1:9430")
+///     .unwrap();
+/// println!("listening on {}", server.local_addr());
+/// ```
+#[derive(Debug)]
+pub struct HashServer {
+    config: HashServerConfig,
+}
+
+impl HashServer {
+    pub fn new(config: HashServerConfig) -> HashServer {
+        HashServer { config }
+    }
+
+    /// Listens on `addr` and starts the workers.
+    pub fn listen<A: ToSocketAddrs>(self, addr: A) -> io::Result<HashServerHandle> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            config: self.config,
+            stop: AtomicBool::new(false),
+            next_conn: AtomicU64::new(0),
+            queued: AtomicUsize::new(0),
+            conns: Mutex::new(HashMap::new()),
+        });
+        let (queue, connections) = mpsc::sync_channel::<TcpStream>(shared.config.queue);
+        let connections = Arc::new(Mutex::new(connections));
+        for _ in 0..shared.config.workers.max(1) {
+            let shared = Arc::clone(&shared);
+            let connections = Arc::clone(&connections);
+            thread::spawn(move || {
+                let mut matrix = Matrix::new();
+                loop {
+                    let stream = match connections.lock().unwrap().recv() {
+                        Ok(stream) => stream,
+                        Err(_) => break,
+                    };
+                    shared.queued.fetch_sub(1, Ordering::SeqCst);
+                    serve(&shared, stream, &mut matrix);
+                }
+            });
+        }
+        let accept = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shared.stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        shared.queued.fetch_add(1, Ordering::SeqCst);
+                        if let Err(TrySendError::Full(mut stream)) = queue.try_send(stream) {
+                            shared.queued.fetch_sub(1, Ordering::SeqCst);
+                            let reply = error_reply(&Value::Null, -32603, "Server busy");
+                            let _ = http::write_response(&mut stream, 503, &reply, false);
+                            let _ = stream.shutdown(Shutdown::Write);
+                        }
+                    }
+                }
+                // Dropping the queue stops the workers once it is drained.
+            })
+        };
+        Ok(HashServerHandle {
+            shared,
+            local_addr,
+            accept: Some(accept),
+        })
+    }
+}
+
+/// A running [`HashServer`]. Dropping it shuts the server down.
+pub struct HashServerHandle {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl HashServerHandle {
+    /// Returns the address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting connections and closes the open ones.
+    pub fn shutdown(&mut self) {
+        if let Some(accept) = self.accept.take() {
+            self.shared.stop.store(true, Ordering::SeqCst);
+            // Wake the accept loop up.
+            let _ = TcpStream::connect(self.local_addr);
+            let _ = accept.join();
+            for stream in self.shared.conns.lock().unwrap().values() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+impl Drop for HashServerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl fmt::Debug for HashServerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HashServerHandle")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+struct Shared {
+    config: HashServerConfig,
+    stop: AtomicBool,
+    next_conn: AtomicU64,
+    // Connections waiting for a worker.
+    queued: AtomicUsize,
+    // Open connections, to close them on shutdown.
+    conns: Mutex<HashMap<u64, TcpStream>>,
+}
+
+// serve answers the requests of one connection until it closes.
+fn serve(shared: &Shared, stream: TcpStream, matrix: &mut Matrix) {
+    let config = &shared.config;
+    let _ = stream.set_read_timeout(Some(config.idle_timeout));
+    let id = shared.next_conn.fetch_add(1, Ordering::Relaxed);
+    let (mut writer, registered) = match (stream.try_clone(), stream.try_clone()) {
+        (Ok(writer), Ok(registered)) => (writer, registered),
+        _ => return,
+    };
+    shared.conns.lock().unwrap().insert(id, registered);
+    let mut reader = BufReader::new(stream);
+    let mut first = true;
+    while !shared.stop.load(Ordering::SeqCst) {
+        if !first && !wait_for_request(shared, &mut reader) {
+            break;
+        }
+        first = false;
+        let request = match http::read_request(&mut reader, config.max_body) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(err) => {
+                if let Some(status) = http::too_large_status(&err) {
+                    let reply = error_reply(&Value::Null, -32600, "Request too large");
+                    let _ = http::write_response(&mut writer, status, &reply, false);
+                } else if err.kind() == io::ErrorKind::InvalidData {
+                    let _ = http::write_response(&mut writer, 400, "", false);
+                }
+                break;
+            }
+        };
+        let (status, reply) = if request.method != "POST" {
+            (405, error_reply(&Value::Null, -32600, "Invalid Request"))
+        } else {
+            match serde_json::from_slice::<Value>(&request.body) {
+                Ok(call) => {
+                    // A panic in a hash function must not take the worker down.
+                    let result =
+                        panic::catch_unwind(AssertUnwindSafe(|| handle(config, &call, matrix)))
+                            .unwrap_or_else(|_| Err((-32603, "Internal error".to_string())));
+                    match result {
+                        Ok(result) => (
+                            200,
+                            json!({"result": result, "error": null, "id": call["id"]}).to_string(),
+                        ),
+                        Err((code, message)) => (
+                            if code == -32601 { 404 } else { 500 },
+                            error_reply(&call["id"], code, &message),
+                        ),
+                    }
+                }
+                Err(_) => (500, error_reply(&Value::Null, -32700, "Parse error")),
+            }
+        };
+        let keep_alive = request.keep_alive() && shared.queued.load(Ordering::SeqCst) == 0;
+        if http::write_response(&mut writer, status, &reply, keep_alive).is_err() || !keep_alive {
+            break;
+        }
+    }
+    shared.conns.lock().unwrap().remove(&id);
+}
+
+// wait_for_request waits for the next request of a kept-alive connection. It gives up after the
+// idle timeout, or as soon as another connection is waiting for the worker.
+fn wait_for_request(shared: &Shared, reader: &mut BufReader<TcpStream>) -> bool {
+    if !reader.buffer().is_empty() {
+        return true;
+    }
+    let started = Instant::now();
+    let _ = reader.get_ref().set_read_timeout(Some(KEEP_ALIVE_POLL));
+    let ready = loop {
+        match reader.fill_buf() {
+            Ok(buf) => break !buf.is_empty(),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                if shared.queued.load(Ordering::SeqCst) > 0
+                    || shared.stop.load(Ordering::SeqCst)
+                    || started.elapsed() >= shared.config.idle_timeout
+                {
+                    break false;
+                }
+            }
+            Err(_) => break false,
+        }
+    };
+    let _ = reader
+        .get_ref()
+        .set_read_timeout(Some(shared.config.idle_timeout));
+    ready
+}
+
+fn error_reply(id: &Value, code: i64, message: &str) -> String {
+    json!({"result": null, "error": {"code": code, "message": message}, "id": id}).to_string()
+}
+
+type Reply = Result<Value, (i64, String)>;
+
+fn handle(config: &HashServerConfig, call: &Value, matrix: &mut Matrix) -> Reply {
+    let params = &call["params"];
+    match call["method"].as_str() {
+        Some("hash") => {
+            let algorithm = algorithm_param(param(params, 0, "algorithm"))?;
+            let data = hex_param(config, param(params, 1, "hex"), "hex")?;
+            Ok(json!(to_hex(&algorithm.sum(data))))
+        }
+        Some("verify_header") => verify_header(config, params),
+        Some("lyra2") => lyra2(config, params, matrix),
+        Some(_) => Err((-32601, "Method not found".to_string())),
+        None => Err((-32600, "Invalid Request".to_string())),
+    }
+}
+
+fn verify_header(config: &HashServerConfig, params: &Value) -> Reply {
+    let header = hex_param(config, param(params, 0, "hex"), "hex")?;
+    let header = BlockHeader::parse(&header).map_err(|err| invalid_params(&err.to_string()))?;
+    let bits = match param(params, 1, "bits") {
+        Value::Null => header.bits,
+        Value::String(bits) => {
+            u32::from_str_radix(bits, 16).map_err(|_| invalid_params("malformed bits"))?
+        }
+        bits => bits
+            .as_u64()
+            .and_then(|bits| u32::try_from(bits).ok())
+            .ok_or_else(|| invalid_params("malformed bits"))?,
+    };
+    let algorithm = match param(params, 2, "algorithm") {
+        Value::Null => Algorithm::Lyra2REv2,
+        algorithm => algorithm_param(algorithm)?,
+    };
+    let target = Target::from_compact(bits).map_err(|err| invalid_params(&err.to_string()))?;
+    let pow_hash = header.pow_hash(algorithm);
+    Ok(json!({
+        "valid": target.is_met_by(pow_hash.as_bytes()),
+        "pow_hash": pow_hash.to_string(),
+        "block_hash": header.block_hash().to_string(),
+        "target": to_hex(&target.to_be_bytes()),
+    }))
+}
+
+fn lyra2(config: &HashServerConfig, params: &Value, matrix: &mut Matrix) -> Reply {
+    let costs = param(params, 0, "params");
+    let cost = |index: usize, name: &str| {
+        param(costs, index, name)
+            .as_u64()
+            .ok_or_else(|| invalid_params(&format!("missing or malformed {}", name)))
+    };
+    let klen = cost(0, "klen")?;
+    let time_cost = cost(1, "t_cost")?;
+    let rows = cost(2, "rows")?;
+    let cols = cost(3, "cols")?;
+    let pwd = hex_param(config, param(params, 1, "pwd"), "pwd")?;
+    let salt = hex_param(config, param(params, 2, "salt"), "salt")?;
+    if !(1..=MAX_LYRA2_KLEN).contains(&klen) {
+        return Err(invalid_params(&format!(
+            "klen must be 1 to {}",
+            MAX_LYRA2_KLEN
+        )));
+    }
+    if !(1..=config.max_time_cost).contains(&time_cost) {
+        return Err(invalid_params(&format!(
+            "t_cost must be 1 to {}",
+            config.max_time_cost
+        )));
+    }
+    if rows < 3 || cols < 1 {
+        return Err(invalid_params(
+            "rows must be at least 3 and cols at least 1",
+        ));
+    }
+    let memory = rows
+        .checked_mul(cols)
+        .and_then(|cells| cells.checked_mul(LYRA2_COLUMN_SIZE))
+        .filter(|&memory| memory <= config.max_lyra2_memory)
+        .ok_or_else(|| {
+            invalid_params(&format!(
+                "rows * cols exceeds {} bytes of memory",
+                config.max_lyra2_memory
+            ))
+        })?;
+    // The padded password, salt and parameters are laid out in the matrix first.
+    let input_blocks = (pwd.len() + salt.len() + 48) as u64 / 64 + 1;
+    if input_blocks * 64 > memory {
+        return Err(invalid_params("pwd and salt do not fit the matrix"));
+    }
+    let key = lyra2_with_matrix(matrix, klen, &pwd, &salt, time_cost, rows, cols);
+    Ok(json!(to_hex(&key)))
+}
+
+// param returns the param at `index` of positional params or `name` of named ones.
+fn param<'a>(params: &'a Value, index: usize, name: &str) -> &'a Value {
+    match params {
+        Value::Array(params) => params.get(index).unwrap_or(&Value::Null),
+        Value::Object(params) => params.get(name).unwrap_or(&Value::Null),
+        _ => &Value::Null,
+    }
+}
+
+fn hex_param(
+    config: &HashServerConfig,
+    value: &Value,
+    name: &str,
+) -> Result<Vec<u8>, (i64, String)> {
+    let hex = value
+        .as_str()
+        .ok_or_else(|| invalid_params(&format!("missing {}", name)))?;
+    if hex.len() > 2 * config.max_param {
+        return Err(invalid_params(&format!(
+            "{} exceeds {} bytes",
+            name, config.max_param
+        )));
+    }
+    from_hex(hex).ok_or_else(|| invalid_params(&format!("malformed {}", name)))
+}
+
+fn algorithm_param(value: &Value) -> Result<Algorithm, (i64, String)> {
+    value
+        .as_str()
+        .ok_or_else(|| invalid_params("missing algorithm"))?
+        .parse()
+        .map_err(|err: crate::algorithm::ParseAlgorithmError| invalid_params(&err.to_string()))
+}
+
+fn invalid_params(message: &str) -> (i64, String) {
+    (-32602, message.to_string())
+}
+
+#[test]
+fn hashd_cal() {
+    use crate::rpc::{RpcCallError, RpcClient};
+    use crate::scan::Scanner;
+    use std::io::{Read, Write};
+
+    let config = HashServerConfig {
+        workers: 1,
+        queue: 1,
+        max_body: 1024,
+        max_param: 128,
+        max_lyra2_memory: 96 * 64,
+        ..HashServerConfig::new()
+    };
+    let server = HashServer::new(config).listen("127.0.0.1:0").unwrap();
+    let client = RpcClient::new(&server.local_addr().to_string());
+    let rpc_error = |result: Result<Value, RpcCallError>| match result {
+        Err(RpcCallError::Rpc { code, .. }) => code,
+        result => panic!("unexpected result {:?}", result),
+    };
+
+    // The vectors of the README.
+    let data = to_hex("脇山珠美ちゃんかわいい！".as_bytes());
+    assert_eq!(
+        client.call("hash", json!(["lyra2rev2", data])).unwrap(),
+        "bdaaa569c4f4918da66b02f2d0a2093a51e3d1735ee6023e9a93185c7bff40bc"
+    );
+    let header = "700000005d385ba114d079971b29a9418fd0549e7d68a95c7f168621a314201000000000578586d149fd07b22f3a8a347c516de7052f034d2b76ff68e0d6ecff9b77a45489e3fd511732011df0731000";
+    assert_eq!(
+        client
+            .call("hash", json!({"algorithm": "Lyra2REv3", "hex": header}))
+            .unwrap(),
+        "5d7b298258e78881c7831ba1e46751b089efdf1fdb9eb01edd03b8d7ed39eafb"
+    );
+    let params = json!({"klen": 32, "t_cost": 1, "rows": 4, "cols": 4});
+    assert_eq!(
+        client
+            .call("lyra2", json!([params, to_hex(b"abc"), to_hex(b"abc")]))
+            .unwrap(),
+        "8f63758bd178f014ea3fd4df09ff0a61646dc574a0b6bcf2890ec529a6a7360c"
+    );
+    assert_eq!(
+        client
+            .call(
+                "lyra2",
+                json!([[32, 1, 4, 4], to_hex(b"abc"), to_hex(b"abc")])
+            )
+            .unwrap(),
+        "8f63758bd178f014ea3fd4df09ff0a61646dc574a0b6bcf2890ec529a6a7360c"
+    );
+
+    // A regtest header mined here is valid at its own bits and not at a mainnet target.
+    let mut header = BlockHeader {
+        version: 0x20000000,
+        prev_block: crate::header::Hash256([0x11; 32]),
+        merkle_root: crate::header::Hash256([0x22; 32]),
+        time: 1_600_000_000,
+        bits: 0x207fffff,
+        nonce: 0,
+    };
+    let target = header.target().unwrap();
+    header.nonce = Scanner::new().scan(&header, 0..=63, &target, Algorithm::Lyra2REv3, 1)[0].nonce;
+    let verified = client
+        .call("verify_header", json!([header.to_hex(), null, "lyra2rev3"]))
+        .unwrap();
+    assert_eq!(verified["valid"], true);
+    assert_eq!(
+        verified["pow_hash"],
+        header.pow_hash(Algorithm::Lyra2REv3).to_string()
+    );
+    assert_eq!(verified["block_hash"], header.block_hash().to_string());
+    let verified = client
+        .call(
+            "verify_header",
+            json!({"hex": header.to_hex(), "bits": "1d00ffff", "algorithm": "lyra2rev3"}),
+        )
+        .unwrap();
+    assert_eq!(verified["valid"], false);
+    assert_eq!(
+        verified["target"],
+        "00000000ffff0000000000000000000000000000000000000000000000000000"
+    );
+
+    // Bad and oversized params are RPC errors.
+    let cases = [
+        ("nosuch", json!([]), -32601),
+        ("hash", json!(["sha256", "00"]), -32602),
+        ("hash", json!(["lyra2rev2", "0"]), -32602),
+        ("hash", json!(["lyra2rev2", "00".repeat(129)]), -32602),
+        ("verify_header", json!(["00"]), -32602),
+        ("verify_header", json!([header.to_hex(), "zz"]), -32602),
+        ("lyra2", json!([[32, 1, 2, 4], "", ""]), -32602),
+        ("lyra2", json!([[32, 0, 4, 4], "", ""]), -32602),
+        ("lyra2", json!([[97, 1, 4, 4], "", ""]), -32602),
+        ("lyra2", json!([[32, 1, 8, 16], "", ""]), -32602),
+        (
+            "lyra2",
+            json!([[32, 1, 3, 1], "00".repeat(128), "00".repeat(128)]),
+            -32602,
+        ),
+        ("lyra2", json!([{"klen": 32}, "", ""]), -32602),
+    ];
+    for (method, params, code) in cases {
+        assert_eq!(rpc_error(client.call(method, params)), code, "{}", method);
+    }
+
+    // Bodies over the limit are refused before they are read.
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\nContent-Length: 2048\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+
+    // So are headers over 16 KiB.
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    let head = "POST / HTTP/1.1\r\nX-Padding: ";
+    write!(stream, "{}{}", head, "a".repeat(16 * 1024 - head.len())).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 431 "), "{}", response);
+
+    let call = |stream: &mut TcpStream| {
+        let body = json!({"id": 1, "method": "hash", "params": ["lyra2rev2", "00"]}).to_string();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        io::BufRead::read_line(&mut reader, &mut line).unwrap();
+        line
+    };
+    let closed = |stream: &mut TcpStream| {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).is_ok()
+    };
+
+    // With the only worker waiting for a first request and the queue full, a third connection
+    // is turned away.
+    let mut first = TcpStream::connect(server.local_addr()).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let mut queued = TcpStream::connect(server.local_addr()).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let mut busy = TcpStream::connect(server.local_addr()).unwrap();
+    let mut response = String::new();
+    busy.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 503 "), "{}", response);
+
+    // A keep-alive connection is closed after its response while another one waits.
+    assert!(call(&mut first).starts_with("HTTP/1.1 200 "));
+    assert!(closed(&mut first));
+    assert!(call(&mut queued).starts_with("HTTP/1.1 200 "));
+
+    // An idle keep-alive connection gives the worker up to a new one.
+    let mut late = TcpStream::connect(server.local_addr()).unwrap();
+    assert!(call(&mut late).starts_with("HTTP/1.1 200 "));
+    assert!(closed(&mut queued));
+}
@@ -0,0 +1,104 @@
+// pow_hasher defines a `digest` hasher for a chained PoW algorithm whose first stage is
+// Blake-256. Input is streamed into the Blake-256 state and `$finish` runs the remaining
+// stages over the Blake-256 output at finalization.
+macro_rules! pow_hasher {
+    ($name:ident, $alg:expr, $finish:path) => {
+        #[doc = concat!(
+            $alg,
+            " hasher implementing the `digest` traits.\n\n",
+            "Input is streamed into the Blake-256 state; the rest of the chain runs at finalization."
+        )]
+        #[derive(Clone, Default)]
+        pub struct $name {
+            blake: crate::blake256::Blake256,
+        }
+
+        impl core::fmt::Debug for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.write_str(concat!(stringify!($name), " { ... }"))
+            }
+        }
+
+        impl digest::HashMarker for $name {}
+
+        impl digest::OutputSizeUser for $name {
+            type OutputSize = digest::typenum::U32;
+        }
+
+        impl digest::Update for $name {
+            fn update(&mut self, data: &[u8]) {
+                digest::Update::update(&mut self.blake, data);
+            }
+        }
+
+        impl digest::FixedOutput for $name {
+            fn finalize_into(self, out: &mut digest::Output<Self>) {
+                let result_blake = digest::FixedOutput::finalize_fixed(self.blake);
+                out.copy_from_slice(&$finish(result_blake.to_vec()));
+            }
+        }
+
+        impl digest::FixedOutputReset for $name {
+            fn finalize_into_reset(&mut self, out: &mut digest::Output<Self>) {
+                let result_blake = digest::FixedOutputReset::finalize_fixed_reset(&mut self.blake);
+                out.copy_from_slice(&$finish(result_blake.to_vec()));
+            }
+        }
+
+        impl digest::Reset for $name {
+            fn reset(&mut self) {
+                digest::Reset::reset(&mut self.blake);
+            }
+        }
+    };
+}
+
+pub(crate) use pow_hasher;
+
+#[test]
+fn pow_hasher_cal() {
+    use digest::Digest;
+
+    fn streamed<D: Digest>(input: &[u8]) -> Vec<u8> {
+        let mut hasher = D::new();
+        for chunk in input.chunks(7) {
+            hasher.update(chunk);
+        }
+        hasher.finalize().to_vec()
+    }
+
+    let base = "脇山珠美ちゃんかわいい！".as_bytes().to_vec();
+    assert_eq!(
+        streamed::<crate::lyra2re::Lyra2RE>(&base),
+        crate::lyra2re::sum(base.clone())
+    );
+    assert_eq!(
+        streamed::<crate::lyra2rev2::Lyra2REv2>(&base),
+        crate::lyra2rev2::sum(base.clone())
+    );
+    assert_eq!(
+        streamed::<crate::lyra2rev3::Lyra2REv3>(&base),
+        crate::lyra2rev3::sum(base.clone())
+    );
+    assert_eq!(
+        streamed::<crate::lyra2z::Lyra2Z>(&base),
+        crate::lyra2z::sum(base.clone())
+    );
+
+    let mut hasher = crate::lyra2rev2::Lyra2REv2::new();
+    hasher.update(b"garbage");
+    hasher.reset();
+    hasher.update(b"abc");
+    assert_eq!(
+        "80ec5344227c5d0bfd63038f00c3fe5aecddd1a1122043b0a90b5fd67b1e8f32",
+        hasher
+            .finalize_reset()
+            .iter()
+            .map(|n| format!("{:02x}", n))
+            .collect::<String>()
+    );
+    assert_eq!(
+        hasher.finalize().to_vec(),
+        crate::lyra2rev2::sum(Vec::new())
+    );
+}
@@ -0,0 +1,268 @@
+//! # header
+//!
+//! `header` crate has the 80-byte block header and its PoW and block hashes.
+use crate::algorithm::Algorithm;
+use crate::blake256::Midstate;
+use crate::target::{Target, TargetError};
+use crate::utils::{from_hex, read_u32_le, to_hex};
+use sha2::{Digest, Sha256};
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// A 32-byte hash in internal (serialized) byte order.
+///
+/// It is displayed and parsed in the reversed byte order used by block explorers and nodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Hash256(pub [u8; 32]);
+
+impl Hash256 {
+    /// Returns the hash in internal byte order.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Returns `sha256(sha256(data))`.
+    pub fn sha256d(data: &[u8]) -> Hash256 {
+        Hash256(Sha256::digest(Sha256::digest(data)).into())
+    }
+
+    /// Returns the hash from a byte slice in internal byte order.
+    pub fn from_slice(data: &[u8]) -> Result<Hash256, ParseHeaderError> {
+        <[u8; 32]>::try_from(data)
+            .map(Hash256)
+            .map_err(|_| ParseHeaderError::InvalidLength(data.len()))
+    }
+}
+
+impl fmt::Display for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut reversed = self.0;
+        reversed.reverse();
+        f.write_str(&to_hex(&reversed))
+    }
+}
+
+impl FromStr for Hash256 {
+    type Err = ParseHeaderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = from_hex(s).ok_or(ParseHeaderError::InvalidHex)?;
+        bytes.reverse();
+        Hash256::from_slice(&bytes)
+    }
+}
+
+/// An 80-byte block header as used by Monacoin, Vertcoin and other Bitcoin-derived chains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockHeader {
+    pub version: i32,
+    pub prev_block: Hash256,
+    pub merkle_root: Hash256,
+    pub time: u32,
+    pub bits: u32,
+    pub nonce: u32,
+}
+
+impl BlockHeader {
+    /// The length of a serialized header.
+    pub const SIZE: usize = 80;
+
+    /// Returns the consensus serialization of the header.
+    pub fn serialize(&self) -> [u8; 80] {
+        let mut out = [0u8; 80];
+        out[0..4].copy_from_slice(&self.version.to_le_bytes());
+        out[4..36].copy_from_slice(&self.prev_block.0);
+        out[36..68].copy_from_slice(&self.merkle_root.0);
+        out[68..72].copy_from_slice(&self.time.to_le_bytes());
+        out[72..76].copy_from_slice(&self.bits.to_le_bytes());
+        out[76..80].copy_from_slice(&self.nonce.to_le_bytes());
+        out
+    }
+
+    /// Parses a consensus-serialized header. `data` must be exactly 80 bytes.
+    pub fn parse(data: &[u8]) -> Result<BlockHeader, ParseHeaderError> {
+        if data.len() != Self::SIZE {
+            return Err(ParseHeaderError::InvalidLength(data.len()));
+        }
+        Ok(BlockHeader {
+            version: read_u32_le(&data[0..4]) as i32,
+            prev_block: Hash256::from_slice(&data[4..36])?,
+            merkle_root: Hash256::from_slice(&data[36..68])?,
+            time: read_u32_le(&data[68..72]),
+            bits: read_u32_le(&data[72..76]),
+            nonce: read_u32_le(&data[76..80]),
+        })
+    }
+
+    /// Parses a hex-encoded consensus-serialized header.
+    pub fn from_hex(hex: &str) -> Result<BlockHeader, ParseHeaderError> {
+        let data = from_hex(hex.trim()).ok_or(ParseHeaderError::InvalidHex)?;
+        Self::parse(&data)
+    }
+
+    /// Returns the hex encoding of the consensus serialization.
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.serialize())
+    }
+
+    /// Returns the PoW hash of the header, i.e. `algorithm` applied to the serialized header.
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::algorithm::Algorithm;
+    /// use lyra2::header::BlockHeader;
+    ///
+    /// // The Lyra2REv3 vector of the README, whose hash is displayed byte-reversed.
+    /// let header = BlockHeader::from_hex("700000005d385ba114d079971b29a9418fd0549e7d68a95c7f168621a314201000000000578586d149fd07b22f3a8a347c516de7052f034d2b76ff68e0d6ecff9b77a45489e3fd511732011df0731000").unwrap();
+    /// assert_eq!(
+    ///     header.pow_hash(Algorithm::Lyra2REv3).to_string(),
+    ///     "fbea39edd7b803dd1eb09edb1fdfef89b05167e4a11b83c78188e75882297b5d"
+    /// );
+    /// ```
+    pub fn pow_hash(&self, algorithm: Algorithm) -> Hash256 {
+        let mut out = [0u8; 32];
+        out.copy_from_slice(&algorithm.sum(self.serialize().to_vec()));
+        Hash256(out)
+    }
+
+    /// Returns the target encoded in `bits`.
+    pub fn target(&self) -> Result<Target, TargetError> {
+        Target::from_compact(self.bits)
+    }
+
+    /// Returns true if the PoW hash under `algorithm` meets the target encoded in `bits`.
+    /// Headers whose `bits` do not decode never meet it.
+    pub fn meets_target(&self, algorithm: Algorithm) -> bool {
+        match self.target() {
+            Ok(target) => target.is_met_by(self.pow_hash(algorithm).as_bytes()),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the Blake-256 midstate of the first 64 bytes, which do not depend on `time`,
+    /// `bits` or `nonce`.
+    pub fn midstate(&self) -> Midstate {
+        let data = self.serialize();
+        let mut prefix = [0u8; 64];
+        prefix.copy_from_slice(&data[..64]);
+        Midstate::new(&prefix)
+    }
+
+    /// Returns the last 16 bytes of the serialization: the tail of the merkle root, `time`,
+    /// `bits` and `nonce`.
+    pub fn tail(&self) -> [u8; 16] {
+        let data = self.serialize();
+        let mut tail = [0u8; 16];
+        tail.copy_from_slice(&data[64..]);
+        tail
+    }
+
+    /// Returns the double-SHA256 block identifier.
+    pub fn block_hash(&self) -> Hash256 {
+        Hash256::sha256d(&self.serialize())
+    }
+}
+
+impl FromStr for BlockHeader {
+    type Err = ParseHeaderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BlockHeader::from_hex(s)
+    }
+}
+
+/// An error returned when parsing a [`BlockHeader`] or [`Hash256`] fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseHeaderError {
+    /// The input has the wrong number of bytes.
+    InvalidLength(usize),
+    /// The input is not valid hex.
+    InvalidHex,
+}
+
+impl fmt::Display for ParseHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseHeaderError::InvalidLength(len) => write!(f, "invalid length: {} bytes", len),
+            ParseHeaderError::InvalidHex => f.write_str("invalid hex string"),
+        }
+    }
+}
+
+impl error::Error for ParseHeaderError {}
+
+#[test]
+fn block_header_cal() {
+    // Monacoin mainnet genesis block.
+    let mona = BlockHeader {
+        version: 1,
+        prev_block: Hash256::default(),
+        merkle_root: "35e405a8a46f4dbc1941727aaf338939323c3b955232d0317f8731fe07ac4ba6"
+            .parse()
+            .unwrap(),
+        time: 1388479472,
+        bits: 0x1e0ffff0,
+        nonce: 1234534,
+    };
+    assert_eq!(
+        mona.to_hex(),
+        "010000000000000000000000000000000000000000000000000000000000000000000000a64bac07fe31877f31d03252953b3c32398933af7a724119bc4d6fa4a805e435f083c252f0ff0f1e66d61200"
+    );
+    assert_eq!(
+        mona.block_hash().to_string(),
+        "ff9f1c0116d19de7c9963845e129f9ed1bfc0b376eb54fd7afa42e0d418c8bb6"
+    );
+    assert_eq!(BlockHeader::from_hex(&mona.to_hex()), Ok(mona));
+
+    // Vertcoin mainnet genesis block.
+    let vtc: BlockHeader = "010000000000000000000000000000000000000000000000000000000000000000000000e72301fc49323ee151cf1048230f032ca589753ba7086222a5c023e3a08cf34a8b35cf52f0ff0f1e0eba5700"
+        .parse()
+        .unwrap();
+    assert_eq!(vtc.version, 1);
+    assert_eq!(
+        vtc.merkle_root.to_string(),
+        "4af38ca0e323c0a5226208a73b7589a52c030f234810cf51e13e3249fc0123e7"
+    );
+    assert_eq!(vtc.time, 1389311371);
+    assert_eq!(vtc.bits, 0x1e0ffff0);
+    assert_eq!(vtc.nonce, 5749262);
+    assert_eq!(
+        vtc.block_hash().to_string(),
+        "4d96a915f49d40b1e5c2844d1ee2dccb90013a990ccea12c492d22110489f0c4"
+    );
+    // Both genesis blocks predate Lyra2 and were mined with scrypt.
+    assert_eq!(
+        vtc.target().unwrap().difficulty(&Target::DIFF1_NODE),
+        0.000244140625
+    );
+    assert!(!vtc.meets_target(Algorithm::Lyra2REv3));
+    assert!(!mona.meets_target(Algorithm::Lyra2REv2));
+
+    // The Lyra2REv3 header vector of the upstream README, displayed byte-reversed.
+    let header: BlockHeader = "700000005d385ba114d079971b29a9418fd0549e7d68a95c7f168621a314201000000000578586d149fd07b22f3a8a347c516de7052f034d2b76ff68e0d6ecff9b77a45489e3fd511732011df0731000"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        header.pow_hash(Algorithm::Lyra2REv3).to_string(),
+        "fbea39edd7b803dd1eb09edb1fdfef89b05167e4a11b83c78188e75882297b5d"
+    );
+    assert!(!header.meets_target(Algorithm::Lyra2REv3));
+
+    let midstate = vtc.midstate();
+    for algorithm in Algorithm::ALL.iter() {
+        assert_eq!(
+            algorithm.sum_midstate(&midstate, &vtc.tail()),
+            vtc.pow_hash(*algorithm).0.to_vec()
+        );
+    }
+
+    assert_eq!(
+        BlockHeader::parse(&[0u8; 79]),
+        Err(ParseHeaderError::InvalidLength(79))
+    );
+    assert_eq!(
+        BlockHeader::from_hex("zz"),
+        Err(ParseHeaderError::InvalidHex)
+    );
+}
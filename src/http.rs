@@ -0,0 +1,256 @@
+// Just enough HTTP/1.1 for the JSON-RPC endpoints of this crate: requests with a
+// Content-Length body, responses with a JSON body, and basic auth.
+use std::error;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+
+// The most bytes of request line and headers read_request reads.
+const MAX_HEAD: u64 = 16 * 1024;
+
+pub(crate) struct Request {
+    pub method: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    // header returns the value of the header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // keep_alive returns whether the client wants the connection kept open after the
+    // response, the HTTP/1.1 default.
+    pub fn keep_alive(&self) -> bool {
+        !self
+            .header("connection")
+            .is_some_and(|value| value.eq_ignore_ascii_case("close"))
+    }
+
+    // basic_auth returns the user and password of basic auth, if any.
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        let credentials = self.header("authorization")?.strip_prefix("Basic ")?;
+        let credentials = String::from_utf8(base64_decode(credentials.trim())?).ok()?;
+        let (user, password) = credentials.split_once(':')?;
+        Some((user.to_string(), password.to_string()))
+    }
+}
+
+// read_request reads the next request of a connection, or None at its end. A request line
+// and headers over MAX_HEAD bytes, or a body over `max_body` bytes, are refused with
+// InvalidData.
+pub(crate) fn read_request<R: BufRead>(
+    reader: &mut R,
+    max_body: usize,
+) -> io::Result<Option<Request>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let mut head = (&mut *reader).take(MAX_HEAD);
+    let mut read_line = |line: &mut String| {
+        let n = head.read_line(line)?;
+        if head.limit() == 0 && !line.ends_with('\n') {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, TooLarge::Head));
+        }
+        Ok(n)
+    };
+    let mut line = String::new();
+    if read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let method = line
+        .split(' ')
+        .next()
+        .filter(|method| !method.trim().is_empty())
+        .ok_or_else(|| invalid("malformed request line"))?
+        .to_string();
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if read_line(&mut line)? == 0 {
+            return Err(invalid("truncated headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| invalid("malformed header"))?;
+        headers.push((name.to_string(), value.trim().to_string()));
+        if headers.len() > 100 {
+            return Err(invalid("too many headers"));
+        }
+    }
+    let mut request = Request {
+        method,
+        headers,
+        body: Vec::new(),
+    };
+    let len = match request.header("content-length") {
+        Some(len) => len
+            .parse()
+            .map_err(|_| invalid("malformed Content-Length"))?,
+        None => 0,
+    };
+    if len > max_body {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, TooLarge::Body));
+    }
+    request.body = vec![0; len];
+    reader.read_exact(&mut request.body)?;
+    Ok(Some(request))
+}
+
+// TooLarge is the error of read_request for a request over a limit.
+#[derive(Debug)]
+enum TooLarge {
+    Head,
+    Body,
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TooLarge::Head => f.write_str("request headers too large"),
+            TooLarge::Body => f.write_str("request body too large"),
+        }
+    }
+}
+
+impl error::Error for TooLarge {}
+
+// too_large_status returns the response status of read_request refusing a request over a
+// limit: 431 for the head and 413 for the body, or None for other errors.
+pub(crate) fn too_large_status(err: &io::Error) -> Option<u16> {
+    match err.get_ref()?.downcast_ref::<TooLarge>()? {
+        TooLarge::Head => Some(431),
+        TooLarge::Body => Some(413),
+    }
+}
+
+// write_response writes a response with a JSON body.
+pub(crate) fn write_response<W: Write>(
+    writer: &mut W,
+    status: u16,
+    body: &str,
+    keep_alive: bool,
+) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        if keep_alive { "keep-alive" } else { "close" },
+        body
+    )?;
+    writer.flush()
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// base64 encodes `data` with the standard alphabet and padding, for basic auth.
+pub(crate) fn base64(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &byte)| n | u32::from(byte) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// base64_decode is the inverse of base64.
+pub(crate) fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.as_bytes();
+    if encoded.len() % 4 != 0 {
+        return None;
+    }
+    let mut decoded = Vec::new();
+    for chunk in encoded.chunks(4) {
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+        let mut n = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let value = BASE64.iter().position(|&b| b == c)? as u32;
+            n = n << 6 | value;
+        }
+        n <<= 6 * padding as u32;
+        decoded.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(decoded)
+}
+
+#[test]
+fn http_cal() {
+    assert_eq!(base64(b"user:pass"), "dXNlcjpwYXNz");
+    assert_eq!(base64(b"ab"), "YWI=");
+    assert_eq!(base64(b"a"), "YQ==");
+    for data in [&b""[..], b"a", b"ab", b"abc", b"rpcuser:p@ss:word"] {
+        assert_eq!(base64_decode(&base64(data)).as_deref(), Some(data));
+    }
+    assert_eq!(base64_decode("YQ="), None);
+    assert_eq!(base64_decode("Y==="), None);
+
+    let raw = b"POST / HTTP/1.1\r\nHost: localhost\r\nauthorization: Basic dXNlcjpwYXNz\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}POST / HTTP/1.1\r\n";
+    let mut reader = io::BufReader::new(&raw[..]);
+    let request = read_request(&mut reader, 1024).unwrap().unwrap();
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.body, b"{}");
+    assert_eq!(request.header("Host"), Some("localhost"));
+    assert_eq!(
+        request.basic_auth(),
+        Some(("user".to_string(), "pass".to_string()))
+    );
+    assert!(!request.keep_alive());
+    assert!(read_request(&mut reader, 1024).is_err());
+    let err = read_request(&mut io::BufReader::new(&raw[..]), 1)
+        .err()
+        .unwrap();
+    assert_eq!(too_large_status(&err), Some(413));
+    assert_eq!(
+        too_large_status(&io::Error::from(io::ErrorKind::InvalidData)),
+        None
+    );
+    let long = format!(
+        "POST / HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+        "a".repeat(MAX_HEAD as usize)
+    );
+    let err = read_request(&mut io::BufReader::new(long.as_bytes()), 1024)
+        .err()
+        .unwrap();
+    assert_eq!(too_large_status(&err), Some(431));
+    assert!(read_request(&mut io::BufReader::new(&b""[..]), 1)
+        .unwrap()
+        .is_none());
+
+    let mut response = Vec::new();
+    write_response(&mut response, 200, "{}", true).unwrap();
+    assert_eq!(
+        response,
+        b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: keep-alive\r\n\r\n{}"
+    );
+}
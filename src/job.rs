@@ -0,0 +1,310 @@
+//! # job
+//!
+//! `job` crate turns a pool job into block headers: it assembles the coinbase transaction from
+//! the job and the extranonces, folds its hash through the merkle branch and fills in the
+//! 80-byte header that the PoW algorithms hash. A [`Template`] is the pool side: it makes jobs
+//! and turns solved ones back into blocks.
+use crate::block::{self, Block, Transaction, TxIn, TxOut};
+use crate::header::{BlockHeader, Hash256};
+use crate::merkle;
+
+/// A mining job: everything of a block header but the merkle root, which depends on the
+/// extranonces placed in the coinbase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub job_id: String,
+    /// The previous block hash in internal byte order.
+    pub prev_hash: Hash256,
+    pub coinb1: Vec<u8>,
+    pub coinb2: Vec<u8>,
+    /// Merkle branch hashes in internal byte order, from the coinbase up.
+    pub merkle_branch: Vec<Hash256>,
+    pub version: i32,
+    pub bits: u32,
+    pub time: u32,
+    /// Whether earlier jobs must be abandoned.
+    pub clean_jobs: bool,
+}
+
+impl Job {
+    /// Returns the coinbase transaction `coinb1 || extranonce1 || extranonce2 || coinb2`.
+    pub fn coinbase(&self, extranonce1: &[u8], extranonce2: &[u8]) -> Vec<u8> {
+        [
+            self.coinb1.as_slice(),
+            extranonce1,
+            extranonce2,
+            self.coinb2.as_slice(),
+        ]
+        .concat()
+    }
+
+    /// Returns the merkle root for the given extranonces, folding the coinbase hash with the
+    /// merkle branch.
+    pub fn merkle_root(&self, extranonce1: &[u8], extranonce2: &[u8]) -> Hash256 {
+        merkle::fold_branch(
+            Hash256::sha256d(&self.coinbase(extranonce1, extranonce2)),
+            &self.merkle_branch,
+        )
+    }
+
+    /// Returns the block header for the given extranonces, `time` and `nonce`.
+    pub fn header(
+        &self,
+        extranonce1: &[u8],
+        extranonce2: &[u8],
+        time: u32,
+        nonce: u32,
+    ) -> BlockHeader {
+        BlockHeader {
+            version: self.version,
+            prev_block: self.prev_hash,
+            merkle_root: self.merkle_root(extranonce1, extranonce2),
+            time,
+            bits: self.bits,
+            nonce,
+        }
+    }
+}
+
+/// The contents of the next block but for the extranonces, time and nonce, e.g. from
+/// `getblocktemplate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    /// The previous block hash in internal byte order.
+    pub prev_hash: Hash256,
+    /// The height of the block, pushed first in the coinbase script (BIP 34).
+    pub height: u32,
+    pub version: i32,
+    pub bits: u32,
+    /// The earliest time of the block.
+    pub time: u32,
+    /// The value of the coinbase output: the subsidy and the fees.
+    pub coinbase_value: u64,
+    /// The script the coinbase pays to.
+    pub payout_script: Vec<u8>,
+    /// Bytes pushed after the extranonces in the coinbase script, e.g. a pool tag.
+    pub coinbase_tag: Vec<u8>,
+    /// The transactions after the coinbase.
+    pub transactions: Vec<Transaction>,
+}
+
+impl Template {
+    /// Returns the coinbase with `extranonce` in its script. When a transaction has witnesses,
+    /// the coinbase gets a zero witness reserved value and the witness commitment output.
+    pub fn coinbase(&self, extranonce: &[u8]) -> Transaction {
+        let mut script_sig = Vec::new();
+        block::push_int(&mut script_sig, i64::from(self.height));
+        block::push_data(&mut script_sig, extranonce);
+        if !self.coinbase_tag.is_empty() {
+            block::push_data(&mut script_sig, &self.coinbase_tag);
+        }
+        let mut outputs = vec![TxOut {
+            value: self.coinbase_value,
+            script_pubkey: self.payout_script.clone(),
+        }];
+        let segwit = self.transactions.iter().any(Transaction::has_witness);
+        if segwit {
+            // The coinbase wtxid counts as zero, so the commitment does not depend on it.
+            let wtxids: Vec<Hash256> = [Hash256::default()]
+                .into_iter()
+                .chain(self.transactions.iter().map(Transaction::wtxid))
+                .collect();
+            outputs.push(TxOut {
+                value: 0,
+                script_pubkey: block::commitment_script(&merkle::merkle_root(&wtxids), &[0; 32]),
+            });
+        }
+        Transaction {
+            version: 1,
+            inputs: vec![TxIn {
+                prev_txid: Hash256::default(),
+                prev_index: u32::MAX,
+                script_sig,
+                sequence: u32::MAX,
+                witness: if segwit {
+                    vec![vec![0; 32]]
+                } else {
+                    Vec::new()
+                },
+            }],
+            outputs,
+            lock_time: 0,
+        }
+    }
+
+    /// Returns a job whose coinbase has room for `extranonce_size` bytes of extranonce1 and
+    /// extranonce2.
+    pub fn job(&self, job_id: &str, extranonce_size: usize, clean_jobs: bool) -> Job {
+        let coinbase = self
+            .coinbase(&vec![0; extranonce_size])
+            .serialize_no_witness();
+        // version, input count, outpoint, script length, height push and extranonce opcode,
+        // which takes more than one byte past 75 bytes of extranonce.
+        let mut height = Vec::new();
+        block::push_int(&mut height, i64::from(self.height));
+        let mut extranonce = Vec::new();
+        block::push_data(&mut extranonce, &vec![0; extranonce_size]);
+        let opcode_len = extranonce.len() - extranonce_size;
+        let script_len = coinbase[41] as usize;
+        let offset = 41 + if script_len < 0xfd { 1 } else { 3 } + height.len() + opcode_len;
+        let txids: Vec<Hash256> = [Hash256::default()]
+            .into_iter()
+            .chain(self.transactions.iter().map(Transaction::txid))
+            .collect();
+        Job {
+            job_id: job_id.to_string(),
+            prev_hash: self.prev_hash,
+            coinb1: coinbase[..offset].to_vec(),
+            coinb2: coinbase[offset + extranonce_size..].to_vec(),
+            merkle_branch: merkle::merkle_branch(&txids),
+            version: self.version,
+            bits: self.bits,
+            time: self.time,
+            clean_jobs,
+        }
+    }
+
+    /// Returns the block solved with the given extranonces, `time` and `nonce`.
+    pub fn block(&self, extranonce1: &[u8], extranonce2: &[u8], time: u32, nonce: u32) -> Block {
+        let coinbase = self.coinbase(&[extranonce1, extranonce2].concat());
+        let transactions: Vec<Transaction> = [coinbase]
+            .into_iter()
+            .chain(self.transactions.iter().cloned())
+            .collect();
+        let txids: Vec<Hash256> = transactions.iter().map(Transaction::txid).collect();
+        Block {
+            header: BlockHeader {
+                version: self.version,
+                prev_block: self.prev_hash,
+                merkle_root: merkle::merkle_root(&txids),
+                time,
+                bits: self.bits,
+                nonce,
+            },
+            transactions,
+        }
+    }
+}
+
+/// Reverses the bytes of every 4-byte word, converting a previous block hash between the
+/// stratum wire encoding and internal byte order. The conversion is its own inverse.
+pub fn swap_words(bytes: &mut [u8]) {
+    for word in bytes.chunks_exact_mut(4) {
+        word.reverse();
+    }
+}
+
+#[test]
+fn job_cal() {
+    use crate::algorithm::Algorithm;
+    use crate::target::Target;
+    use crate::utils::{from_hex, to_hex};
+
+    // The Monacoin genesis coinbase, split around eight bytes of its scriptSig as a pool would
+    // split it around the extranonces.
+    let coinbase = from_hex("01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff5f04ffff001d01044c564465632e20333174682032303133204a6170616e2c205468652077696e6e696e67206e756d62657273206f6620746865203230313320596561722d456e64204a756d626f204c6f74746572793a32332d313330393136ffffffff0100f2052a010000004341040184710fa689ad5023690c80f3a49c8f13f8d45b8c857fbcbc8bc4a8e4d3eb4b10f4d4604fa08dce601aaf0f470216fe1b51850b4acf21b179c45070ac7b03a9ac00000000").unwrap();
+    let job = Job {
+        job_id: "0".to_string(),
+        prev_hash: Hash256::default(),
+        coinb1: coinbase[..47].to_vec(),
+        coinb2: coinbase[55..].to_vec(),
+        merkle_branch: Vec::new(),
+        version: 1,
+        bits: 0x1e0ffff0,
+        time: 1388479472,
+        clean_jobs: true,
+    };
+    let (extranonce1, extranonce2) = (&coinbase[47..51], &coinbase[51..55]);
+    assert_eq!(to_hex(extranonce1), "01044c56");
+    assert_eq!(job.coinbase(extranonce1, extranonce2), coinbase);
+    assert_eq!(
+        job.merkle_root(extranonce1, extranonce2).to_string(),
+        "35e405a8a46f4dbc1941727aaf338939323c3b955232d0317f8731fe07ac4ba6"
+    );
+    let header = job.header(extranonce1, extranonce2, job.time, 1234534);
+    assert_eq!(header.to_hex(), "010000000000000000000000000000000000000000000000000000000000000000000000a64bac07fe31877f31d03252953b3c32398933af7a724119bc4d6fa4a805e435f083c252f0ff0f1e66d61200");
+    assert_eq!(
+        header.block_hash().to_string(),
+        "ff9f1c0116d19de7c9963845e129f9ed1bfc0b376eb54fd7afa42e0d418c8bb6"
+    );
+    assert!(Target::MAX.is_met_by(header.pow_hash(Algorithm::Lyra2REv2).as_bytes()));
+
+    // With a branch, the coinbase hash is the left side of every pair.
+    let sibling = Hash256::sha256d(b"sibling");
+    let mut branched = job.clone();
+    branched.merkle_branch = vec![sibling];
+    assert_eq!(
+        branched.merkle_root(extranonce1, extranonce2),
+        merkle::merkle_root(&[Hash256::sha256d(&coinbase), sibling])
+    );
+
+    let mut prev_hash = from_hex("000102030405060708090a0b0c0d0e0f").unwrap();
+    swap_words(&mut prev_hash);
+    assert_eq!(to_hex(&prev_hash), "03020100070605040b0a09080f0e0d0c");
+}
+
+#[test]
+fn template_cal() {
+    use crate::algorithm::Algorithm;
+
+    let spend = |witness: Vec<Vec<u8>>, salt: u8| Transaction {
+        version: 2,
+        inputs: vec![TxIn {
+            prev_txid: Hash256::sha256d(&[salt]),
+            prev_index: 0,
+            script_sig: Vec::new(),
+            sequence: u32::MAX,
+            witness,
+        }],
+        outputs: vec![TxOut {
+            value: 1000,
+            script_pubkey: vec![0x51],
+        }],
+        lock_time: 0,
+    };
+    let mut template = Template {
+        prev_hash: Hash256::sha256d(b"tip"),
+        height: 1_000_000,
+        version: 0x20000000,
+        bits: 0x207fffff,
+        time: 1_600_000_000,
+        coinbase_value: 25 * 100_000_000,
+        payout_script: vec![0x51],
+        coinbase_tag: b"/lyra2/".to_vec(),
+        transactions: vec![spend(Vec::new(), 1), spend(Vec::new(), 2)],
+    };
+    let (extranonce1, extranonce2) = ([1, 2, 3, 4], [5, 6, 7, 8]);
+    for segwit in [false, true] {
+        if segwit {
+            template.transactions.push(spend(vec![vec![0x30; 71]], 3));
+        }
+        let job = template.job("1", 8, true);
+        let mut block = template.block(&extranonce1, &extranonce2, template.time, 0);
+        assert_eq!(
+            job.coinbase(&extranonce1, &extranonce2),
+            block.transactions[0].serialize_no_witness()
+        );
+        assert_eq!(
+            job.header(&extranonce1, &extranonce2, template.time, 0),
+            block.header
+        );
+        assert_eq!(block.coinbase_height(), Some(1_000_000));
+        assert_eq!(block.witness_commitment().is_some(), segwit);
+        while !block.header.meets_target(Algorithm::Lyra2REv2) {
+            block.header.nonce += 1;
+        }
+        block.validate(Algorithm::Lyra2REv2).unwrap();
+    }
+
+    // Past 75 bytes the extranonces are pushed with OP_PUSHDATA1.
+    let (extranonce1, extranonce2) = ([1; 40], [2; 40]);
+    let job = template.job("2", 80, true);
+    assert!(job.coinb1.ends_with(&[0x4c, 80]));
+    assert_eq!(
+        job.coinbase(&extranonce1, &extranonce2),
+        template
+            .block(&extranonce1, &extranonce2, template.time, 0)
+            .transactions[0]
+            .serialize_no_witness()
+    );
+}
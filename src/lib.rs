@@ -1,9 +1,37 @@
-mod bmw;
+pub mod blake256;
+pub mod bmw;
 mod cubehash;
+mod hasher;
 mod lyra2mod;
 mod utils;
+mod http;
 pub mod lyra2;
 pub mod lyra2z;
 pub mod lyra2re;
 pub mod lyra2rev2;
 pub mod lyra2rev3;
+pub mod chain;
+pub mod algorithm;
+pub mod coin;
+pub mod difficulty;
+pub mod batch;
+pub mod block;
+pub mod genesis;
+pub mod getwork;
+#[cfg(feature = "hashd")]
+pub mod hashd;
+pub mod header;
+pub mod target;
+pub mod retarget;
+pub mod rpc;
+pub mod scan;
+pub mod solo;
+pub mod merkle;
+pub mod job;
+pub mod stratum;
+#[cfg(feature = "sv2")]
+pub mod sv2;
+pub mod tree;
+pub mod vardiff;
+pub mod verify;
+pub mod work;
@@ -21,7 +21,7 @@ const BLOCKLENBLAKE2SAFEBYTES: i64 = BLOCKLENBLAKE2SAFEINT64 * 8; //same as abov
 
 /*Blake2b's rotation*/
 fn rotr64(w: u64, c: u8) -> u64 {
-    (w >> c) | (w << (64 - c))
+    w.rotate_right(c as u32)
 }
 
 /*g is Blake2b's G function*/
@@ -168,7 +168,7 @@ fn squeeze(mut state: [u64; 16], output_size: u64) -> Vec<u8> {
  * @param s    The current state of the sponge
  * @param w    The block to be absorbed (BLOCK_LEN_INT64 words)
  */
-fn absorb_block(mut s: [u64; 16], w: Vec<u64>) -> [u64; 16] {
+fn absorb_block(mut s: [u64; 16], w: &[u64]) -> [u64; 16] {
     //XORs the first BLOCK_LEN_INT64 words of "in" with the current state
     s[0] ^= w[0];
     s[1] ^= w[1];
@@ -195,7 +195,7 @@ fn absorb_block(mut s: [u64; 16], w: Vec<u64>) -> [u64; 16] {
  * @param s    The current state of the sponge
  * @param w    The block to be absorbed (BLOCK_LEN_BLAKE2_SAFE_INT64 words)
  */
-fn absorb_block_blake2_safe(mut s: [u64; 16], w: Vec<u64>) -> [u64; 16] {
+fn absorb_block_blake2_safe(mut s: [u64; 16], w: &[u64]) -> [u64; 16] {
     //XORs the first BLOCK_LEN_BLAKE2_SAFE_INT64 words of "in" with the current state
     s[0] ^= w[0];
     s[1] ^= w[1];
@@ -210,6 +210,20 @@ fn absorb_block_blake2_safe(mut s: [u64; 16], w: Vec<u64>) -> [u64; 16] {
     s
 }
 
+/// Memory matrix of [`lyra2`], kept between calls by [`lyra2_with_matrix`] so that hashing
+/// many inputs does not reallocate it.
+#[derive(Debug, Clone, Default)]
+pub struct Matrix {
+    pub(crate) words: Vec<u64>,
+}
+
+impl Matrix {
+    /// Returns an empty matrix. It grows to the size needed on first use.
+    pub fn new() -> Matrix {
+        Matrix::default()
+    }
+}
+
 // lyra2 Executes Lyra2 based on the G function from Blake2b. This version supports salts and passwords
 // whose combined length is smaller than the size of the memory matrix, (i.e., (n_rows x n_cols x b) bits,
 // where "b" is the underlying sponge's bitrate). In this implementation, the "basil" is composed by all
@@ -249,6 +263,35 @@ pub fn lyra2(
     time_cost: u64,
     n_rows: u64,
     n_cols: u64,
+) -> Vec<u8> {
+    lyra2_with_matrix(&mut Matrix::new(), k, &pwd, &salt, time_cost, n_rows, n_cols)
+}
+
+/// Same as [`lyra2`], but reuses the memory matrix in `matrix` instead of allocating a new one.
+/// Keep one [`Matrix`] per thread when hashing many inputs.
+/// # Examples
+///
+/// ```
+/// let mut matrix = lyra2::lyra2::Matrix::new();
+/// let base1 = "abc".as_bytes().to_vec();
+/// assert_eq!(
+///     lyra2::lyra2::lyra2_with_matrix(&mut matrix, 32, &base1, &base1, 1, 4, 4),
+///     lyra2::lyra2::sum(base1)
+/// );
+/// ```
+///
+/// # Panics
+///
+/// `time_cost` < 1, `n_rows` < 3
+///
+pub fn lyra2_with_matrix(
+    matrix: &mut Matrix,
+    k: u64,
+    pwd: &[u8],
+    salt: &[u8],
+    time_cost: u64,
+    n_rows: u64,
+    n_cols: u64,
 ) -> Vec<u8> {
     //============================= parameter check ============================//
     if time_cost < 1 {panic!()};
@@ -271,7 +314,8 @@ pub fn lyra2(
 
     let row_len_int64: i64 = BLOCKLENINT64 * n_cols as i64;
     let mut _i: i64 = n_rows as i64 * row_len_int64;
-    let mut whole_matrix: Vec<u64> = vec![0; _i as usize];
+    let whole_matrix = &mut matrix.words;
+    whole_matrix.clear();
     whole_matrix.resize(_i as usize, 0);
 
     //==========================================================================/
@@ -334,7 +378,7 @@ pub fn lyra2(
     let mut ptr_word = 0;
     for _i in 0..n_blocks_input {
         let (_, mut _right) = &whole_matrix.split_at(ptr_word);
-        state = absorb_block_blake2_safe(state, _right.to_vec()); //absorbs each block of pad(pwd || salt || basil)
+        state = absorb_block_blake2_safe(state, _right); //absorbs each block of pad(pwd || salt || basil)
         ptr_word += BLOCKLENBLAKE2SAFEINT64 as usize; //goes to next block of pad(pwd || salt || basil)
     }
 
@@ -646,7 +690,7 @@ pub fn lyra2(
     //============================ Wrap-up Phase ===============================//
     //Absorbs the last block of the memory matrix
     let (_, mut _right) = &whole_matrix.split_at((rowa * row_len_int64) as usize);
-    state = absorb_block(state, _right.to_vec());
+    state = absorb_block(state, _right);
     //Squeezes the key
     squeeze(state, k)
     //==========================================================================/
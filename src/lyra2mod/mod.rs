@@ -1,3 +1,4 @@
+use crate::lyra2::Matrix;
 use crate::utils::read_u64_le;
 
 const BLAKE2BIV: [u64; 8] = [
@@ -18,7 +19,7 @@ const BLOCKLENBLAKE2SAFEBYTES: i64 = BLOCKLENBLAKE2SAFEINT64 * 8; //same as abov
 
 /*Blake2b's rotation*/
 fn rotr64(w: u64, c: u8) -> u64 {
-    (w >> c) | (w << (64 - c))
+    w.rotate_right(c as u32)
 }
 
 /*g is Blake2b's G function*/
@@ -165,7 +166,7 @@ fn squeeze(mut state: [u64; 16], output_size: u64) -> Vec<u8> {
  * @param s    The current state of the sponge
  * @param w    The block to be absorbed (BLOCK_LEN_INT64 words)
  */
-fn absorb_block(mut s: [u64; 16], w: Vec<u64>) -> [u64; 16] {
+fn absorb_block(mut s: [u64; 16], w: &[u64]) -> [u64; 16] {
     //XORs the first BLOCK_LEN_INT64 words of "in" with the current state
     s[0] ^= w[0];
     s[1] ^= w[1];
@@ -192,7 +193,7 @@ fn absorb_block(mut s: [u64; 16], w: Vec<u64>) -> [u64; 16] {
  * @param s    The current state of the sponge
  * @param w    The block to be absorbed (BLOCK_LEN_BLAKE2_SAFE_INT64 words)
  */
-fn absorb_block_blake2_safe(mut s: [u64; 16], w: Vec<u64>) -> [u64; 16] {
+fn absorb_block_blake2_safe(mut s: [u64; 16], w: &[u64]) -> [u64; 16] {
     //XORs the first BLOCK_LEN_BLAKE2_SAFE_INT64 words of "in" with the current state
     s[0] ^= w[0];
     s[1] ^= w[1];
@@ -207,7 +208,7 @@ fn absorb_block_blake2_safe(mut s: [u64; 16], w: Vec<u64>) -> [u64; 16] {
     s
 }
 
-// lyra2 Executes Lyra2 based on the G function from Blake2b. This version supports salts and passwords
+// lyra2mod_with_matrix Executes Lyra2 based on the G function from Blake2b. This version supports salts and passwords
 // whose combined length is smaller than the size of the memory matrix, (i.e., (n_rows x n_cols x b) bits,
 // where "b" is the underlying sponge's bitrate). In this implementation, the "basil" is composed by all
 // integer parameters (treated as type "unsigned int") in the order they are provided, plus the value
@@ -219,10 +220,12 @@ fn absorb_block_blake2_safe(mut s: [u64; 16], w: Vec<u64>) -> [u64; 16] {
 // @param time_cost Parameter to determine the processing time (T)
 // @param n_rows Number or rows of the memory matrix (R)
 // @param n_cols Number of columns of the memory matrix (C)
-pub fn lyra2mod(
+// @param matrix Memory matrix, reused across calls
+pub fn lyra2mod_with_matrix(
+    matrix: &mut Matrix,
     k: u64,
-    pwd: Vec<u8>,
-    salt: Vec<u8>,
+    pwd: &[u8],
+    salt: &[u8],
     time_cost: u64,
     n_rows: u64,
     n_cols: u64,
@@ -248,7 +251,8 @@ pub fn lyra2mod(
 
     let row_len_int64: i64 = BLOCKLENINT64 * n_cols as i64;
     let mut _i: i64 = n_rows as i64 * row_len_int64;
-    let mut whole_matrix: Vec<u64> = vec![0; _i as usize];
+    let whole_matrix = &mut matrix.words;
+    whole_matrix.clear();
     whole_matrix.resize(_i as usize, 0);
 
     //==========================================================================/
@@ -311,7 +315,7 @@ pub fn lyra2mod(
     let mut ptr_word = 0;
     for _i in 0..n_blocks_input {
         let (_, mut _right) = &whole_matrix.split_at(ptr_word);
-        state = absorb_block_blake2_safe(state, _right.to_vec()); //absorbs each block of pad(pwd || salt || basil)
+        state = absorb_block_blake2_safe(state, _right); //absorbs each block of pad(pwd || salt || basil)
         ptr_word += BLOCKLENBLAKE2SAFEINT64 as usize; //goes to next block of pad(pwd || salt || basil)
     }
 
@@ -625,21 +629,16 @@ pub fn lyra2mod(
     //============================ Wrap-up Phase ===============================//
     //Absorbs the last block of the memory matrix
     let (_, mut _right) = &whole_matrix.split_at((rowa * row_len_int64) as usize);
-    state = absorb_block(state, _right.to_vec());
+    state = absorb_block(state, _right);
     //Squeezes the key
     squeeze(state, k)
     //==========================================================================/
 }
 
-pub fn sum(input: Vec<u8>) -> Vec<u8> {
-    let input2 = input.clone();
-    lyra2mod(32, input, input2, 1, 4, 4)
-}
-
 #[test]
 fn lyra2mod_hash_cal() {
     let base1 = "abc".as_bytes().to_vec();
-    let lyra2mod_result1 = sum(base1);
+    let lyra2mod_result1 = lyra2mod_with_matrix(&mut Matrix::new(), 32, &base1, &base1, 1, 4, 4);
     assert_eq!(
         "0c36444f2885b72f3528af3b1f59174f8fd5c20b712988306962784c5f8ac462",
         lyra2mod_result1
@@ -650,12 +649,12 @@ fn lyra2mod_hash_cal() {
 
     let base3 = "脇山珠美ちゃんかわいい！".as_bytes().to_vec();
     let base4 = base3.clone();
-    let lyra2mod_result2 = lyra2mod(48, base3, base4, 1, 3, 4);
+    let lyra2mod_result2 = lyra2mod_with_matrix(&mut Matrix::new(), 48, &base3, &base4, 1, 3, 4);
     assert_eq!("c937cfe0ee21a8e7c1d1871245ea717457edbee2de8bf544e50f807349a3460c52cb6bb10bd0b7328504bc2ad984e1f3", lyra2mod_result2.iter().map(|n| format!("{:02x}", n)).collect::<String>());
 
     let base5 = "😀😁😂".as_bytes().to_vec();
     let base6 = base5.clone();
-    let lyra2mod_result3 = lyra2mod(16, base5, base6, 1, 4, 2);
+    let lyra2mod_result3 = lyra2mod_with_matrix(&mut Matrix::new(), 16, &base5, &base6, 1, 4, 2);
     assert_eq!(
         "3cd143a8903f35aa32fbe9395706cfd6",
         lyra2mod_result3
@@ -1,10 +1,12 @@
 //! # lyra2re
 //!
 //! `lyra2re` crate has necessary formulas to calculate `lyra2re`.
-use crate::lyra2;
-use digest::generic_array::typenum::U32;
-use blake_hash::Digest as BlakeDigest;
-use sha3::Digest;
+use crate::blake256::{self, Midstate};
+use crate::chain::{self, HashChain, StageOutput};
+use crate::hasher::pow_hasher;
+use crate::lyra2::Matrix;
+
+pow_hasher!(Lyra2RE, "Lyra2RE", finish);
 
 /// Returns the calculation result of lyra2re.
 /// # Examples
@@ -21,17 +23,48 @@ use sha3::Digest;
 /// );
 /// ```
 pub fn sum(input: Vec<u8>) -> Vec<u8> {
-    let result_blake = blake_hash::Blake256::digest(&input).to_vec();
-
-    let result_keccak256_1 = sha3::Keccak256::digest(result_blake).to_vec();
+    finish(blake256::sum(input))
+}
 
-    let result_keccak256_2 = result_keccak256_1.clone();
+// finish runs the stages of lyra2re that follow Blake-256.
+fn finish(result_blake: Vec<u8>) -> Vec<u8> {
+    finish_with(&mut Matrix::new(), result_blake)
+}
 
-    let result_lyra2 = lyra2::lyra2(32, result_keccak256_1, result_keccak256_2, 1, 8, 8);
+// finish_with is finish reusing the Lyra2 memory matrix in `matrix`.
+pub(crate) fn finish_with(matrix: &mut Matrix, result_blake: Vec<u8>) -> Vec<u8> {
+    chain::finish_with(&chain::LYRA2RE, matrix, result_blake)
+}
 
-    let result_skein = skein_hash::Skein512::<U32>::digest(&result_lyra2);
+/// Returns the calculation result of lyra2re for an 80-byte message, given the Blake-256
+/// [`Midstate`] of its first 64 bytes and its last 16 bytes. Equals [`sum`] of the whole message.
+/// # Examples
+///
+/// ```
+/// use lyra2::blake256::Midstate;
+///
+/// let header = [7u8; 80];
+/// let midstate = Midstate::new(header[..64].try_into().unwrap());
+/// assert_eq!(
+///     lyra2::lyra2re::sum_midstate(&midstate, header[64..].try_into().unwrap()),
+///     lyra2::lyra2re::sum(header.to_vec())
+/// );
+/// ```
+pub fn sum_midstate(midstate: &Midstate, tail: &[u8; 16]) -> Vec<u8> {
+    finish(midstate.finish(tail).to_vec())
+}
 
-    groestl::Groestl256::digest(result_skein).to_vec()
+/// Returns the output of every stage of lyra2re, in order. The last entry equals [`sum`].
+/// # Examples
+///
+/// ```
+/// let base1 = "abc".as_bytes().to_vec();
+/// let trace = lyra2::lyra2re::sum_with_trace(base1.clone());
+/// assert_eq!(trace[0].name, "blake256");
+/// assert_eq!(trace.last().unwrap().output.to_vec(), lyra2::lyra2re::sum(base1));
+/// ```
+pub fn sum_with_trace(input: Vec<u8>) -> Vec<StageOutput> {
+    HashChain::lyra2re().sum_with_trace(input)
 }
 
 #[test]
@@ -1,10 +1,12 @@
 //! # lyra2rev3
 //!
 //! `lyra2rev3` crate has necessary formulas to calculate `lyra2rev3`. For vertcoin etc...
-use crate::bmw;
-use crate::cubehash;
-use crate::lyra2mod;
-use blake_hash::Digest;
+use crate::blake256::{self, Midstate};
+use crate::chain::{self, HashChain, StageOutput};
+use crate::hasher::pow_hasher;
+use crate::lyra2::Matrix;
+
+pow_hasher!(Lyra2REv3, "Lyra2REv3", finish);
 
 /// Returns the calculation result of lyra2rev3.
 /// # Examples
@@ -21,15 +23,48 @@ use blake_hash::Digest;
 /// );
 /// ```
 pub fn sum(input: Vec<u8>) -> Vec<u8> {
-    let result_blake = blake_hash::Blake256::digest(&input).to_vec();
+    finish(blake256::sum(input))
+}
 
-    let result_lyra2_mod_1 = lyra2mod::sum(result_blake);
+// finish runs the stages of lyra2rev3 that follow Blake-256.
+fn finish(result_blake: Vec<u8>) -> Vec<u8> {
+    finish_with(&mut Matrix::new(), result_blake)
+}
 
-    let result_cube = cubehash::sum(result_lyra2_mod_1);
+// finish_with is finish reusing the Lyra2 memory matrix in `matrix`.
+pub(crate) fn finish_with(matrix: &mut Matrix, result_blake: Vec<u8>) -> Vec<u8> {
+    chain::finish_with(&chain::LYRA2REV3, matrix, result_blake)
+}
 
-    let result_lyra2_mod_2 = lyra2mod::sum(result_cube);
+/// Returns the calculation result of lyra2rev3 for an 80-byte message, given the Blake-256
+/// [`Midstate`] of its first 64 bytes and its last 16 bytes. Equals [`sum`] of the whole message.
+/// # Examples
+///
+/// ```
+/// use lyra2::blake256::Midstate;
+///
+/// let header = [7u8; 80];
+/// let midstate = Midstate::new(header[..64].try_into().unwrap());
+/// assert_eq!(
+///     lyra2::lyra2rev3::sum_midstate(&midstate, header[64..].try_into().unwrap()),
+///     lyra2::lyra2rev3::sum(header.to_vec())
+/// );
+/// ```
+pub fn sum_midstate(midstate: &Midstate, tail: &[u8; 16]) -> Vec<u8> {
+    finish(midstate.finish(tail).to_vec())
+}
 
-    bmw::sum(result_lyra2_mod_2)
+/// Returns the output of every stage of lyra2rev3, in order. The last entry equals [`sum`].
+/// # Examples
+///
+/// ```
+/// let base1 = "abc".as_bytes().to_vec();
+/// let trace = lyra2::lyra2rev3::sum_with_trace(base1.clone());
+/// assert_eq!(trace[0].name, "blake256");
+/// assert_eq!(trace.last().unwrap().output.to_vec(), lyra2::lyra2rev3::sum(base1));
+/// ```
+pub fn sum_with_trace(input: Vec<u8>) -> Vec<StageOutput> {
+    HashChain::lyra2rev3().sum_with_trace(input)
 }
 
 #[test]
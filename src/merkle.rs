@@ -0,0 +1,73 @@
+//! # merkle
+//!
+//! `merkle` crate computes Bitcoin-style merkle roots over double-SHA256 transaction hashes,
+//! and the merkle branch a pool sends so that miners can fold a coinbase of their own into it.
+use crate::header::Hash256;
+
+/// Returns the merkle root of `hashes`, given in internal byte order. A level with an odd
+/// number of hashes pairs the last hash with itself. The root of no hashes is all zeros.
+pub fn merkle_root(hashes: &[Hash256]) -> Hash256 {
+    if hashes.is_empty() {
+        return Hash256::default();
+    }
+    let mut level = hashes.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| parent(&pair[0], pair.last().unwrap()))
+            .collect();
+    }
+    level[0]
+}
+
+/// Returns the merkle branch of the first hash of `hashes`, the sibling at each level from the
+/// bottom up. This is the `merkle_branch` of `mining.notify`, with `hashes[0]` standing for the
+/// coinbase; only `hashes[1..]` affect the result.
+pub fn merkle_branch(hashes: &[Hash256]) -> Vec<Hash256> {
+    let mut branch = Vec::new();
+    let mut level = hashes.to_vec();
+    while level.len() > 1 {
+        branch.push(level[1]);
+        level = level
+            .chunks(2)
+            .map(|pair| parent(&pair[0], pair.last().unwrap()))
+            .collect();
+    }
+    branch
+}
+
+/// Folds `leaf` with `branch` up to the merkle root, `leaf` being the leftmost hash of every
+/// pair.
+pub fn fold_branch(leaf: Hash256, branch: &[Hash256]) -> Hash256 {
+    branch
+        .iter()
+        .fold(leaf, |node, sibling| parent(&node, sibling))
+}
+
+fn parent(left: &Hash256, right: &Hash256) -> Hash256 {
+    Hash256::sha256d(&[left.0, right.0].concat())
+}
+
+#[test]
+fn merkle_cal() {
+    let hashes: Vec<Hash256> = (0u8..7).map(|i| Hash256::sha256d(&[i])).collect();
+    assert_eq!(merkle_root(&[]), Hash256::default());
+    assert_eq!(merkle_root(&hashes[..1]), hashes[0]);
+    assert_eq!(merkle_root(&hashes[..2]), parent(&hashes[0], &hashes[1]));
+    assert_eq!(
+        merkle_root(&hashes[..3]),
+        parent(
+            &parent(&hashes[0], &hashes[1]),
+            &parent(&hashes[2], &hashes[2])
+        )
+    );
+    assert!(merkle_branch(&hashes[..1]).is_empty());
+    for n in 1..=hashes.len() {
+        let branch = merkle_branch(&hashes[..n]);
+        assert_eq!(fold_branch(hashes[0], &branch), merkle_root(&hashes[..n]));
+    }
+    assert_eq!(
+        merkle_branch(&hashes[..4]),
+        vec![hashes[1], parent(&hashes[2], &hashes[3])]
+    );
+}
@@ -0,0 +1,370 @@
+//! # retarget
+//!
+//! `retarget` crate computes the `nBits` a block must have from the headers before it, with the
+//! difficulty retargeting rules used by Lyra2 coins: Kimoto Gravity Well, Dark Gravity Wave v3,
+//! DigiShield and LWMA.
+//!
+//! The arithmetic follows the reference node implementations step by step, including their
+//! integer truncation, so the results match the `nBits` of the chains bit for bit.
+use crate::coin::ChainParams;
+use crate::header::BlockHeader;
+use crate::target::{Target, TargetError};
+use std::error;
+use std::fmt;
+
+/// A difficulty retargeting rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retarget {
+    /// Kimoto Gravity Well: averages the targets of between `past_blocks_min` and
+    /// `past_blocks_max` blocks, stopping early once their block rate leaves the event horizon.
+    Kgw {
+        past_blocks_min: u32,
+        past_blocks_max: u32,
+    },
+    /// Dark Gravity Wave v3: averages the targets of the last `past_blocks` blocks and scales
+    /// by their timespan, limited to a factor of 3 either way.
+    DarkGravityWave3 { past_blocks: u32 },
+    /// DigiShield: scales the last target by the timespan of the last `interval` blocks, damped
+    /// by a factor of 8 and limited to -25% / +50%.
+    DigiShield { interval: u32 },
+    /// LWMA-1: a linearly weighted moving average of the solve times of the last `window`
+    /// blocks, each limited to six target spacings.
+    Lwma { window: u32 },
+}
+
+impl Retarget {
+    /// Dark Gravity Wave v3 over 24 blocks, as in Monacoin and Dash.
+    pub const DGW3: Retarget = Retarget::DarkGravityWave3 { past_blocks: 24 };
+
+    /// Returns Kimoto Gravity Well with the window of Megacoin and Vertcoin: from a quarter of a
+    /// day to a week of blocks at `target_spacing` seconds.
+    pub fn kgw(target_spacing: u32) -> Retarget {
+        Retarget::Kgw {
+            past_blocks_min: 60 * 60 * 24 / 4 / target_spacing,
+            past_blocks_max: 60 * 60 * 24 * 7 / target_spacing,
+        }
+    }
+
+    /// Returns the number of headers right before `height` that [`Retarget::next_bits`] reads.
+    pub fn window(&self, height: u32) -> usize {
+        let last = height.saturating_sub(1);
+        let needed = match *self {
+            // The genesis block never takes part in the average.
+            Retarget::Kgw {
+                past_blocks_min,
+                past_blocks_max,
+            } => {
+                if last == 0 || last < past_blocks_min {
+                    0
+                } else {
+                    past_blocks_max.min(last)
+                }
+            }
+            Retarget::DarkGravityWave3 { past_blocks } => {
+                if height == 0 || last < past_blocks {
+                    0
+                } else {
+                    past_blocks
+                }
+            }
+            Retarget::DigiShield { interval: n } | Retarget::Lwma { window: n } => {
+                if height == 0 || last < n {
+                    0
+                } else {
+                    n + 1
+                }
+            }
+        };
+        needed as usize
+    }
+
+    /// Returns the `nBits` required of the block at `height`, given `prev`, the headers right
+    /// before it in chain order. Only the last [`Retarget::window`] headers are read; early
+    /// blocks without enough history get the compact `pow_limit` of `params`.
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::coin::Coin;
+    /// use lyra2::header::BlockHeader;
+    /// use lyra2::retarget::Retarget;
+    ///
+    /// let params = Coin::Monacoin.params();
+    /// let genesis = BlockHeader::from_hex("010000000000000000000000000000000000000000000000000000000000000000000000a64bac07fe31877f31d03252953b3c32398933af7a724119bc4d6fa4a805e435f083c252f0ff0f1e66d61200").unwrap();
+    /// assert_eq!(Retarget::DGW3.next_bits(&params, 1, &[genesis]).unwrap(), 0x1e0fffff);
+    /// ```
+    pub fn next_bits(
+        &self,
+        params: &ChainParams,
+        height: u32,
+        prev: &[BlockHeader],
+    ) -> Result<u32, RetargetError> {
+        let needed = self.window(height);
+        if prev.len() < needed {
+            return Err(RetargetError::InsufficientHistory {
+                needed,
+                got: prev.len(),
+            });
+        }
+        if needed == 0 {
+            return Ok(params.pow_limit.to_compact());
+        }
+        let window = &prev[prev.len() - needed..];
+        let spacing = i64::from(params.target_spacing);
+        let target = match *self {
+            Retarget::Kgw {
+                past_blocks_min, ..
+            } => kgw(window, spacing, past_blocks_min)?,
+            Retarget::DarkGravityWave3 { .. } => dgw3(window, spacing)?,
+            Retarget::DigiShield { .. } => digishield(window, spacing)?,
+            Retarget::Lwma { .. } => lwma(window, spacing)?,
+        };
+        Ok(target.min(params.pow_limit).to_compact())
+    }
+}
+
+fn target(header: &BlockHeader) -> Result<Target, RetargetError> {
+    Target::from_compact(header.bits).map_err(RetargetError::InvalidBits)
+}
+
+// kgw reads `window` from the newest header back, as KimotoGravityWell() in Megacoin and
+// Vertcoin does.
+fn kgw(
+    window: &[BlockHeader],
+    spacing: i64,
+    past_blocks_min: u32,
+) -> Result<Target, RetargetError> {
+    let last = window.last().unwrap();
+    let mut average = Target::ZERO;
+    let mut actual = 0i64;
+    let mut expected = 0i64;
+    for (mass, reading) in (1u64..).zip(window.iter().rev()) {
+        let bits = target(reading)?;
+        average = if mass == 1 {
+            bits
+        } else if bits > average {
+            average.saturating_add(&bits.saturating_sub(&average).div(mass))
+        } else {
+            average.saturating_sub(&average.saturating_sub(&bits).div(mass))
+        };
+
+        actual = (i64::from(last.time) - i64::from(reading.time)).max(0);
+        expected = spacing * mass as i64;
+        let ratio = if actual != 0 && expected != 0 {
+            expected as f64 / actual as f64
+        } else {
+            1.0
+        };
+        let horizon = 1.0 + 0.7084 * (mass as f64 / 144.0).powf(-1.228);
+        if mass >= u64::from(past_blocks_min) && (ratio <= 1.0 / horizon || ratio >= horizon) {
+            break;
+        }
+    }
+    if actual != 0 && expected != 0 {
+        average = average.mul_div(actual as u64, expected as u64);
+    }
+    Ok(average)
+}
+
+// dgw3 follows DarkGravityWave() of Dash, which gives the same results as the original v3.
+fn dgw3(window: &[BlockHeader], spacing: i64) -> Result<Target, RetargetError> {
+    let mut average = Target::ZERO;
+    for (count, header) in (1u64..).zip(window.iter().rev()) {
+        let bits = target(header)?;
+        average = if count == 1 {
+            bits
+        } else {
+            average
+                .saturating_mul(count)
+                .saturating_add(&bits)
+                .div(count + 1)
+        };
+    }
+    let expected = window.len() as i64 * spacing;
+    let actual = (i64::from(window[window.len() - 1].time) - i64::from(window[0].time))
+        .clamp(expected / 3, expected * 3);
+    Ok(average.mul_div(actual as u64, expected as u64))
+}
+
+// digishield follows the DigiShield branch of Dogecoin's CalculateDogecoinNextWorkRequired().
+fn digishield(window: &[BlockHeader], spacing: i64) -> Result<Target, RetargetError> {
+    let last = &window[window.len() - 1];
+    let expected = (window.len() as i64 - 1) * spacing;
+    let actual = i64::from(last.time) - i64::from(window[0].time);
+    let modulated = (expected + (actual - expected) / 8)
+        .clamp(expected - expected / 4, expected + expected / 2);
+    Ok(target(last)?.mul_div(modulated as u64, expected as u64))
+}
+
+// lwma follows LWMA-1 by zawy12, including its handling of out-of-order timestamps.
+fn lwma(window: &[BlockHeader], spacing: i64) -> Result<Target, RetargetError> {
+    let n = window.len() as i64 - 1;
+    let k = n * (n + 1) * spacing / 2;
+    let mut previous = i64::from(window[0].time);
+    let mut weighted = 0i64;
+    let mut average = Target::ZERO;
+    for (j, header) in (1i64..).zip(&window[1..]) {
+        let time = i64::from(header.time).max(previous + 1);
+        weighted += (6 * spacing).min(time - previous) * j;
+        previous = time;
+        average = average.saturating_add(&target(header)?.div(n as u64).div(k as u64));
+    }
+    Ok(average.saturating_mul(weighted as u64))
+}
+
+/// An error returned when the required `nBits` cannot be computed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetargetError {
+    /// Fewer headers were given than the rule reads.
+    InsufficientHistory { needed: usize, got: usize },
+    /// A header in the window has invalid `nBits`.
+    InvalidBits(TargetError),
+}
+
+impl fmt::Display for RetargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetargetError::InsufficientHistory { needed, got } => {
+                write!(f, "retarget needs {} previous headers, got {}", needed, got)
+            }
+            RetargetError::InvalidBits(err) => write!(f, "invalid previous nBits: {}", err),
+        }
+    }
+}
+
+impl error::Error for RetargetError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RetargetError::InvalidBits(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn retarget_cal() {
+    use crate::coin::Coin;
+    use crate::header::Hash256;
+
+    fn chain(bits: u32, steps: &[u32]) -> Vec<BlockHeader> {
+        let mut time = 1500000000;
+        steps
+            .iter()
+            .map(|step| {
+                time += step;
+                BlockHeader {
+                    version: 2,
+                    prev_block: Hash256::default(),
+                    merkle_root: Hash256::default(),
+                    time,
+                    bits,
+                    nonce: 0,
+                }
+            })
+            .collect()
+    }
+    let repeat = |parts: &[(u32, usize)]| -> Vec<u32> {
+        parts
+            .iter()
+            .flat_map(|&(step, count)| std::iter::repeat(step).take(count))
+            .collect()
+    };
+
+    // Expected values are from an independent big-integer transcription of the node code.
+    let mona = Coin::Monacoin.params();
+    let vtc = Coin::Vertcoin.params();
+    let bits = 0x1b0404cb;
+    for (rule, params, steps, expected) in [
+        // DGW3 divides a 23-block timespan by 24 target spacings.
+        (Retarget::DGW3, &mona, repeat(&[(90, 24)]), 0x1b03d9ed),
+        (
+            Retarget::DGW3,
+            &mona,
+            repeat(&[(90, 12), (30, 12)]),
+            0x1b0282fe,
+        ),
+        (Retarget::DGW3, &mona, repeat(&[(1, 24)]), 0x1b0156ee),
+        (Retarget::kgw(150), &vtc, repeat(&[(150, 300)]), 0x1b04015d),
+        // A hashrate spike leaves the event horizon and cuts the window short.
+        (
+            Retarget::kgw(150),
+            &vtc,
+            repeat(&[(150, 150), (15, 150)]),
+            0x1a662a34,
+        ),
+        (
+            Retarget::DigiShield { interval: 1 },
+            &mona,
+            repeat(&[(90, 1), (900, 1)]),
+            0x1b060730,
+        ),
+        (
+            Retarget::DigiShield { interval: 1 },
+            &mona,
+            repeat(&[(90, 2)]),
+            bits,
+        ),
+        (
+            Retarget::DigiShield { interval: 1 },
+            &mona,
+            repeat(&[(90, 1), (0, 1)]),
+            0x1b03870d,
+        ),
+        (
+            Retarget::Lwma { window: 45 },
+            &mona,
+            repeat(&[(90, 46)]),
+            0x1b0404ca,
+        ),
+    ] {
+        let prev = chain(bits, &steps);
+        let height = prev.len() as u32 + 1;
+        assert_eq!(rule.window(height), prev.len());
+        assert_eq!(rule.next_bits(params, height, &prev).unwrap(), expected);
+    }
+
+    // LWMA treats timestamps that go backwards as one second after the latest one.
+    let mut prev = chain(bits, &repeat(&[(90, 46)]));
+    prev[20].time -= 1000;
+    prev[30].time += 2000;
+    assert_eq!(
+        Retarget::Lwma { window: 45 }
+            .next_bits(&mona, 10000, &prev)
+            .unwrap(),
+        0x1b026a97
+    );
+
+    // Slow blocks at the limit stay at the limit.
+    let prev = chain(0x1e0ffff0, &repeat(&[(900, 24)]));
+    assert_eq!(
+        Retarget::DGW3.next_bits(&mona, 10000, &prev).unwrap(),
+        0x1e0fffff
+    );
+
+    // Early blocks get the limit without reading any history.
+    assert_eq!(Retarget::DGW3.window(24), 0);
+    assert_eq!(Retarget::DGW3.window(25), 24);
+    assert_eq!(Retarget::kgw(150).window(144), 0);
+    assert_eq!(Retarget::kgw(150).window(145), 144);
+    assert_eq!(Retarget::kgw(150).window(100000), 4032);
+    assert_eq!(Retarget::Lwma { window: 45 }.window(46), 46);
+    for rule in [
+        Retarget::DGW3,
+        Retarget::kgw(150),
+        Retarget::DigiShield { interval: 1 },
+        Retarget::Lwma { window: 45 },
+    ] {
+        assert_eq!(rule.next_bits(&vtc, 0, &[]).unwrap(), 0x1e0fffff);
+        assert_eq!(
+            rule.next_bits(&vtc, 10000, &[]),
+            Err(RetargetError::InsufficientHistory {
+                needed: rule.window(10000),
+                got: 0
+            })
+        );
+    }
+    let prev = chain(0x04923456, &repeat(&[(90, 24)]));
+    assert!(matches!(
+        Retarget::DGW3.next_bits(&mona, 10000, &prev),
+        Err(RetargetError::InvalidBits(_))
+    ));
+}
@@ -0,0 +1,594 @@
+//! # rpc
+//!
+//! `rpc` crate calls monacoind, vertcoind and other Bitcoin-derived nodes over JSON-RPC, reads
+//! the JSON they return, checks the block headers in it with the algorithms of this crate and
+//! turns block templates into [`Template`]s.
+use crate::algorithm::Algorithm;
+use crate::block::Transaction;
+use crate::coin::{ChainParams, CoinError};
+use crate::header::{BlockHeader, Hash256};
+use crate::http::base64;
+use crate::job::Template;
+use crate::target::{Target, TargetError};
+use crate::utils::from_hex;
+use crate::work::Work;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// A JSON-RPC client of the HTTP interface of bitcoind-compatible nodes.
+#[derive(Debug)]
+pub struct RpcClient {
+    addr: String,
+    auth: Option<String>,
+    timeout: Duration,
+    max_response: usize,
+    next_id: AtomicU64,
+}
+
+impl RpcClient {
+    /// Returns a client of the node at `addr`, `host:port`, without credentials.
+    pub fn new(addr: &str) -> RpcClient {
+        RpcClient {
+            addr: addr.to_string(),
+            auth: None,
+            timeout: Duration::from_secs(30),
+            max_response: 64 * 1024 * 1024,
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Authenticates with HTTP basic auth, e.g. as `rpcuser` and `rpcpassword`.
+    pub fn auth(mut self, user: &str, password: &str) -> RpcClient {
+        self.auth = Some(base64(format!("{}:{}", user, password).as_bytes()));
+        self
+    }
+
+    /// Gives up on a call that is not answered within `timeout`. The default is 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> RpcClient {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Refuses responses over `bytes` bytes, headers included. The default is 64 MiB, room for
+    /// the template of a full block.
+    pub fn max_response(mut self, bytes: usize) -> RpcClient {
+        self.max_response = bytes;
+        self
+    }
+
+    /// Calls `method` with `params` and returns its result. Each call is a request on a new
+    /// connection.
+    pub fn call(&self, method: &str, params: Value) -> Result<Value, RpcCallError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let body = json!({"jsonrpc": "1.0", "id": id, "method": method, "params": params});
+        let body = body.to_string();
+        let mut request = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.addr,
+            body.len()
+        );
+        if let Some(auth) = &self.auth {
+            request += &format!("Authorization: Basic {}\r\n", auth);
+        }
+        request += "\r\n";
+        request += &body;
+
+        let mut stream = TcpStream::connect(&self.addr)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.write_all(request.as_bytes())?;
+        let mut response = Vec::new();
+        stream
+            .take(self.max_response as u64 + 1)
+            .read_to_end(&mut response)?;
+        if response.len() > self.max_response {
+            return Err(RpcCallError::TooLarge(self.max_response));
+        }
+
+        let (status, body) = parse_response(&response)?;
+        // Nodes answer RPC errors with an error status and the error in the body.
+        let reply: Value = match serde_json::from_slice(body) {
+            Ok(reply) => reply,
+            Err(_) if status != 200 => return Err(RpcCallError::Http(status)),
+            Err(err) => return Err(RpcCallError::Json(err)),
+        };
+        match reply.get("error") {
+            Some(error) if !error.is_null() => Err(RpcCallError::Rpc {
+                code: error["code"].as_i64().unwrap_or(0),
+                message: error["message"].as_str().unwrap_or_default().to_string(),
+            }),
+            _ if status != 200 => Err(RpcCallError::Http(status)),
+            _ => Ok(reply.get("result").cloned().unwrap_or(Value::Null)),
+        }
+    }
+}
+
+// parse_response splits an HTTP response into its status and body.
+fn parse_response(response: &[u8]) -> Result<(u16, &[u8]), RpcCallError> {
+    let malformed = || RpcCallError::Protocol("malformed HTTP response".to_string());
+    let split = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(malformed)?;
+    let head = std::str::from_utf8(&response[..split]).map_err(|_| malformed())?;
+    let mut body = &response[split + 4..];
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(malformed)?;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                let len: usize = value.trim().parse().map_err(|_| malformed())?;
+                body = body.get(..len).ok_or_else(malformed)?;
+            }
+        }
+    }
+    Ok((status, body))
+}
+
+/// The verbose result of `getblockheader <hash> true`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcHeader {
+    /// The header rebuilt from the JSON fields.
+    pub header: BlockHeader,
+    /// The `hash` field, as reported by the node.
+    pub hash: Option<Hash256>,
+    pub height: Option<u32>,
+    pub chainwork: Option<Work>,
+}
+
+#[derive(Deserialize)]
+struct RawHeader {
+    hash: Option<String>,
+    height: Option<u32>,
+    version: i64,
+    previousblockhash: Option<String>,
+    merkleroot: String,
+    time: u32,
+    bits: String,
+    nonce: u32,
+    chainwork: Option<String>,
+}
+
+impl RpcHeader {
+    /// Parses `getblockheader` JSON. `previousblockhash` is missing from the genesis block and
+    /// taken as zero; `hash`, `height` and `chainwork` are optional. The `result` member of a
+    /// whole JSON-RPC response is accepted too.
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::rpc::RpcHeader;
+    ///
+    /// let json = r#"{"hash": "ff9f1c0116d19de7c9963845e129f9ed1bfc0b376eb54fd7afa42e0d418c8bb6", "height": 0, "version": 1, "merkleroot": "35e405a8a46f4dbc1941727aaf338939323c3b955232d0317f8731fe07ac4ba6", "time": 1388479472, "nonce": 1234534, "bits": "1e0ffff0"}"#;
+    /// let rpc = RpcHeader::from_json(json).unwrap();
+    /// assert_eq!(Some(rpc.header.block_hash()), rpc.hash);
+    /// ```
+    pub fn from_json(json: &str) -> Result<RpcHeader, RpcError> {
+        let value: serde_json::Value = serde_json::from_str(json)?;
+        let value = match value.get("result") {
+            Some(result) if value.get("merkleroot").is_none() => result.clone(),
+            _ => value,
+        };
+        let raw: RawHeader = serde_json::from_value(value)?;
+        let hash = |field: &'static str, hex: &str| {
+            hex.parse::<Hash256>()
+                .map_err(|_| RpcError::InvalidField(field, hex.to_string()))
+        };
+        let bits = u32::from_str_radix(&raw.bits, 16)
+            .map_err(|_| RpcError::InvalidField("bits", raw.bits.clone()))?;
+        Ok(RpcHeader {
+            header: BlockHeader {
+                version: raw.version as i32,
+                prev_block: match &raw.previousblockhash {
+                    Some(prev) => hash("previousblockhash", prev)?,
+                    None => Hash256::default(),
+                },
+                merkle_root: hash("merkleroot", &raw.merkleroot)?,
+                time: raw.time,
+                bits,
+                nonce: raw.nonce,
+            },
+            hash: raw.hash.as_deref().map(|h| hash("hash", h)).transpose()?,
+            height: raw.height,
+            chainwork: raw
+                .chainwork
+                .as_deref()
+                .map(|work| {
+                    work.parse()
+                        .map_err(|_| RpcError::InvalidField("chainwork", work.to_string()))
+                })
+                .transpose()?,
+        })
+    }
+
+    /// Checks the header with the algorithm `params` has at its height. The JSON must have a
+    /// `height`.
+    pub fn check(&self, params: &ChainParams) -> Result<HeaderCheck, RpcError> {
+        let height = self.height.ok_or(RpcError::MissingField("height"))?;
+        self.check_with(params.algorithm_at(height)?)
+    }
+
+    /// Checks the header with `algorithm`.
+    pub fn check_with(&self, algorithm: Algorithm) -> Result<HeaderCheck, RpcError> {
+        let target = self.header.target()?;
+        let block_hash = self.header.block_hash();
+        let pow_hash = self.header.pow_hash(algorithm);
+        Ok(HeaderCheck {
+            block_hash,
+            hash_matches: self.hash.map(|hash| hash == block_hash),
+            algorithm,
+            pow_hash,
+            target,
+            meets_target: target.is_met_by(pow_hash.as_bytes()),
+        })
+    }
+}
+
+/// The result of checking a header from RPC JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderCheck {
+    /// The block hash of the rebuilt header.
+    pub block_hash: Hash256,
+    /// Whether the rebuilt header hashes to the `hash` field, if the JSON has one. A mismatch
+    /// means a field was mistyped or the JSON was edited.
+    pub hash_matches: Option<bool>,
+    pub algorithm: Algorithm,
+    pub pow_hash: Hash256,
+    /// The target encoded in `bits`.
+    pub target: Target,
+    /// Whether the PoW hash satisfies `bits`.
+    pub meets_target: bool,
+}
+
+impl HeaderCheck {
+    /// Returns true if the header is what the node reported and its PoW meets its target.
+    pub fn is_valid(&self) -> bool {
+        self.hash_matches != Some(false) && self.meets_target
+    }
+}
+
+#[derive(Deserialize)]
+struct RawTemplate {
+    version: i64,
+    previousblockhash: String,
+    transactions: Vec<RawTemplateTransaction>,
+    coinbasevalue: u64,
+    bits: String,
+    curtime: u32,
+    height: u32,
+    default_witness_commitment: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawTemplateTransaction {
+    data: String,
+    txid: Option<String>,
+}
+
+impl Template {
+    /// Reads the result of `getblocktemplate` (BIP 22), called with the `segwit` rule. The
+    /// coinbase pays `coinbasevalue` to `payout_script` and the template has no coinbase tag.
+    ///
+    /// Each transaction must hash to its `txid`. When a transaction has witnesses, the witness
+    /// commitment of the coinbase must equal `default_witness_commitment` if the node sends
+    /// one; without them the coinbase leaves the commitment out.
+    pub fn from_getblocktemplate(
+        value: &serde_json::Value,
+        payout_script: &[u8],
+    ) -> Result<Template, RpcError> {
+        let raw = RawTemplate::deserialize(value)?;
+        let hex = |field: &'static str, hex: &str| {
+            from_hex(hex).ok_or_else(|| RpcError::InvalidField(field, hex.to_string()))
+        };
+        let transactions = raw
+            .transactions
+            .iter()
+            .map(|tx| {
+                let transaction = Transaction::parse(&hex("data", &tx.data)?)
+                    .map_err(|_| RpcError::InvalidField("data", tx.data.clone()))?;
+                match &tx.txid {
+                    Some(txid) if txid.parse() != Ok(transaction.txid()) => {
+                        Err(RpcError::InvalidField("txid", txid.clone()))
+                    }
+                    _ => Ok(transaction),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let template = Template {
+            prev_hash: raw.previousblockhash.parse().map_err(|_| {
+                RpcError::InvalidField("previousblockhash", raw.previousblockhash.clone())
+            })?,
+            height: raw.height,
+            version: raw.version as i32,
+            bits: u32::from_str_radix(&raw.bits, 16)
+                .map_err(|_| RpcError::InvalidField("bits", raw.bits.clone()))?,
+            time: raw.curtime,
+            coinbase_value: raw.coinbasevalue,
+            payout_script: payout_script.to_vec(),
+            coinbase_tag: Vec::new(),
+            transactions,
+        };
+        let segwit = template.transactions.iter().any(Transaction::has_witness);
+        if let (true, Some(commitment)) = (segwit, &raw.default_witness_commitment) {
+            let coinbase = template.coinbase(&[]);
+            if coinbase.outputs[1].script_pubkey != hex("default_witness_commitment", commitment)? {
+                return Err(RpcError::InvalidField(
+                    "default_witness_commitment",
+                    commitment.clone(),
+                ));
+            }
+        }
+        Ok(template)
+    }
+}
+
+/// An error returned when RPC JSON cannot be read or checked.
+#[derive(Debug)]
+pub enum RpcError {
+    /// The JSON is malformed or lacks a required field.
+    Json(serde_json::Error),
+    /// A field needed for the requested check is missing.
+    MissingField(&'static str),
+    /// A field does not hold a valid value.
+    InvalidField(&'static str, String),
+    /// `bits` does not decode to a target.
+    InvalidBits(TargetError),
+    /// The header is hashed with an algorithm this crate does not implement.
+    Coin(CoinError),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Json(err) => write!(f, "invalid RPC JSON: {}", err),
+            RpcError::MissingField(field) => write!(f, "missing field: {}", field),
+            RpcError::InvalidField(field, value) => {
+                write!(f, "invalid field {}: {}", field, value)
+            }
+            RpcError::InvalidBits(err) => write!(f, "{}", err),
+            RpcError::Coin(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for RpcError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RpcError::Json(err) => Some(err),
+            RpcError::Coin(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for RpcError {
+    fn from(err: serde_json::Error) -> Self {
+        RpcError::Json(err)
+    }
+}
+
+impl From<TargetError> for RpcError {
+    fn from(err: TargetError) -> Self {
+        RpcError::InvalidBits(err)
+    }
+}
+
+impl From<CoinError> for RpcError {
+    fn from(err: CoinError) -> Self {
+        RpcError::Coin(err)
+    }
+}
+
+/// An error of a JSON-RPC call to a node.
+#[derive(Debug)]
+pub enum RpcCallError {
+    /// The connection to the node failed.
+    Io(io::Error),
+    /// The node answered with an HTTP error status and no JSON-RPC error, e.g. 401 for wrong
+    /// credentials.
+    Http(u16),
+    /// The node answered with invalid JSON.
+    Json(serde_json::Error),
+    /// The answer did not follow HTTP or JSON-RPC.
+    Protocol(String),
+    /// The node answered a call with an error.
+    Rpc { code: i64, message: String },
+    /// The answer is longer than the given limit in bytes.
+    TooLarge(usize),
+}
+
+impl fmt::Display for RpcCallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcCallError::Io(err) => write!(f, "node connection failed: {}", err),
+            RpcCallError::Http(status) => write!(f, "node answered HTTP {}", status),
+            RpcCallError::Json(err) => write!(f, "invalid JSON-RPC response: {}", err),
+            RpcCallError::Protocol(msg) => write!(f, "JSON-RPC protocol error: {}", msg),
+            RpcCallError::Rpc { code, message } => write!(f, "RPC error {}: {}", code, message),
+            RpcCallError::TooLarge(limit) => {
+                write!(f, "JSON-RPC response over {} bytes", limit)
+            }
+        }
+    }
+}
+
+impl error::Error for RpcCallError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RpcCallError::Io(err) => Some(err),
+            RpcCallError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RpcCallError {
+    fn from(err: io::Error) -> Self {
+        RpcCallError::Io(err)
+    }
+}
+
+#[test]
+fn rpc_header_cal() {
+    use crate::coin::{Coin, Epoch};
+
+    // getblockheader output for the genesis blocks, with the fields that depend on the
+    // current tip (confirmations, nextblockhash) left out.
+    let mona = r#"{
+        "hash": "ff9f1c0116d19de7c9963845e129f9ed1bfc0b376eb54fd7afa42e0d418c8bb6",
+        "height": 0,
+        "version": 1,
+        "versionHex": "00000001",
+        "merkleroot": "35e405a8a46f4dbc1941727aaf338939323c3b955232d0317f8731fe07ac4ba6",
+        "time": 1388479472,
+        "mediantime": 1388479472,
+        "nonce": 1234534,
+        "bits": "1e0ffff0",
+        "difficulty": 0.000244140625,
+        "chainwork": "0000000000000000000000000000000000000000000000000000000000100010",
+        "nTx": 1
+    }"#;
+    let vtc = r#"{"result": {
+        "hash": "4d96a915f49d40b1e5c2844d1ee2dccb90013a990ccea12c492d22110489f0c4",
+        "height": 0,
+        "version": 1,
+        "versionHex": "00000001",
+        "merkleroot": "4af38ca0e323c0a5226208a73b7589a52c030f234810cf51e13e3249fc0123e7",
+        "time": 1389311371,
+        "mediantime": 1389311371,
+        "nonce": 5749262,
+        "bits": "1e0ffff0",
+        "difficulty": 0.000244140625,
+        "chainwork": "0000000000000000000000000000000000000000000000000000000000100010",
+        "nTx": 1
+    }, "error": null, "id": 1}"#;
+    for (json, hex, coin) in [
+        (mona, "010000000000000000000000000000000000000000000000000000000000000000000000a64bac07fe31877f31d03252953b3c32398933af7a724119bc4d6fa4a805e435f083c252f0ff0f1e66d61200", Coin::Monacoin),
+        (vtc, "010000000000000000000000000000000000000000000000000000000000000000000000e72301fc49323ee151cf1048230f032ca589753ba7086222a5c023e3a08cf34a8b35cf52f0ff0f1e0eba5700", Coin::Vertcoin),
+    ] {
+        let rpc = RpcHeader::from_json(json).unwrap();
+        assert_eq!(rpc.header.to_hex(), hex);
+        assert_eq!(rpc.height, Some(0));
+        assert_eq!(
+            rpc.chainwork,
+            Some(Work::from_bits(rpc.header.bits).unwrap())
+        );
+        // Both genesis blocks were mined with scrypt.
+        assert!(matches!(
+            rpc.check(&coin.params()),
+            Err(RpcError::Coin(CoinError::UnsupportedAlgorithm { .. }))
+        ));
+        let check = rpc.check_with(Algorithm::Lyra2REv2).unwrap();
+        assert_eq!(check.hash_matches, Some(true));
+        assert!(!check.meets_target);
+    }
+
+    // A block mined with Lyra2REv2 at minimal difficulty.
+    let mut header = BlockHeader {
+        version: 0x20000000,
+        prev_block: "ff9f1c0116d19de7c9963845e129f9ed1bfc0b376eb54fd7afa42e0d418c8bb6"
+            .parse()
+            .unwrap(),
+        merkle_root: Hash256::sha256d(b"coinbase"),
+        time: 1388479562,
+        bits: 0x207fffff,
+        nonce: 0,
+    };
+    while !header.meets_target(Algorithm::Lyra2REv2) {
+        header.nonce += 1;
+    }
+    let json = format!(
+        r#"{{"hash": "{}", "height": 1, "version": {}, "versionHex": "{:08x}", "previousblockhash": "{}", "merkleroot": "{}", "time": {}, "nonce": {}, "bits": "{:08x}"}}"#,
+        header.block_hash(),
+        header.version,
+        header.version,
+        header.prev_block,
+        header.merkle_root,
+        header.time,
+        header.nonce,
+        header.bits
+    );
+    let mut params = Coin::Monacoin.params();
+    params.schedule = vec![Epoch::new(0, "lyra2rev2")];
+    let rpc = RpcHeader::from_json(&json).unwrap();
+    assert_eq!(rpc.header, header);
+    let check = rpc.check(&params).unwrap();
+    assert!(check.is_valid());
+    assert_eq!(check.pow_hash, header.pow_hash(Algorithm::Lyra2REv2));
+    assert_eq!(check.target, Target::from_compact(0x207fffff).unwrap());
+
+    // An edited nonce no longer matches the reported hash.
+    let edited = json.replace(
+        &format!("\"nonce\": {}", header.nonce),
+        &format!("\"nonce\": {}", header.nonce + 1),
+    );
+    let check = RpcHeader::from_json(&edited)
+        .unwrap()
+        .check(&params)
+        .unwrap();
+    assert_eq!(check.hash_matches, Some(false));
+    assert!(!check.is_valid());
+
+    let no_height = json.replace("\"height\": 1, ", "");
+    assert!(matches!(
+        RpcHeader::from_json(&no_height).unwrap().check(&params),
+        Err(RpcError::MissingField("height"))
+    ));
+    assert!(matches!(
+        RpcHeader::from_json(&json.replace("207fffff", "zz")),
+        Err(RpcError::InvalidField("bits", _))
+    ));
+    assert!(matches!(RpcHeader::from_json("{}"), Err(RpcError::Json(_))));
+}
+
+#[test]
+fn rpc_client_cal() {
+    use std::net::TcpListener;
+    use std::thread;
+
+    // A node answering every call with the same reply, read in full first.
+    let node = |reply: String| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                while !request.ends_with(b"}") {
+                    let mut buf = [0; 1024];
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                    reply.len(),
+                    reply
+                );
+            }
+        });
+        addr
+    };
+
+    let result = format!("\"{}\"", "00".repeat(1024));
+    let addr = node(format!(r#"{{"result":{},"error":null,"id":1}}"#, result));
+    let client = RpcClient::new(&addr);
+    assert_eq!(
+        client.call("getblockhash", json!([0])).unwrap().to_string(),
+        result
+    );
+    let client = client.max_response(1024);
+    assert!(matches!(
+        client.call("getblockhash", json!([0])),
+        Err(RpcCallError::TooLarge(1024))
+    ));
+}
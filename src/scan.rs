@@ -0,0 +1,276 @@
+//! # scan
+//!
+//! `scan` crate searches a nonce range of a block header template for hashes that meet a target,
+//! on several threads.
+//!
+//! Each thread keeps its own Lyra2 memory matrix and shares the Blake-256 midstate of the
+//! header, so only the last 16 bytes are rehashed per nonce.
+use crate::algorithm::Algorithm;
+use crate::header::{BlockHeader, Hash256};
+use crate::lyra2::Matrix;
+use crate::target::Target;
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A nonce whose PoW hash meets the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Solution {
+    pub nonce: u32,
+    pub hash: Hash256,
+}
+
+/// Progress of a running scan, passed to the report callback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Report {
+    /// Hashes computed so far by all threads.
+    pub hashes: u64,
+    /// Time since the scan started.
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// Returns the average hashrate since the scan started, in hashes per second.
+    pub fn hashrate(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.hashes as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Shortest interval between two hashrate reports of a [`Scanner`].
+pub const MIN_REPORT_INTERVAL: Duration = Duration::from_millis(1);
+
+type ReportFn = dyn Fn(&Report) + Send + Sync;
+
+/// Configures a scan: the stop flag and periodic hashrate reports.
+pub struct Scanner {
+    stop: Arc<AtomicBool>,
+    report_interval: Duration,
+    on_report: Option<Box<ReportFn>>,
+}
+
+impl Scanner {
+    /// Returns a scanner with its own stop flag and no reports.
+    pub fn new() -> Scanner {
+        Scanner {
+            stop: Arc::new(AtomicBool::new(false)),
+            report_interval: Duration::from_secs(5),
+            on_report: None,
+        }
+    }
+
+    /// Uses `stop` as the stop flag. Setting it makes every thread return after its current
+    /// hash; solutions found so far are still returned.
+    pub fn stop_flag(mut self, stop: Arc<AtomicBool>) -> Scanner {
+        self.stop = stop;
+        self
+    }
+
+    /// Calls `on_report` every `interval` while scanning, and once more when the scan ends.
+    /// An interval below [`MIN_REPORT_INTERVAL`] is raised to it.
+    pub fn on_report<F>(mut self, interval: Duration, on_report: F) -> Scanner
+    where
+        F: Fn(&Report) + Send + Sync + 'static,
+    {
+        self.report_interval = interval.max(MIN_REPORT_INTERVAL);
+        self.on_report = Some(Box::new(on_report));
+        self
+    }
+
+    /// Returns the stop flag of this scanner.
+    pub fn stop(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    /// Hashes `header` with every nonce in `nonces` on `threads` threads and returns the
+    /// solutions, ordered by nonce. The range is split into `threads` contiguous parts, the
+    /// first part going to the first thread.
+    pub fn scan(
+        &self,
+        header: &BlockHeader,
+        nonces: RangeInclusive<u32>,
+        target: &Target,
+        algorithm: Algorithm,
+        threads: usize,
+    ) -> Vec<Solution> {
+        let started = Instant::now();
+        let hashes = AtomicU64::new(0);
+        let midstate = header.midstate();
+        let tail = header.tail();
+        let parts = split(nonces, threads.max(1));
+
+        let mut solutions = thread::scope(|s| {
+            let workers: Vec<_> = parts
+                .into_iter()
+                .map(|part| {
+                    let hashes = &hashes;
+                    let midstate = &midstate;
+                    let stop = &self.stop;
+                    s.spawn(move || {
+                        let mut matrix = Matrix::new();
+                        let mut tail = tail;
+                        let mut found = Vec::new();
+                        for nonce in part.map(|nonce| nonce as u32) {
+                            if stop.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            tail[12..].copy_from_slice(&nonce.to_le_bytes());
+                            let mut hash = [0u8; 32];
+                            hash.copy_from_slice(&algorithm.sum_midstate_with(
+                                &mut matrix,
+                                midstate,
+                                &tail,
+                            ));
+                            hashes.fetch_add(1, Ordering::Relaxed);
+                            if target.is_met_by(&hash) {
+                                found.push(Solution {
+                                    nonce,
+                                    hash: Hash256(hash),
+                                });
+                            }
+                        }
+                        found
+                    })
+                })
+                .collect();
+
+            if let Some(on_report) = &self.on_report {
+                let mut next = started + self.report_interval;
+                while !workers.iter().all(|w| w.is_finished()) {
+                    thread::sleep(Duration::from_millis(10).min(self.report_interval));
+                    if Instant::now() >= next {
+                        on_report(&Report {
+                            hashes: hashes.load(Ordering::Relaxed),
+                            elapsed: started.elapsed(),
+                        });
+                        next += self.report_interval;
+                    }
+                }
+            }
+
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().expect("scan thread panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        if let Some(on_report) = &self.on_report {
+            on_report(&Report {
+                hashes: hashes.load(Ordering::Relaxed),
+                elapsed: started.elapsed(),
+            });
+        }
+        solutions.sort_by_key(|solution| solution.nonce);
+        solutions
+    }
+}
+
+impl Default for Scanner {
+    fn default() -> Self {
+        Scanner::new()
+    }
+}
+
+impl fmt::Debug for Scanner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scanner")
+            .field("stop", &self.stop)
+            .field("report_interval", &self.report_interval)
+            .finish()
+    }
+}
+
+/// Hashes `header` with every nonce in `nonces` on `threads` threads and returns the solutions
+/// that meet `target`, ordered by nonce. See [`Scanner`] for a stop flag and hashrate reports.
+/// # Examples
+///
+/// ```
+/// use lyra2::algorithm::Algorithm;
+/// use lyra2::header::BlockHeader;
+/// use lyra2::target::Target;
+///
+/// let header = BlockHeader::from_hex("010000000000000000000000000000000000000000000000000000000000000000000000a64bac07fe31877f31d03252953b3c32398933af7a724119bc4d6fa4a805e435f083c252f0ff0f1e66d61200").unwrap();
+/// let target = Target::from_difficulty(4.0, &Target::MAX);
+/// for solution in lyra2::scan::scan(&header, 0..=15, &target, Algorithm::Lyra2REv2, 2) {
+///     let mut found = header;
+///     found.nonce = solution.nonce;
+///     assert_eq!(found.pow_hash(Algorithm::Lyra2REv2), solution.hash);
+/// }
+/// ```
+pub fn scan(
+    header: &BlockHeader,
+    nonces: RangeInclusive<u32>,
+    target: &Target,
+    algorithm: Algorithm,
+    threads: usize,
+) -> Vec<Solution> {
+    Scanner::new().scan(header, nonces, target, algorithm, threads)
+}
+
+// split divides `nonces` into `parts` contiguous half-open ranges whose lengths differ by at
+// most one. Empty ranges are kept so that part `i` always belongs to thread `i`.
+fn split(nonces: RangeInclusive<u32>, parts: usize) -> Vec<Range<u64>> {
+    let (start, end) = (u64::from(*nonces.start()), u64::from(*nonces.end()));
+    let len = if start <= end { end - start + 1 } else { 0 };
+    let parts = parts as u64;
+    (0..parts)
+        .map(|i| start + len * i / parts..start + len * (i + 1) / parts)
+        .collect()
+}
+
+#[test]
+fn scan_cal() {
+    use std::sync::Mutex;
+
+    assert_eq!(split(0..=9, 3), vec![0..3, 3..6, 6..10]);
+    assert_eq!(
+        split(0..=u32::MAX, 2),
+        vec![0..0x8000_0000, 0x8000_0000..0x1_0000_0000]
+    );
+    assert_eq!(split(5..=5, 2), vec![5..5, 5..6]);
+
+    let header = BlockHeader::from_hex("010000000000000000000000000000000000000000000000000000000000000000000000e72301fc49323ee151cf1048230f032ca589753ba7086222a5c023e3a08cf34a8b35cf52f0ff0f1e0eba5700").unwrap();
+    let target = Target::from_difficulty(8.0, &Target::MAX);
+    for algorithm in [Algorithm::Lyra2REv2, Algorithm::Lyra2REv3] {
+        let expected: Vec<Solution> = (0..=63)
+            .filter_map(|nonce| {
+                let mut candidate = header;
+                candidate.nonce = nonce;
+                let hash = candidate.pow_hash(algorithm);
+                if target.is_met_by(hash.as_bytes()) {
+                    Some(Solution { nonce, hash })
+                } else {
+                    None
+                }
+            })
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(scan(&header, 0..=63, &target, algorithm, 1), expected);
+        assert_eq!(scan(&header, 0..=63, &target, algorithm, 3), expected);
+    }
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&reports);
+    let scanner = Scanner::new().on_report(Duration::from_millis(1), move |report| {
+        sink.lock().unwrap().push(*report)
+    });
+    scanner.scan(&header, 0..=31, &Target::ZERO, Algorithm::Lyra2REv2, 2);
+    assert_eq!(reports.lock().unwrap().last().unwrap().hashes, 32);
+
+    let scanner = Scanner::new().on_report(Duration::ZERO, |_| {});
+    assert_eq!(scanner.report_interval, MIN_REPORT_INTERVAL);
+    scanner.scan(&header, 0..=7, &Target::ZERO, Algorithm::Lyra2REv2, 1);
+
+    let scanner = Scanner::new();
+    scanner.stop().store(true, Ordering::Relaxed);
+    assert!(scanner
+        .scan(&header, 0..=63, &Target::MAX, Algorithm::Lyra2REv2, 2)
+        .is_empty());
+}
@@ -0,0 +1,466 @@
+//! # solo
+//!
+//! `solo` crate mines alone against a node: it polls `getblocktemplate` with an [`RpcClient`], builds
+//! the block paying to a payout script, scans nonces with the algorithms of this crate and
+//! hands solved blocks back with `submitblock`.
+use crate::algorithm::Algorithm;
+use crate::block::Block;
+use crate::header::Hash256;
+use crate::job::Template;
+use crate::rpc::{RpcCallError, RpcClient, RpcError};
+use crate::scan::Scanner;
+use crate::target::{Target, TargetError};
+use serde_json::{json, Value};
+use std::error;
+use std::fmt;
+use std::sync::atomic::Ordering;
+
+/// A block mined and accepted by the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MinedBlock {
+    pub block: Block,
+    pub block_hash: Hash256,
+    pub pow_hash: Hash256,
+    pub height: u32,
+}
+
+// Nonces scanned by each thread between polls of the node.
+const CHUNK: u32 = 1 << 16;
+
+/// Mines blocks from the templates of a node.
+/// # Examples
+///
+/// ```no_run
+/// use lyra2::algorithm::Algorithm;
+/// use lyra2::rpc::RpcClient;
+/// use lyra2::solo::SoloMiner;
+///
+/// let client = RpcClient::new("127.0.0.1:9402").auth("user", "password");
+/// // Pays to OP_TRUE, which anyone can spend.
+/// let miner = SoloMiner::new(client, Algorithm::Lyra2REv2, &[0x51]).threads(4);
+/// let mined = miner.mine_block().unwrap();
+/// println!("mined block {} at height {}", mined.block_hash, mined.height);
+/// ```
+pub struct SoloMiner {
+    client: RpcClient,
+    algorithm: Algorithm,
+    payout_script: Vec<u8>,
+    coinbase_tag: Vec<u8>,
+    threads: usize,
+    chunk: u32,
+    scanner: Scanner,
+}
+
+impl SoloMiner {
+    /// Returns a miner paying to `payout_script`, on one thread.
+    pub fn new(client: RpcClient, algorithm: Algorithm, payout_script: &[u8]) -> SoloMiner {
+        SoloMiner {
+            client,
+            algorithm,
+            payout_script: payout_script.to_vec(),
+            coinbase_tag: Vec::new(),
+            threads: 1,
+            chunk: CHUNK,
+            scanner: Scanner::new(),
+        }
+    }
+
+    /// Pushes `tag` after the extranonce in the coinbase script.
+    pub fn coinbase_tag(mut self, tag: &[u8]) -> SoloMiner {
+        self.coinbase_tag = tag.to_vec();
+        self
+    }
+
+    /// Scans on `threads` threads.
+    pub fn threads(mut self, threads: usize) -> SoloMiner {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Polls the node for a new template after each thread has scanned `nonces` nonces.
+    pub fn chunk(mut self, nonces: u32) -> SoloMiner {
+        self.chunk = nonces.max(1);
+        self
+    }
+
+    /// Scans with `scanner`, e.g. for its stop flag or hashrate reports.
+    pub fn scanner(mut self, scanner: Scanner) -> SoloMiner {
+        self.scanner = scanner;
+        self
+    }
+
+    /// Returns the RPC client of the node.
+    pub fn client(&self) -> &RpcClient {
+        &self.client
+    }
+
+    /// Fetches the current template of the node.
+    pub fn template(&self) -> Result<Template, SoloError> {
+        let result = self
+            .client
+            .call("getblocktemplate", json!([{"rules": ["segwit"]}]))?;
+        let mut template = Template::from_getblocktemplate(&result, &self.payout_script)?;
+        template.coinbase_tag = self.coinbase_tag.clone();
+        Ok(template)
+    }
+
+    /// Sends `block` to the node with `submitblock`. The node accepts it with a null result;
+    /// anything else is the reason it was rejected.
+    pub fn submit(&self, block: &Block) -> Result<(), SoloError> {
+        match self.client.call("submitblock", json!([block.to_hex()]))? {
+            Value::Null => Ok(()),
+            Value::String(reason) => Err(SoloError::Rejected(reason)),
+            other => Err(SoloError::Call(RpcCallError::Protocol(format!(
+                "unexpected submitblock result: {}",
+                other
+            )))),
+        }
+    }
+
+    /// Mines a block on the current template and submits it. The node is polled for a new
+    /// template between chunks of nonces, and the block moves to it when it changes; once the
+    /// nonces of an extranonce run out, the next extranonce is tried.
+    pub fn mine_block(&self) -> Result<MinedBlock, SoloError> {
+        let stop = self.scanner.stop();
+        let chunk = self.chunk.saturating_mul(self.threads as u32);
+        let mut template = self.template()?;
+        'template: loop {
+            let target = Target::from_compact(template.bits)?;
+            for extranonce in 0u64.. {
+                let mut block = template.block(&extranonce.to_le_bytes(), &[], template.time, 0);
+                let mut start = 0u32;
+                loop {
+                    if stop.load(Ordering::Relaxed) {
+                        return Err(SoloError::Stopped);
+                    }
+                    let end = start.saturating_add(chunk - 1);
+                    let solutions = self.scanner.scan(
+                        &block.header,
+                        start..=end,
+                        &target,
+                        self.algorithm,
+                        self.threads,
+                    );
+                    if let Some(solution) = solutions.first() {
+                        block.header.nonce = solution.nonce;
+                        self.submit(&block)?;
+                        return Ok(MinedBlock {
+                            block_hash: block.header.block_hash(),
+                            pow_hash: solution.hash,
+                            height: template.height,
+                            block,
+                        });
+                    }
+                    let next = self.template()?;
+                    if next != template {
+                        template = next;
+                        continue 'template;
+                    }
+                    if end == u32::MAX {
+                        break;
+                    }
+                    start = end + 1;
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for SoloMiner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SoloMiner")
+            .field("client", &self.client)
+            .field("algorithm", &self.algorithm)
+            .field("payout_script", &self.payout_script)
+            .field("coinbase_tag", &self.coinbase_tag)
+            .field("threads", &self.threads)
+            .field("chunk", &self.chunk)
+            .finish()
+    }
+}
+
+/// An error of solo mining.
+#[derive(Debug)]
+pub enum SoloError {
+    /// A call to the node failed.
+    Call(RpcCallError),
+    /// The block template cannot be read.
+    Template(RpcError),
+    /// The template's `bits` do not decode to a target.
+    InvalidBits(TargetError),
+    /// `submitblock` rejected the block for the given reason.
+    Rejected(String),
+    /// The stop flag was set.
+    Stopped,
+}
+
+impl fmt::Display for SoloError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SoloError::Call(err) => write!(f, "{}", err),
+            SoloError::Template(err) => write!(f, "invalid block template: {}", err),
+            SoloError::InvalidBits(err) => write!(f, "{}", err),
+            SoloError::Rejected(reason) => write!(f, "block rejected: {}", reason),
+            SoloError::Stopped => f.write_str("mining stopped"),
+        }
+    }
+}
+
+impl error::Error for SoloError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SoloError::Call(err) => Some(err),
+            SoloError::Template(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<RpcCallError> for SoloError {
+    fn from(err: RpcCallError) -> Self {
+        SoloError::Call(err)
+    }
+}
+
+impl From<RpcError> for SoloError {
+    fn from(err: RpcError) -> Self {
+        SoloError::Template(err)
+    }
+}
+
+impl From<TargetError> for SoloError {
+    fn from(err: TargetError) -> Self {
+        SoloError::InvalidBits(err)
+    }
+}
+
+#[test]
+fn solo_cal() {
+    use crate::block::{Transaction, TxIn, TxOut};
+    use crate::utils::to_hex;
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    // A mock node: it serves canned getblocktemplate results in turn, repeating the last one,
+    // and records submitted blocks.
+    #[derive(Default)]
+    struct Node {
+        templates: Vec<Value>,
+        polls: usize,
+        submitted: Vec<String>,
+    }
+
+    fn serve(node: &Mutex<Node>, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut authorized = false;
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            authorized |= line == "Authorization: Basic dXNlcjpwYXNz";
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                len = value.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body)?;
+        let request: Value = serde_json::from_slice(&body).unwrap();
+        let (status, reply) = if !authorized {
+            (401, String::new())
+        } else {
+            let mut node = node.lock().unwrap();
+            let result = match request["method"].as_str().unwrap() {
+                "getblocktemplate" => {
+                    let index = node.polls.min(node.templates.len() - 1);
+                    node.polls += 1;
+                    Ok(node.templates[index].clone())
+                }
+                "submitblock" => {
+                    let hex = request["params"][0].as_str().unwrap().to_string();
+                    if node.submitted.contains(&hex) {
+                        Ok(json!("duplicate"))
+                    } else {
+                        node.submitted.push(hex);
+                        Ok(Value::Null)
+                    }
+                }
+                _ => Err(json!({"code": -32601, "message": "Method not found"})),
+            };
+            match result {
+                Ok(result) => (
+                    200,
+                    json!({"result": result, "error": null, "id": request["id"]}).to_string(),
+                ),
+                Err(error) => (
+                    404,
+                    json!({"result": null, "error": error, "id": request["id"]}).to_string(),
+                ),
+            }
+        };
+        write!(
+            &stream,
+            "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            reply.len(),
+            reply
+        )
+    }
+
+    let node = Arc::new(Mutex::new(Node::default()));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    {
+        let node = Arc::clone(&node);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let _ = serve(&node, stream.unwrap());
+            }
+        });
+    }
+
+    // The first template is out of reach of a few nonces; the node then moves to a new tip at
+    // regtest difficulty with a segwit transaction. Regtest templates cannot be fetched here,
+    // so the second one is built in bitcoind's format from a synthetic transaction.
+    let spend = Transaction {
+        version: 2,
+        inputs: vec![TxIn {
+            prev_txid: Hash256::sha256d(b"utxo"),
+            prev_index: 1,
+            script_sig: Vec::new(),
+            sequence: 0xfffffffd,
+            witness: vec![vec![0x30; 71], vec![0x02; 33]],
+        }],
+        outputs: vec![TxOut {
+            value: 4_999_990_000,
+            script_pubkey: vec![0x00, 0x14, 0xaa, 0xbb],
+        }],
+        lock_time: 100,
+    };
+    let next = Template {
+        prev_hash: Hash256::sha256d(b"tip 100"),
+        height: 101,
+        version: 0x20000000,
+        bits: 0x207fffff,
+        time: 1_700_000_600,
+        coinbase_value: 5_000_010_000,
+        payout_script: vec![0x51],
+        coinbase_tag: Vec::new(),
+        transactions: vec![spend.clone()],
+    };
+    let commitment = to_hex(&next.coinbase(&[]).outputs[1].script_pubkey);
+    let template =
+        |prev: &Hash256, bits: &str, height: u32, transactions: Value, commitment: &str| {
+            json!({
+                "capabilities": ["proposal"],
+                "version": 0x20000000,
+                "rules": ["csv", "!segwit"],
+                "vbavailable": {},
+                "vbrequired": 0,
+                "previousblockhash": prev.to_string(),
+                "transactions": transactions,
+                "coinbaseaux": {},
+                "coinbasevalue": 5_000_010_000u64,
+                "longpollid": format!("{}{}", prev, height),
+                "target": "7fffff0000000000000000000000000000000000000000000000000000000000",
+                "mintime": 1_700_000_000,
+                "mutable": ["time", "transactions", "prevblock"],
+                "noncerange": "00000000ffffffff",
+                "sigoplimit": 80000,
+                "sizelimit": 4000000,
+                "weightlimit": 4000000,
+                "curtime": 1_700_000_600,
+                "bits": bits,
+                "height": height,
+                "default_witness_commitment": commitment,
+            })
+        };
+    let transactions = json!([{
+        "data": to_hex(&spend.serialize()),
+        "txid": spend.txid().to_string(),
+        "hash": spend.wtxid().to_string(),
+        "depends": [],
+        "fee": 10_000,
+        "sigops": 1,
+        "weight": 561,
+    }]);
+    node.lock().unwrap().templates = vec![
+        template(
+            &Hash256::sha256d(b"tip 99"),
+            "1d00ffff",
+            100,
+            json!([]),
+            "6a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9",
+        ),
+        template(
+            &next.prev_hash,
+            "207fffff",
+            101,
+            transactions.clone(),
+            &commitment,
+        ),
+    ];
+
+    let client = RpcClient::new(&addr).auth("user", "pass");
+    let miner = SoloMiner::new(client, Algorithm::Lyra2REv2, &[0x51])
+        .coinbase_tag(b"/solo/")
+        .chunk(16);
+    let first = miner.template().unwrap();
+    assert_eq!(first.height, 100);
+    assert_eq!(first.bits, 0x1d00ffff);
+    assert!(first.transactions.is_empty());
+    assert_eq!(first.coinbase_tag, b"/solo/");
+
+    let mined = miner.mine_block().unwrap();
+    let submitted = node.lock().unwrap().submitted.clone();
+    assert_eq!(submitted, vec![mined.block.to_hex()]);
+    let block = Block::from_hex(&submitted[0]).unwrap();
+    assert_eq!(block, mined.block);
+    assert_eq!(mined.height, 101);
+    assert_eq!(block.header.prev_block, next.prev_hash);
+    assert_eq!(block.header.time, 1_700_000_600);
+    assert_eq!(block.header.block_hash(), mined.block_hash);
+    assert_eq!(block.coinbase_height(), Some(101));
+    assert_eq!(block.transactions[1], spend);
+    let coinbase = &block.transactions[0];
+    assert_eq!(coinbase.outputs[0].value, 5_000_010_000);
+    assert_eq!(coinbase.outputs[0].script_pubkey, vec![0x51]);
+    assert_eq!(to_hex(&coinbase.outputs[1].script_pubkey), commitment);
+    assert!(coinbase.inputs[0]
+        .script_sig
+        .ends_with(&[&[6][..], b"/solo/"].concat()));
+    block.validate(Algorithm::Lyra2REv2).unwrap();
+
+    // The node's answers to bad calls.
+    assert!(matches!(
+        miner.submit(&block),
+        Err(SoloError::Rejected(reason)) if reason == "duplicate"
+    ));
+    assert!(matches!(
+        miner.client().call("getblockcount", json!([])),
+        Err(RpcCallError::Rpc { code: -32601, .. })
+    ));
+    let anonymous = SoloMiner::new(RpcClient::new(&addr), Algorithm::Lyra2REv2, &[0x51]);
+    assert!(matches!(
+        anonymous.template(),
+        Err(SoloError::Call(RpcCallError::Http(401)))
+    ));
+
+    // A template whose commitment or txids do not match its transactions is refused.
+    let mut bad = template(&next.prev_hash, "207fffff", 101, transactions, &commitment);
+    bad["default_witness_commitment"] = json!(commitment.replace("aa21a9ed", "aa21a9ee"));
+    assert!(matches!(
+        Template::from_getblocktemplate(&bad, &[0x51]),
+        Err(RpcError::InvalidField("default_witness_commitment", _))
+    ));
+    bad["transactions"][0]["txid"] = json!(spend.wtxid().to_string());
+    assert!(matches!(
+        Template::from_getblocktemplate(&bad, &[0x51]),
+        Err(RpcError::InvalidField("txid", _))
+    ));
+}
@@ -0,0 +1,472 @@
+use super::{Job, StratumError};
+use crate::algorithm::Algorithm;
+use crate::difficulty::Convention;
+use crate::header::Hash256;
+use crate::scan::Scanner;
+use crate::target::Target;
+use crate::utils::{from_hex, to_hex};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::RangeInclusive;
+
+// Longest message line a pool may send; notify lines carry whole coinbase halves.
+const MAX_LINE_SIZE: u64 = 64 * 1024;
+
+/// Settings of a stratum [`Client`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Worker name sent with `mining.authorize` and `mining.submit`.
+    pub worker: String,
+    pub password: String,
+    /// Sent with `mining.subscribe`.
+    pub user_agent: String,
+    /// The PoW algorithm shares are hashed with.
+    pub algorithm: Algorithm,
+    /// The target of difficulty 1, used to turn `mining.set_difficulty` into a share target.
+    pub diff1: Target,
+    /// Number of threads used by [`Client::mine`].
+    pub threads: usize,
+}
+
+impl Config {
+    /// Returns a config with the NOMP difficulty-1 target of `algorithm`, as a default
+    /// [`super::Server`] uses, and one thread.
+    pub fn new(worker: &str, password: &str, algorithm: Algorithm) -> Config {
+        Config {
+            worker: worker.to_string(),
+            password: password.to_string(),
+            user_agent: concat!("lyra2/", env!("CARGO_PKG_VERSION")).to_string(),
+            algorithm,
+            diff1: Convention::Nomp.diff1(algorithm),
+            threads: 1,
+        }
+    }
+}
+
+/// A notification received from the pool.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// `mining.notify`. The job is now the client's current job.
+    Job(Job),
+    /// `mining.set_difficulty`. It applies to work created afterwards.
+    Difficulty(f64),
+    /// `mining.set_extranonce`. It applies to work created afterwards.
+    Extranonce,
+    /// Any other notification.
+    Other { method: String, params: Value },
+}
+
+/// A unit of work: the current job with a fresh extranonce2 and a possibly rolled ntime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Work {
+    pub job: Job,
+    pub extranonce1: Vec<u8>,
+    pub extranonce2: Vec<u8>,
+    pub time: u32,
+    /// The share target at the time the work was created.
+    pub target: Target,
+}
+
+/// A share found by [`Client::mine`], ready for [`Client::submit`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub job_id: String,
+    pub extranonce2: Vec<u8>,
+    pub time: u32,
+    pub nonce: u32,
+    pub hash: Hash256,
+}
+
+impl Share {
+    /// Returns the params of `mining.submit` for `worker`:
+    /// `[worker, job_id, extranonce2, ntime, nonce]`, with ntime and nonce as big-endian hex.
+    pub fn to_submit(&self, worker: &str) -> Value {
+        json!([
+            worker,
+            self.job_id,
+            to_hex(&self.extranonce2),
+            format!("{:08x}", self.time),
+            format!("{:08x}", self.nonce),
+        ])
+    }
+}
+
+/// A blocking Stratum v1 mining client.
+/// # Examples
+///
+/// ```no_run
+/// use lyra2::algorithm::Algorithm;
+/// use lyra2::stratum::{Client, Config, Event};
+///
+/// let config = Config::new("worker.1", "x", Algorithm::Lyra2REv2);
+/// let mut client = Client::connect("127.0.0.1:3333", config).unwrap();
+/// client.subscribe().unwrap();
+/// client.authorize().unwrap();
+/// loop {
+///     if let Event::Job(_) = client.next_event().unwrap() {
+///         let work = client.work(0).unwrap();
+///         for share in client.mine(&work, 0..=0xffff) {
+///             client.submit(&share).unwrap();
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Client {
+    config: Config,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    next_id: u64,
+    extranonce1: Vec<u8>,
+    extranonce2_size: usize,
+    extranonce2_counter: u64,
+    difficulty: f64,
+    job: Option<Job>,
+    events: VecDeque<Event>,
+}
+
+impl Client {
+    /// Connects to a pool. Call [`Client::subscribe`] and [`Client::authorize`] next.
+    pub fn connect<A: ToSocketAddrs>(addr: A, config: Config) -> Result<Client, StratumError> {
+        let writer = TcpStream::connect(addr)?;
+        writer.set_nodelay(true)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Client {
+            config,
+            reader,
+            writer,
+            next_id: 1,
+            extranonce1: Vec::new(),
+            extranonce2_size: 4,
+            extranonce2_counter: 0,
+            difficulty: 1.0,
+            job: None,
+            events: VecDeque::new(),
+        })
+    }
+
+    /// Sends `mining.subscribe` and stores extranonce1 and the extranonce2 size.
+    pub fn subscribe(&mut self) -> Result<(), StratumError> {
+        let result = self.call("mining.subscribe", json!([self.config.user_agent]))?;
+        let (extranonce1, extranonce2_size) = result
+            .as_array()
+            .filter(|result| result.len() >= 3)
+            .and_then(|result| Some((result[1].as_str()?, result[2].as_u64()?)))
+            .ok_or_else(|| StratumError::Protocol("malformed subscribe result".to_string()))?;
+        self.set_extranonce(extranonce1, extranonce2_size)
+    }
+
+    /// Sends `mining.authorize` for the configured worker.
+    pub fn authorize(&mut self) -> Result<(), StratumError> {
+        let params = json!([self.config.worker, self.config.password]);
+        match self.call("mining.authorize", params)? {
+            Value::Bool(true) => Ok(()),
+            _ => Err(StratumError::Rejected("authorization failed".to_string())),
+        }
+    }
+
+    /// Submits a share. A share the pool rejects is a [`StratumError::Rejected`].
+    pub fn submit(&mut self, share: &Share) -> Result<(), StratumError> {
+        let params = share.to_submit(&self.config.worker);
+        match self.call("mining.submit", params)? {
+            Value::Bool(true) => Ok(()),
+            _ => Err(StratumError::Rejected("share rejected".to_string())),
+        }
+    }
+
+    /// Returns the next notification, waiting for the pool if none is queued.
+    pub fn next_event(&mut self) -> Result<Event, StratumError> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            let message = self.read_message()?;
+            if message.get("method").is_some() {
+                self.handle_notification(&message)?;
+            }
+        }
+    }
+
+    /// Returns the current job, if any.
+    pub fn job(&self) -> Option<&Job> {
+        self.job.as_ref()
+    }
+
+    /// Returns the extranonce1 assigned by the pool.
+    pub fn extranonce1(&self) -> &[u8] {
+        &self.extranonce1
+    }
+
+    /// Returns the current share difficulty.
+    pub fn difficulty(&self) -> f64 {
+        self.difficulty
+    }
+
+    /// Returns the current share target.
+    pub fn target(&self) -> Target {
+        Target::from_difficulty(self.difficulty, &self.config.diff1)
+    }
+
+    /// Returns work for the current job with the next extranonce2 and ntime rolled forward by
+    /// `ntime_offset` seconds, or `None` before the first job.
+    pub fn work(&mut self, ntime_offset: u32) -> Option<Work> {
+        let job = self.job.clone()?;
+        let counter = self.extranonce2_counter.to_le_bytes();
+        self.extranonce2_counter = self.extranonce2_counter.wrapping_add(1);
+        let mut extranonce2 = vec![0u8; self.extranonce2_size];
+        for (byte, counter) in extranonce2.iter_mut().zip(counter.iter()) {
+            *byte = *counter;
+        }
+        Some(Work {
+            time: job.time.wrapping_add(ntime_offset),
+            job,
+            extranonce1: self.extranonce1.clone(),
+            extranonce2,
+            target: self.target(),
+        })
+    }
+
+    /// Hashes `work` with every nonce in `nonces` using the configured algorithm and threads,
+    /// and returns the shares that meet the work's target.
+    pub fn mine(&self, work: &Work, nonces: RangeInclusive<u32>) -> Vec<Share> {
+        let header = work
+            .job
+            .header(&work.extranonce1, &work.extranonce2, work.time, 0);
+        Scanner::new()
+            .scan(
+                &header,
+                nonces,
+                &work.target,
+                self.config.algorithm,
+                self.config.threads,
+            )
+            .into_iter()
+            .map(|solution| Share {
+                job_id: work.job.job_id.clone(),
+                extranonce2: work.extranonce2.clone(),
+                time: work.time,
+                nonce: solution.nonce,
+                hash: solution.hash,
+            })
+            .collect()
+    }
+
+    // call sends a request and waits for its response, queueing notifications that arrive in
+    // the meantime.
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, StratumError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({"id": id, "method": method, "params": params});
+        self.writer.write_all(format!("{}\n", request).as_bytes())?;
+        loop {
+            let message = self.read_message()?;
+            if message.get("method").is_some() {
+                self.handle_notification(&message)?;
+                continue;
+            }
+            if message.get("id").and_then(Value::as_u64) != Some(id) {
+                continue;
+            }
+            return match message.get("error") {
+                Some(error) if !error.is_null() => Err(StratumError::Rejected(error.to_string())),
+                _ => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+            };
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Value, StratumError> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if (&mut self.reader)
+                .take(MAX_LINE_SIZE)
+                .read_line(&mut line)?
+                == 0
+            {
+                return Err(StratumError::Protocol("connection closed".to_string()));
+            }
+            if !line.ends_with('\n') && line.len() as u64 == MAX_LINE_SIZE {
+                return Err(StratumError::Protocol("message line too long".to_string()));
+            }
+            if !line.trim().is_empty() {
+                return Ok(serde_json::from_str(&line)?);
+            }
+        }
+    }
+
+    fn handle_notification(&mut self, message: &Value) -> Result<(), StratumError> {
+        let method = message["method"].as_str().unwrap_or_default().to_string();
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let event = match method.as_str() {
+            "mining.notify" => {
+                let job = Job::from_notify(&params)?;
+                self.job = Some(job.clone());
+                Event::Job(job)
+            }
+            "mining.set_difficulty" => {
+                let difficulty = params[0].as_f64().ok_or_else(|| {
+                    StratumError::Protocol("malformed mining.set_difficulty".to_string())
+                })?;
+                self.difficulty = difficulty;
+                Event::Difficulty(difficulty)
+            }
+            "mining.set_extranonce" => {
+                let extranonce1 = params[0].as_str().unwrap_or_default().to_string();
+                let extranonce2_size = params[1].as_u64().unwrap_or(0);
+                self.set_extranonce(&extranonce1, extranonce2_size)?;
+                Event::Extranonce
+            }
+            _ => Event::Other { method, params },
+        };
+        self.events.push_back(event);
+        Ok(())
+    }
+
+    fn set_extranonce(
+        &mut self,
+        extranonce1: &str,
+        extranonce2_size: u64,
+    ) -> Result<(), StratumError> {
+        if extranonce2_size == 0 || extranonce2_size > 8 {
+            return Err(StratumError::Protocol(format!(
+                "unsupported extranonce2 size: {}",
+                extranonce2_size
+            )));
+        }
+        self.extranonce1 = from_hex(extranonce1)
+            .ok_or_else(|| StratumError::Protocol("malformed extranonce1".to_string()))?;
+        self.extranonce2_size = extranonce2_size as usize;
+        self.extranonce2_counter = 0;
+        Ok(())
+    }
+}
+
+#[test]
+fn stratum_client_cal() {
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let job = Job {
+        job_id: "6a".to_string(),
+        prev_hash: "4d96a915f49d40b1e5c2844d1ee2dccb90013a990ccea12c492d22110489f0c4"
+            .parse()
+            .unwrap(),
+        coinb1: from_hex("01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0d03").unwrap(),
+        coinb2: from_hex("ffffffff0100f2052a010000001976a914000000000000000000000000000000000000000088ac00000000").unwrap(),
+        merkle_branch: vec![Hash256([0x11; 32])],
+        version: 0x2000_0000,
+        bits: 0x1e0ffff0,
+        time: 1_600_000_000,
+        clean_jobs: true,
+    };
+    // One share in 16 meets the target.
+    let difficulty = 1.0 / 1_048_576.0;
+    let extranonce1 = vec![0xf0, 0x00, 0x00, 0x0f];
+
+    // The mock pool checks every submitted share against its own copy of the job.
+    let server_job = job.clone();
+    let server_extranonce1 = extranonce1.clone();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut accepted = 0;
+        for line in BufReader::new(stream).lines() {
+            let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+            let id = request["id"].clone();
+            let params = &request["params"];
+            let response = match request["method"].as_str().unwrap() {
+                "mining.subscribe" => json!({"id": id, "result": [
+                    [["mining.set_difficulty", "1"], ["mining.notify", "1"]],
+                    to_hex(&server_extranonce1),
+                    4
+                ], "error": null}),
+                "mining.authorize" => {
+                    let response = json!({"id": id, "result": true, "error": null});
+                    writeln!(writer, "{}", response).unwrap();
+                    writeln!(
+                        writer,
+                        "{}",
+                        json!({"id": null, "method": "mining.set_difficulty", "params": [difficulty]})
+                    )
+                    .unwrap();
+                    json!({"id": null, "method": "mining.notify", "params": server_job.to_notify()})
+                }
+                "mining.submit" => {
+                    assert_eq!(params[0], "worker.1");
+                    assert_eq!(params[1], server_job.job_id.as_str());
+                    let extranonce2 = from_hex(params[2].as_str().unwrap()).unwrap();
+                    let time = u32::from_str_radix(params[3].as_str().unwrap(), 16).unwrap();
+                    let nonce = u32::from_str_radix(params[4].as_str().unwrap(), 16).unwrap();
+                    let header = server_job.header(&server_extranonce1, &extranonce2, time, nonce);
+                    let target = Convention::Nomp.share_target(Algorithm::Lyra2REv2, difficulty);
+                    if target.is_met_by(header.pow_hash(Algorithm::Lyra2REv2).as_bytes()) {
+                        accepted += 1;
+                        json!({"id": id, "result": true, "error": null})
+                    } else {
+                        json!({"id": id, "result": null, "error": [23, "Low difficulty share", null]})
+                    }
+                }
+                _ => json!({"id": id, "result": null, "error": [20, "Unknown method", null]}),
+            };
+            writeln!(writer, "{}", response).unwrap();
+        }
+        accepted
+    });
+
+    let config = Config::new("worker.1", "x", Algorithm::Lyra2REv2);
+    let mut client = Client::connect(addr, config).unwrap();
+    client.subscribe().unwrap();
+    assert_eq!(client.extranonce1(), extranonce1.as_slice());
+    client.authorize().unwrap();
+    assert_eq!(client.next_event().unwrap(), Event::Difficulty(difficulty));
+    assert_eq!(client.next_event().unwrap(), Event::Job(job.clone()));
+
+    let work = client.work(0).unwrap();
+    assert_eq!(work.extranonce2, vec![0, 0, 0, 0]);
+    let shares = client.mine(&work, 0..=63);
+    assert!(!shares.is_empty());
+    for share in &shares {
+        client.submit(share).unwrap();
+    }
+
+    // ntime rolling changes the header, so shares must be re-mined.
+    let rolled = client.work(1).unwrap();
+    assert_eq!(rolled.extranonce2, vec![1, 0, 0, 0]);
+    assert_eq!(rolled.time, job.time + 1);
+    let mut stale = shares[0].clone();
+    stale.nonce = (0..=u32::MAX)
+        .find(|&nonce| {
+            let header = job.header(&extranonce1, &work.extranonce2, work.time, nonce);
+            !work
+                .target
+                .is_met_by(header.pow_hash(Algorithm::Lyra2REv2).as_bytes())
+        })
+        .unwrap();
+    assert!(matches!(
+        client.submit(&stale),
+        Err(StratumError::Rejected(_))
+    ));
+
+    drop(client);
+    assert_eq!(server.join().unwrap(), shares.len());
+
+    // A pool line without an end is cut off instead of buffered without bound.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.write_all(&vec![b' '; MAX_LINE_SIZE as usize]);
+        stream
+    });
+    let config = Config::new("worker.1", "x", Algorithm::Lyra2REv2);
+    let mut client = Client::connect(addr, config).unwrap();
+    assert_eq!(
+        client.subscribe().unwrap_err().to_string(),
+        "stratum protocol error: message line too long"
+    );
+    drop(server.join().unwrap());
+}
@@ -0,0 +1,192 @@
+//! # stratum
+//!
+//! `stratum` crate speaks Stratum v1 over TCP JSON lines: [`Job`]s from `mining.notify`, a
+//! mining [`Client`] that hashes them with the algorithms of this crate, and a small pool
+//! [`Server`] that hands out jobs and checks shares.
+use crate::header::Hash256;
+use crate::job::swap_words;
+use crate::utils::{from_hex, to_hex};
+use serde_json::{json, Value};
+use std::error;
+use std::fmt;
+use std::io;
+
+mod client;
+mod server;
+
+pub use crate::job::Job;
+pub use client::{Client, Config, Event, Share, Work};
+pub use server::{FoundBlock, Server, ServerConfig, ServerHandle, EXTRANONCE1_SIZE};
+
+impl Job {
+    /// Parses the params of `mining.notify`:
+    /// `[job_id, prevhash, coinb1, coinb2, merkle_branch, version, nbits, ntime, clean_jobs]`.
+    ///
+    /// `prevhash` is sent with each 4-byte word byte-swapped; `version`, `nbits` and `ntime`
+    /// are big-endian hex.
+    pub fn from_notify(params: &Value) -> Result<Job, StratumError> {
+        let params = params
+            .as_array()
+            .filter(|params| params.len() >= 9)
+            .ok_or_else(|| StratumError::Protocol("malformed mining.notify".to_string()))?;
+        let mut prev_hash = hex_param(&params[1], "prevhash")?;
+        swap_words(&mut prev_hash);
+        let merkle_branch = params[4]
+            .as_array()
+            .ok_or_else(|| StratumError::Protocol("malformed merkle_branch".to_string()))?
+            .iter()
+            .map(|hash| {
+                Hash256::from_slice(&hex_param(hash, "merkle_branch")?)
+                    .map_err(|_| StratumError::Protocol("malformed merkle_branch".to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Job {
+            job_id: str_param(&params[0], "job_id")?.to_string(),
+            prev_hash: Hash256::from_slice(&prev_hash)
+                .map_err(|_| StratumError::Protocol("malformed prevhash".to_string()))?,
+            coinb1: hex_param(&params[2], "coinb1")?,
+            coinb2: hex_param(&params[3], "coinb2")?,
+            merkle_branch,
+            version: u32_param(&params[5], "version")? as i32,
+            bits: u32_param(&params[6], "nbits")?,
+            time: u32_param(&params[7], "ntime")?,
+            clean_jobs: params[8].as_bool().unwrap_or(false),
+        })
+    }
+
+    /// Returns the params of `mining.notify` for this job, the inverse of [`Job::from_notify`].
+    pub fn to_notify(&self) -> Value {
+        let mut prev_hash = self.prev_hash.0;
+        swap_words(&mut prev_hash);
+        json!([
+            self.job_id,
+            to_hex(&prev_hash),
+            to_hex(&self.coinb1),
+            to_hex(&self.coinb2),
+            self.merkle_branch
+                .iter()
+                .map(|hash| to_hex(hash.as_bytes()))
+                .collect::<Vec<_>>(),
+            format!("{:08x}", self.version as u32),
+            format!("{:08x}", self.bits),
+            format!("{:08x}", self.time),
+            self.clean_jobs,
+        ])
+    }
+}
+
+fn str_param<'a>(value: &'a Value, name: &str) -> Result<&'a str, StratumError> {
+    value
+        .as_str()
+        .ok_or_else(|| StratumError::Protocol(format!("malformed {}", name)))
+}
+
+fn hex_param(value: &Value, name: &str) -> Result<Vec<u8>, StratumError> {
+    from_hex(str_param(value, name)?)
+        .ok_or_else(|| StratumError::Protocol(format!("malformed {}", name)))
+}
+
+fn u32_param(value: &Value, name: &str) -> Result<u32, StratumError> {
+    u32::from_str_radix(str_param(value, name)?, 16)
+        .map_err(|_| StratumError::Protocol(format!("malformed {}", name)))
+}
+
+/// An error of a stratum connection.
+#[derive(Debug)]
+pub enum StratumError {
+    /// The connection failed.
+    Io(io::Error),
+    /// A line was not valid JSON.
+    Json(serde_json::Error),
+    /// A message did not follow the protocol.
+    Protocol(String),
+    /// The pool answered a request with an error.
+    Rejected(String),
+}
+
+impl fmt::Display for StratumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StratumError::Io(err) => write!(f, "stratum connection failed: {}", err),
+            StratumError::Json(err) => write!(f, "invalid stratum message: {}", err),
+            StratumError::Protocol(msg) => write!(f, "stratum protocol error: {}", msg),
+            StratumError::Rejected(msg) => write!(f, "rejected by pool: {}", msg),
+        }
+    }
+}
+
+impl error::Error for StratumError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            StratumError::Io(err) => Some(err),
+            StratumError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StratumError {
+    fn from(err: io::Error) -> Self {
+        StratumError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for StratumError {
+    fn from(err: serde_json::Error) -> Self {
+        StratumError::Json(err)
+    }
+}
+
+#[test]
+fn stratum_job_cal() {
+    let params = json!([
+        "1",
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "",
+        "",
+        [],
+        "00000001",
+        "1e0ffff0",
+        "52c283f0",
+        true
+    ]);
+    let job = Job::from_notify(&params).unwrap();
+    assert_eq!(job.version, 1);
+    assert_eq!(job.bits, 0x1e0ffff0);
+    assert_eq!(job.time, 1388479472);
+    assert!(job.clean_jobs);
+    assert_eq!(Job::from_notify(&job.to_notify()).unwrap(), job);
+    let header = job.header(&[], &[], job.time + 1, 1234534);
+    assert_eq!(header.time, 1388479473);
+    assert_eq!(header.nonce, 1234534);
+    assert_eq!(header.merkle_root, Hash256::sha256d(&[]));
+
+    // prevhash words are byte-swapped on the wire.
+    let params = json!([
+        "2",
+        "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+        "01",
+        "02",
+        ["0000000000000000000000000000000000000000000000000000000000000000"],
+        "20000000",
+        "1b0404cb",
+        "5f5e1000",
+        false
+    ]);
+    let job = Job::from_notify(&params).unwrap();
+    assert_eq!(
+        to_hex(job.prev_hash.as_bytes()),
+        "03020100070605040b0a09080f0e0d0c13121110171615141b1a19181f1e1d1c"
+    );
+    assert_eq!(
+        job.coinbase(&[0xaa], &[0xbb, 0xcc]),
+        vec![1, 0xaa, 0xbb, 0xcc, 2]
+    );
+    let leaf = Hash256::sha256d(&[1, 0xaa, 0xbb, 0xcc, 2]);
+    assert_eq!(
+        job.merkle_root(&[0xaa], &[0xbb, 0xcc]),
+        Hash256::sha256d(&[leaf.0, [0u8; 32]].concat())
+    );
+    assert_eq!(job.to_notify(), params);
+    assert!(Job::from_notify(&json!(["3"])).is_err());
+}
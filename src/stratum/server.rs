@@ -0,0 +1,946 @@
+use super::StratumError;
+use crate::algorithm::Algorithm;
+use crate::block::Block;
+use crate::difficulty::Convention;
+use crate::header::{BlockHeader, Hash256};
+use crate::job::{Job, Template};
+use crate::utils::{from_hex, to_hex};
+use crate::vardiff::{Clock, SystemClock, VarDiff, VarDiffConfig};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Size in bytes of the extranonce1 the server assigns to each session.
+pub const EXTRANONCE1_SIZE: usize = 4;
+
+// How far past the job time a share's ntime may be rolled, as nodes allow for block times.
+const MAX_NTIME_ROLL: u32 = 7200;
+
+// Longest request line a miner may send before its session is closed.
+const MAX_LINE_SIZE: u64 = 16 * 1024;
+
+// Messages queued for a session before it is closed as too slow to read them.
+const OUTBOX_SIZE: usize = 256;
+
+// How long a write to a miner may block before its session is closed.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings of a stratum [`Server`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    /// The PoW algorithm shares are hashed with.
+    pub algorithm: Algorithm,
+    /// How share difficulty maps to a share target.
+    pub convention: Convention,
+    /// The share difficulty every session starts with.
+    pub difficulty: f64,
+    /// Retargets the difficulty of each session to its share rate, starting from
+    /// `difficulty`.
+    pub vardiff: Option<VarDiffConfig>,
+    /// Size in bytes of the extranonce2 that miners roll.
+    pub extranonce2_size: usize,
+    /// Number of jobs of the current template chain that shares are accepted for.
+    pub max_jobs: usize,
+    /// Connections served at once; more are closed as soon as they are accepted.
+    pub max_sessions: usize,
+    /// Time a miner may send nothing before its session is closed.
+    pub idle_timeout: Duration,
+}
+
+impl ServerConfig {
+    /// Returns a config with NOMP difficulty 1 without vardiff, a 4-byte extranonce2, 4 live
+    /// jobs, up to 1024 sessions and a 10-minute idle timeout.
+    pub fn new(algorithm: Algorithm) -> ServerConfig {
+        ServerConfig {
+            algorithm,
+            convention: Convention::Nomp,
+            difficulty: 1.0,
+            vardiff: None,
+            extranonce2_size: 4,
+            max_jobs: 4,
+            max_sessions: 1024,
+            idle_timeout: Duration::from_secs(600),
+        }
+    }
+}
+
+/// A share that solved a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundBlock {
+    pub block: Block,
+    pub block_hash: Hash256,
+    pub pow_hash: Hash256,
+    /// The worker name that submitted the share.
+    pub worker: String,
+}
+
+type BlockFn = dyn Fn(&FoundBlock) + Send + Sync;
+
+/// Configures a Stratum v1 pool server: the block callback and the vardiff clock.
+/// [`Server::listen`] starts it.
+/// # Examples
+///
+/// ```no_run
+/// use lyra2::algorithm::Algorithm;
+/// use lyra2::stratum::{Server, ServerConfig};
+/// # fn template() -> lyra2::job::Template { unimplemented!() }
+///
+/// let server = Server::new(ServerConfig::new(Algorithm::Lyra2REv3))
+///     .on_block(|found| println!("block {}: {}", found.block_hash, found.block.to_hex()))
+///     .listen("0.0.0.0:3333")
+///     .unwrap();
+/// server.set_template(template());
+/// ```
+pub struct Server {
+    config: ServerConfig,
+    on_block: Option<Box<BlockFn>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Server {
+    /// Returns a server without a block callback, on the system clock.
+    pub fn new(config: ServerConfig) -> Server {
+        Server {
+            config,
+            on_block: None,
+            clock: Arc::new(SystemClock::new()),
+        }
+    }
+
+    /// Calls `on_block` with every share that meets the network target of its job, on the
+    /// thread of the submitting session before the share is answered.
+    pub fn on_block<F>(mut self, on_block: F) -> Server
+    where
+        F: Fn(&FoundBlock) + Send + Sync + 'static,
+    {
+        self.on_block = Some(Box::new(on_block));
+        self
+    }
+
+    /// Times shares for vardiff with `clock`.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Server {
+        self.clock = clock;
+        self
+    }
+
+    /// Listens on `addr` and serves each connection on its own thread. Sessions get work once a
+    /// template is set with [`ServerHandle::set_template`].
+    pub fn listen<A: ToSocketAddrs>(self, addr: A) -> Result<ServerHandle, StratumError> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            config: self.config,
+            on_block: self.on_block,
+            clock: self.clock,
+            stop: AtomicBool::new(false),
+            connections: AtomicUsize::new(0),
+            state: Mutex::new(State::default()),
+        });
+        let accept = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shared.stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+                    // Only this loop adds connections, so the count cannot pass the limit.
+                    if shared.connections.load(Ordering::SeqCst) >= shared.config.max_sessions {
+                        continue;
+                    }
+                    shared.connections.fetch_add(1, Ordering::SeqCst);
+                    let shared = Arc::clone(&shared);
+                    thread::spawn(move || {
+                        serve(&shared, stream);
+                        shared.connections.fetch_sub(1, Ordering::SeqCst);
+                    });
+                }
+            })
+        };
+        Ok(ServerHandle {
+            shared,
+            local_addr,
+            accept: Some(accept),
+        })
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// A running [`Server`]. Dropping it shuts the server down.
+pub struct ServerHandle {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// Returns the address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Makes a job of `template` the current one and sends it to every authorized session.
+    /// When the previous block changes, earlier jobs become stale and the job has
+    /// `clean_jobs` set.
+    pub fn set_template(&self, template: Template) {
+        let extranonce_size = EXTRANONCE1_SIZE + self.shared.config.extranonce2_size;
+        let mut state = self.shared.state.lock().unwrap();
+        state.next_job_id += 1;
+        let clean = state
+            .jobs
+            .back()
+            .map_or(true, |entry| entry.template.prev_hash != template.prev_hash);
+        if clean {
+            state.jobs.clear();
+        }
+        let job = template.job(&format!("{:x}", state.next_job_id), extranonce_size, clean);
+        state.jobs.push_back(JobEntry {
+            job,
+            template,
+            shares: HashSet::new(),
+        });
+        while state.jobs.len() > self.shared.config.max_jobs.max(1) {
+            state.jobs.pop_front();
+        }
+        let live: HashSet<String> = state
+            .jobs
+            .iter()
+            .map(|entry| entry.job.job_id.clone())
+            .collect();
+        let job = state.jobs.back().unwrap().job.clone();
+        let notify = notification("mining.notify", job.to_notify());
+        for session in state.sessions.values_mut() {
+            if session.worker.is_some() {
+                session
+                    .job_difficulty
+                    .retain(|job_id, _| live.contains(job_id));
+                session
+                    .job_difficulty
+                    .insert(job.job_id.clone(), session.difficulty);
+                session.outbox.send(&notify);
+            }
+        }
+    }
+
+    /// Sets the share difficulty of the session with `extranonce1` and sends it to the miner.
+    /// It applies to jobs sent afterwards; shares of earlier jobs are checked against the lower
+    /// of the old and new difficulty. With vardiff, the session retargets from there on.
+    /// Returns false if there is no such session.
+    pub fn set_difficulty(&self, extranonce1: &[u8], difficulty: f64) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let session = match state
+            .sessions
+            .values_mut()
+            .find(|s| s.extranonce1 == extranonce1)
+        {
+            Some(session) => session,
+            None => return false,
+        };
+        if let Some(vardiff) = &mut session.vardiff {
+            vardiff.set_difficulty(difficulty);
+        }
+        session.change_difficulty(difficulty);
+        true
+    }
+
+    /// Returns the number of connected sessions.
+    pub fn sessions(&self) -> usize {
+        self.shared.state.lock().unwrap().sessions.len()
+    }
+
+    /// Stops accepting connections and closes every session.
+    pub fn shutdown(&mut self) {
+        if let Some(accept) = self.accept.take() {
+            self.shared.stop.store(true, Ordering::SeqCst);
+            // Wake the accept loop up.
+            let _ = TcpStream::connect(self.local_addr);
+            let _ = accept.join();
+            for session in self.shared.state.lock().unwrap().sessions.values() {
+                session.outbox.close();
+            }
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl fmt::Debug for ServerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerHandle")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+struct Shared {
+    config: ServerConfig,
+    on_block: Option<Box<BlockFn>>,
+    clock: Arc<dyn Clock>,
+    stop: AtomicBool,
+    // Connections being served, at most `config.max_sessions`.
+    connections: AtomicUsize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // Live jobs, the current one last.
+    jobs: VecDeque<JobEntry>,
+    next_job_id: u64,
+    next_session: u32,
+    sessions: HashMap<u32, Session>,
+}
+
+struct JobEntry {
+    job: Job,
+    template: Template,
+    // (extranonce1, extranonce2, ntime, nonce) of accepted shares.
+    shares: HashSet<(Vec<u8>, Vec<u8>, u32, u32)>,
+}
+
+struct Session {
+    outbox: Outbox,
+    extranonce1: Vec<u8>,
+    subscribed: bool,
+    worker: Option<String>,
+    difficulty: f64,
+    // The difficulty of each live job as the miner received it.
+    job_difficulty: HashMap<String, f64>,
+    vardiff: Option<VarDiff>,
+}
+
+impl Session {
+    // change_difficulty sets the difficulty of later jobs and sends it to the miner.
+    fn change_difficulty(&mut self, difficulty: f64) {
+        self.difficulty = difficulty;
+        for job_difficulty in self.job_difficulty.values_mut() {
+            *job_difficulty = job_difficulty.min(difficulty);
+        }
+        self.outbox
+            .send(&notification("mining.set_difficulty", json!([difficulty])));
+    }
+}
+
+// Outbox queues the messages of a session for its writer thread, so that no miner is written
+// to while the state is locked.
+#[derive(Clone)]
+struct Outbox {
+    sender: SyncSender<String>,
+    stream: Arc<TcpStream>,
+}
+
+impl Outbox {
+    // start spawns the writer thread of `stream`, which closes the connection when a write
+    // fails or times out.
+    fn start(stream: TcpStream) -> Outbox {
+        let (sender, receiver) = mpsc::sync_channel::<String>(OUTBOX_SIZE);
+        let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+        let stream = Arc::new(stream);
+        let writer = Arc::clone(&stream);
+        thread::spawn(move || {
+            for line in receiver {
+                if writeln!(&*writer, "{}", line).is_err() {
+                    let _ = writer.shutdown(Shutdown::Both);
+                    break;
+                }
+            }
+        });
+        Outbox { sender, stream }
+    }
+
+    // send queues one JSON line; a miner that falls too far behind is disconnected, which
+    // surfaces as a read error of the session.
+    fn send(&self, message: &Value) {
+        match self.sender.try_send(message.to_string()) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => self.close(),
+        }
+    }
+
+    fn close(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+// Stratum error codes.
+const OTHER: i64 = 20;
+const STALE: i64 = 21;
+const DUPLICATE: i64 = 22;
+const LOW_DIFFICULTY: i64 = 23;
+const UNAUTHORIZED: i64 = 24;
+const NOT_SUBSCRIBED: i64 = 25;
+
+// serve runs one session until the connection closes.
+fn serve(shared: &Shared, stream: TcpStream) {
+    let outbox = match stream.try_clone() {
+        Ok(writer) => Outbox::start(writer),
+        Err(_) => return,
+    };
+    let _ = stream.set_nodelay(true);
+    let _ = stream.set_read_timeout(Some(shared.config.idle_timeout));
+    let id = {
+        let mut state = shared.state.lock().unwrap();
+        let id = state.next_session;
+        state.next_session = state.next_session.wrapping_add(1);
+        let vardiff = shared.config.vardiff.clone().map(|config| {
+            VarDiff::new(config, shared.config.difficulty).clock(Arc::clone(&shared.clock))
+        });
+        state.sessions.insert(
+            id,
+            Session {
+                outbox: outbox.clone(),
+                extranonce1: id.to_be_bytes().to_vec(),
+                subscribed: false,
+                worker: None,
+                difficulty: vardiff
+                    .as_ref()
+                    .map_or(shared.config.difficulty, VarDiff::difficulty),
+                job_difficulty: HashMap::new(),
+                vardiff,
+            },
+        );
+        id
+    };
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        match (&mut reader).take(MAX_LINE_SIZE).read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if !line.ends_with('\n') && line.len() as u64 == MAX_LINE_SIZE {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request: Value = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(_) => break,
+        };
+        let id_value = request.get("id").cloned().unwrap_or(Value::Null);
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+        let mut found = None;
+        let result = match method {
+            "mining.subscribe" => subscribe(shared, &mut shared.state.lock().unwrap(), id),
+            "mining.authorize" => authorize(&mut shared.state.lock().unwrap(), id, &params),
+            "mining.submit" => submit(shared, id, &params).map(|block| {
+                found = block;
+                json!(true)
+            }),
+            "mining.extranonce.subscribe" => Ok(json!(false)),
+            _ => Err((OTHER, "Unknown method")),
+        };
+        // The callback runs before the miner hears of the share, and unlocked so that it may
+        // set a new template.
+        if let (Some(found), Some(on_block)) = (found, &shared.on_block) {
+            on_block(&found);
+        }
+        let mut state = shared.state.lock().unwrap();
+        let response = match result {
+            Ok(result) => json!({"id": id_value, "result": result, "error": null}),
+            Err((code, msg)) => {
+                json!({"id": id_value, "result": null, "error": [code, msg, null]})
+            }
+        };
+        // An accepted share is timed for vardiff before the miner hears of it.
+        let update = if method == "mining.submit" && response["result"] == json!(true) {
+            let session = state.sessions.get_mut(&id).unwrap();
+            session.vardiff.as_mut().and_then(VarDiff::on_share)
+        } else {
+            None
+        };
+        outbox.send(&response);
+        // A new worker gets its difficulty and the current job after the response.
+        if method == "mining.authorize" && response["result"] == json!(true) {
+            start_work(&mut state, id);
+        }
+        // A retarget applies to jobs sent afterwards.
+        if let Some(update) = update {
+            let session = state.sessions.get_mut(&id).unwrap();
+            session.change_difficulty(update.difficulty);
+        }
+    }
+    shared.state.lock().unwrap().sessions.remove(&id);
+}
+
+type Reply = Result<Value, (i64, &'static str)>;
+
+fn subscribe(shared: &Shared, state: &mut State, id: u32) -> Reply {
+    let session = state.sessions.get_mut(&id).unwrap();
+    session.subscribed = true;
+    let subscription = format!("{:08x}", id);
+    Ok(json!([
+        [
+            ["mining.set_difficulty", subscription],
+            ["mining.notify", subscription]
+        ],
+        to_hex(&session.extranonce1),
+        shared.config.extranonce2_size,
+    ]))
+}
+
+fn authorize(state: &mut State, id: u32, params: &Value) -> Reply {
+    let session = state.sessions.get_mut(&id).unwrap();
+    if !session.subscribed {
+        return Err((NOT_SUBSCRIBED, "Not subscribed"));
+    }
+    let worker = params[0].as_str().ok_or((OTHER, "Malformed request"))?;
+    session.worker = Some(worker.to_string());
+    Ok(json!(true))
+}
+
+fn start_work(state: &mut State, id: u32) {
+    let job = state.jobs.back().map(|entry| entry.job.clone());
+    let session = state.sessions.get_mut(&id).unwrap();
+    session.outbox.send(&notification(
+        "mining.set_difficulty",
+        json!([session.difficulty]),
+    ));
+    if let Some(job) = job {
+        session
+            .job_difficulty
+            .insert(job.job_id.clone(), session.difficulty);
+        session
+            .outbox
+            .send(&notification("mining.notify", job.to_notify()));
+    }
+}
+
+// A share that passed the checks which need no hashing.
+struct Share {
+    worker: String,
+    job_id: String,
+    // (extranonce1, extranonce2, ntime, nonce), as recorded in `JobEntry::shares`.
+    key: (Vec<u8>, Vec<u8>, u32, u32),
+    header: BlockHeader,
+    difficulty: f64,
+}
+
+// submit checks a share and returns the block it solves, if any. The share is hashed between
+// two short holds of the state lock, so that other sessions are not held up by it.
+fn submit(
+    shared: &Shared,
+    id: u32,
+    params: &Value,
+) -> Result<Option<FoundBlock>, (i64, &'static str)> {
+    let config = &shared.config;
+    let share = check_share(config, &shared.state.lock().unwrap(), id, params)?;
+    let pow_hash = share.header.pow_hash(config.algorithm);
+    let share_target = config
+        .convention
+        .share_target(config.algorithm, share.difficulty);
+    if !share_target.is_met_by(pow_hash.as_bytes()) {
+        return Err((LOW_DIFFICULTY, "Low difficulty share"));
+    }
+
+    let mut state = shared.state.lock().unwrap();
+    // The job may have gone stale, or the share been submitted again, while hashing.
+    let entry = state
+        .jobs
+        .iter_mut()
+        .find(|entry| entry.job.job_id == share.job_id)
+        .ok_or((STALE, "Job not found"))?;
+    if !entry.shares.insert(share.key.clone()) {
+        return Err((DUPLICATE, "Duplicate share"));
+    }
+    let meets_network = share
+        .header
+        .target()
+        .is_ok_and(|target| target.is_met_by(pow_hash.as_bytes()));
+    if !meets_network {
+        return Ok(None);
+    }
+    let (extranonce1, extranonce2, time, nonce) = share.key;
+    Ok(Some(FoundBlock {
+        block: entry
+            .template
+            .block(&extranonce1, &extranonce2, time, nonce),
+        block_hash: share.header.block_hash(),
+        pow_hash,
+        worker: share.worker,
+    }))
+}
+
+// check_share parses a share and checks it against its session and job.
+fn check_share(
+    config: &ServerConfig,
+    state: &State,
+    id: u32,
+    params: &Value,
+) -> Result<Share, (i64, &'static str)> {
+    let session = state.sessions.get(&id).unwrap();
+    let worker = match &session.worker {
+        Some(worker) if params[0].as_str() == Some(worker.as_str()) => worker.clone(),
+        _ => return Err((UNAUTHORIZED, "Unauthorized worker")),
+    };
+    let extranonce1 = session.extranonce1.clone();
+    let malformed = (OTHER, "Malformed request");
+    let job_id = params[1].as_str().ok_or(malformed)?;
+    let extranonce2 = params[2].as_str().and_then(from_hex).ok_or(malformed)?;
+    let time = params[3]
+        .as_str()
+        .and_then(|time| u32::from_str_radix(time, 16).ok())
+        .ok_or(malformed)?;
+    let nonce = params[4]
+        .as_str()
+        .and_then(|nonce| u32::from_str_radix(nonce, 16).ok())
+        .ok_or(malformed)?;
+    if extranonce2.len() != config.extranonce2_size {
+        return Err((OTHER, "Incorrect size of extranonce2"));
+    }
+    let difficulty = session
+        .job_difficulty
+        .get(job_id)
+        .copied()
+        .unwrap_or(session.difficulty);
+
+    let entry = state
+        .jobs
+        .iter()
+        .find(|entry| entry.job.job_id == job_id)
+        .ok_or((STALE, "Job not found"))?;
+    if time < entry.job.time || time > entry.job.time.saturating_add(MAX_NTIME_ROLL) {
+        return Err((OTHER, "ntime out of range"));
+    }
+    let key = (extranonce1, extranonce2, time, nonce);
+    if entry.shares.contains(&key) {
+        return Err((DUPLICATE, "Duplicate share"));
+    }
+    Ok(Share {
+        worker,
+        job_id: job_id.to_string(),
+        header: entry.job.header(&key.0, &key.1, time, nonce),
+        key,
+        difficulty,
+    })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({"id": null, "method": method, "params": params})
+}
+
+#[test]
+fn stratum_server_cal() {
+    use super::{Client, Config, Event};
+    use crate::block::{Transaction, TxIn, TxOut};
+    use crate::vardiff::SimClock;
+    use std::time::Duration;
+
+    // A scripted miner speaking raw JSON lines.
+    struct Miner {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Miner {
+        fn call(&mut self, id: u64, method: &str, params: Value) -> Value {
+            let request = json!({"id": id, "method": method, "params": params});
+            writeln!(self.writer, "{}", request).unwrap();
+            loop {
+                let message = self.next();
+                if message["id"] == json!(id) {
+                    return message;
+                }
+            }
+        }
+
+        fn next(&mut self) -> Value {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+    }
+
+    let template = |prev: &[u8], bits: u32, height: u32| Template {
+        prev_hash: Hash256::sha256d(prev),
+        height,
+        version: 0x20000000,
+        bits,
+        time: 1_600_000_000,
+        coinbase_value: 25 * 100_000_000,
+        payout_script: vec![0x51],
+        coinbase_tag: b"/test/".to_vec(),
+        transactions: vec![Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                prev_txid: Hash256::sha256d(b"utxo"),
+                prev_index: 0,
+                script_sig: vec![0x51],
+                sequence: u32::MAX,
+                witness: Vec::new(),
+            }],
+            outputs: vec![TxOut {
+                value: 1000,
+                script_pubkey: vec![0x51],
+            }],
+            lock_time: 0,
+        }],
+    };
+
+    // One hash in 16 is a share; the first template's network target is out of reach.
+    let mut config = ServerConfig::new(Algorithm::Lyra2REv2);
+    config.difficulty = 1.0 / 1_048_576.0;
+    let share_target = config
+        .convention
+        .share_target(config.algorithm, config.difficulty);
+    let found = Arc::new(Mutex::new(Vec::new()));
+    let server = {
+        let found = Arc::clone(&found);
+        Server::new(config.clone())
+            .on_block(move |block| found.lock().unwrap().push(block.clone()))
+            .listen("127.0.0.1:0")
+            .unwrap()
+    };
+    server.set_template(template(b"tip 99", 0x1d00ffff, 100));
+
+    let stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let mut miner = Miner {
+        reader: BufReader::new(stream.try_clone().unwrap()),
+        writer: stream,
+    };
+    assert_eq!(
+        miner.call(1, "mining.authorize", json!(["w", "x"]))["error"][0],
+        NOT_SUBSCRIBED
+    );
+    let subscribed = miner.call(2, "mining.subscribe", json!(["scripted/1.0"]));
+    let extranonce1 = from_hex(subscribed["result"][1].as_str().unwrap()).unwrap();
+    assert_eq!(extranonce1.len(), EXTRANONCE1_SIZE);
+    assert_eq!(subscribed["result"][2], 4);
+    let submit = |job_id: &str, extranonce2: &str, time: u32, nonce: u32| {
+        json!([
+            "w",
+            job_id,
+            extranonce2,
+            format!("{:08x}", time),
+            format!("{:08x}", nonce)
+        ])
+    };
+    assert_eq!(
+        miner.call(3, "mining.submit", submit("1", "00000000", 0, 0))["error"][0],
+        UNAUTHORIZED
+    );
+    assert_eq!(
+        miner.call(4, "mining.authorize", json!(["w", "x"]))["result"],
+        true
+    );
+    assert_eq!(
+        miner.next(),
+        notification("mining.set_difficulty", json!([config.difficulty]))
+    );
+    let job = Job::from_notify(&miner.next()["params"]).unwrap();
+    assert!(job.clean_jobs);
+
+    let is_share = |job: &Job, nonce: u32| {
+        let header = job.header(&extranonce1, &[0; 4], job.time, nonce);
+        share_target.is_met_by(header.pow_hash(Algorithm::Lyra2REv2).as_bytes())
+    };
+    let good = (0..).find(|&nonce| is_share(&job, nonce)).unwrap();
+    let low = (0..).find(|&nonce| !is_share(&job, nonce)).unwrap();
+    let error = |response: Value| response["error"][0].as_i64();
+    let accepted = miner.call(
+        5,
+        "mining.submit",
+        submit(&job.job_id, "00000000", job.time, good),
+    );
+    assert_eq!(accepted["result"], true);
+    let cases = [
+        (submit(&job.job_id, "00000000", job.time, good), DUPLICATE),
+        (
+            submit(&job.job_id, "00000000", job.time, low),
+            LOW_DIFFICULTY,
+        ),
+        (submit("ff", "00000000", job.time, good), STALE),
+        (submit(&job.job_id, "0000", job.time, good), OTHER),
+        (submit(&job.job_id, "00000000", job.time - 1, good), OTHER),
+    ];
+    for (id, (params, code)) in cases.into_iter().enumerate() {
+        assert_eq!(
+            error(miner.call(6 + id as u64, "mining.submit", params)),
+            Some(code)
+        );
+    }
+
+    // The crate's own client mines the same job with its own extranonce1.
+    let config = Config::new("worker.1", "x", Algorithm::Lyra2REv2);
+    let mut client = Client::connect(server.local_addr(), config).unwrap();
+    client.subscribe().unwrap();
+    assert_ne!(client.extranonce1(), extranonce1.as_slice());
+    client.authorize().unwrap();
+    assert!(matches!(client.next_event().unwrap(), Event::Difficulty(_)));
+    assert!(matches!(client.next_event().unwrap(), Event::Job(_)));
+    let work = client.work(0).unwrap();
+    for share in client.mine(&work, 0..=63) {
+        client.submit(&share).unwrap();
+    }
+    assert!(found.lock().unwrap().is_empty());
+    assert_eq!(server.sessions(), 2);
+
+    // A new block at minimal difficulty: old jobs are stale and every share is a block.
+    let next = template(b"tip 100", 0x207fffff, 101);
+    server.set_template(next.clone());
+    let new_job = Job::from_notify(&miner.next()["params"]).unwrap();
+    assert!(new_job.clean_jobs);
+    assert_eq!(
+        error(miner.call(
+            20,
+            "mining.submit",
+            submit(&job.job_id, "00000000", job.time, good)
+        )),
+        Some(STALE)
+    );
+    let nonce = (0..).find(|&nonce| is_share(&new_job, nonce)).unwrap();
+    let accepted = miner.call(
+        21,
+        "mining.submit",
+        submit(&new_job.job_id, "00000000", new_job.time, nonce),
+    );
+    assert_eq!(accepted["result"], true);
+    let blocks = found.lock().unwrap().clone();
+    assert_eq!(blocks.len(), 1);
+    let block = &blocks[0];
+    assert_eq!(block.worker, "w");
+    assert_eq!(block.block.header.prev_block, next.prev_hash);
+    assert_eq!(block.block.header.nonce, nonce);
+    assert_eq!(block.block.header.block_hash(), block.block_hash);
+    assert_eq!(block.block.coinbase_height(), Some(101));
+    assert_eq!(block.block.transactions.len(), 2);
+    block.block.validate(Algorithm::Lyra2REv2).unwrap();
+
+    // A difficulty change reaches the miner.
+    assert!(server.set_difficulty(&extranonce1, 0.5));
+    assert_eq!(
+        miner.next(),
+        notification("mining.set_difficulty", json!([0.5]))
+    );
+    assert!(!server.set_difficulty(&[0xff; 4], 0.5));
+    drop(client);
+
+    // With vardiff, a miner submitting a share every second is retargeted towards one every
+    // 15 seconds once the retarget interval has passed on the simulated clock.
+    let mut config = ServerConfig::new(Algorithm::Lyra2REv2);
+    config.difficulty = 1.0 / 1_048_576.0;
+    config.vardiff = Some(VarDiffConfig {
+        retarget_time: Duration::from_secs(4),
+        min_difficulty: config.difficulty,
+        ..VarDiffConfig::default()
+    });
+    let clock = SimClock::new();
+    let server = Server::new(config.clone())
+        .clock(Arc::new(clock.clone()))
+        .listen("127.0.0.1:0")
+        .unwrap();
+    server.set_template(template(b"tip 99", 0x1d00ffff, 100));
+    let stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let mut miner = Miner {
+        reader: BufReader::new(stream.try_clone().unwrap()),
+        writer: stream,
+    };
+    let subscribed = miner.call(1, "mining.subscribe", json!([]));
+    let extranonce1 = from_hex(subscribed["result"][1].as_str().unwrap()).unwrap();
+    miner.call(2, "mining.authorize", json!(["w", "x"]));
+    assert_eq!(
+        miner.next(),
+        notification("mining.set_difficulty", json!([config.difficulty]))
+    );
+    let job = Job::from_notify(&miner.next()["params"]).unwrap();
+    let is_share = |nonce: u32| {
+        let header = job.header(&extranonce1, &[0; 4], job.time, nonce);
+        share_target.is_met_by(header.pow_hash(Algorithm::Lyra2REv2).as_bytes())
+    };
+    let mut shares = (0..).filter(|&nonce| is_share(nonce));
+    for id in 3..6 {
+        clock.advance(Duration::from_secs(1));
+        let nonce = shares.next().unwrap();
+        let accepted = miner.call(
+            id,
+            "mining.submit",
+            submit(&job.job_id, "00000000", job.time, nonce),
+        );
+        assert_eq!(accepted["result"], true);
+    }
+    assert_eq!(
+        miner.next(),
+        notification("mining.set_difficulty", json!([15.0 * config.difficulty]))
+    );
+    // Shares of the job sent before still count at its difficulty.
+    let nonce = shares.next().unwrap();
+    let accepted = miner.call(
+        6,
+        "mining.submit",
+        submit(&job.job_id, "00000000", job.time, nonce),
+    );
+    assert_eq!(accepted["result"], true);
+    server.set_template(template(b"tip 99", 0x1d00ffff, 100));
+    assert!(
+        !Job::from_notify(&miner.next()["params"])
+            .unwrap()
+            .clean_jobs
+    );
+
+    // A request line over the limit closes the session.
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let _ = stream.write_all(&vec![b' '; MAX_LINE_SIZE as usize]);
+    assert!(matches!(stream.read(&mut [0; 1]), Ok(0) | Err(_)));
+
+    // Connections past the limit are closed, and so are idle ones.
+    let mut config = ServerConfig::new(Algorithm::Lyra2REv2);
+    config.max_sessions = 1;
+    config.idle_timeout = Duration::from_millis(200);
+    let server = Server::new(config).listen("127.0.0.1:0").unwrap();
+    let mut idle = TcpStream::connect(server.local_addr()).unwrap();
+    while server.sessions() == 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    let mut refused = TcpStream::connect(server.local_addr()).unwrap();
+    for stream in [&mut refused, &mut idle] {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        assert!(matches!(stream.read(&mut [0; 1]), Ok(0) | Err(_)));
+    }
+    while server.shared.connections.load(Ordering::SeqCst) > 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    let mut miner = TcpStream::connect(server.local_addr()).unwrap();
+    writeln!(
+        miner,
+        "{}",
+        json!({"id": 1, "method": "mining.subscribe", "params": []})
+    )
+    .unwrap();
+    let mut line = String::new();
+    BufReader::new(miner).read_line(&mut line).unwrap();
+    assert!(line.contains("\"error\":null"));
+}
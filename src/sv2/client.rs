@@ -0,0 +1,560 @@
+use super::noise::{self, Receiver, Sender};
+use super::{Message, Sv2Error, MINING_PROTOCOL, VERSION};
+use crate::algorithm::Algorithm;
+use crate::header::{BlockHeader, Hash256};
+use crate::merkle;
+use crate::scan::Scanner;
+use crate::target::Target;
+use secp256k1::XOnlyPublicKey;
+use std::collections::{HashMap, VecDeque};
+use std::io::BufReader;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::RangeInclusive;
+
+/// Settings of a Stratum V2 [`Client`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// User identity sent when opening a channel.
+    pub user: String,
+    /// Sent with `SetupConnection`.
+    pub vendor: String,
+    pub firmware: String,
+    /// The PoW algorithm shares are hashed with.
+    pub algorithm: Algorithm,
+    /// Hashes per second the pool can expect, to pick the channel target.
+    pub nominal_hash_rate: f32,
+    /// The easiest share target the client accepts.
+    pub max_target: Target,
+    /// Number of threads used by [`Client::mine`].
+    pub threads: usize,
+}
+
+impl Config {
+    /// Returns a config without a hash rate or target limit and one thread.
+    pub fn new(user: &str, algorithm: Algorithm) -> Config {
+        Config {
+            user: user.to_string(),
+            vendor: "lyra2".to_string(),
+            firmware: env!("CARGO_PKG_VERSION").to_string(),
+            algorithm,
+            nominal_hash_rate: 0.0,
+            max_target: Target::MAX,
+            threads: 1,
+        }
+    }
+}
+
+/// A mining channel opened by the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Channel {
+    pub channel_id: u32,
+    /// Whether the channel is extended: the client rolls an extranonce of `extranonce_size`
+    /// bytes after the prefix instead of getting a merkle root.
+    pub extended: bool,
+    /// The current share target.
+    pub target: Target,
+    pub extranonce_prefix: Vec<u8>,
+    pub extranonce_size: usize,
+}
+
+/// A message received from the pool.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A new job for the channel. A future job waits for the `SetNewPrevHash` naming it; any
+    /// other job is now the client's current job.
+    Job { job_id: u32, future: bool },
+    /// `SetNewPrevHash`. The job it names is now the client's current job.
+    PrevHash { job_id: u32 },
+    /// `SetTarget`. It applies to work created afterwards.
+    Target(Target),
+    /// Shares up to `last_sequence_number` were accepted.
+    SharesAccepted {
+        last_sequence_number: u32,
+        count: u32,
+        shares_sum: u64,
+    },
+    /// The share with `sequence_number` was rejected.
+    ShareRejected {
+        sequence_number: u32,
+        error_code: String,
+    },
+    /// The pool closed the channel.
+    ChannelClosed { reason_code: String },
+    /// Any other message.
+    Other(Message),
+}
+
+/// A unit of work: the header of the current job with a fresh extranonce and a possibly
+/// rolled ntime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Work {
+    pub job_id: u32,
+    /// The header to hash, with a zero nonce.
+    pub header: BlockHeader,
+    /// The extranonce after the channel prefix; empty on standard channels.
+    pub extranonce: Vec<u8>,
+    /// The share target at the time the work was created.
+    pub target: Target,
+}
+
+/// A share found by [`Client::mine`], ready for [`Client::submit`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub job_id: u32,
+    pub extranonce: Vec<u8>,
+    pub version: u32,
+    pub time: u32,
+    pub nonce: u32,
+    pub hash: Hash256,
+}
+
+// A job as received: header-only or with the coinbase around the extranonce.
+#[derive(Debug, Clone)]
+struct ChannelJob {
+    min_ntime: Option<u32>,
+    version: u32,
+    kind: JobKind,
+}
+
+#[derive(Debug, Clone)]
+enum JobKind {
+    Standard(Hash256),
+    Extended {
+        merkle_path: Vec<Hash256>,
+        coinbase_tx_prefix: Vec<u8>,
+        coinbase_tx_suffix: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PrevHash {
+    prev_hash: Hash256,
+    min_ntime: u32,
+    nbits: u32,
+}
+
+/// A blocking Stratum V2 mining client with one channel.
+/// # Examples
+///
+/// ```no_run
+/// use lyra2::algorithm::Algorithm;
+/// use lyra2::sv2::{Client, Config, Event, XOnlyPublicKey};
+///
+/// # let authority: XOnlyPublicKey = unimplemented!();
+/// let config = Config::new("worker.1", Algorithm::Lyra2REv2);
+/// let mut client = Client::connect("127.0.0.1:3336", &authority, config).unwrap();
+/// client.open_standard_channel().unwrap();
+/// loop {
+///     if let Event::Job { .. } | Event::PrevHash { .. } = client.next_event().unwrap() {
+///         if let Some(work) = client.work(0) {
+///             for share in client.mine(&work, 0..=0xffff) {
+///                 client.submit(&share).unwrap();
+///             }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Client {
+    config: Config,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    sender: Sender,
+    receiver: Receiver,
+    next_request_id: u32,
+    next_sequence_number: u32,
+    channel: Option<Channel>,
+    jobs: HashMap<u32, ChannelJob>,
+    prev_hash: Option<PrevHash>,
+    job_id: Option<u32>,
+    extranonce_counter: u64,
+    events: VecDeque<Event>,
+}
+
+impl Client {
+    /// Connects to a pool, checks that its key is certified by `authority` and sets up a
+    /// mining connection. Open a channel next.
+    pub fn connect<A: ToSocketAddrs>(
+        addr: A,
+        authority: &XOnlyPublicKey,
+        config: Config,
+    ) -> Result<Client, Sv2Error> {
+        let mut writer = TcpStream::connect(addr)?;
+        writer.set_nodelay(true)?;
+        let peer = writer.peer_addr()?;
+        let (sender, receiver) = noise::initiate(&mut writer, authority)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        let mut client = Client {
+            config,
+            reader,
+            writer,
+            sender,
+            receiver,
+            next_request_id: 1,
+            next_sequence_number: 0,
+            channel: None,
+            jobs: HashMap::new(),
+            prev_hash: None,
+            job_id: None,
+            extranonce_counter: 0,
+            events: VecDeque::new(),
+        };
+        client.send(&Message::SetupConnection {
+            protocol: MINING_PROTOCOL,
+            min_version: VERSION,
+            max_version: VERSION,
+            flags: 0,
+            endpoint_host: peer.ip().to_string(),
+            endpoint_port: peer.port(),
+            vendor: client.config.vendor.clone(),
+            hardware_version: String::new(),
+            firmware: client.config.firmware.clone(),
+            device_id: String::new(),
+        })?;
+        match client.read_message()? {
+            Message::SetupConnectionSuccess { .. } => Ok(client),
+            Message::SetupConnectionError { error_code, .. } => Err(Sv2Error::Rejected(error_code)),
+            _ => Err(Sv2Error::Protocol(
+                "expected SetupConnection response".to_string(),
+            )),
+        }
+    }
+
+    /// Opens a standard channel, whose jobs are headers with a merkle root set by the pool.
+    pub fn open_standard_channel(&mut self) -> Result<Channel, Sv2Error> {
+        let request_id = self.next_request_id();
+        self.send(&Message::OpenStandardMiningChannel {
+            request_id,
+            user_identity: self.config.user.clone(),
+            nominal_hash_rate: self.config.nominal_hash_rate,
+            max_target: self.config.max_target,
+        })?;
+        self.open_channel(request_id)
+    }
+
+    /// Opens an extended channel, on which the client rolls an extranonce of at least
+    /// `min_extranonce_size` bytes.
+    pub fn open_extended_channel(&mut self, min_extranonce_size: u16) -> Result<Channel, Sv2Error> {
+        let request_id = self.next_request_id();
+        self.send(&Message::OpenExtendedMiningChannel {
+            request_id,
+            user_identity: self.config.user.clone(),
+            nominal_hash_rate: self.config.nominal_hash_rate,
+            max_target: self.config.max_target,
+            min_extranonce_size,
+        })?;
+        self.open_channel(request_id)
+    }
+
+    /// Submits a share on the open channel and returns its sequence number. The pool answers
+    /// later with [`Event::SharesAccepted`] or [`Event::ShareRejected`].
+    pub fn submit(&mut self, share: &Share) -> Result<u32, Sv2Error> {
+        let channel = self
+            .channel
+            .as_ref()
+            .ok_or_else(|| Sv2Error::Protocol("no open channel".to_string()))?;
+        let sequence_number = self.next_sequence_number;
+        self.next_sequence_number = self.next_sequence_number.wrapping_add(1);
+        let message = if channel.extended {
+            Message::SubmitSharesExtended {
+                channel_id: channel.channel_id,
+                sequence_number,
+                job_id: share.job_id,
+                nonce: share.nonce,
+                ntime: share.time,
+                version: share.version,
+                extranonce: share.extranonce.clone(),
+            }
+        } else {
+            Message::SubmitSharesStandard {
+                channel_id: channel.channel_id,
+                sequence_number,
+                job_id: share.job_id,
+                nonce: share.nonce,
+                ntime: share.time,
+                version: share.version,
+            }
+        };
+        self.send(&message)?;
+        Ok(sequence_number)
+    }
+
+    /// Returns the next event, waiting for the pool if none is queued.
+    pub fn next_event(&mut self) -> Result<Event, Sv2Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            let message = self.read_message()?;
+            self.handle_message(message);
+        }
+    }
+
+    /// Returns the open channel, if any.
+    pub fn channel(&self) -> Option<&Channel> {
+        self.channel.as_ref()
+    }
+
+    /// Returns work for the current job with the next extranonce and ntime rolled forward by
+    /// `ntime_offset` seconds, or `None` without a current job.
+    pub fn work(&mut self, ntime_offset: u32) -> Option<Work> {
+        let channel = self.channel.as_ref()?;
+        let job_id = self.job_id?;
+        let job = self.jobs.get(&job_id)?;
+        let prev_hash = self.prev_hash?;
+        let (merkle_root, extranonce) = match &job.kind {
+            JobKind::Standard(merkle_root) => (*merkle_root, Vec::new()),
+            JobKind::Extended {
+                merkle_path,
+                coinbase_tx_prefix,
+                coinbase_tx_suffix,
+            } => {
+                let counter = self.extranonce_counter.to_le_bytes();
+                self.extranonce_counter = self.extranonce_counter.wrapping_add(1);
+                let mut extranonce = vec![0u8; channel.extranonce_size];
+                for (byte, counter) in extranonce.iter_mut().zip(counter.iter()) {
+                    *byte = *counter;
+                }
+                let coinbase = [
+                    coinbase_tx_prefix.as_slice(),
+                    &channel.extranonce_prefix,
+                    &extranonce,
+                    coinbase_tx_suffix,
+                ]
+                .concat();
+                let merkle_root = merkle::fold_branch(Hash256::sha256d(&coinbase), merkle_path);
+                (merkle_root, extranonce)
+            }
+        };
+        let min_ntime = job.min_ntime.unwrap_or(prev_hash.min_ntime);
+        Some(Work {
+            job_id,
+            header: BlockHeader {
+                version: job.version as i32,
+                prev_block: prev_hash.prev_hash,
+                merkle_root,
+                time: min_ntime
+                    .max(prev_hash.min_ntime)
+                    .wrapping_add(ntime_offset),
+                bits: prev_hash.nbits,
+                nonce: 0,
+            },
+            extranonce,
+            target: channel.target,
+        })
+    }
+
+    /// Hashes `work` with every nonce in `nonces` using the configured algorithm and threads,
+    /// and returns the shares that meet the work's target.
+    pub fn mine(&self, work: &Work, nonces: RangeInclusive<u32>) -> Vec<Share> {
+        Scanner::new()
+            .scan(
+                &work.header,
+                nonces,
+                &work.target,
+                self.config.algorithm,
+                self.config.threads,
+            )
+            .into_iter()
+            .map(|solution| Share {
+                job_id: work.job_id,
+                extranonce: work.extranonce.clone(),
+                version: work.header.version as u32,
+                time: work.header.time,
+                nonce: solution.nonce,
+                hash: solution.hash,
+            })
+            .collect()
+    }
+
+    fn next_request_id(&mut self) -> u32 {
+        let request_id = self.next_request_id;
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+        request_id
+    }
+
+    // open_channel waits for the response to an open channel request, queueing the events
+    // that arrive in the meantime.
+    fn open_channel(&mut self, request_id: u32) -> Result<Channel, Sv2Error> {
+        loop {
+            let channel = match self.read_message()? {
+                Message::OpenStandardMiningChannelSuccess {
+                    request_id: id,
+                    channel_id,
+                    target,
+                    extranonce_prefix,
+                    ..
+                } if id == request_id => Channel {
+                    channel_id,
+                    extended: false,
+                    target,
+                    extranonce_prefix,
+                    extranonce_size: 0,
+                },
+                Message::OpenExtendedMiningChannelSuccess {
+                    request_id: id,
+                    channel_id,
+                    target,
+                    extranonce_size,
+                    extranonce_prefix,
+                } if id == request_id => Channel {
+                    channel_id,
+                    extended: true,
+                    target,
+                    extranonce_prefix,
+                    extranonce_size: extranonce_size as usize,
+                },
+                Message::OpenMiningChannelError {
+                    request_id: id,
+                    error_code,
+                } if id == request_id => return Err(Sv2Error::Rejected(error_code)),
+                message => {
+                    self.handle_message(message);
+                    continue;
+                }
+            };
+            if channel.extended && !(1..=8).contains(&channel.extranonce_size) {
+                return Err(Sv2Error::Protocol(format!(
+                    "unsupported extranonce size: {}",
+                    channel.extranonce_size
+                )));
+            }
+            self.channel = Some(channel.clone());
+            self.jobs.clear();
+            self.prev_hash = None;
+            self.job_id = None;
+            self.extranonce_counter = 0;
+            return Ok(channel);
+        }
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        let channel_id = match &self.channel {
+            Some(channel) => channel.channel_id,
+            None => {
+                self.events.push_back(Event::Other(message));
+                return;
+            }
+        };
+        let event = match message {
+            Message::NewMiningJob {
+                channel_id: id,
+                job_id,
+                min_ntime,
+                version,
+                merkle_root,
+            } if id == channel_id => self.add_job(
+                job_id,
+                ChannelJob {
+                    min_ntime,
+                    version,
+                    kind: JobKind::Standard(merkle_root),
+                },
+            ),
+            Message::NewExtendedMiningJob {
+                channel_id: id,
+                job_id,
+                min_ntime,
+                version,
+                merkle_path,
+                coinbase_tx_prefix,
+                coinbase_tx_suffix,
+                ..
+            } if id == channel_id => self.add_job(
+                job_id,
+                ChannelJob {
+                    min_ntime,
+                    version,
+                    kind: JobKind::Extended {
+                        merkle_path,
+                        coinbase_tx_prefix,
+                        coinbase_tx_suffix,
+                    },
+                },
+            ),
+            Message::SetNewPrevHash {
+                channel_id: id,
+                job_id,
+                prev_hash,
+                min_ntime,
+                nbits,
+            } if id == channel_id => {
+                self.prev_hash = Some(PrevHash {
+                    prev_hash,
+                    min_ntime,
+                    nbits,
+                });
+                // Jobs of the previous block are stale; the named future job is current.
+                self.jobs
+                    .retain(|&id, job| id == job_id || job.min_ntime.is_none());
+                self.job_id = self.jobs.get_mut(&job_id).map(|job| {
+                    job.min_ntime = Some(min_ntime);
+                    job_id
+                });
+                Event::PrevHash { job_id }
+            }
+            Message::SetTarget {
+                channel_id: id,
+                maximum_target,
+            } if id == channel_id => {
+                if let Some(channel) = &mut self.channel {
+                    channel.target = maximum_target;
+                }
+                Event::Target(maximum_target)
+            }
+            Message::SubmitSharesSuccess {
+                channel_id: id,
+                last_sequence_number,
+                new_submits_accepted_count,
+                new_shares_sum,
+            } if id == channel_id => Event::SharesAccepted {
+                last_sequence_number,
+                count: new_submits_accepted_count,
+                shares_sum: new_shares_sum,
+            },
+            Message::SubmitSharesError {
+                channel_id: id,
+                sequence_number,
+                error_code,
+            } if id == channel_id => Event::ShareRejected {
+                sequence_number,
+                error_code,
+            },
+            Message::CloseChannel {
+                channel_id: id,
+                reason_code,
+            } if id == channel_id => {
+                self.channel = None;
+                self.job_id = None;
+                Event::ChannelClosed { reason_code }
+            }
+            message => Event::Other(message),
+        };
+        self.events.push_back(event);
+    }
+
+    fn add_job(&mut self, job_id: u32, job: ChannelJob) -> Event {
+        let future = job.min_ntime.is_none();
+        self.jobs.insert(job_id, job);
+        if !future && self.prev_hash.is_some() {
+            self.job_id = Some(job_id);
+        }
+        Event::Job { job_id, future }
+    }
+
+    fn send(&mut self, message: &Message) -> Result<(), Sv2Error> {
+        let frame = message.to_frame()?;
+        self.sender.write_frame(&mut self.writer, &frame)
+    }
+
+    // read_message returns the next message of the core protocols, skipping unknown ones.
+    fn read_message(&mut self) -> Result<Message, Sv2Error> {
+        loop {
+            let frame = self.receiver.read_frame(&mut self.reader)?;
+            match Message::from_frame(&frame) {
+                Err(Sv2Error::UnknownMessage { .. }) => continue,
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,948 @@
+use super::Sv2Error;
+use crate::header::Hash256;
+use crate::target::Target;
+
+/// Size in bytes of a frame header: extension type, message type and payload length.
+pub const HEADER_SIZE: usize = 6;
+
+/// The largest payload a frame can carry, as its length is a 24-bit integer.
+pub const MAX_PAYLOAD_SIZE: usize = 0xff_ffff;
+
+// The high bit of the extension type marks messages addressed to a channel.
+const CHANNEL_MSG: u16 = 0x8000;
+
+/// Message types of the common and mining protocols.
+pub mod msg_type {
+    pub const SETUP_CONNECTION: u8 = 0x00;
+    pub const SETUP_CONNECTION_SUCCESS: u8 = 0x01;
+    pub const SETUP_CONNECTION_ERROR: u8 = 0x02;
+    pub const OPEN_STANDARD_MINING_CHANNEL: u8 = 0x10;
+    pub const OPEN_STANDARD_MINING_CHANNEL_SUCCESS: u8 = 0x11;
+    pub const OPEN_MINING_CHANNEL_ERROR: u8 = 0x12;
+    pub const OPEN_EXTENDED_MINING_CHANNEL: u8 = 0x13;
+    pub const OPEN_EXTENDED_MINING_CHANNEL_SUCCESS: u8 = 0x14;
+    pub const NEW_MINING_JOB: u8 = 0x15;
+    pub const UPDATE_CHANNEL: u8 = 0x16;
+    pub const CLOSE_CHANNEL: u8 = 0x18;
+    pub const SUBMIT_SHARES_STANDARD: u8 = 0x1a;
+    pub const SUBMIT_SHARES_EXTENDED: u8 = 0x1b;
+    pub const SUBMIT_SHARES_SUCCESS: u8 = 0x1c;
+    pub const SUBMIT_SHARES_ERROR: u8 = 0x1d;
+    pub const NEW_EXTENDED_MINING_JOB: u8 = 0x1f;
+    pub const SET_NEW_PREV_HASH: u8 = 0x20;
+    pub const SET_TARGET: u8 = 0x21;
+}
+
+/// A plaintext frame: a 6-byte header and its payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The extension type without the channel bit; 0 for the core protocols.
+    pub extension_type: u16,
+    /// Whether the message is addressed to a channel, the high bit of the extension type.
+    pub channel_msg: bool,
+    pub msg_type: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Returns the frame header. The payload length is a 24-bit little-endian integer.
+    pub fn header(&self) -> [u8; HEADER_SIZE] {
+        let extension_type = self.extension_type | if self.channel_msg { CHANNEL_MSG } else { 0 };
+        let len = (self.payload.len() as u32).to_le_bytes();
+        let ext = extension_type.to_le_bytes();
+        [ext[0], ext[1], self.msg_type, len[0], len[1], len[2]]
+    }
+
+    /// Parses a frame header into an empty frame and the length of its payload.
+    pub fn parse_header(header: &[u8; HEADER_SIZE]) -> (Frame, usize) {
+        let extension_type = u16::from_le_bytes([header[0], header[1]]);
+        let len = u32::from_le_bytes([header[3], header[4], header[5], 0]) as usize;
+        let frame = Frame {
+            extension_type: extension_type & !CHANNEL_MSG,
+            channel_msg: extension_type & CHANNEL_MSG != 0,
+            msg_type: header[2],
+            payload: Vec::new(),
+        };
+        (frame, len)
+    }
+
+    /// Returns the header followed by the payload.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.header()[..], &self.payload].concat()
+    }
+
+    /// Parses a whole frame, the inverse of [`Frame::to_bytes`].
+    pub fn from_bytes(data: &[u8]) -> Result<Frame, Sv2Error> {
+        if data.len() < HEADER_SIZE {
+            return Err(Sv2Error::Malformed("truncated frame header".to_string()));
+        }
+        let (mut frame, len) = Frame::parse_header(data[..HEADER_SIZE].try_into().unwrap());
+        if data.len() != HEADER_SIZE + len {
+            return Err(Sv2Error::Malformed("frame length mismatch".to_string()));
+        }
+        frame.payload = data[HEADER_SIZE..].to_vec();
+        Ok(frame)
+    }
+}
+
+/// A message of the common or mining protocol.
+///
+/// Integers are little-endian; 256-bit values such as targets and hashes are sent as 32
+/// little-endian bytes, hashes in the internal byte order of the block header.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    SetupConnection {
+        /// 0 for the mining protocol.
+        protocol: u8,
+        min_version: u16,
+        max_version: u16,
+        flags: u32,
+        endpoint_host: String,
+        endpoint_port: u16,
+        vendor: String,
+        hardware_version: String,
+        firmware: String,
+        device_id: String,
+    },
+    SetupConnectionSuccess {
+        used_version: u16,
+        flags: u32,
+    },
+    SetupConnectionError {
+        flags: u32,
+        error_code: String,
+    },
+    OpenStandardMiningChannel {
+        request_id: u32,
+        user_identity: String,
+        /// Expected hashes per second of the device.
+        nominal_hash_rate: f32,
+        /// The easiest target the device accepts.
+        max_target: Target,
+    },
+    OpenStandardMiningChannelSuccess {
+        request_id: u32,
+        channel_id: u32,
+        target: Target,
+        extranonce_prefix: Vec<u8>,
+        group_channel_id: u32,
+    },
+    OpenExtendedMiningChannel {
+        request_id: u32,
+        user_identity: String,
+        nominal_hash_rate: f32,
+        max_target: Target,
+        min_extranonce_size: u16,
+    },
+    OpenExtendedMiningChannelSuccess {
+        request_id: u32,
+        channel_id: u32,
+        target: Target,
+        /// Size in bytes of the extranonce the miner rolls after the prefix.
+        extranonce_size: u16,
+        extranonce_prefix: Vec<u8>,
+    },
+    OpenMiningChannelError {
+        request_id: u32,
+        error_code: String,
+    },
+    UpdateChannel {
+        channel_id: u32,
+        nominal_hash_rate: f32,
+        maximum_target: Target,
+    },
+    CloseChannel {
+        channel_id: u32,
+        reason_code: String,
+    },
+    /// A header-only job: the merkle root is fixed by the pool.
+    NewMiningJob {
+        channel_id: u32,
+        job_id: u32,
+        /// `None` for a future job, which becomes current with the `SetNewPrevHash` naming it.
+        min_ntime: Option<u32>,
+        version: u32,
+        merkle_root: Hash256,
+    },
+    NewExtendedMiningJob {
+        channel_id: u32,
+        job_id: u32,
+        min_ntime: Option<u32>,
+        version: u32,
+        version_rolling_allowed: bool,
+        merkle_path: Vec<Hash256>,
+        /// The coinbase transaction up to the extranonce prefix.
+        coinbase_tx_prefix: Vec<u8>,
+        /// The coinbase transaction after the extranonce.
+        coinbase_tx_suffix: Vec<u8>,
+    },
+    SetNewPrevHash {
+        channel_id: u32,
+        job_id: u32,
+        prev_hash: Hash256,
+        min_ntime: u32,
+        nbits: u32,
+    },
+    SetTarget {
+        channel_id: u32,
+        maximum_target: Target,
+    },
+    SubmitSharesStandard {
+        channel_id: u32,
+        sequence_number: u32,
+        job_id: u32,
+        nonce: u32,
+        ntime: u32,
+        version: u32,
+    },
+    SubmitSharesExtended {
+        channel_id: u32,
+        sequence_number: u32,
+        job_id: u32,
+        nonce: u32,
+        ntime: u32,
+        version: u32,
+        extranonce: Vec<u8>,
+    },
+    SubmitSharesSuccess {
+        channel_id: u32,
+        last_sequence_number: u32,
+        new_submits_accepted_count: u32,
+        new_shares_sum: u64,
+    },
+    SubmitSharesError {
+        channel_id: u32,
+        sequence_number: u32,
+        error_code: String,
+    },
+}
+
+impl Message {
+    /// Returns the message type.
+    pub fn msg_type(&self) -> u8 {
+        use msg_type::*;
+        match self {
+            Message::SetupConnection { .. } => SETUP_CONNECTION,
+            Message::SetupConnectionSuccess { .. } => SETUP_CONNECTION_SUCCESS,
+            Message::SetupConnectionError { .. } => SETUP_CONNECTION_ERROR,
+            Message::OpenStandardMiningChannel { .. } => OPEN_STANDARD_MINING_CHANNEL,
+            Message::OpenStandardMiningChannelSuccess { .. } => {
+                OPEN_STANDARD_MINING_CHANNEL_SUCCESS
+            }
+            Message::OpenExtendedMiningChannel { .. } => OPEN_EXTENDED_MINING_CHANNEL,
+            Message::OpenExtendedMiningChannelSuccess { .. } => {
+                OPEN_EXTENDED_MINING_CHANNEL_SUCCESS
+            }
+            Message::OpenMiningChannelError { .. } => OPEN_MINING_CHANNEL_ERROR,
+            Message::UpdateChannel { .. } => UPDATE_CHANNEL,
+            Message::CloseChannel { .. } => CLOSE_CHANNEL,
+            Message::NewMiningJob { .. } => NEW_MINING_JOB,
+            Message::NewExtendedMiningJob { .. } => NEW_EXTENDED_MINING_JOB,
+            Message::SetNewPrevHash { .. } => SET_NEW_PREV_HASH,
+            Message::SetTarget { .. } => SET_TARGET,
+            Message::SubmitSharesStandard { .. } => SUBMIT_SHARES_STANDARD,
+            Message::SubmitSharesExtended { .. } => SUBMIT_SHARES_EXTENDED,
+            Message::SubmitSharesSuccess { .. } => SUBMIT_SHARES_SUCCESS,
+            Message::SubmitSharesError { .. } => SUBMIT_SHARES_ERROR,
+        }
+    }
+
+    /// Returns whether the message is addressed to a channel and has the channel bit set.
+    pub fn is_channel_msg(&self) -> bool {
+        matches!(
+            self,
+            Message::UpdateChannel { .. }
+                | Message::CloseChannel { .. }
+                | Message::NewMiningJob { .. }
+                | Message::NewExtendedMiningJob { .. }
+                | Message::SetNewPrevHash { .. }
+                | Message::SetTarget { .. }
+                | Message::SubmitSharesStandard { .. }
+                | Message::SubmitSharesExtended { .. }
+                | Message::SubmitSharesSuccess { .. }
+                | Message::SubmitSharesError { .. }
+        )
+    }
+
+    /// Encodes the message into a frame. Strings and byte fields longer than their type allows
+    /// are a [`Sv2Error::Malformed`].
+    pub fn to_frame(&self) -> Result<Frame, Sv2Error> {
+        let mut w = Writer(Vec::new());
+        match self {
+            Message::SetupConnection {
+                protocol,
+                min_version,
+                max_version,
+                flags,
+                endpoint_host,
+                endpoint_port,
+                vendor,
+                hardware_version,
+                firmware,
+                device_id,
+            } => {
+                w.u8(*protocol);
+                w.u16(*min_version);
+                w.u16(*max_version);
+                w.u32(*flags);
+                w.str0_255(endpoint_host)?;
+                w.u16(*endpoint_port);
+                w.str0_255(vendor)?;
+                w.str0_255(hardware_version)?;
+                w.str0_255(firmware)?;
+                w.str0_255(device_id)?;
+            }
+            Message::SetupConnectionSuccess {
+                used_version,
+                flags,
+            } => {
+                w.u16(*used_version);
+                w.u32(*flags);
+            }
+            Message::SetupConnectionError { flags, error_code } => {
+                w.u32(*flags);
+                w.str0_255(error_code)?;
+            }
+            Message::OpenStandardMiningChannel {
+                request_id,
+                user_identity,
+                nominal_hash_rate,
+                max_target,
+            } => {
+                w.u32(*request_id);
+                w.str0_255(user_identity)?;
+                w.f32(*nominal_hash_rate);
+                w.u256(&max_target.to_le_bytes());
+            }
+            Message::OpenStandardMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target,
+                extranonce_prefix,
+                group_channel_id,
+            } => {
+                w.u32(*request_id);
+                w.u32(*channel_id);
+                w.u256(&target.to_le_bytes());
+                w.b0_32(extranonce_prefix)?;
+                w.u32(*group_channel_id);
+            }
+            Message::OpenExtendedMiningChannel {
+                request_id,
+                user_identity,
+                nominal_hash_rate,
+                max_target,
+                min_extranonce_size,
+            } => {
+                w.u32(*request_id);
+                w.str0_255(user_identity)?;
+                w.f32(*nominal_hash_rate);
+                w.u256(&max_target.to_le_bytes());
+                w.u16(*min_extranonce_size);
+            }
+            Message::OpenExtendedMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target,
+                extranonce_size,
+                extranonce_prefix,
+            } => {
+                w.u32(*request_id);
+                w.u32(*channel_id);
+                w.u256(&target.to_le_bytes());
+                w.u16(*extranonce_size);
+                w.b0_32(extranonce_prefix)?;
+            }
+            Message::OpenMiningChannelError {
+                request_id,
+                error_code,
+            } => {
+                w.u32(*request_id);
+                w.str0_255(error_code)?;
+            }
+            Message::UpdateChannel {
+                channel_id,
+                nominal_hash_rate,
+                maximum_target,
+            } => {
+                w.u32(*channel_id);
+                w.f32(*nominal_hash_rate);
+                w.u256(&maximum_target.to_le_bytes());
+            }
+            Message::CloseChannel {
+                channel_id,
+                reason_code,
+            } => {
+                w.u32(*channel_id);
+                w.str0_255(reason_code)?;
+            }
+            Message::NewMiningJob {
+                channel_id,
+                job_id,
+                min_ntime,
+                version,
+                merkle_root,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*job_id);
+                w.option_u32(*min_ntime);
+                w.u32(*version);
+                w.u256(merkle_root.as_bytes());
+            }
+            Message::NewExtendedMiningJob {
+                channel_id,
+                job_id,
+                min_ntime,
+                version,
+                version_rolling_allowed,
+                merkle_path,
+                coinbase_tx_prefix,
+                coinbase_tx_suffix,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*job_id);
+                w.option_u32(*min_ntime);
+                w.u32(*version);
+                w.u8(u8::from(*version_rolling_allowed));
+                if merkle_path.len() > 255 {
+                    return Err(Sv2Error::Malformed("merkle path too long".to_string()));
+                }
+                w.u8(merkle_path.len() as u8);
+                for hash in merkle_path {
+                    w.u256(hash.as_bytes());
+                }
+                w.b0_64k(coinbase_tx_prefix)?;
+                w.b0_64k(coinbase_tx_suffix)?;
+            }
+            Message::SetNewPrevHash {
+                channel_id,
+                job_id,
+                prev_hash,
+                min_ntime,
+                nbits,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*job_id);
+                w.u256(prev_hash.as_bytes());
+                w.u32(*min_ntime);
+                w.u32(*nbits);
+            }
+            Message::SetTarget {
+                channel_id,
+                maximum_target,
+            } => {
+                w.u32(*channel_id);
+                w.u256(&maximum_target.to_le_bytes());
+            }
+            Message::SubmitSharesStandard {
+                channel_id,
+                sequence_number,
+                job_id,
+                nonce,
+                ntime,
+                version,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*sequence_number);
+                w.u32(*job_id);
+                w.u32(*nonce);
+                w.u32(*ntime);
+                w.u32(*version);
+            }
+            Message::SubmitSharesExtended {
+                channel_id,
+                sequence_number,
+                job_id,
+                nonce,
+                ntime,
+                version,
+                extranonce,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*sequence_number);
+                w.u32(*job_id);
+                w.u32(*nonce);
+                w.u32(*ntime);
+                w.u32(*version);
+                w.b0_32(extranonce)?;
+            }
+            Message::SubmitSharesSuccess {
+                channel_id,
+                last_sequence_number,
+                new_submits_accepted_count,
+                new_shares_sum,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*last_sequence_number);
+                w.u32(*new_submits_accepted_count);
+                w.u64(*new_shares_sum);
+            }
+            Message::SubmitSharesError {
+                channel_id,
+                sequence_number,
+                error_code,
+            } => {
+                w.u32(*channel_id);
+                w.u32(*sequence_number);
+                w.str0_255(error_code)?;
+            }
+        }
+        Ok(Frame {
+            extension_type: 0,
+            channel_msg: self.is_channel_msg(),
+            msg_type: self.msg_type(),
+            payload: w.0,
+        })
+    }
+
+    /// Decodes the message of a frame of the core protocols. Other extensions and message
+    /// types are a [`Sv2Error::UnknownMessage`].
+    pub fn from_frame(frame: &Frame) -> Result<Message, Sv2Error> {
+        use msg_type::*;
+        let unknown = Sv2Error::UnknownMessage {
+            extension_type: frame.extension_type,
+            msg_type: frame.msg_type,
+        };
+        if frame.extension_type != 0 {
+            return Err(unknown);
+        }
+        let mut r = Reader(&frame.payload);
+        let message = match frame.msg_type {
+            SETUP_CONNECTION => Message::SetupConnection {
+                protocol: r.u8()?,
+                min_version: r.u16()?,
+                max_version: r.u16()?,
+                flags: r.u32()?,
+                endpoint_host: r.str0_255()?,
+                endpoint_port: r.u16()?,
+                vendor: r.str0_255()?,
+                hardware_version: r.str0_255()?,
+                firmware: r.str0_255()?,
+                device_id: r.str0_255()?,
+            },
+            SETUP_CONNECTION_SUCCESS => Message::SetupConnectionSuccess {
+                used_version: r.u16()?,
+                flags: r.u32()?,
+            },
+            SETUP_CONNECTION_ERROR => Message::SetupConnectionError {
+                flags: r.u32()?,
+                error_code: r.str0_255()?,
+            },
+            OPEN_STANDARD_MINING_CHANNEL => Message::OpenStandardMiningChannel {
+                request_id: r.u32()?,
+                user_identity: r.str0_255()?,
+                nominal_hash_rate: r.f32()?,
+                max_target: Target::from_le_bytes(r.u256()?),
+            },
+            OPEN_STANDARD_MINING_CHANNEL_SUCCESS => Message::OpenStandardMiningChannelSuccess {
+                request_id: r.u32()?,
+                channel_id: r.u32()?,
+                target: Target::from_le_bytes(r.u256()?),
+                extranonce_prefix: r.bytes(1)?,
+                group_channel_id: r.u32()?,
+            },
+            OPEN_EXTENDED_MINING_CHANNEL => Message::OpenExtendedMiningChannel {
+                request_id: r.u32()?,
+                user_identity: r.str0_255()?,
+                nominal_hash_rate: r.f32()?,
+                max_target: Target::from_le_bytes(r.u256()?),
+                min_extranonce_size: r.u16()?,
+            },
+            OPEN_EXTENDED_MINING_CHANNEL_SUCCESS => Message::OpenExtendedMiningChannelSuccess {
+                request_id: r.u32()?,
+                channel_id: r.u32()?,
+                target: Target::from_le_bytes(r.u256()?),
+                extranonce_size: r.u16()?,
+                extranonce_prefix: r.bytes(1)?,
+            },
+            OPEN_MINING_CHANNEL_ERROR => Message::OpenMiningChannelError {
+                request_id: r.u32()?,
+                error_code: r.str0_255()?,
+            },
+            UPDATE_CHANNEL => Message::UpdateChannel {
+                channel_id: r.u32()?,
+                nominal_hash_rate: r.f32()?,
+                maximum_target: Target::from_le_bytes(r.u256()?),
+            },
+            CLOSE_CHANNEL => Message::CloseChannel {
+                channel_id: r.u32()?,
+                reason_code: r.str0_255()?,
+            },
+            NEW_MINING_JOB => Message::NewMiningJob {
+                channel_id: r.u32()?,
+                job_id: r.u32()?,
+                min_ntime: r.option_u32()?,
+                version: r.u32()?,
+                merkle_root: Hash256(r.u256()?),
+            },
+            NEW_EXTENDED_MINING_JOB => Message::NewExtendedMiningJob {
+                channel_id: r.u32()?,
+                job_id: r.u32()?,
+                min_ntime: r.option_u32()?,
+                version: r.u32()?,
+                version_rolling_allowed: r.bool()?,
+                merkle_path: (0..r.u8()?)
+                    .map(|_| r.u256().map(Hash256))
+                    .collect::<Result<_, _>>()?,
+                coinbase_tx_prefix: r.bytes(2)?,
+                coinbase_tx_suffix: r.bytes(2)?,
+            },
+            SET_NEW_PREV_HASH => Message::SetNewPrevHash {
+                channel_id: r.u32()?,
+                job_id: r.u32()?,
+                prev_hash: Hash256(r.u256()?),
+                min_ntime: r.u32()?,
+                nbits: r.u32()?,
+            },
+            SET_TARGET => Message::SetTarget {
+                channel_id: r.u32()?,
+                maximum_target: Target::from_le_bytes(r.u256()?),
+            },
+            SUBMIT_SHARES_STANDARD => Message::SubmitSharesStandard {
+                channel_id: r.u32()?,
+                sequence_number: r.u32()?,
+                job_id: r.u32()?,
+                nonce: r.u32()?,
+                ntime: r.u32()?,
+                version: r.u32()?,
+            },
+            SUBMIT_SHARES_EXTENDED => Message::SubmitSharesExtended {
+                channel_id: r.u32()?,
+                sequence_number: r.u32()?,
+                job_id: r.u32()?,
+                nonce: r.u32()?,
+                ntime: r.u32()?,
+                version: r.u32()?,
+                extranonce: r.bytes(1)?,
+            },
+            SUBMIT_SHARES_SUCCESS => Message::SubmitSharesSuccess {
+                channel_id: r.u32()?,
+                last_sequence_number: r.u32()?,
+                new_submits_accepted_count: r.u32()?,
+                new_shares_sum: r.u64()?,
+            },
+            SUBMIT_SHARES_ERROR => Message::SubmitSharesError {
+                channel_id: r.u32()?,
+                sequence_number: r.u32()?,
+                error_code: r.str0_255()?,
+            },
+            _ => return Err(unknown),
+        };
+        if !r.0.is_empty() {
+            return Err(Sv2Error::Malformed(format!(
+                "trailing bytes after message 0x{:02x}",
+                frame.msg_type
+            )));
+        }
+        Ok(message)
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u256(&mut self, value: &[u8; 32]) {
+        self.0.extend_from_slice(value);
+    }
+
+    // option_u32 writes OPTION[U32]: a 0 or 1 count, then the value if any.
+    fn option_u32(&mut self, value: Option<u32>) {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                self.u32(value);
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn str0_255(&mut self, value: &str) -> Result<(), Sv2Error> {
+        self.short_bytes(value.as_bytes(), 255)
+    }
+
+    fn b0_32(&mut self, value: &[u8]) -> Result<(), Sv2Error> {
+        self.short_bytes(value, 32)
+    }
+
+    fn short_bytes(&mut self, value: &[u8], max: usize) -> Result<(), Sv2Error> {
+        if value.len() > max {
+            return Err(Sv2Error::Malformed(format!(
+                "field of {} bytes exceeds {}",
+                value.len(),
+                max
+            )));
+        }
+        self.u8(value.len() as u8);
+        self.0.extend_from_slice(value);
+        Ok(())
+    }
+
+    fn b0_64k(&mut self, value: &[u8]) -> Result<(), Sv2Error> {
+        let len = u16::try_from(value.len()).map_err(|_| {
+            Sv2Error::Malformed(format!("field of {} bytes exceeds 65535", value.len()))
+        })?;
+        self.u16(len);
+        self.0.extend_from_slice(value);
+        Ok(())
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Sv2Error> {
+        if self.0.len() < n {
+            return Err(Sv2Error::Malformed("truncated message".to_string()));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, Sv2Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, Sv2Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Sv2Error::Malformed("invalid bool".to_string())),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, Sv2Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Sv2Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Sv2Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, Sv2Error> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u256(&mut self) -> Result<[u8; 32], Sv2Error> {
+        Ok(self.take(32)?.try_into().unwrap())
+    }
+
+    fn option_u32(&mut self) -> Result<Option<u32>, Sv2Error> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.u32()?)),
+            _ => Err(Sv2Error::Malformed("invalid option".to_string())),
+        }
+    }
+
+    // bytes reads a byte field with a length prefix of `len_size` bytes.
+    fn bytes(&mut self, len_size: usize) -> Result<Vec<u8>, Sv2Error> {
+        let len = match len_size {
+            1 => self.u8()? as usize,
+            _ => self.u16()? as usize,
+        };
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn str0_255(&mut self) -> Result<String, Sv2Error> {
+        String::from_utf8(self.bytes(1)?)
+            .map_err(|_| Sv2Error::Malformed("invalid UTF-8 string".to_string()))
+    }
+}
+
+#[test]
+fn sv2_codec_cal() {
+    use crate::utils::to_hex;
+
+    // SetupConnection as the specification lays it out.
+    let setup = Message::SetupConnection {
+        protocol: 0,
+        min_version: 2,
+        max_version: 2,
+        flags: 1,
+        endpoint_host: "pool".to_string(),
+        endpoint_port: 3336,
+        vendor: "lyra2".to_string(),
+        hardware_version: String::new(),
+        firmware: "0.1".to_string(),
+        device_id: String::new(),
+    };
+    let frame = setup.to_frame().unwrap();
+    assert_eq!(
+        to_hex(&frame.to_bytes()),
+        "0000001c0000\
+         00 0200 0200 01000000 04706f6f6c 080d 056c79726132 00 03302e31 00"
+            .replace(' ', "")
+    );
+    assert_eq!(Message::from_frame(&frame).unwrap(), setup);
+
+    // Channel messages set the high bit of the extension type.
+    let job = Message::NewMiningJob {
+        channel_id: 7,
+        job_id: 3,
+        min_ntime: None,
+        version: 0x2000_0000,
+        merkle_root: Hash256([0xab; 32]),
+    };
+    let bytes = job.to_frame().unwrap().to_bytes();
+    assert_eq!(to_hex(&bytes[..HEADER_SIZE]), "0080152d0000");
+    assert_eq!(bytes.len(), HEADER_SIZE + 45);
+    let frame = Frame::from_bytes(&bytes).unwrap();
+    assert!(frame.channel_msg);
+    assert_eq!(Message::from_frame(&frame).unwrap(), job);
+
+    let target = Target::from_compact(0x1e0ffff0).unwrap();
+    let messages = [
+        Message::SetupConnectionSuccess {
+            used_version: 2,
+            flags: 0,
+        },
+        Message::SetupConnectionError {
+            flags: 0,
+            error_code: "unsupported-protocol".to_string(),
+        },
+        Message::OpenStandardMiningChannel {
+            request_id: 1,
+            user_identity: "worker.1".to_string(),
+            nominal_hash_rate: 1.5e6,
+            max_target: Target::MAX,
+        },
+        Message::OpenStandardMiningChannelSuccess {
+            request_id: 1,
+            channel_id: 2,
+            target,
+            extranonce_prefix: vec![0, 0, 0, 2],
+            group_channel_id: 0,
+        },
+        Message::OpenExtendedMiningChannel {
+            request_id: 2,
+            user_identity: "worker.2".to_string(),
+            nominal_hash_rate: 0.0,
+            max_target: target,
+            min_extranonce_size: 8,
+        },
+        Message::OpenExtendedMiningChannelSuccess {
+            request_id: 2,
+            channel_id: 3,
+            target,
+            extranonce_size: 8,
+            extranonce_prefix: vec![0, 0, 0, 3],
+        },
+        Message::OpenMiningChannelError {
+            request_id: 3,
+            error_code: "unknown-user".to_string(),
+        },
+        Message::UpdateChannel {
+            channel_id: 2,
+            nominal_hash_rate: 1e9,
+            maximum_target: target,
+        },
+        Message::CloseChannel {
+            channel_id: 2,
+            reason_code: String::new(),
+        },
+        Message::NewExtendedMiningJob {
+            channel_id: 3,
+            job_id: 4,
+            min_ntime: Some(1_600_000_000),
+            version: 0x2000_0000,
+            version_rolling_allowed: true,
+            merkle_path: vec![Hash256([1; 32]), Hash256([2; 32])],
+            coinbase_tx_prefix: vec![0x01; 300],
+            coinbase_tx_suffix: vec![0x02; 40],
+        },
+        Message::SetNewPrevHash {
+            channel_id: 3,
+            job_id: 4,
+            prev_hash: Hash256([3; 32]),
+            min_ntime: 1_600_000_000,
+            nbits: 0x1e0ffff0,
+        },
+        Message::SetTarget {
+            channel_id: 3,
+            maximum_target: target,
+        },
+        Message::SubmitSharesStandard {
+            channel_id: 2,
+            sequence_number: 0,
+            job_id: 4,
+            nonce: 1234534,
+            ntime: 1_600_000_001,
+            version: 0x2000_0000,
+        },
+        Message::SubmitSharesExtended {
+            channel_id: 3,
+            sequence_number: 1,
+            job_id: 4,
+            nonce: 1,
+            ntime: 1_600_000_001,
+            version: 0x2000_0000,
+            extranonce: vec![0xff; 8],
+        },
+        Message::SubmitSharesSuccess {
+            channel_id: 2,
+            last_sequence_number: 1,
+            new_submits_accepted_count: 2,
+            new_shares_sum: 2,
+        },
+        Message::SubmitSharesError {
+            channel_id: 2,
+            sequence_number: 2,
+            error_code: "difficulty-too-low".to_string(),
+        },
+    ];
+    for message in messages {
+        let frame = message.to_frame().unwrap();
+        assert_eq!(frame.channel_msg, message.is_channel_msg());
+        let decoded = Frame::from_bytes(&frame.to_bytes()).unwrap();
+        assert_eq!(Message::from_frame(&decoded).unwrap(), message);
+    }
+
+    // Truncated, padded and unknown messages are refused.
+    let mut frame = job.to_frame().unwrap();
+    frame.payload.pop();
+    assert!(matches!(
+        Message::from_frame(&frame),
+        Err(Sv2Error::Malformed(_))
+    ));
+    frame.payload.extend_from_slice(&[0, 0]);
+    assert!(matches!(
+        Message::from_frame(&frame),
+        Err(Sv2Error::Malformed(_))
+    ));
+    frame.msg_type = 0x7f;
+    assert!(matches!(
+        Message::from_frame(&frame),
+        Err(Sv2Error::UnknownMessage { msg_type: 0x7f, .. })
+    ));
+    assert!(Frame::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    let too_long = Message::CloseChannel {
+        channel_id: 0,
+        reason_code: "x".repeat(256),
+    };
+    assert!(too_long.to_frame().is_err());
+}
@@ -0,0 +1,88 @@
+//! # sv2
+//!
+//! `sv2` crate speaks the Stratum V2 mining protocol: binary [`Frame`]s carrying
+//! [`Message`]s, encrypted after a Noise NX handshake in which the pool proves its key with a
+//! [`Certificate`] of an authority the miner trusts. A mining [`Client`] opens a standard
+//! channel for header-only jobs, or an extended one to roll its own extranonce, and hashes them
+//! with the algorithms of this crate; a small pool [`Server`] hands out jobs and checks shares.
+//!
+//! Only the common and mining protocols are implemented, without group channels, version
+//! rolling or job negotiation.
+use std::error;
+use std::fmt;
+use std::io;
+
+mod client;
+mod codec;
+mod noise;
+mod server;
+
+pub use crate::stratum::FoundBlock;
+pub use client::{Channel, Client, Config, Event, Share, Work};
+pub use codec::{msg_type, Frame, Message, HEADER_SIZE, MAX_PAYLOAD_SIZE};
+pub use noise::{
+    generate_keypair, initiate, respond, Certificate, Receiver, Sender, ServerKeys, PROTOCOL_NAME,
+};
+pub use secp256k1::{Keypair, XOnlyPublicKey};
+pub use server::{Server, ServerConfig, ServerHandle, EXTRANONCE_PREFIX_SIZE};
+
+/// The protocol number of the mining protocol in `SetupConnection`.
+pub const MINING_PROTOCOL: u8 = 0;
+
+/// The protocol version this crate speaks.
+pub const VERSION: u16 = 2;
+
+/// `SetupConnection` flag of a device that only takes header-only jobs.
+pub const REQUIRES_STANDARD_JOBS: u32 = 1;
+
+/// An error of a Stratum V2 connection.
+#[derive(Debug)]
+pub enum Sv2Error {
+    /// The connection failed.
+    Io(io::Error),
+    /// The Noise handshake failed or a message did not decrypt.
+    Handshake(String),
+    /// A frame or message did not decode.
+    Malformed(String),
+    /// A message of an extension or type this crate does not know.
+    UnknownMessage { extension_type: u16, msg_type: u8 },
+    /// A message did not follow the protocol.
+    Protocol(String),
+    /// The pool refused the connection or a channel, with this error code.
+    Rejected(String),
+}
+
+impl fmt::Display for Sv2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sv2Error::Io(err) => write!(f, "sv2 connection failed: {}", err),
+            Sv2Error::Handshake(msg) => write!(f, "sv2 handshake failed: {}", msg),
+            Sv2Error::Malformed(msg) => write!(f, "malformed sv2 message: {}", msg),
+            Sv2Error::UnknownMessage {
+                extension_type,
+                msg_type,
+            } => write!(
+                f,
+                "unknown sv2 message 0x{:02x} of extension 0x{:04x}",
+                msg_type, extension_type
+            ),
+            Sv2Error::Protocol(msg) => write!(f, "sv2 protocol error: {}", msg),
+            Sv2Error::Rejected(code) => write!(f, "rejected by pool: {}", code),
+        }
+    }
+}
+
+impl error::Error for Sv2Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Sv2Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Sv2Error {
+    fn from(err: io::Error) -> Self {
+        Sv2Error::Io(err)
+    }
+}
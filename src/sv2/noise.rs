@@ -0,0 +1,578 @@
+use super::codec::{Frame, HEADER_SIZE, MAX_PAYLOAD_SIZE};
+use super::Sv2Error;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use hmac::{Hmac, Mac};
+use secp256k1::ellswift::{ElligatorSwift, ElligatorSwiftParty};
+use secp256k1::{schnorr, Keypair, PublicKey, Secp256k1, SecretKey, XOnlyPublicKey};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The Noise protocol name of Stratum V2, hashed into the initial handshake state.
+pub const PROTOCOL_NAME: &[u8] = b"Noise_NX_Secp256k1+EllSwift_ChaChaPoly_SHA256";
+
+// Sizes of the handshake messages: an ElligatorSwift key, then the responder's encrypted
+// static key and encrypted certificate.
+const ELLSWIFT_SIZE: usize = 64;
+const MAC_SIZE: usize = 16;
+const CERTIFICATE_SIZE: usize = 74;
+const RESPONSE_SIZE: usize = ELLSWIFT_SIZE + ELLSWIFT_SIZE + MAC_SIZE + CERTIFICATE_SIZE + MAC_SIZE;
+
+// The largest plaintext of one encrypted chunk, so that it fits a Noise message with its MAC.
+const MAX_CHUNK_SIZE: usize = 65535 - MAC_SIZE;
+
+/// The signature of an authority over the static key of a pool, sent in the handshake so that
+/// miners configured with the authority key know they talk to the right pool.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Certificate {
+    pub version: u16,
+    /// Unix time the certificate is valid from.
+    pub valid_from: u32,
+    /// Unix time the certificate expires.
+    pub not_valid_after: u32,
+    /// A BIP340 signature over SHA-256 of `version || valid_from || not_valid_after` and the
+    /// x-only static key.
+    pub signature: [u8; 64],
+}
+
+impl Certificate {
+    /// Signs the static key `server_key` with `authority`.
+    pub fn sign(
+        authority: &Keypair,
+        server_key: &XOnlyPublicKey,
+        valid_from: u32,
+        not_valid_after: u32,
+    ) -> Certificate {
+        let mut certificate = Certificate {
+            version: 0,
+            valid_from,
+            not_valid_after,
+            signature: [0; 64],
+        };
+        let message = certificate.message(server_key);
+        certificate.signature = Secp256k1::new()
+            .sign_schnorr_with_aux_rand(&message, authority, &random())
+            .serialize();
+        certificate
+    }
+
+    /// Returns whether the certificate is a valid signature of `authority` over `server_key`
+    /// and `now`, a Unix time, falls in its validity period.
+    pub fn verify(
+        &self,
+        authority: &XOnlyPublicKey,
+        server_key: &XOnlyPublicKey,
+        now: u32,
+    ) -> bool {
+        if now < self.valid_from || now > self.not_valid_after {
+            return false;
+        }
+        let signature = match schnorr::Signature::from_slice(&self.signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        Secp256k1::verification_only()
+            .verify_schnorr(&signature, &self.message(server_key), authority)
+            .is_ok()
+    }
+
+    /// Returns the 74-byte encoding sent in the handshake.
+    pub fn to_bytes(&self) -> [u8; CERTIFICATE_SIZE] {
+        let mut bytes = [0; CERTIFICATE_SIZE];
+        bytes[..10].copy_from_slice(&self.signed_fields());
+        bytes[10..].copy_from_slice(&self.signature);
+        bytes
+    }
+
+    /// Parses the 74-byte encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Certificate> {
+        if bytes.len() != CERTIFICATE_SIZE {
+            return None;
+        }
+        Some(Certificate {
+            version: u16::from_le_bytes([bytes[0], bytes[1]]),
+            valid_from: u32::from_le_bytes(bytes[2..6].try_into().unwrap()),
+            not_valid_after: u32::from_le_bytes(bytes[6..10].try_into().unwrap()),
+            signature: bytes[10..].try_into().unwrap(),
+        })
+    }
+
+    fn signed_fields(&self) -> [u8; 10] {
+        let mut fields = [0; 10];
+        fields[..2].copy_from_slice(&self.version.to_le_bytes());
+        fields[2..6].copy_from_slice(&self.valid_from.to_le_bytes());
+        fields[6..].copy_from_slice(&self.not_valid_after.to_le_bytes());
+        fields
+    }
+
+    fn message(&self, server_key: &XOnlyPublicKey) -> secp256k1::Message {
+        let digest = Sha256::new()
+            .chain_update(self.signed_fields())
+            .chain_update(server_key.serialize())
+            .finalize();
+        secp256k1::Message::from_digest_slice(&digest).unwrap()
+    }
+}
+
+impl fmt::Debug for Certificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Certificate")
+            .field("version", &self.version)
+            .field("valid_from", &self.valid_from)
+            .field("not_valid_after", &self.not_valid_after)
+            .finish()
+    }
+}
+
+/// The static key of a pool with its certificate.
+#[derive(Clone)]
+pub struct ServerKeys {
+    pub keypair: Keypair,
+    pub certificate: Certificate,
+}
+
+impl ServerKeys {
+    /// Returns a fresh static key certified by `authority` for the given period, in Unix time.
+    pub fn generate(authority: &Keypair, valid_from: u32, not_valid_after: u32) -> ServerKeys {
+        let keypair = generate_keypair();
+        let certificate = Certificate::sign(
+            authority,
+            &keypair.x_only_public_key().0,
+            valid_from,
+            not_valid_after,
+        );
+        ServerKeys {
+            keypair,
+            certificate,
+        }
+    }
+
+    /// Returns the x-only static key.
+    pub fn public_key(&self) -> XOnlyPublicKey {
+        self.keypair.x_only_public_key().0
+    }
+}
+
+impl fmt::Debug for ServerKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerKeys")
+            .field("public_key", &self.public_key())
+            .field("certificate", &self.certificate)
+            .finish()
+    }
+}
+
+/// Returns a key pair from the operating system's random source, as an authority or a static
+/// key.
+pub fn generate_keypair() -> Keypair {
+    let secp = Secp256k1::new();
+    loop {
+        if let Ok(keypair) = Keypair::from_seckey_slice(&secp, &random()) {
+            return keypair;
+        }
+    }
+}
+
+fn random() -> [u8; 32] {
+    let mut bytes = [0; 32];
+    getrandom::getrandom(&mut bytes).expect("no system random source");
+    bytes
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as u32)
+}
+
+fn hmac(key: &[u8], data: &[&[u8]]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).unwrap();
+    for data in data {
+        mac.update(data);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+// hkdf returns the two outputs of the Noise HKDF.
+fn hkdf(chaining_key: &[u8; 32], input: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let temp = hmac(chaining_key, &[input]);
+    let first = hmac(&temp, &[&[1]]);
+    let second = hmac(&temp, &[&first, &[2]]);
+    (first, second)
+}
+
+// An AEAD key with its message counter, the CipherState of Noise.
+struct Cipher {
+    cipher: ChaCha20Poly1305,
+    nonce: u64,
+}
+
+impl Cipher {
+    fn new(key: &[u8; 32]) -> Cipher {
+        Cipher {
+            cipher: ChaCha20Poly1305::new(key.into()),
+            nonce: 0,
+        }
+    }
+
+    // next_nonce returns 32 zero bits followed by the little-endian counter.
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[4..].copy_from_slice(&self.nonce.to_le_bytes());
+        self.nonce += 1;
+        nonce
+    }
+
+    fn encrypt(&mut self, ad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .encrypt(
+                &nonce.into(),
+                Payload {
+                    msg: plaintext,
+                    aad: ad,
+                },
+            )
+            .expect("plaintext too long")
+    }
+
+    fn decrypt(&mut self, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Sv2Error> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .decrypt(
+                &nonce.into(),
+                Payload {
+                    msg: ciphertext,
+                    aad: ad,
+                },
+            )
+            .map_err(|_| Sv2Error::Handshake("decryption failed".to_string()))
+    }
+}
+
+// The SymmetricState of Noise.
+struct Symmetric {
+    chaining_key: [u8; 32],
+    hash: [u8; 32],
+    cipher: Option<Cipher>,
+}
+
+impl Symmetric {
+    // new initializes the state from the protocol name, with an empty prologue.
+    fn new() -> Symmetric {
+        let hash: [u8; 32] = Sha256::digest(PROTOCOL_NAME).into();
+        let mut state = Symmetric {
+            chaining_key: hash,
+            hash,
+            cipher: None,
+        };
+        state.mix_hash(&[]);
+        state
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        self.hash = Sha256::new()
+            .chain_update(self.hash)
+            .chain_update(data)
+            .finalize()
+            .into();
+    }
+
+    fn mix_key(&mut self, input: &[u8; 32]) {
+        let (chaining_key, key) = hkdf(&self.chaining_key, input);
+        self.chaining_key = chaining_key;
+        self.cipher = Some(Cipher::new(&key));
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let ciphertext = match &mut self.cipher {
+            Some(cipher) => cipher.encrypt(&self.hash, plaintext),
+            None => plaintext.to_vec(),
+        };
+        self.mix_hash(&ciphertext);
+        ciphertext
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, Sv2Error> {
+        let plaintext = match &mut self.cipher {
+            Some(cipher) => cipher.decrypt(&self.hash, ciphertext)?,
+            None => ciphertext.to_vec(),
+        };
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    // split returns the initiator-to-responder and the responder-to-initiator ciphers.
+    fn split(&self) -> (Cipher, Cipher) {
+        let (first, second) = hkdf(&self.chaining_key, &[]);
+        (Cipher::new(&first), Cipher::new(&second))
+    }
+}
+
+// An ephemeral or static key with its ElligatorSwift encoding.
+struct EllKey {
+    secret: SecretKey,
+    encoded: ElligatorSwift,
+}
+
+impl EllKey {
+    fn new(secret: SecretKey) -> EllKey {
+        let secp = Secp256k1::new();
+        EllKey {
+            encoded: ElligatorSwift::from_seckey(&secp, secret, Some(random())),
+            secret,
+        }
+    }
+
+    fn generate() -> EllKey {
+        EllKey::new(generate_keypair().secret_key())
+    }
+}
+
+// ecdh returns the BIP324 x-only shared secret of an initiator key and a responder key, the
+// initiator being party A.
+fn ecdh(
+    initiator: ElligatorSwift,
+    responder: ElligatorSwift,
+    ours: &EllKey,
+    we_initiate: bool,
+) -> [u8; 32] {
+    let party = if we_initiate {
+        ElligatorSwiftParty::A
+    } else {
+        ElligatorSwiftParty::B
+    };
+    ElligatorSwift::shared_secret(initiator, responder, ours.secret, party, None).to_secret_bytes()
+}
+
+/// The sending half of an encrypted connection.
+pub struct Sender {
+    cipher: Cipher,
+}
+
+impl Sender {
+    /// Encrypts and writes a frame: the header as one Noise message, then the payload in
+    /// messages of up to 65519 bytes of plaintext.
+    pub fn write_frame<W: Write>(&mut self, writer: &mut W, frame: &Frame) -> Result<(), Sv2Error> {
+        if frame.payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(Sv2Error::Malformed("frame too large".to_string()));
+        }
+        let mut bytes = self.cipher.encrypt(&[], &frame.header());
+        for chunk in frame.payload.chunks(MAX_CHUNK_SIZE) {
+            bytes.extend_from_slice(&self.cipher.encrypt(&[], chunk));
+        }
+        writer.write_all(&bytes)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The receiving half of an encrypted connection.
+pub struct Receiver {
+    cipher: Cipher,
+}
+
+impl Receiver {
+    /// Reads and decrypts a frame written by [`Sender::write_frame`].
+    pub fn read_frame<R: Read>(&mut self, reader: &mut R) -> Result<Frame, Sv2Error> {
+        let mut header = [0; HEADER_SIZE + MAC_SIZE];
+        reader.read_exact(&mut header)?;
+        let header = self.cipher.decrypt(&[], &header)?;
+        let (mut frame, mut len) = Frame::parse_header(header.as_slice().try_into().unwrap());
+        frame.payload.reserve(len);
+        let mut chunk = vec![0; MAX_CHUNK_SIZE + MAC_SIZE];
+        while len > 0 {
+            let size = len.min(MAX_CHUNK_SIZE);
+            let chunk = &mut chunk[..size + MAC_SIZE];
+            reader.read_exact(chunk)?;
+            frame
+                .payload
+                .extend_from_slice(&self.cipher.decrypt(&[], chunk)?);
+            len -= size;
+        }
+        Ok(frame)
+    }
+}
+
+impl fmt::Debug for Sender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("nonce", &self.cipher.nonce)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Receiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("nonce", &self.cipher.nonce)
+            .finish()
+    }
+}
+
+/// Runs the initiator side of the handshake over `stream`: sends an ephemeral key, then checks
+/// that the pool's static key is certified by `authority` at the current time.
+pub fn initiate<S: Read + Write>(
+    stream: &mut S,
+    authority: &XOnlyPublicKey,
+) -> Result<(Sender, Receiver), Sv2Error> {
+    let mut state = Symmetric::new();
+    let ephemeral = EllKey::generate();
+    let e = ephemeral.encoded.to_array();
+    state.mix_hash(&e);
+    state.encrypt_and_hash(&[]);
+    stream.write_all(&e)?;
+    stream.flush()?;
+
+    let mut response = [0; RESPONSE_SIZE];
+    stream.read_exact(&mut response)?;
+    let (re, rest) = response.split_at(ELLSWIFT_SIZE);
+    let (encrypted_static, encrypted_certificate) = rest.split_at(ELLSWIFT_SIZE + MAC_SIZE);
+    let re = ElligatorSwift::from_array(re.try_into().unwrap());
+    state.mix_hash(&re.to_array());
+    state.mix_key(&ecdh(ephemeral.encoded, re, &ephemeral, true));
+    let rs = state.decrypt_and_hash(encrypted_static)?;
+    let rs = ElligatorSwift::from_array(rs.as_slice().try_into().unwrap());
+    state.mix_key(&ecdh(ephemeral.encoded, rs, &ephemeral, true));
+    let certificate = state.decrypt_and_hash(encrypted_certificate)?;
+    let certificate = Certificate::from_bytes(&certificate)
+        .ok_or_else(|| Sv2Error::Handshake("malformed certificate".to_string()))?;
+    let server_key = PublicKey::from_ellswift(rs).x_only_public_key().0;
+    if !certificate.verify(authority, &server_key, now()) {
+        return Err(Sv2Error::Handshake(
+            "pool key not certified by the authority".to_string(),
+        ));
+    }
+    let (send, receive) = state.split();
+    Ok((Sender { cipher: send }, Receiver { cipher: receive }))
+}
+
+/// Runs the responder side of the handshake over `stream` with the pool's static key.
+pub fn respond<S: Read + Write>(
+    stream: &mut S,
+    keys: &ServerKeys,
+) -> Result<(Sender, Receiver), Sv2Error> {
+    let mut state = Symmetric::new();
+    let mut re = [0; ELLSWIFT_SIZE];
+    stream.read_exact(&mut re)?;
+    state.mix_hash(&re);
+    state.decrypt_and_hash(&[])?;
+    let re = ElligatorSwift::from_array(re);
+
+    let ephemeral = EllKey::generate();
+    let static_key = EllKey::new(keys.keypair.secret_key());
+    let mut response = ephemeral.encoded.to_array().to_vec();
+    state.mix_hash(&response);
+    state.mix_key(&ecdh(re, ephemeral.encoded, &ephemeral, false));
+    response.extend_from_slice(&state.encrypt_and_hash(&static_key.encoded.to_array()));
+    state.mix_key(&ecdh(re, static_key.encoded, &static_key, false));
+    response.extend_from_slice(&state.encrypt_and_hash(&keys.certificate.to_bytes()));
+    stream.write_all(&response)?;
+    stream.flush()?;
+    let (receive, send) = state.split();
+    Ok((Sender { cipher: send }, Receiver { cipher: receive }))
+}
+
+#[test]
+fn sv2_noise_cal() {
+    use crate::utils::{from_hex, to_hex};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    // HKDF of the Noise specification, checked against HMAC-SHA256 by hand.
+    let (first, second) = hkdf(&[0; 32], b"input");
+    let temp = hmac(&[0; 32], &[b"input"]);
+    assert_eq!(first, hmac(&temp, &[&[1]]));
+    assert_ne!(first, second);
+
+    // The ECDH of the handshake is the ElligatorSwift XDH of BIP 324, as the Stratum V2
+    // specification requires: vectors of the BIP 324 test suite, as an initiator and as a
+    // responder.
+    let vectors = [
+        (
+            "61062ea5071d800bbfd59e2e8b53d47d194b095ae5a4df04936b49772ef0d4d7",
+            "ec0adff257bbfe500c188c80b4fdd640f6b45a482bbc15fc7cef5931deff0aa186f6eb9bba7b85dc4dcc28b28722de1e3d9108b985e2967045668f66098e475b",
+            "a4a94dfce69b4a2a0a099313d10f9f7e7d649d60501c9e1d274c300e0d89aafaffffffffffffffffffffffffffffffffffffffffffffffffffffffff8faf88d5",
+            true,
+            "c6992a117f5edbea70c3f511d32d26b9798be4b81a62eaee1a5acaa8459a3592",
+        ),
+        (
+            "1f9c581b35231838f0f17cf0c979835baccb7f3abbbb96ffcc318ab71e6e126f",
+            "a1855e10e94e00baa23041d916e259f7044e491da6171269694763f018c7e63693d29575dcb464ac816baa1be353ba12e3876cba7628bd0bd8e755e721eb0140",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2f0000000000000000000000000000000000000000000000000000000000000000",
+            false,
+            "a0138f564f74d0ad70bc337dacc9d0bf1d2349364caf1188a1e6e8ddb3b7b184",
+        ),
+    ];
+    for (secret, ours, theirs, initiating, shared) in vectors {
+        let ell = |hex| ElligatorSwift::from_array(from_hex(hex).unwrap().try_into().unwrap());
+        let key = EllKey {
+            secret: SecretKey::from_slice(&from_hex(secret).unwrap()).unwrap(),
+            encoded: ell(ours),
+        };
+        let (initiator, responder) = if initiating {
+            (ell(ours), ell(theirs))
+        } else {
+            (ell(theirs), ell(ours))
+        };
+        assert_eq!(
+            to_hex(&ecdh(initiator, responder, &key, initiating)),
+            shared
+        );
+    }
+
+    let authority = generate_keypair();
+    let keys = ServerKeys::generate(&authority, now() - 60, now() + 3600);
+    let authority_key = authority.x_only_public_key().0;
+    let certificate = keys.certificate;
+    assert_eq!(
+        Certificate::from_bytes(&certificate.to_bytes()),
+        Some(certificate)
+    );
+    assert!(certificate.verify(&authority_key, &keys.public_key(), now()));
+    assert!(!certificate.verify(&authority_key, &keys.public_key(), now() + 7200));
+    assert!(!certificate.verify(&keys.public_key(), &keys.public_key(), now()));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_keys = keys.clone();
+    let server = thread::spawn(move || {
+        // The first connection echoes one frame; the second one has a stale certificate.
+        let (mut stream, _) = listener.accept().unwrap();
+        let (mut sender, mut receiver) = respond(&mut stream, &server_keys).unwrap();
+        let frame = receiver.read_frame(&mut stream).unwrap();
+        sender.write_frame(&mut stream, &frame).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut expired = server_keys;
+        expired.certificate = Certificate::sign(&authority, &expired.public_key(), 0, 1);
+        let _ = respond(&mut stream, &expired);
+    });
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let (mut sender, mut receiver) = initiate(&mut stream, &authority_key).unwrap();
+    // A payload over one Noise message is split into chunks.
+    let frame = Frame {
+        extension_type: 0,
+        channel_msg: true,
+        msg_type: 0x15,
+        payload: (0..100_000u32).map(|i| i as u8).collect(),
+    };
+    sender.write_frame(&mut stream, &frame).unwrap();
+    assert_eq!(receiver.read_frame(&mut stream).unwrap(), frame);
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    assert!(matches!(
+        initiate(&mut stream, &authority_key),
+        Err(Sv2Error::Handshake(_))
+    ));
+    server.join().unwrap();
+
+    // A tampered ciphertext fails to decrypt.
+    let mut alice = Cipher::new(&[7; 32]);
+    let mut bob = Cipher::new(&[7; 32]);
+    let mut ciphertext = alice.encrypt(b"ad", b"hello");
+    ciphertext[0] ^= 1;
+    assert!(bob.decrypt(b"ad", &ciphertext).is_err());
+}
@@ -0,0 +1,1059 @@
+use super::noise::{self, Sender, ServerKeys};
+use super::{FoundBlock, Frame, Message, Sv2Error, MINING_PROTOCOL, VERSION};
+use crate::algorithm::Algorithm;
+use crate::difficulty::Convention;
+use crate::header::BlockHeader;
+use crate::job::{Job, Template};
+use crate::target::Target;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::BufReader;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Size in bytes of the extranonce prefix the server assigns to each channel.
+pub const EXTRANONCE_PREFIX_SIZE: usize = 4;
+
+// How far past the job time a share's ntime may be rolled, as nodes allow for block times.
+const MAX_NTIME_ROLL: u32 = 7200;
+
+// The largest extranonce of a channel, prefix included.
+const MAX_EXTRANONCE_SIZE: usize = 32;
+
+// Frames queued for a session before it is closed as too slow to read them.
+const OUTBOX_SIZE: usize = 256;
+
+// How long a write to a miner may block before its session is closed.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings of a Stratum V2 [`Server`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    /// The PoW algorithm shares are hashed with.
+    pub algorithm: Algorithm,
+    /// The share target of every channel, unless the miner asks for a harder one.
+    pub target: Target,
+    /// Size in bytes of the extranonce that miners of extended channels roll, at least.
+    pub extranonce_size: usize,
+    /// Number of jobs of the current template chain that shares are accepted for.
+    pub max_jobs: usize,
+    /// Connections served at once; more are closed as soon as they are accepted.
+    pub max_sessions: usize,
+    /// Time a connection has to complete the Noise handshake.
+    pub handshake_timeout: Duration,
+    /// Time a miner may send nothing after the handshake before its session is closed.
+    pub idle_timeout: Duration,
+}
+
+impl ServerConfig {
+    /// Returns a config with the NOMP target of difficulty 1, a 4-byte extranonce, 4 live jobs,
+    /// up to 1024 sessions, a 10-second handshake timeout and a 10-minute idle timeout.
+    pub fn new(algorithm: Algorithm) -> ServerConfig {
+        ServerConfig {
+            algorithm,
+            target: Convention::Nomp.share_target(algorithm, 1.0),
+            extranonce_size: 4,
+            max_jobs: 4,
+            max_sessions: 1024,
+            handshake_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(600),
+        }
+    }
+}
+
+type BlockFn = dyn Fn(&FoundBlock) + Send + Sync;
+
+/// Configures a Stratum V2 pool server with its static key and block callback.
+/// [`Server::listen`] starts it.
+/// # Examples
+///
+/// ```no_run
+/// use lyra2::algorithm::Algorithm;
+/// use lyra2::sv2::{generate_keypair, Server, ServerConfig, ServerKeys};
+/// # fn template() -> lyra2::job::Template { unimplemented!() }
+///
+/// let authority = generate_keypair();
+/// let keys = ServerKeys::generate(&authority, 0, u32::MAX);
+/// let server = Server::new(ServerConfig::new(Algorithm::Lyra2REv3), keys)
+///     .on_block(|found| println!("block {}: {}", found.block_hash, found.block.to_hex()))
+///     .listen("0.0.0.0:3336")
+///     .unwrap();
+/// server.set_template(template());
+/// ```
+pub struct Server {
+    config: ServerConfig,
+    keys: ServerKeys,
+    on_block: Option<Box<BlockFn>>,
+}
+
+impl Server {
+    /// Returns a server with the static key and certificate `keys`, without a block callback.
+    pub fn new(config: ServerConfig, keys: ServerKeys) -> Server {
+        Server {
+            config,
+            keys,
+            on_block: None,
+        }
+    }
+
+    /// Calls `on_block` with every share that meets the network target of its job, on the
+    /// thread of the submitting session before the share is answered.
+    pub fn on_block<F>(mut self, on_block: F) -> Server
+    where
+        F: Fn(&FoundBlock) + Send + Sync + 'static,
+    {
+        self.on_block = Some(Box::new(on_block));
+        self
+    }
+
+    /// Listens on `addr` and serves each connection on its own thread. Channels get work once
+    /// a template is set with [`ServerHandle::set_template`].
+    pub fn listen<A: ToSocketAddrs>(self, addr: A) -> Result<ServerHandle, Sv2Error> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            config: self.config,
+            keys: self.keys,
+            on_block: self.on_block,
+            stop: AtomicBool::new(false),
+            connections: AtomicUsize::new(0),
+            state: Mutex::new(State::default()),
+        });
+        let accept = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shared.stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+                    // Only this loop adds connections, so the count cannot pass the limit.
+                    if shared.connections.load(Ordering::SeqCst) >= shared.config.max_sessions {
+                        continue;
+                    }
+                    shared.connections.fetch_add(1, Ordering::SeqCst);
+                    let shared = Arc::clone(&shared);
+                    thread::spawn(move || {
+                        serve(&shared, stream);
+                        shared.connections.fetch_sub(1, Ordering::SeqCst);
+                    });
+                }
+            })
+        };
+        Ok(ServerHandle {
+            shared,
+            local_addr,
+            accept: Some(accept),
+        })
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("config", &self.config)
+            .field("keys", &self.keys)
+            .finish()
+    }
+}
+
+/// A running [`Server`]. Dropping it shuts the server down.
+pub struct ServerHandle {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// Returns the address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Makes a job of `template` the current one and sends it to every channel. When the
+    /// previous block changes, earlier jobs become stale: the job is sent as a future job and
+    /// activated with `SetNewPrevHash`.
+    pub fn set_template(&self, template: Template) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.next_job_id += 1;
+        let job_id = state.next_job_id;
+        let clean = state
+            .jobs
+            .back()
+            .map_or(true, |entry| entry.template.prev_hash != template.prev_hash);
+        if clean {
+            state.jobs.clear();
+        }
+        state.jobs.push_back(JobEntry { job_id, template });
+        while state.jobs.len() > self.shared.config.max_jobs.max(1) {
+            state.jobs.pop_front();
+        }
+        let live: HashSet<u32> = state.jobs.iter().map(|entry| entry.job_id).collect();
+        let State { jobs, sessions, .. } = &mut *state;
+        let entry = jobs.back().unwrap();
+        for session in sessions.values_mut() {
+            for (&channel_id, channel) in &mut session.channels {
+                channel.jobs.retain(|job_id, _| live.contains(job_id));
+                channel.shares.retain(|share| live.contains(&share.0));
+                send_job(&session.outbox, channel_id, channel, entry, clean);
+            }
+        }
+    }
+
+    /// Sets the share target of the channel `channel_id` and sends it to the miner. It applies
+    /// to every job of the channel. Returns false if there is no such channel.
+    pub fn set_target(&self, channel_id: u32, target: Target) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        for session in state.sessions.values_mut() {
+            if let Some(channel) = session.channels.get_mut(&channel_id) {
+                channel.target = target;
+                session.outbox.send(&Message::SetTarget {
+                    channel_id,
+                    maximum_target: target,
+                });
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns the number of open channels.
+    pub fn channels(&self) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .sessions
+            .values()
+            .map(|session| session.channels.len())
+            .sum()
+    }
+
+    /// Stops accepting connections and closes every session.
+    pub fn shutdown(&mut self) {
+        if let Some(accept) = self.accept.take() {
+            self.shared.stop.store(true, Ordering::SeqCst);
+            // Wake the accept loop up.
+            let _ = TcpStream::connect(self.local_addr);
+            let _ = accept.join();
+            for session in self.shared.state.lock().unwrap().sessions.values() {
+                session.outbox.close();
+            }
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl fmt::Debug for ServerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerHandle")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+struct Shared {
+    config: ServerConfig,
+    keys: ServerKeys,
+    on_block: Option<Box<BlockFn>>,
+    stop: AtomicBool,
+    // Connections being served, at most `config.max_sessions`.
+    connections: AtomicUsize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // Live jobs, the current one last.
+    jobs: VecDeque<JobEntry>,
+    next_job_id: u32,
+    next_channel_id: u32,
+    next_session: u32,
+    sessions: HashMap<u32, Session>,
+}
+
+struct JobEntry {
+    job_id: u32,
+    template: Template,
+}
+
+// Outbox queues the frames of a session for its writer thread, which encrypts them in order,
+// so that no miner is written to while the state is locked.
+#[derive(Clone)]
+struct Outbox {
+    frames: SyncSender<Frame>,
+    stream: Arc<TcpStream>,
+}
+
+impl Outbox {
+    // start spawns the writer thread of `stream`, which closes the connection when a write
+    // fails or times out.
+    fn start(stream: TcpStream, mut sender: Sender) -> Outbox {
+        let (frames, receiver) = mpsc::sync_channel::<Frame>(OUTBOX_SIZE);
+        let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+        let stream = Arc::new(stream);
+        let writer = Arc::clone(&stream);
+        thread::spawn(move || {
+            for frame in receiver {
+                if sender.write_frame(&mut &*writer, &frame).is_err() {
+                    let _ = writer.shutdown(Shutdown::Both);
+                    break;
+                }
+            }
+        });
+        Outbox { frames, stream }
+    }
+
+    // send queues one message; a miner that falls too far behind is disconnected, which
+    // surfaces as a read error of the session.
+    fn send(&self, message: &Message) {
+        let frame = match message.to_frame() {
+            Ok(frame) => frame,
+            Err(_) => return,
+        };
+        match self.frames.try_send(frame) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+            Err(TrySendError::Full(_)) => self.close(),
+        }
+    }
+
+    fn close(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+struct Session {
+    outbox: Outbox,
+    setup: bool,
+    channels: HashMap<u32, Channel>,
+}
+
+struct Channel {
+    user: String,
+    extended: bool,
+    extranonce_prefix: Vec<u8>,
+    // Size of the extranonce after the prefix; 0 on standard channels.
+    extranonce_size: usize,
+    target: Target,
+    // The jobs of the channel as sent, their coinbase sized for the channel's extranonce.
+    jobs: HashMap<u32, Job>,
+    // (job_id, extranonce, ntime, nonce, version) of accepted shares.
+    shares: HashSet<(u32, Vec<u8>, u32, u32, u32)>,
+}
+
+struct Submission {
+    channel_id: u32,
+    job_id: u32,
+    extranonce: Option<Vec<u8>>,
+    nonce: u32,
+    ntime: u32,
+    version: u32,
+}
+
+// A share that passed the checks which need no hashing.
+struct Share {
+    channel_id: u32,
+    // (job_id, extranonce, ntime, nonce, version), as recorded in `Channel::shares`.
+    key: (u32, Vec<u8>, u32, u32, u32),
+    header: BlockHeader,
+    target: Target,
+}
+
+// serve runs one session until the connection closes.
+fn serve(shared: &Shared, mut stream: TcpStream) {
+    let _ = stream.set_nodelay(true);
+    let _ = stream.set_read_timeout(Some(shared.config.handshake_timeout));
+    let (sender, mut receiver) = match noise::respond(&mut stream, &shared.keys) {
+        Ok(halves) => halves,
+        Err(_) => return,
+    };
+    let _ = stream.set_read_timeout(Some(shared.config.idle_timeout));
+    let outbox = match stream.try_clone() {
+        Ok(writer) => Outbox::start(writer, sender),
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    let id = {
+        let mut state = shared.state.lock().unwrap();
+        let id = state.next_session;
+        state.next_session = state.next_session.wrapping_add(1);
+        state.sessions.insert(
+            id,
+            Session {
+                outbox: outbox.clone(),
+                setup: false,
+                channels: HashMap::new(),
+            },
+        );
+        id
+    };
+    loop {
+        let message = match receiver
+            .read_frame(&mut reader)
+            .and_then(|frame| Message::from_frame(&frame))
+        {
+            Ok(message) => message,
+            Err(Sv2Error::UnknownMessage { .. }) => continue,
+            Err(_) => break,
+        };
+        let handled = match message {
+            Message::SubmitSharesStandard {
+                channel_id,
+                sequence_number,
+                job_id,
+                nonce,
+                ntime,
+                version,
+            } => respond_submit(
+                shared,
+                id,
+                sequence_number,
+                Submission {
+                    channel_id,
+                    job_id,
+                    extranonce: None,
+                    nonce,
+                    ntime,
+                    version,
+                },
+            ),
+            Message::SubmitSharesExtended {
+                channel_id,
+                sequence_number,
+                job_id,
+                nonce,
+                ntime,
+                version,
+                extranonce,
+            } => respond_submit(
+                shared,
+                id,
+                sequence_number,
+                Submission {
+                    channel_id,
+                    job_id,
+                    extranonce: Some(extranonce),
+                    nonce,
+                    ntime,
+                    version,
+                },
+            ),
+            message => handle(shared, &mut shared.state.lock().unwrap(), id, message),
+        };
+        if handled.is_err() {
+            break;
+        }
+    }
+    shared.state.lock().unwrap().sessions.remove(&id);
+}
+
+// handle answers one message other than a share. An error closes the connection.
+fn handle(shared: &Shared, state: &mut State, id: u32, message: Message) -> Result<(), ()> {
+    let config = &shared.config;
+    let session = state.sessions.get_mut(&id).unwrap();
+    let outbox = session.outbox.clone();
+    if !session.setup {
+        let (protocol, min_version, max_version) = match message {
+            Message::SetupConnection {
+                protocol,
+                min_version,
+                max_version,
+                ..
+            } => (protocol, min_version, max_version),
+            _ => return Err(()),
+        };
+        let error_code = if protocol != MINING_PROTOCOL {
+            "unsupported-protocol"
+        } else if !(min_version..=max_version).contains(&VERSION) {
+            "protocol-version-mismatch"
+        } else {
+            session.setup = true;
+            outbox.send(&Message::SetupConnectionSuccess {
+                used_version: VERSION,
+                flags: 0,
+            });
+            return Ok(());
+        };
+        outbox.send(&Message::SetupConnectionError {
+            flags: 0,
+            error_code: error_code.to_string(),
+        });
+        return Err(());
+    }
+
+    match message {
+        Message::OpenStandardMiningChannel {
+            request_id,
+            user_identity,
+            max_target,
+            ..
+        } => open_channel(
+            shared,
+            state,
+            id,
+            request_id,
+            user_identity,
+            max_target,
+            None,
+        ),
+        Message::OpenExtendedMiningChannel {
+            request_id,
+            user_identity,
+            max_target,
+            min_extranonce_size,
+            ..
+        } => {
+            let extranonce_size = config.extranonce_size.max(min_extranonce_size as usize);
+            if EXTRANONCE_PREFIX_SIZE + extranonce_size > MAX_EXTRANONCE_SIZE {
+                outbox.send(&Message::OpenMiningChannelError {
+                    request_id,
+                    error_code: "min-extranonce-size-too-large".to_string(),
+                });
+                return Ok(());
+            }
+            open_channel(
+                shared,
+                state,
+                id,
+                request_id,
+                user_identity,
+                max_target,
+                Some(extranonce_size),
+            )
+        }
+        Message::UpdateChannel {
+            channel_id,
+            maximum_target,
+            ..
+        } => {
+            if let Some(channel) = session.channels.get_mut(&channel_id) {
+                if maximum_target < channel.target {
+                    channel.target = maximum_target;
+                    outbox.send(&Message::SetTarget {
+                        channel_id,
+                        maximum_target,
+                    });
+                }
+            }
+            Ok(())
+        }
+        Message::CloseChannel { channel_id, .. } => {
+            session.channels.remove(&channel_id);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+// open_channel opens a standard channel, or an extended one with `extranonce_size`, and sends
+// it the current job.
+fn open_channel(
+    shared: &Shared,
+    state: &mut State,
+    id: u32,
+    request_id: u32,
+    user: String,
+    max_target: Target,
+    extranonce_size: Option<usize>,
+) -> Result<(), ()> {
+    state.next_channel_id = state.next_channel_id.wrapping_add(1);
+    let channel_id = state.next_channel_id;
+    let mut channel = Channel {
+        user,
+        extended: extranonce_size.is_some(),
+        extranonce_prefix: channel_id.to_be_bytes().to_vec(),
+        extranonce_size: extranonce_size.unwrap_or(0),
+        target: shared.config.target.min(max_target),
+        jobs: HashMap::new(),
+        shares: HashSet::new(),
+    };
+    let session = state.sessions.get_mut(&id).unwrap();
+    let response = match extranonce_size {
+        Some(extranonce_size) => Message::OpenExtendedMiningChannelSuccess {
+            request_id,
+            channel_id,
+            target: channel.target,
+            extranonce_size: extranonce_size as u16,
+            extranonce_prefix: channel.extranonce_prefix.clone(),
+        },
+        None => Message::OpenStandardMiningChannelSuccess {
+            request_id,
+            channel_id,
+            target: channel.target,
+            extranonce_prefix: channel.extranonce_prefix.clone(),
+            group_channel_id: 0,
+        },
+    };
+    session.outbox.send(&response);
+    if let Some(entry) = state.jobs.back() {
+        send_job(&session.outbox, channel_id, &mut channel, entry, true);
+    }
+    session.channels.insert(channel_id, channel);
+    Ok(())
+}
+
+// send_job makes the job of `entry` for a channel and sends it; a future job is followed by
+// the `SetNewPrevHash` that activates it.
+fn send_job(
+    outbox: &Outbox,
+    channel_id: u32,
+    channel: &mut Channel,
+    entry: &JobEntry,
+    future: bool,
+) {
+    let template = &entry.template;
+    let job = template.job(
+        &entry.job_id.to_string(),
+        channel.extranonce_prefix.len() + channel.extranonce_size,
+        future,
+    );
+    let min_ntime = if future { None } else { Some(template.time) };
+    let message = if channel.extended {
+        Message::NewExtendedMiningJob {
+            channel_id,
+            job_id: entry.job_id,
+            min_ntime,
+            version: job.version as u32,
+            version_rolling_allowed: false,
+            merkle_path: job.merkle_branch.clone(),
+            coinbase_tx_prefix: job.coinb1.clone(),
+            coinbase_tx_suffix: job.coinb2.clone(),
+        }
+    } else {
+        Message::NewMiningJob {
+            channel_id,
+            job_id: entry.job_id,
+            min_ntime,
+            version: job.version as u32,
+            merkle_root: job.merkle_root(&channel.extranonce_prefix, &[]),
+        }
+    };
+    outbox.send(&message);
+    if future {
+        outbox.send(&Message::SetNewPrevHash {
+            channel_id,
+            job_id: entry.job_id,
+            prev_hash: template.prev_hash,
+            min_ntime: template.time,
+            nbits: template.bits,
+        });
+    }
+    channel.jobs.insert(entry.job_id, job);
+}
+
+// respond_submit checks a share, hands a block it solves to the callback and answers the
+// share. Each accepted share is acknowledged on its own and counts as one share. A share before
+// `SetupConnection` closes the connection.
+fn respond_submit(
+    shared: &Shared,
+    id: u32,
+    sequence_number: u32,
+    submission: Submission,
+) -> Result<(), ()> {
+    let channel_id = submission.channel_id;
+    let (outbox, share) = {
+        let state = shared.state.lock().unwrap();
+        let session = &state.sessions[&id];
+        if !session.setup {
+            return Err(());
+        }
+        (session.outbox.clone(), check_share(&state, id, submission))
+    };
+    let result = share.and_then(|share| submit(shared, id, share));
+    // The callback runs unlocked so that it may set a new template.
+    if let (Ok(Some(found)), Some(on_block)) = (&result, &shared.on_block) {
+        on_block(found);
+    }
+    let response = match &result {
+        Ok(_) => Message::SubmitSharesSuccess {
+            channel_id,
+            last_sequence_number: sequence_number,
+            new_submits_accepted_count: 1,
+            new_shares_sum: 1,
+        },
+        Err(error_code) => Message::SubmitSharesError {
+            channel_id,
+            sequence_number,
+            error_code: error_code.to_string(),
+        },
+    };
+    outbox.send(&response);
+    Ok(())
+}
+
+// check_share checks a share against its channel and job.
+fn check_share(state: &State, id: u32, submission: Submission) -> Result<Share, &'static str> {
+    let channel = state.sessions[&id]
+        .channels
+        .get(&submission.channel_id)
+        .ok_or("invalid-channel-id")?;
+    let extranonce = match submission.extranonce {
+        Some(extranonce) if channel.extended => {
+            if extranonce.len() != channel.extranonce_size {
+                return Err("invalid-extranonce-size");
+            }
+            extranonce
+        }
+        None if !channel.extended => Vec::new(),
+        _ => return Err("invalid-channel-id"),
+    };
+    let job = match channel.jobs.get(&submission.job_id) {
+        Some(job) => job,
+        None if submission.job_id <= state.next_job_id => return Err("stale-share"),
+        None => return Err("invalid-job-id"),
+    };
+    let (ntime, nonce) = (submission.ntime, submission.nonce);
+    if ntime < job.time || ntime > job.time.saturating_add(MAX_NTIME_ROLL) {
+        return Err("invalid-timestamp");
+    }
+    if submission.version != job.version as u32 {
+        return Err("invalid-version");
+    }
+    let key = (
+        submission.job_id,
+        extranonce,
+        ntime,
+        nonce,
+        submission.version,
+    );
+    if channel.shares.contains(&key) {
+        return Err("duplicate-share");
+    }
+    Ok(Share {
+        channel_id: submission.channel_id,
+        header: job.header(&channel.extranonce_prefix, &key.1, ntime, nonce),
+        key,
+        target: channel.target,
+    })
+}
+
+// submit hashes a checked share and returns the block it solves, if any. The hash is taken
+// without holding the state lock, so that other sessions are not held up by it.
+fn submit(shared: &Shared, id: u32, share: Share) -> Result<Option<FoundBlock>, &'static str> {
+    let pow_hash = share.header.pow_hash(shared.config.algorithm);
+    if !share.target.is_met_by(pow_hash.as_bytes()) {
+        return Err("difficulty-too-low");
+    }
+
+    let mut state = shared.state.lock().unwrap();
+    let State { jobs, sessions, .. } = &mut *state;
+    // The channel may have closed, its job gone stale or the share been submitted again while
+    // hashing.
+    let channel = sessions
+        .get_mut(&id)
+        .unwrap()
+        .channels
+        .get_mut(&share.channel_id)
+        .ok_or("invalid-channel-id")?;
+    let job_id = share.key.0;
+    if !channel.jobs.contains_key(&job_id) {
+        return Err("stale-share");
+    }
+    if !channel.shares.insert(share.key.clone()) {
+        return Err("duplicate-share");
+    }
+    let meets_network = share
+        .header
+        .target()
+        .is_ok_and(|target| target.is_met_by(pow_hash.as_bytes()));
+    if !meets_network {
+        return Ok(None);
+    }
+    let entry = jobs
+        .iter()
+        .find(|entry| entry.job_id == job_id)
+        .ok_or("stale-share")?;
+    let (_, extranonce, ntime, nonce, _) = &share.key;
+    Ok(Some(FoundBlock {
+        block: entry
+            .template
+            .block(&channel.extranonce_prefix, extranonce, *ntime, *nonce),
+        block_hash: share.header.block_hash(),
+        pow_hash,
+        worker: channel.user.clone(),
+    }))
+}
+
+#[test]
+fn sv2_server_cal() {
+    use super::{generate_keypair, Client, Config, Event, Share};
+    use crate::block::{Transaction, TxIn, TxOut};
+    use crate::header::Hash256;
+    use std::io::Read;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    let template = |prev: &[u8], bits: u32, height: u32| Template {
+        prev_hash: Hash256::sha256d(prev),
+        height,
+        version: 0x20000000,
+        bits,
+        time: 1_600_000_000,
+        coinbase_value: 25 * 100_000_000,
+        payout_script: vec![0x51],
+        coinbase_tag: b"/test/".to_vec(),
+        transactions: vec![Transaction {
+            version: 2,
+            inputs: vec![TxIn {
+                prev_txid: Hash256::sha256d(b"utxo"),
+                prev_index: 0,
+                script_sig: vec![0x51],
+                sequence: u32::MAX,
+                witness: Vec::new(),
+            }],
+            outputs: vec![TxOut {
+                value: 1000,
+                script_pubkey: vec![0x51],
+            }],
+            lock_time: 0,
+        }],
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+    let authority = generate_keypair();
+    let authority_key = authority.x_only_public_key().0;
+    let keys = ServerKeys::generate(&authority, now - 60, now + 3600);
+
+    // One hash in 16 is a share; the first template's network target is out of reach.
+    let mut config = ServerConfig::new(Algorithm::Lyra2REv2);
+    config.target = Convention::Nomp.share_target(Algorithm::Lyra2REv2, 1.0 / 1_048_576.0);
+    let found = Arc::new(Mutex::new(Vec::new()));
+    let server = {
+        let found = Arc::clone(&found);
+        Server::new(config.clone(), keys)
+            .on_block(move |block| found.lock().unwrap().push(block.clone()))
+            .listen("127.0.0.1:0")
+            .unwrap()
+    };
+    let first = template(b"tip 99", 0x1d00ffff, 100);
+    server.set_template(first.clone());
+
+    // A pool whose key the authority did not sign is refused.
+    let stranger = generate_keypair().x_only_public_key().0;
+    let config_for = |user: &str| Config::new(user, Algorithm::Lyra2REv2);
+    assert!(matches!(
+        Client::connect(server.local_addr(), &stranger, config_for("w")),
+        Err(Sv2Error::Handshake(_))
+    ));
+
+    // Connections past the limit are closed, and so are stalled handshakes.
+    let mut limited = ServerConfig::new(Algorithm::Lyra2REv2);
+    limited.max_sessions = 1;
+    limited.handshake_timeout = Duration::from_millis(200);
+    let limited = Server::new(
+        limited,
+        ServerKeys::generate(&authority, now - 60, now + 3600),
+    )
+    .listen("127.0.0.1:0")
+    .unwrap();
+    let mut stalled = TcpStream::connect(limited.local_addr()).unwrap();
+    while limited.shared.connections.load(Ordering::SeqCst) == 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    let mut refused = TcpStream::connect(limited.local_addr()).unwrap();
+    for stream in [&mut refused, &mut stalled] {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        assert!(matches!(stream.read(&mut [0; 1]), Ok(0) | Err(_)));
+    }
+    while limited.shared.connections.load(Ordering::SeqCst) > 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    Client::connect(limited.local_addr(), &authority_key, config_for("w")).unwrap();
+
+    // A connection for another protocol is refused after the handshake.
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let (mut sender, mut receiver) = noise::initiate(&mut stream, &authority_key).unwrap();
+    let setup = Message::SetupConnection {
+        protocol: 1,
+        min_version: 2,
+        max_version: 2,
+        flags: 0,
+        endpoint_host: String::new(),
+        endpoint_port: 0,
+        vendor: String::new(),
+        hardware_version: String::new(),
+        firmware: String::new(),
+        device_id: String::new(),
+    };
+    sender
+        .write_frame(&mut stream, &setup.to_frame().unwrap())
+        .unwrap();
+    let response = Message::from_frame(&receiver.read_frame(&mut stream).unwrap()).unwrap();
+    assert_eq!(
+        response,
+        Message::SetupConnectionError {
+            flags: 0,
+            error_code: "unsupported-protocol".to_string()
+        }
+    );
+
+    // A standard channel gets a header-only job with its own merkle root.
+    let mut client =
+        Client::connect(server.local_addr(), &authority_key, config_for("w.1")).unwrap();
+    let channel = client.open_standard_channel().unwrap();
+    assert!(!channel.extended);
+    assert_eq!(channel.target, config.target);
+    assert_eq!(channel.extranonce_prefix.len(), EXTRANONCE_PREFIX_SIZE);
+    let job_id = match client.next_event().unwrap() {
+        Event::Job {
+            job_id,
+            future: true,
+        } => job_id,
+        event => panic!("unexpected event {:?}", event),
+    };
+    assert_eq!(client.next_event().unwrap(), Event::PrevHash { job_id });
+    let work = client.work(0).unwrap();
+    let expected = first.block(&channel.extranonce_prefix, &[], first.time, 0);
+    assert_eq!(work.header, expected.header);
+    assert!(work.extranonce.is_empty());
+
+    let shares = client.mine(&work, 0..=63);
+    assert!(!shares.is_empty());
+    for share in &shares {
+        client.submit(share).unwrap();
+    }
+    for sequence_number in 0..shares.len() as u32 {
+        assert_eq!(
+            client.next_event().unwrap(),
+            Event::SharesAccepted {
+                last_sequence_number: sequence_number,
+                count: 1,
+                shares_sum: 1
+            }
+        );
+    }
+    let nonces: Vec<u32> = shares.iter().map(|share| share.nonce).collect();
+    let low = (0..=63).find(|nonce| !nonces.contains(nonce)).unwrap();
+    let mut stale = Share {
+        nonce: low,
+        ..shares[0].clone()
+    };
+    let rejected = |client: &mut Client, share: &Share| {
+        let sequence_number = client.submit(share).unwrap();
+        match client.next_event().unwrap() {
+            Event::ShareRejected {
+                sequence_number: rejected,
+                error_code,
+            } if rejected == sequence_number => error_code,
+            event => panic!("unexpected event {:?}", event),
+        }
+    };
+    assert_eq!(rejected(&mut client, &stale), "difficulty-too-low");
+    assert_eq!(rejected(&mut client, &shares[0]), "duplicate-share");
+    stale.time = work.header.time - 1;
+    assert_eq!(rejected(&mut client, &stale), "invalid-timestamp");
+    stale.job_id = 99;
+    assert_eq!(rejected(&mut client, &stale), "invalid-job-id");
+
+    // An extended channel rolls its extranonce into the coinbase.
+    let mut extended =
+        Client::connect(server.local_addr(), &authority_key, config_for("w.2")).unwrap();
+    let channel2 = extended.open_extended_channel(8).unwrap();
+    assert!(channel2.extended);
+    assert_eq!(channel2.extranonce_size, 8);
+    assert_ne!(channel2.extranonce_prefix, channel.extranonce_prefix);
+    assert!(matches!(
+        extended.next_event().unwrap(),
+        Event::Job { future: true, .. }
+    ));
+    assert!(matches!(
+        extended.next_event().unwrap(),
+        Event::PrevHash { .. }
+    ));
+    let work2 = extended.work(0).unwrap();
+    assert_eq!(work2.extranonce, vec![0; 8]);
+    let expected = first.block(
+        &channel2.extranonce_prefix,
+        &work2.extranonce,
+        first.time,
+        0,
+    );
+    assert_eq!(work2.header, expected.header);
+    assert_ne!(extended.work(0).unwrap().header, work2.header);
+    let shares2 = extended.mine(&work2, 0..=63);
+    assert!(!shares2.is_empty());
+    extended.submit(&shares2[0]).unwrap();
+    assert!(matches!(
+        extended.next_event().unwrap(),
+        Event::SharesAccepted { .. }
+    ));
+    assert!(extended.open_extended_channel(64).is_err());
+    assert_eq!(server.channels(), 2);
+    assert!(found.lock().unwrap().is_empty());
+
+    // A new block at minimal difficulty: old jobs are stale and every share is a block.
+    let next = template(b"tip 100", 0x207fffff, 101);
+    server.set_template(next.clone());
+    assert!(matches!(
+        client.next_event().unwrap(),
+        Event::Job { future: true, .. }
+    ));
+    assert!(matches!(
+        client.next_event().unwrap(),
+        Event::PrevHash { .. }
+    ));
+    assert_eq!(rejected(&mut client, &shares[0]), "stale-share");
+    let work = client.work(0).unwrap();
+    assert_eq!(work.header.prev_block, next.prev_hash);
+    let share = client.mine(&work, 0..=63).remove(0);
+    client.submit(&share).unwrap();
+    assert!(matches!(
+        client.next_event().unwrap(),
+        Event::SharesAccepted { .. }
+    ));
+    // The block callback runs after the response is sent.
+    for _ in 0..100 {
+        if !found.lock().unwrap().is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let blocks = found.lock().unwrap().clone();
+    assert_eq!(blocks.len(), 1);
+    let block = &blocks[0];
+    assert_eq!(block.worker, "w.1");
+    let mut header = work.header;
+    header.nonce = share.nonce;
+    assert_eq!(block.block.header, header);
+    assert_eq!(block.block.header.block_hash(), block.block_hash);
+    assert_eq!(block.block.coinbase_height(), Some(101));
+    block.block.validate(Algorithm::Lyra2REv2).unwrap();
+
+    // A job on the same block is current at once.
+    let mut same = next.clone();
+    same.time += 10;
+    server.set_template(same);
+    let job_id = match client.next_event().unwrap() {
+        Event::Job {
+            job_id,
+            future: false,
+        } => job_id,
+        event => panic!("unexpected event {:?}", event),
+    };
+    let work = client.work(0).unwrap();
+    assert_eq!(work.job_id, job_id);
+    assert_eq!(work.header.time, next.time + 10);
+
+    // A target change reaches the miner.
+    let target = Target::from_compact(0x1f00ffff).unwrap();
+    assert!(server.set_target(channel.channel_id, target));
+    assert_eq!(client.next_event().unwrap(), Event::Target(target));
+    assert_eq!(client.work(0).unwrap().target, target);
+    assert!(!server.set_target(999, target));
+}
@@ -0,0 +1,561 @@
+//! # target
+//!
+//! `target` crate has the 256-bit PoW target, its compact (`nBits`) encoding and difficulty.
+//!
+//! Hashes are compared as little-endian 256-bit integers, which is how nodes read the 32 bytes
+//! returned by `sum`.
+use crate::utils::from_hex;
+use std::cmp::Ordering;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// A 256-bit PoW target. A hash meets the target when, read as a little-endian integer, it is
+/// less than or equal to the target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Target {
+    // Little-endian 64-bit limbs.
+    limbs: [u64; 4],
+}
+
+impl Target {
+    /// The zero target, which no hash other than zero meets.
+    pub const ZERO: Target = Target { limbs: [0; 4] };
+
+    /// The largest target, which every hash meets.
+    pub const MAX: Target = Target {
+        limbs: [u64::MAX; 4],
+    };
+
+    /// Difficulty-1 target of nodes (`getdifficulty`, `getblockheader`), i.e. compact
+    /// `0x1d00ffff`.
+    pub const DIFF1_NODE: Target = Target {
+        limbs: [0, 0, 0, 0x0000_0000_FFFF_0000],
+    };
+
+    /// Difficulty-1 target of pools ("true difficulty"), i.e.
+    /// `0x00000000ffffffff...ffff`.
+    pub const DIFF1_POOL: Target = Target {
+        limbs: [u64::MAX, u64::MAX, u64::MAX, 0x0000_0000_FFFF_FFFF],
+    };
+
+    /// Returns the target from 32 little-endian bytes, the byte order of `sum` outputs.
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Target {
+        let mut limbs = [0u64; 4];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            *limb = u64::from_le_bytes(word);
+        }
+        Target { limbs }
+    }
+
+    /// Returns the target as 32 little-endian bytes.
+    pub fn to_le_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (chunk, limb) in bytes.chunks_exact_mut(8).zip(&self.limbs) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Returns the target as 32 big-endian bytes, the order it is usually printed in.
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = self.to_le_bytes();
+        bytes.reverse();
+        bytes
+    }
+
+    /// Decodes a compact target (`nBits`) as nodes do. A set sign bit or a value that does not
+    /// fit in 256 bits is an error; a zero mantissa decodes to [`Target::ZERO`].
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::target::Target;
+    ///
+    /// let target = Target::from_compact(0x1e0ffff0).unwrap();
+    /// assert_eq!(
+    ///     "00000ffff0000000000000000000000000000000000000000000000000000000",
+    ///     target.to_string()
+    /// );
+    /// assert_eq!(0x1e0ffff0, target.to_compact());
+    /// ```
+    pub fn from_compact(bits: u32) -> Result<Target, TargetError> {
+        let size = bits >> 24;
+        let word = bits & 0x007F_FFFF;
+        if word != 0 && bits & 0x0080_0000 != 0 {
+            return Err(TargetError::Negative(bits));
+        }
+        if word != 0 && (size > 34 || (word > 0xFF && size > 33) || (word > 0xFFFF && size > 32)) {
+            return Err(TargetError::Overflow(bits));
+        }
+        let target = if size <= 3 {
+            Target::from_u64(u64::from(word >> (8 * (3 - size))))
+        } else {
+            Target::from_u64(u64::from(word)).shl(8 * (size - 3))
+        };
+        Ok(target)
+    }
+
+    /// Encodes the target in compact form (`nBits`), dropping all but the 23 most significant
+    /// bits as nodes do.
+    pub fn to_compact(&self) -> u32 {
+        let mut size = (self.bits() + 7) / 8;
+        let mut compact = if size <= 3 {
+            (self.limbs[0] << (8 * (3 - size))) as u32
+        } else {
+            self.shr(8 * (size - 3)).limbs[0] as u32
+        };
+        if compact & 0x0080_0000 != 0 {
+            compact >>= 8;
+            size += 1;
+        }
+        compact | (size << 24)
+    }
+
+    /// Returns the difficulty of this target relative to `diff1`, i.e. `diff1 / self`.
+    ///
+    /// Use [`Target::DIFF1_NODE`] to match the difficulty reported by nodes and
+    /// [`Target::DIFF1_POOL`] to match share difficulty on pools.
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::target::Target;
+    ///
+    /// let target = Target::from_compact(0x1e0ffff0).unwrap();
+    /// assert_eq!(0.000244140625, target.difficulty(&Target::DIFF1_NODE));
+    /// ```
+    pub fn difficulty(&self, diff1: &Target) -> f64 {
+        diff1.to_f64() / self.to_f64()
+    }
+
+    /// Returns the target whose difficulty relative to `diff1` is `difficulty`, i.e.
+    /// `diff1 / difficulty` rounded down. Non-positive difficulties give [`Target::MAX`] and an
+    /// infinite difficulty gives [`Target::ZERO`].
+    pub fn from_difficulty(difficulty: f64, diff1: &Target) -> Target {
+        if difficulty.is_nan() || difficulty <= 0.0 {
+            return Target::MAX;
+        }
+        if difficulty.is_infinite() {
+            return Target::ZERO;
+        }
+        // difficulty = mantissa * 2^exponent with an odd mantissa.
+        let bits = difficulty.to_bits();
+        let biased = ((bits >> 52) & 0x7FF) as i32;
+        let (mut mantissa, mut exponent) = if biased == 0 {
+            (bits & 0x000F_FFFF_FFFF_FFFF, -1074)
+        } else {
+            (bits & 0x000F_FFFF_FFFF_FFFF | 1 << 52, biased - 1075)
+        };
+        let zeros = mantissa.trailing_zeros();
+        mantissa >>= zeros;
+        exponent += zeros as i32;
+
+        let mut wide = [0u64; 8];
+        wide[..4].copy_from_slice(&diff1.limbs);
+        if exponent < 0 {
+            let shift = (-exponent) as u32;
+            if shift > 256 {
+                return if *diff1 == Target::ZERO {
+                    Target::ZERO
+                } else {
+                    Target::MAX
+                };
+            }
+            wide = shl_wide(wide, shift);
+        }
+        wide = div_wide(wide, mantissa);
+        if exponent > 0 {
+            wide = shr_wide(wide, exponent as u32);
+        }
+        if wide[4..].iter().any(|&limb| limb != 0) {
+            return Target::MAX;
+        }
+        let mut limbs = [0u64; 4];
+        limbs.copy_from_slice(&wide[..4]);
+        Target { limbs }
+    }
+
+    /// Returns true if `hash`, read as a little-endian integer, is at most this target.
+    pub fn is_met_by(&self, hash: &[u8; 32]) -> bool {
+        Target::from_le_bytes(*hash) <= *self
+    }
+
+    /// Returns `self * factor`, or [`Target::MAX`] if the product does not fit in 256 bits.
+    pub(crate) fn saturating_mul(&self, factor: u64) -> Target {
+        let mut limbs = [0u64; 4];
+        let mut carry = 0u128;
+        for (out, &limb) in limbs.iter_mut().zip(&self.limbs) {
+            let cur = u128::from(limb) * u128::from(factor) + carry;
+            *out = cur as u64;
+            carry = cur >> 64;
+        }
+        if carry != 0 {
+            return Target::MAX;
+        }
+        Target { limbs }
+    }
+
+    /// Returns `self / divisor` rounded down. `divisor` must not be zero.
+    pub(crate) fn div(&self, divisor: u64) -> Target {
+        let mut wide = [0u64; 8];
+        wide[..4].copy_from_slice(&self.limbs);
+        let wide = div_wide(wide, divisor);
+        let mut limbs = [0u64; 4];
+        limbs.copy_from_slice(&wide[..4]);
+        Target { limbs }
+    }
+
+    /// Returns `self * factor / divisor` rounded down, with an exact intermediate product, or
+    /// [`Target::MAX`] if the result does not fit in 256 bits. `divisor` must not be zero.
+    pub(crate) fn mul_div(&self, factor: u64, divisor: u64) -> Target {
+        let mut wide = [0u64; 8];
+        let mut carry = 0u128;
+        for (out, &limb) in wide.iter_mut().zip(&self.limbs) {
+            let cur = u128::from(limb) * u128::from(factor) + carry;
+            *out = cur as u64;
+            carry = cur >> 64;
+        }
+        wide[4] = carry as u64;
+        let wide = div_wide(wide, divisor);
+        if wide[4..].iter().any(|&limb| limb != 0) {
+            return Target::MAX;
+        }
+        let mut limbs = [0u64; 4];
+        limbs.copy_from_slice(&wide[..4]);
+        Target { limbs }
+    }
+
+    /// Returns `self + other`, or [`Target::MAX`] if the sum does not fit in 256 bits.
+    pub(crate) fn saturating_add(&self, other: &Target) -> Target {
+        let mut limbs = [0u64; 4];
+        let mut carry = false;
+        for (out, (a, b)) in limbs.iter_mut().zip(self.limbs.iter().zip(&other.limbs)) {
+            let (sum, c1) = a.overflowing_add(*b);
+            let (sum, c2) = sum.overflowing_add(u64::from(carry));
+            *out = sum;
+            carry = c1 || c2;
+        }
+        if carry {
+            return Target::MAX;
+        }
+        Target { limbs }
+    }
+
+    /// Returns `self - other`, or [`Target::ZERO`] if `other` is larger.
+    pub(crate) fn saturating_sub(&self, other: &Target) -> Target {
+        if other > self {
+            return Target::ZERO;
+        }
+        let mut limbs = [0u64; 4];
+        let mut borrow = false;
+        for (out, (a, b)) in limbs.iter_mut().zip(self.limbs.iter().zip(&other.limbs)) {
+            let (diff, b1) = a.overflowing_sub(*b);
+            let (diff, b2) = diff.overflowing_sub(u64::from(borrow));
+            *out = diff;
+            borrow = b1 || b2;
+        }
+        Target { limbs }
+    }
+
+    fn from_u64(value: u64) -> Target {
+        Target {
+            limbs: [value, 0, 0, 0],
+        }
+    }
+
+    // bits returns the position of the highest set bit plus one.
+    fn bits(&self) -> u32 {
+        for i in (0..4).rev() {
+            if self.limbs[i] != 0 {
+                return 64 * i as u32 + 64 - self.limbs[i].leading_zeros();
+            }
+        }
+        0
+    }
+
+    fn shl(&self, shift: u32) -> Target {
+        let mut wide = [0u64; 8];
+        wide[..4].copy_from_slice(&self.limbs);
+        let wide = shl_wide(wide, shift.min(256));
+        let mut limbs = [0u64; 4];
+        limbs.copy_from_slice(&wide[..4]);
+        Target { limbs }
+    }
+
+    fn shr(&self, shift: u32) -> Target {
+        let mut wide = [0u64; 8];
+        wide[..4].copy_from_slice(&self.limbs);
+        let wide = shr_wide(wide, shift.min(256));
+        let mut limbs = [0u64; 4];
+        limbs.copy_from_slice(&wide[..4]);
+        Target { limbs }
+    }
+
+    fn to_f64(self) -> f64 {
+        self.limbs.iter().rev().fold(0.0, |acc, &limb| {
+            acc * 18_446_744_073_709_551_616.0 + limb as f64
+        })
+    }
+}
+
+// shl_wide shifts a little-endian 512-bit integer left by `shift` (< 512) bits.
+fn shl_wide(value: [u64; 8], shift: u32) -> [u64; 8] {
+    let limbs = (shift / 64) as usize;
+    let bits = shift % 64;
+    let mut out = [0u64; 8];
+    for i in (limbs..8).rev() {
+        out[i] = value[i - limbs] << bits;
+        if bits > 0 && i > limbs {
+            out[i] |= value[i - limbs - 1] >> (64 - bits);
+        }
+    }
+    out
+}
+
+// shr_wide shifts a little-endian 512-bit integer right by `shift` (< 512) bits.
+fn shr_wide(value: [u64; 8], shift: u32) -> [u64; 8] {
+    let limbs = (shift / 64) as usize;
+    let bits = shift % 64;
+    let mut out = [0u64; 8];
+    for i in 0..8 - limbs {
+        out[i] = value[i + limbs] >> bits;
+        if bits > 0 && i + limbs + 1 < 8 {
+            out[i] |= value[i + limbs + 1] << (64 - bits);
+        }
+    }
+    out
+}
+
+// div_wide divides a little-endian 512-bit integer by a non-zero `divisor`, rounding down.
+fn div_wide(value: [u64; 8], divisor: u64) -> [u64; 8] {
+    let mut out = [0u64; 8];
+    let mut rem = 0u128;
+    for i in (0..8).rev() {
+        let cur = (rem << 64) | u128::from(value[i]);
+        out[i] = (cur / u128::from(divisor)) as u64;
+        rem = cur % u128::from(divisor);
+    }
+    out
+}
+
+impl Ord for Target {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs.iter().rev().cmp(other.limbs.iter().rev())
+    }
+}
+
+impl PartialOrd for Target {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for limb in self.limbs.iter().rev() {
+            write!(f, "{:016x}", limb)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Target {
+    type Err = TargetError;
+
+    /// Parses a big-endian hex target of at most 64 digits, as printed by [`fmt::Display`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        if s.len() > 64 {
+            return Err(TargetError::InvalidHex(s.to_string()));
+        }
+        let padded = format!("{:0>64}", s);
+        let mut bytes = from_hex(&padded).ok_or_else(|| TargetError::InvalidHex(s.to_string()))?;
+        bytes.reverse();
+        let mut le = [0u8; 32];
+        le.copy_from_slice(&bytes);
+        Ok(Target::from_le_bytes(le))
+    }
+}
+
+/// Returns true if `hash`, a 32-byte `sum` output, meets `target`.
+/// # Examples
+///
+/// ```
+/// use lyra2::target::{hash_meets_target, Target};
+///
+/// let mut hash = [0xffu8; 32];
+/// hash[28..].copy_from_slice(&[0, 0, 0, 0]);
+/// assert!(hash_meets_target(&hash, &Target::DIFF1_POOL));
+/// assert!(!hash_meets_target(&hash, &Target::DIFF1_NODE));
+/// ```
+pub fn hash_meets_target(hash: &[u8; 32], target: &Target) -> bool {
+    target.is_met_by(hash)
+}
+
+/// An error returned when decoding a [`Target`] fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetError {
+    /// The compact target has its sign bit set.
+    Negative(u32),
+    /// The compact target does not fit in 256 bits.
+    Overflow(u32),
+    /// The string is not a hex number of at most 64 digits.
+    InvalidHex(String),
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetError::Negative(bits) => write!(f, "negative compact target: {:#010x}", bits),
+            TargetError::Overflow(bits) => write!(f, "compact target overflows: {:#010x}", bits),
+            TargetError::InvalidHex(s) => write!(f, "invalid target: {}", s),
+        }
+    }
+}
+
+impl error::Error for TargetError {}
+
+#[test]
+fn target_cal() {
+    // Compact round trips, including the corner cases nodes test.
+    for (bits, hex, compact) in [
+        (
+            0x1e0ffff0,
+            "00000ffff0000000000000000000000000000000000000000000000000000000",
+            0x1e0ffff0,
+        ),
+        (
+            0x1d00ffff,
+            "00000000ffff0000000000000000000000000000000000000000000000000000",
+            0x1d00ffff,
+        ),
+        (
+            0x1b0404cb,
+            "00000000000404cb000000000000000000000000000000000000000000000000",
+            0x1b0404cb,
+        ),
+        (0x01003456, "0", 0),
+        (0x01123456, "12", 0x01120000),
+        (0x02123456, "1234", 0x02123400),
+        (0x05009234, "92340000", 0x05009234),
+        (
+            0x20123456,
+            "1234560000000000000000000000000000000000000000000000000000000000",
+            0x20123456,
+        ),
+    ] {
+        let target = Target::from_compact(bits).unwrap();
+        assert_eq!(target, hex.parse().unwrap());
+        assert_eq!(target.to_compact(), compact);
+    }
+    assert_eq!(Target::from_compact(0x1d00ffff), Ok(Target::DIFF1_NODE));
+    assert_eq!(
+        Target::from_compact(0x04923456),
+        Err(TargetError::Negative(0x04923456))
+    );
+    assert_eq!(
+        Target::from_compact(0xff123456),
+        Err(TargetError::Overflow(0xff123456))
+    );
+    assert_eq!(Target::from_compact(0x00800000), Ok(Target::ZERO));
+
+    // Difficulty in node and pool conventions.
+    let genesis = Target::from_compact(0x1e0ffff0).unwrap();
+    assert_eq!(genesis.difficulty(&Target::DIFF1_NODE), 0.000244140625);
+    assert_eq!(Target::DIFF1_NODE.difficulty(&Target::DIFF1_NODE), 1.0);
+    assert_eq!(
+        Target::DIFF1_NODE.difficulty(&Target::DIFF1_POOL),
+        1.0000152590218967
+    );
+    assert_eq!(
+        Target::from_difficulty(0.000244140625, &Target::DIFF1_NODE),
+        genesis
+    );
+    assert_eq!(
+        Target::from_difficulty(1.0, &Target::DIFF1_POOL),
+        Target::DIFF1_POOL
+    );
+    assert_eq!(
+        Target::from_difficulty(65536.0, &Target::DIFF1_NODE).to_string(),
+        "000000000000ffff000000000000000000000000000000000000000000000000"
+    );
+    assert_eq!(
+        Target::from_difficulty(3.0, &Target::DIFF1_POOL).to_string(),
+        "0000000055555555555555555555555555555555555555555555555555555555"
+    );
+    assert_eq!(
+        Target::from_difficulty(0.0, &Target::DIFF1_POOL),
+        Target::MAX
+    );
+
+    // Monacoin and Vertcoin genesis blocks were mined with scrypt (N=1024 and N=2048). Their
+    // PoW hashes meet the genesis target only when compared little-endian.
+    let mona_pow =
+        from_hex("d515eb711fd74e96487cdae8791bc30d234d97ed9eb515ca97704aebe3080000").unwrap();
+    let vtc_pow =
+        from_hex("b9bb3c4997e1c85316c7e9b21047ebc90da74064861614dd063c5e42cc050000").unwrap();
+    for pow in [mona_pow, vtc_pow] {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&pow);
+        assert!(hash_meets_target(&hash, &genesis));
+        assert!(!hash_meets_target(&hash, &Target::DIFF1_NODE));
+        hash.reverse();
+        assert!(!hash_meets_target(&hash, &genesis));
+    }
+
+    // Integer helpers of the retarget rules saturate instead of wrapping.
+    let one = Target::from_u64(1);
+    assert_eq!(
+        Target::DIFF1_NODE.saturating_mul(3).div(3),
+        Target::DIFF1_NODE
+    );
+    assert_eq!(Target::MAX.saturating_mul(2), Target::MAX);
+    assert_eq!(
+        Target::MAX.mul_div(3, 4),
+        Target::MAX
+            .div(4)
+            .saturating_mul(3)
+            .saturating_add(&Target::from_u64(2))
+    );
+    assert_eq!(Target::MAX.mul_div(2, 1), Target::MAX);
+    assert_eq!(Target::MAX.saturating_add(&one), Target::MAX);
+    assert_eq!(Target::ZERO.saturating_sub(&one), Target::ZERO);
+    assert_eq!(
+        Target::from_u64(u64::MAX).saturating_add(&one).to_string(),
+        "0000000000000000000000000000000000000000000000010000000000000000"
+    );
+    assert_eq!(
+        Target::from_u64(u64::MAX)
+            .saturating_add(&one)
+            .saturating_sub(&one),
+        Target::from_u64(u64::MAX)
+    );
+
+    // The boundary itself meets the target; one more does not.
+    let mut hash = genesis.to_le_bytes();
+    assert!(hash_meets_target(&hash, &genesis));
+    hash[0] = 1;
+    assert!(!hash_meets_target(&hash, &genesis));
+
+    // A Lyra2REv3 PoW hash, that of the upstream README's header vector, against targets one
+    // apart at its value: the comparison reads the whole hash little-endian.
+    let header = from_hex("700000005d385ba114d079971b29a9418fd0549e7d68a95c7f168621a314201000000000578586d149fd07b22f3a8a347c516de7052f034d2b76ff68e0d6ecff9b77a45489e3fd511732011df0731000").unwrap();
+    let mut pow = [0u8; 32];
+    pow.copy_from_slice(&crate::lyra2rev3::sum(header));
+    let boundary = Target::from_le_bytes(pow);
+    assert_eq!(
+        boundary.to_string(),
+        "fbea39edd7b803dd1eb09edb1fdfef89b05167e4a11b83c78188e75882297b5d"
+    );
+    assert!(hash_meets_target(&pow, &boundary));
+    assert!(!hash_meets_target(
+        &pow,
+        &boundary.saturating_sub(&Target::from_u64(1))
+    ));
+    assert!(!hash_meets_target(
+        &pow,
+        &Target::from_compact(0x1b0404cb).unwrap()
+    ));
+}
@@ -0,0 +1,357 @@
+//! # tree
+//!
+//! `tree` crate keeps verified block headers in memory as a tree, tracking forks and the
+//! most-work tip as a light client does.
+//!
+//! Headers must be checked (links aside) before insertion, e.g. with [`crate::verify`]; the
+//! tree only follows their links and sums their work.
+use crate::header::{BlockHeader, Hash256};
+use crate::target::TargetError;
+use crate::work::Work;
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+
+/// A header in the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node {
+    pub header: BlockHeader,
+    pub hash: Hash256,
+    pub height: u32,
+    /// The work of the chain up to and including this header.
+    pub chainwork: Work,
+}
+
+/// A change of the best tip after an insertion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TipChange {
+    pub old_tip: Hash256,
+    pub new_tip: Hash256,
+    /// The height of the last header the old and new best chains share.
+    pub fork_height: u32,
+    /// Headers of the old best chain that left it, i.e. the reorg depth. Zero when the new tip
+    /// extends the old one.
+    pub disconnected: u32,
+    /// Headers that joined the best chain.
+    pub connected: u32,
+}
+
+impl TipChange {
+    /// Returns true if headers left the best chain.
+    pub fn is_reorg(&self) -> bool {
+        self.disconnected > 0
+    }
+}
+
+/// Verified headers descending from one root, with the most-work tip.
+///
+/// Between tips of equal chainwork the first one seen stays best, as nodes do.
+#[derive(Debug, Clone)]
+pub struct HeaderTree {
+    nodes: HashMap<Hash256, Node>,
+    // Headers without children.
+    leaves: HashSet<Hash256>,
+    root: Hash256,
+    tip: Hash256,
+}
+
+impl HeaderTree {
+    /// Returns a tree rooted at the genesis header `root`.
+    pub fn new(root: BlockHeader) -> Result<HeaderTree, TreeError> {
+        let work = Work::from_bits(root.bits).map_err(TreeError::InvalidBits)?;
+        Ok(HeaderTree::with_root(root, 0, work))
+    }
+
+    /// Returns a tree rooted at a trusted header `root` at `height` whose chain has
+    /// `chainwork`, e.g. a checkpoint.
+    pub fn with_root(root: BlockHeader, height: u32, chainwork: Work) -> HeaderTree {
+        let hash = root.block_hash();
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            hash,
+            Node {
+                header: root,
+                hash,
+                height,
+                chainwork,
+            },
+        );
+        HeaderTree {
+            nodes,
+            leaves: [hash].into_iter().collect(),
+            root: hash,
+            tip: hash,
+        }
+    }
+
+    /// Inserts `header` under its parent and returns the change of the best tip, if any.
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::header::{BlockHeader, Hash256};
+    /// use lyra2::tree::HeaderTree;
+    ///
+    /// let genesis = BlockHeader::from_hex("010000000000000000000000000000000000000000000000000000000000000000000000a64bac07fe31877f31d03252953b3c32398933af7a724119bc4d6fa4a805e435f083c252f0ff0f1e66d61200").unwrap();
+    /// let mut tree = HeaderTree::new(genesis).unwrap();
+    /// let mut child = genesis;
+    /// child.prev_block = genesis.block_hash();
+    /// let change = tree.insert(child).unwrap().unwrap();
+    /// assert!(!change.is_reorg());
+    /// assert_eq!(tree.tip().height, 1);
+    /// ```
+    pub fn insert(&mut self, header: BlockHeader) -> Result<Option<TipChange>, TreeError> {
+        let hash = header.block_hash();
+        if self.nodes.contains_key(&hash) {
+            return Err(TreeError::Duplicate(hash));
+        }
+        let parent = self
+            .nodes
+            .get(&header.prev_block)
+            .ok_or(TreeError::UnknownParent(header.prev_block))?;
+        let work = Work::from_bits(header.bits).map_err(TreeError::InvalidBits)?;
+        let node = Node {
+            header,
+            hash,
+            height: parent.height + 1,
+            chainwork: parent.chainwork + work,
+        };
+        self.leaves.remove(&header.prev_block);
+        self.leaves.insert(hash);
+        self.nodes.insert(hash, node);
+
+        if node.chainwork <= self.tip().chainwork {
+            return Ok(None);
+        }
+        let old_tip = self.tip;
+        let fork = *self.fork_point(&old_tip, &hash).expect("same tree");
+        let old_height = self.tip().height;
+        self.tip = hash;
+        Ok(Some(TipChange {
+            old_tip,
+            new_tip: hash,
+            fork_height: fork.height,
+            disconnected: old_height - fork.height,
+            connected: node.height - fork.height,
+        }))
+    }
+
+    /// Returns the root header.
+    pub fn root(&self) -> &Node {
+        &self.nodes[&self.root]
+    }
+
+    /// Returns the tip of the most-work chain.
+    pub fn tip(&self) -> &Node {
+        &self.nodes[&self.tip]
+    }
+
+    /// Returns the header with block hash `hash`.
+    pub fn get(&self, hash: &Hash256) -> Option<&Node> {
+        self.nodes.get(hash)
+    }
+
+    /// Returns the number of headers in the tree.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns true if the tree holds no headers, which never happens as it always holds its
+    /// root. See [`HeaderTree::has_only_root`].
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns true if the tree holds only its root.
+    pub fn has_only_root(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    /// Returns the headers without children, most chainwork first.
+    pub fn tips(&self) -> Vec<&Node> {
+        let mut tips: Vec<&Node> = self.leaves.iter().map(|hash| &self.nodes[hash]).collect();
+        tips.sort_by(|a, b| {
+            b.chainwork
+                .cmp(&a.chainwork)
+                .then_with(|| (a.hash != self.tip).cmp(&(b.hash != self.tip)))
+                .then_with(|| a.hash.cmp(&b.hash))
+        });
+        tips
+    }
+
+    /// Returns the ancestor at `height` of the header `hash`, or the header itself at its own
+    /// height.
+    pub fn ancestor(&self, hash: &Hash256, height: u32) -> Option<&Node> {
+        let mut node = self.nodes.get(hash)?;
+        if height > node.height {
+            return None;
+        }
+        while node.height > height {
+            node = self.nodes.get(&node.header.prev_block)?;
+        }
+        Some(node)
+    }
+
+    /// Returns the last header that the chains of `a` and `b` share.
+    pub fn fork_point(&self, a: &Hash256, b: &Hash256) -> Option<&Node> {
+        let height = self.nodes.get(a)?.height.min(self.nodes.get(b)?.height);
+        let mut a = self.ancestor(a, height)?;
+        let mut b = self.ancestor(b, height)?;
+        while a.hash != b.hash {
+            a = self.nodes.get(&a.header.prev_block)?;
+            b = self.nodes.get(&b.header.prev_block)?;
+        }
+        Some(a)
+    }
+
+    /// Returns how many headers of the best chain a switch to `hash` would disconnect.
+    pub fn reorg_depth(&self, hash: &Hash256) -> Option<u32> {
+        let fork = self.fork_point(&self.tip, hash)?;
+        Some(self.tip().height - fork.height)
+    }
+
+    /// Returns the best chain from the root to the tip.
+    pub fn best_chain(&self) -> Vec<&Node> {
+        let mut chain = Vec::with_capacity((self.tip().height - self.root().height) as usize + 1);
+        let mut node = self.tip();
+        loop {
+            chain.push(node);
+            if node.hash == self.root {
+                break;
+            }
+            node = &self.nodes[&node.header.prev_block];
+        }
+        chain.reverse();
+        chain
+    }
+}
+
+/// An error returned when a header cannot be inserted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeError {
+    /// The header is already in the tree.
+    Duplicate(Hash256),
+    /// The parent of the header is not in the tree.
+    UnknownParent(Hash256),
+    /// `nBits` of the header does not decode to a target.
+    InvalidBits(TargetError),
+}
+
+impl fmt::Display for TreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeError::Duplicate(hash) => write!(f, "header {} is already known", hash),
+            TreeError::UnknownParent(hash) => write!(f, "parent header {} is unknown", hash),
+            TreeError::InvalidBits(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for TreeError {}
+
+#[test]
+fn tree_cal() {
+    use crate::algorithm::Algorithm;
+
+    fn mine(prev: &BlockHeader, bits: u32, salt: u8, algorithm: Algorithm) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: 2,
+            prev_block: prev.block_hash(),
+            merkle_root: Hash256::sha256d(&[salt]),
+            time: prev.time + 90,
+            bits,
+            nonce: 0,
+        };
+        while !header.meets_target(algorithm) {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    let easy = 0x207fffff;
+    let hard = 0x203fffff;
+    let genesis = BlockHeader {
+        version: 1,
+        prev_block: Hash256::default(),
+        merkle_root: Hash256::default(),
+        time: 1500000000,
+        bits: easy,
+        nonce: 0,
+    };
+    let mut tree = HeaderTree::new(genesis).unwrap();
+    assert!(tree.has_only_root());
+    assert!(!tree.is_empty());
+
+    // Main chain G-A1-A2-A3 mined with Lyra2Z, two units of work each.
+    let a1 = mine(&genesis, easy, 1, Algorithm::Lyra2Z);
+    let a2 = mine(&a1, easy, 2, Algorithm::Lyra2Z);
+    let a3 = mine(&a2, easy, 3, Algorithm::Lyra2Z);
+    for header in [a1, a2, a3] {
+        let change = tree.insert(header).unwrap().unwrap();
+        assert!(!change.is_reorg());
+        assert_eq!(change.connected, 1);
+    }
+    assert_eq!(tree.tip().hash, a3.block_hash());
+    assert_eq!(tree.tip().chainwork, "8".parse().unwrap());
+
+    // A fork from A1 mined with Lyra2REv2 at twice the difficulty ties at B2 and wins at B3.
+    let b2 = mine(&a1, hard, 12, Algorithm::Lyra2REv2);
+    let b3 = mine(&b2, hard, 13, Algorithm::Lyra2REv2);
+    assert_eq!(tree.insert(b2).unwrap(), None);
+    assert_eq!(tree.tip().hash, a3.block_hash());
+    assert_eq!(tree.reorg_depth(&b2.block_hash()), Some(2));
+    let change = tree.insert(b3).unwrap().unwrap();
+    assert_eq!(
+        change,
+        TipChange {
+            old_tip: a3.block_hash(),
+            new_tip: b3.block_hash(),
+            fork_height: 1,
+            disconnected: 2,
+            connected: 2,
+        }
+    );
+    assert!(change.is_reorg());
+    assert_eq!(tree.tip().chainwork, "c".parse().unwrap());
+    assert_eq!(
+        tree.best_chain()
+            .iter()
+            .map(|node| node.hash)
+            .collect::<Vec<_>>(),
+        vec![
+            genesis.block_hash(),
+            a1.block_hash(),
+            b2.block_hash(),
+            b3.block_hash()
+        ]
+    );
+    assert_eq!(
+        tree.tips().iter().map(|node| node.hash).collect::<Vec<_>>(),
+        vec![b3.block_hash(), a3.block_hash()]
+    );
+    assert_eq!(
+        tree.fork_point(&a3.block_hash(), &b3.block_hash())
+            .unwrap()
+            .hash,
+        a1.block_hash()
+    );
+    assert_eq!(
+        tree.ancestor(&b3.block_hash(), 1).unwrap().hash,
+        a1.block_hash()
+    );
+    assert!(tree.ancestor(&b3.block_hash(), 4).is_none());
+    assert_eq!(tree.len(), 6);
+
+    assert_eq!(tree.insert(b3), Err(TreeError::Duplicate(b3.block_hash())));
+    let mut orphan = a2;
+    orphan.prev_block = Hash256::sha256d(b"unknown");
+    assert!(matches!(
+        tree.insert(orphan),
+        Err(TreeError::UnknownParent(_))
+    ));
+
+    // A tree from a checkpoint counts work on top of the given chainwork.
+    let mut tree = HeaderTree::with_root(a1, 1, "4".parse().unwrap());
+    tree.insert(a2).unwrap();
+    assert_eq!(tree.tip().height, 2);
+    assert_eq!(tree.tip().chainwork, "6".parse().unwrap());
+}
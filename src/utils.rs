@@ -17,3 +17,18 @@ pub fn read_u64_le(data: &[u8]) -> u64 {
     ((data[6] as u64) << 48) |
     ((data[7] as u64) << 56)
 }
+
+#[inline(always)]
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|n| format!("{:02x}", n)).collect::<String>()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,365 @@
+//! # vardiff
+//!
+//! `vardiff` crate adjusts the share difficulty of a pool connection so that its shares arrive
+//! at a target interval, the variable difficulty of NOMP and most pools since.
+//!
+//! Time comes from a [`Clock`], so tests can drive a controller with a [`SimClock`].
+use crate::algorithm::Algorithm;
+use crate::difficulty::Convention;
+use crate::target::Target;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Shortest share interval a controller converges to.
+const MIN_TARGET_TIME: Duration = Duration::from_millis(1);
+
+// Most share intervals kept between two retargets.
+const MAX_INTERVALS: usize = 4096;
+
+/// A source of monotonic time.
+pub trait Clock: Send + Sync {
+    /// Returns the time elapsed since an arbitrary fixed point.
+    fn now(&self) -> Duration;
+}
+
+/// The wall clock, counting from its creation.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct SimClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl SimClock {
+    /// Returns a clock at zero.
+    pub fn new() -> SimClock {
+        SimClock::default()
+    }
+
+    /// Moves the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for SimClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+}
+
+/// Settings of a [`VarDiff`] controller. The defaults are those of NOMP.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDiffConfig {
+    /// The share interval to converge to.
+    pub target_time: Duration,
+    /// How often the difficulty may change.
+    pub retarget_time: Duration,
+    /// The fraction the average interval may stray from `target_time` without a retarget.
+    pub variance: f64,
+    pub min_difficulty: f64,
+    pub max_difficulty: f64,
+    /// Only ever halves or doubles the difficulty.
+    pub x2_mode: bool,
+}
+
+impl Default for VarDiffConfig {
+    fn default() -> Self {
+        VarDiffConfig {
+            target_time: Duration::from_secs(15),
+            retarget_time: Duration::from_secs(90),
+            variance: 0.3,
+            min_difficulty: 8.0,
+            max_difficulty: 512.0,
+            x2_mode: false,
+        }
+    }
+}
+
+/// A difficulty change, to be sent with `mining.set_difficulty`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Update {
+    pub old_difficulty: f64,
+    pub difficulty: f64,
+}
+
+/// The variable difficulty of one connection.
+/// # Examples
+///
+/// ```
+/// use lyra2::vardiff::{SimClock, VarDiff, VarDiffConfig};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let clock = SimClock::new();
+/// let mut vardiff = VarDiff::new(VarDiffConfig::default(), 8.0).clock(Arc::new(clock.clone()));
+/// // A miner finding a share every 3 seconds gets a higher difficulty.
+/// let mut update = None;
+/// while update.is_none() {
+///     clock.advance(Duration::from_secs(3));
+///     update = vardiff.on_share();
+/// }
+/// assert_eq!(update.unwrap().difficulty, 40.0);
+/// ```
+pub struct VarDiff {
+    config: VarDiffConfig,
+    clock: Arc<dyn Clock>,
+    difficulty: f64,
+    // Intervals between shares since the last retarget, in seconds.
+    intervals: VecDeque<f64>,
+    capacity: usize,
+    last_share: Option<Duration>,
+    next_retarget: Duration,
+}
+
+impl VarDiff {
+    /// Returns a controller starting at `difficulty`, clamped to the configured range, on the
+    /// system clock. When the minimum exceeds the maximum, the maximum wins. A target time
+    /// under a millisecond is raised to one.
+    pub fn new(mut config: VarDiffConfig, difficulty: f64) -> VarDiff {
+        config.target_time = config.target_time.max(MIN_TARGET_TIME);
+        let capacity = ((config.retarget_time.as_secs_f64() / config.target_time.as_secs_f64())
+            * 4.0)
+            .ceil()
+            .clamp(1.0, MAX_INTERVALS as f64) as usize;
+        VarDiff {
+            difficulty: difficulty
+                .max(config.min_difficulty)
+                .min(config.max_difficulty),
+            config,
+            clock: Arc::new(SystemClock::new()),
+            intervals: VecDeque::with_capacity(capacity),
+            capacity,
+            last_share: None,
+            next_retarget: Duration::ZERO,
+        }
+    }
+
+    /// Reads time from `clock`.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> VarDiff {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the current difficulty.
+    pub fn difficulty(&self) -> f64 {
+        self.difficulty
+    }
+
+    /// Sets the difficulty, as an operator overriding the controller, and retargets from there.
+    pub fn set_difficulty(&mut self, difficulty: f64) {
+        self.difficulty = difficulty;
+        self.intervals.clear();
+    }
+
+    /// Returns the share target of the current difficulty under `convention`.
+    pub fn share_target(&self, convention: Convention, algorithm: Algorithm) -> Target {
+        convention.share_target(algorithm, self.difficulty)
+    }
+
+    /// Records a share at the current time and returns the new difficulty, if it changes.
+    pub fn on_share(&mut self) -> Option<Update> {
+        let now = self.clock.now();
+        self.on_share_at(now)
+    }
+
+    /// Records a share at `now`, a time of the clock, and returns the new difficulty, if it
+    /// changes.
+    ///
+    /// The first share only starts the clock, half a retarget interval in. Later ones retarget
+    /// once per retarget interval: when the average interval since the last retarget is outside
+    /// the variance, the difficulty is scaled by `target_time / average` within the limits.
+    pub fn on_share_at(&mut self, now: Duration) -> Option<Update> {
+        let last_share = match self.last_share.replace(now) {
+            Some(last_share) => last_share,
+            None => {
+                self.next_retarget = now.saturating_add(self.config.retarget_time / 2);
+                return None;
+            }
+        };
+        if self.intervals.len() == self.capacity {
+            self.intervals.pop_front();
+        }
+        self.intervals
+            .push_back(now.saturating_sub(last_share).as_secs_f64());
+        if now < self.next_retarget {
+            return None;
+        }
+        self.next_retarget = now.saturating_add(self.config.retarget_time);
+
+        let average = self.intervals.iter().sum::<f64>() / self.intervals.len() as f64;
+        let target = self.config.target_time.as_secs_f64();
+        let mut factor = target / average;
+        if average > target * (1.0 + self.config.variance)
+            && self.difficulty > self.config.min_difficulty
+        {
+            if self.config.x2_mode {
+                factor = 0.5;
+            }
+            factor = factor.max(self.config.min_difficulty / self.difficulty);
+        } else if average < target * (1.0 - self.config.variance)
+            && self.difficulty < self.config.max_difficulty
+        {
+            if self.config.x2_mode {
+                factor = 2.0;
+            }
+            factor = factor.min(self.config.max_difficulty / self.difficulty);
+        } else {
+            return None;
+        }
+        self.intervals.clear();
+        let update = Update {
+            old_difficulty: self.difficulty,
+            difficulty: self.difficulty * factor,
+        };
+        self.difficulty = update.difficulty;
+        Some(update)
+    }
+}
+
+impl fmt::Debug for VarDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VarDiff")
+            .field("config", &self.config)
+            .field("difficulty", &self.difficulty)
+            .field("intervals", &self.intervals)
+            .field("last_share", &self.last_share)
+            .field("next_retarget", &self.next_retarget)
+            .finish()
+    }
+}
+
+#[test]
+fn vardiff_cal() {
+    let secs = Duration::from_secs;
+    let clock = SimClock::new();
+    let config = VarDiffConfig {
+        min_difficulty: 1.0,
+        max_difficulty: 1024.0,
+        ..VarDiffConfig::default()
+    };
+    let mut vardiff = VarDiff::new(config.clone(), 16.0).clock(Arc::new(clock.clone()));
+
+    // A share every 5 seconds: the first retarget comes 45 seconds after the first share and
+    // triples the difficulty.
+    let mut updates = Vec::new();
+    for _ in 0..10 {
+        if let Some(update) = vardiff.on_share() {
+            updates.push((clock.now(), update));
+        }
+        clock.advance(secs(5));
+    }
+    assert_eq!(
+        updates,
+        vec![(
+            secs(45),
+            Update {
+                old_difficulty: 16.0,
+                difficulty: 48.0
+            }
+        )]
+    );
+
+    // A miner at the new difficulty is within the variance and keeps it.
+    for _ in 0..40 {
+        clock.advance(secs(15));
+        assert_eq!(vardiff.on_share(), None);
+    }
+
+    // A miner that slows down to one share a minute is lowered, but not below the minimum. The
+    // first retarget still averages in the 15-second intervals before the slowdown.
+    let mut difficulty = vec![];
+    for _ in 0..20 {
+        clock.advance(secs(60));
+        if let Some(update) = vardiff.on_share() {
+            difficulty.push(update.difficulty);
+        }
+    }
+    assert_eq!(difficulty.len(), 4);
+    assert!((difficulty[0] - 48.0 * 15.0 / 20.625).abs() < 1e-9);
+    assert!(difficulty.windows(2).all(|w| w[1] < w[0]));
+    assert_eq!(vardiff.difficulty(), 1.0);
+
+    // A flood of shares stops at the maximum; x2 mode only doubles.
+    let mut vardiff = VarDiff::new(config.clone(), 512.0);
+    for t in 0..1000 {
+        vardiff.on_share_at(Duration::from_millis(t * 100));
+    }
+    assert_eq!(vardiff.difficulty(), 1024.0);
+    let x2 = VarDiffConfig {
+        x2_mode: true,
+        ..config
+    };
+    let mut vardiff = VarDiff::new(x2, 4.0);
+    let updates: Vec<f64> = (0..200)
+        .filter_map(|t| vardiff.on_share_at(secs(t)))
+        .map(|update| update.difficulty)
+        .collect();
+    assert_eq!(updates, vec![8.0, 16.0]);
+
+    // The share target follows the difficulty as pools convert it.
+    assert_eq!(
+        vardiff.share_target(Convention::Nomp, Algorithm::Lyra2REv2),
+        Convention::Nomp.share_target(Algorithm::Lyra2REv2, 16.0)
+    );
+    assert_eq!(
+        VarDiff::new(VarDiffConfig::default(), 1.0).difficulty(),
+        8.0
+    );
+
+    // A misconfigured range does not panic.
+    let inverted = VarDiffConfig {
+        min_difficulty: 64.0,
+        max_difficulty: 2.0,
+        ..VarDiffConfig::default()
+    };
+    assert_eq!(VarDiff::new(inverted, 8.0).difficulty(), 2.0);
+    let nan = VarDiffConfig {
+        min_difficulty: f64::NAN,
+        ..VarDiffConfig::default()
+    };
+    assert_eq!(VarDiff::new(nan, 8.0).difficulty(), 8.0);
+
+    // A zero target time neither panics nor preallocates without bound.
+    let zero = VarDiffConfig {
+        target_time: Duration::ZERO,
+        retarget_time: Duration::from_secs(u64::MAX),
+        ..VarDiffConfig::default()
+    };
+    let clock = SimClock::new();
+    let mut vardiff = VarDiff::new(zero, 8.0).clock(Arc::new(clock.clone()));
+    assert_eq!(vardiff.config.target_time, MIN_TARGET_TIME);
+    assert_eq!(vardiff.capacity, MAX_INTERVALS);
+    for _ in 0..2 * MAX_INTERVALS {
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(vardiff.on_share(), None);
+    }
+    assert_eq!(vardiff.intervals.len(), MAX_INTERVALS);
+}
@@ -0,0 +1,427 @@
+//! # verify
+//!
+//! `verify` crate checks a run of consecutive block headers, such as an Electrum
+//! `blockchain_headers` file: the previous-hash links, the PoW hash of every header against its
+//! `nBits` with the algorithm of its height, and optionally the `nBits` a retarget rule expects.
+//!
+//! Headers are checked on several threads; the error is always the lowest failing height.
+use crate::coin::ChainParams;
+use crate::header::{BlockHeader, Hash256, ParseHeaderError};
+use crate::lyra2::Matrix;
+use crate::retarget::{Retarget, RetargetError};
+use crate::target::{Target, TargetError};
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+
+// Headers a thread takes at a time.
+const CHUNK: usize = 256;
+
+/// Parses concatenated 80-byte headers. The length of `data` must be a multiple of 80.
+pub fn parse_headers(data: &[u8]) -> Result<Vec<BlockHeader>, ParseHeaderError> {
+    if data.len() % BlockHeader::SIZE != 0 {
+        return Err(ParseHeaderError::InvalidLength(data.len()));
+    }
+    data.chunks_exact(BlockHeader::SIZE)
+        .map(BlockHeader::parse)
+        .collect()
+}
+
+/// Reads a file of concatenated 80-byte headers, such as an Electrum `blockchain_headers` file.
+pub fn read_headers<P: AsRef<Path>>(path: P) -> io::Result<Vec<BlockHeader>> {
+    parse_headers(&fs::read(path)?).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// What a successful verification checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Headers verified.
+    pub headers: usize,
+    /// Headers whose PoW hash was checked.
+    pub pow_checked: usize,
+    /// Headers whose PoW algorithm this crate does not implement, left unchecked.
+    pub pow_skipped: usize,
+    /// Headers whose `nBits` were checked against the retarget rule.
+    pub retarget_checked: usize,
+}
+
+/// Configures a verification: the chain, the height of the first header, the retarget rule and
+/// the number of threads.
+#[derive(Debug, Clone)]
+pub struct Verifier<'a> {
+    params: &'a ChainParams,
+    start_height: u32,
+    retarget: Option<Retarget>,
+    skip_unsupported: bool,
+    threads: usize,
+}
+
+impl<'a> Verifier<'a> {
+    /// Returns a verifier of headers of `params` starting at the genesis block, checking links
+    /// and PoW only, on as many threads as there are cores.
+    pub fn new(params: &'a ChainParams) -> Verifier<'a> {
+        Verifier {
+            params,
+            start_height: 0,
+            retarget: None,
+            skip_unsupported: false,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    /// Sets the height of the first header. The link of the first header to its parent is not
+    /// checked unless it is the genesis block, whose hash must match the chain's.
+    pub fn start_height(mut self, height: u32) -> Verifier<'a> {
+        self.start_height = height;
+        self
+    }
+
+    /// Also checks `nBits` against `retarget`, for every header whose retarget window lies
+    /// within the given headers.
+    pub fn retarget(mut self, retarget: Retarget) -> Verifier<'a> {
+        self.retarget = Some(retarget);
+        self
+    }
+
+    /// Skips the PoW check of headers hashed with algorithms this crate does not implement,
+    /// such as the scrypt blocks before a chain switched to Lyra2, instead of failing on them.
+    pub fn skip_unsupported(mut self, skip: bool) -> Verifier<'a> {
+        self.skip_unsupported = skip;
+        self
+    }
+
+    /// Uses `threads` threads.
+    pub fn threads(mut self, threads: usize) -> Verifier<'a> {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Verifies `headers`, the chain from the start height on. Returns the lowest failing
+    /// height and its reason on failure.
+    pub fn verify(&self, headers: &[BlockHeader]) -> Result<Summary, VerifyError> {
+        // Every header needs a height; the checks below add indexes to the start height.
+        if headers.len() as u64 > u64::from(u32::MAX - self.start_height) + 1 {
+            return Err(VerifyError {
+                height: u32::MAX,
+                failure: Failure::HeightOverflow,
+            });
+        }
+        let next = AtomicUsize::new(0);
+        // The index of the first failing header found so far.
+        let failed_at = AtomicU64::new(u64::MAX);
+
+        let results: Vec<Result<Summary, (usize, Failure)>> = thread::scope(|s| {
+            let workers: Vec<_> = (0..self.threads)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut matrix = Matrix::new();
+                        let mut summary = Summary::default();
+                        loop {
+                            let start = next.fetch_add(CHUNK, Ordering::Relaxed);
+                            if start >= headers.len() {
+                                return Ok(summary);
+                            }
+                            for index in start..(start + CHUNK).min(headers.len()) {
+                                if index as u64 > failed_at.load(Ordering::Relaxed) {
+                                    return Ok(summary);
+                                }
+                                if let Err(failure) =
+                                    self.check(headers, index, &mut matrix, &mut summary)
+                                {
+                                    failed_at.fetch_min(index as u64, Ordering::Relaxed);
+                                    return Err((index, failure));
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| w.join().expect("verify thread panicked"))
+                .collect()
+        });
+
+        let mut total = Summary {
+            headers: headers.len(),
+            ..Summary::default()
+        };
+        let mut first: Option<(usize, Failure)> = None;
+        for result in results {
+            match result {
+                Ok(summary) => {
+                    total.pow_checked += summary.pow_checked;
+                    total.pow_skipped += summary.pow_skipped;
+                    total.retarget_checked += summary.retarget_checked;
+                }
+                Err((index, failure)) => {
+                    if first.as_ref().map_or(true, |(i, _)| index < *i) {
+                        first = Some((index, failure));
+                    }
+                }
+            }
+        }
+        match first {
+            Some((index, failure)) => Err(VerifyError {
+                height: self.start_height + index as u32,
+                failure,
+            }),
+            None => Ok(total),
+        }
+    }
+
+    // check verifies the header at `index`, cheapest checks first.
+    fn check(
+        &self,
+        headers: &[BlockHeader],
+        index: usize,
+        matrix: &mut Matrix,
+        summary: &mut Summary,
+    ) -> Result<(), Failure> {
+        let header = &headers[index];
+        let height = self.start_height + index as u32;
+        if height == 0 {
+            let hash = header.block_hash();
+            if hash != self.params.genesis_hash {
+                return Err(Failure::BadGenesis(hash));
+            }
+        } else if index > 0 {
+            let parent = headers[index - 1].block_hash();
+            if header.prev_block != parent {
+                return Err(Failure::BrokenLink {
+                    expected: parent,
+                    found: header.prev_block,
+                });
+            }
+        }
+
+        let target = header.target().map_err(Failure::InvalidBits)?;
+        // The genesis block is fixed by its hash, whatever its bits and PoW.
+        if height == 0 {
+            summary.pow_skipped += 1;
+            return Ok(());
+        }
+        if let Some(retarget) = self.retarget {
+            match retarget.next_bits(self.params, height, &headers[..index]) {
+                Ok(expected) if expected != header.bits => {
+                    return Err(Failure::UnexpectedBits {
+                        expected,
+                        found: header.bits,
+                    })
+                }
+                Ok(_) => summary.retarget_checked += 1,
+                Err(RetargetError::InsufficientHistory { .. }) => {}
+                Err(RetargetError::InvalidBits(err)) => return Err(Failure::InvalidBits(err)),
+            }
+        }
+
+        let algorithm = match self.params.algorithm_at(height) {
+            Ok(algorithm) => algorithm,
+            Err(_) if self.skip_unsupported => {
+                summary.pow_skipped += 1;
+                return Ok(());
+            }
+            Err(_) => {
+                return Err(Failure::UnsupportedAlgorithm(
+                    self.params
+                        .epoch_at(height)
+                        .map_or("none", |epoch| epoch.algorithm.as_str())
+                        .to_string(),
+                ))
+            }
+        };
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&algorithm.sum_midstate_with(
+            matrix,
+            &header.midstate(),
+            &header.tail(),
+        ));
+        if !target.is_met_by(&hash) {
+            return Err(Failure::HighHash {
+                hash: Hash256(hash),
+                target,
+            });
+        }
+        summary.pow_checked += 1;
+        Ok(())
+    }
+}
+
+/// Why a header failed verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// The header at height 0 is not the chain's genesis block.
+    BadGenesis(Hash256),
+    /// The previous block hash is not the hash of the header before.
+    BrokenLink { expected: Hash256, found: Hash256 },
+    /// `nBits` does not decode to a target.
+    InvalidBits(TargetError),
+    /// `nBits` differs from what the retarget rule expects.
+    UnexpectedBits { expected: u32, found: u32 },
+    /// The header is hashed with an algorithm this crate does not implement.
+    UnsupportedAlgorithm(String),
+    /// The PoW hash does not meet the target.
+    HighHash { hash: Hash256, target: Target },
+    /// The headers run past the largest height, reported as `u32::MAX`.
+    HeightOverflow,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::BadGenesis(hash) => write!(f, "genesis block {} does not match", hash),
+            Failure::BrokenLink { expected, found } => {
+                write!(f, "previous block hash is {}, expected {}", found, expected)
+            }
+            Failure::InvalidBits(err) => write!(f, "{}", err),
+            Failure::UnexpectedBits { expected, found } => {
+                write!(f, "nBits is {:#010x}, expected {:#010x}", found, expected)
+            }
+            Failure::UnsupportedAlgorithm(name) => write!(f, "unsupported algorithm: {}", name),
+            Failure::HighHash { hash, target } => {
+                write!(f, "PoW hash {} is above target {}", hash, target)
+            }
+            Failure::HeightOverflow => f.write_str("the next header has no height"),
+        }
+    }
+}
+
+/// The lowest height of a header that failed verification, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub height: u32,
+    pub failure: Failure,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "header at height {}: {}", self.height, self.failure)
+    }
+}
+
+impl error::Error for VerifyError {}
+
+#[test]
+fn verify_cal() {
+    use crate::algorithm::Algorithm;
+    use crate::coin::Epoch;
+
+    let mut params = ChainParams {
+        name: "testchain".to_string(),
+        ticker: "TEST".to_string(),
+        genesis_hash: Hash256::default(),
+        pow_limit: Target::from_compact(0x207fffff).unwrap(),
+        diff1: Target::DIFF1_NODE,
+        target_spacing: 90,
+        schedule: vec![Epoch::new(0, "lyra2rev2"), Epoch::new(6, "lyra2rev3")],
+    };
+    let rule = Retarget::DigiShield { interval: 1 };
+
+    // Mine a short chain at minimal difficulty, switching algorithm at height 6.
+    let mut headers: Vec<BlockHeader> = Vec::new();
+    for height in 0..12u32 {
+        let mut header = BlockHeader {
+            version: 2,
+            prev_block: headers
+                .last()
+                .map_or(Hash256::default(), |h| h.block_hash()),
+            merkle_root: Hash256::sha256d(&height.to_le_bytes()),
+            time: 1500000000 + 90 * height,
+            bits: rule.next_bits(&params, height, &headers).unwrap(),
+            nonce: 0,
+        };
+        let algorithm = params.algorithm_at(height).unwrap();
+        while !header.meets_target(algorithm) {
+            header.nonce += 1;
+        }
+        headers.push(header);
+    }
+    params.genesis_hash = headers[0].block_hash();
+
+    let summary = Verifier::new(&params)
+        .retarget(rule)
+        .threads(3)
+        .verify(&headers)
+        .unwrap();
+    assert_eq!(
+        summary,
+        Summary {
+            headers: 12,
+            pow_checked: 11,
+            pow_skipped: 1,
+            retarget_checked: 11,
+        }
+    );
+    // A run from the middle of the chain does not check the link of its first header.
+    let summary = Verifier::new(&params)
+        .start_height(4)
+        .verify(&headers[4..])
+        .unwrap();
+    assert_eq!(summary.pow_checked, 8);
+
+    let data: Vec<u8> = headers.iter().flat_map(|h| h.serialize()).collect();
+    assert_eq!(parse_headers(&data).unwrap(), headers);
+    assert_eq!(
+        parse_headers(&data[1..]),
+        Err(ParseHeaderError::InvalidLength(data.len() - 1))
+    );
+
+    let fail = |headers: &[BlockHeader], verifier: Verifier| verifier.verify(headers).unwrap_err();
+
+    let mut broken = headers.clone();
+    broken[7].prev_block = Hash256::default();
+    broken[9].prev_block = Hash256::default();
+    let err = fail(&broken, Verifier::new(&params).threads(2));
+    assert_eq!(err.height, 7);
+    assert!(matches!(err.failure, Failure::BrokenLink { .. }));
+
+    let mut broken = headers.clone();
+    broken[5].bits = 0x207ffffe;
+    let err = fail(&broken, Verifier::new(&params).retarget(rule));
+    assert_eq!(
+        err.failure,
+        Failure::UnexpectedBits {
+            expected: 0x207fffff,
+            found: 0x207ffffe
+        }
+    );
+
+    // A nonce whose hash misses the target, under the algorithm of the height.
+    let mut broken = headers.clone();
+    while broken[8].meets_target(Algorithm::Lyra2REv3) {
+        broken[8].nonce += 1;
+    }
+    let err = fail(&broken, Verifier::new(&params));
+    assert_eq!(err.height, 8);
+    assert!(matches!(err.failure, Failure::HighHash { .. }));
+    assert!(err.to_string().starts_with("header at height 8: PoW hash "));
+
+    let mut other = params.clone();
+    other.genesis_hash = Hash256::default();
+    assert_eq!(fail(&headers, Verifier::new(&other)).height, 0);
+
+    other = params.clone();
+    other.schedule.insert(1, Epoch::new(3, "scrypt"));
+    let err = fail(&headers, Verifier::new(&other));
+    assert_eq!(err.height, 3);
+    assert_eq!(
+        err.failure,
+        Failure::UnsupportedAlgorithm("scrypt".to_string())
+    );
+    let summary = Verifier::new(&other)
+        .skip_unsupported(true)
+        .verify(&headers)
+        .unwrap();
+    assert_eq!(summary.pow_skipped, 4);
+
+    let err = fail(
+        &headers[4..6],
+        Verifier::new(&params).start_height(u32::MAX),
+    );
+    assert_eq!(err.height, u32::MAX);
+    assert_eq!(err.failure, Failure::HeightOverflow);
+}
@@ -0,0 +1,235 @@
+//! # work
+//!
+//! `work` crate has the expected number of hashes behind a block, `2^256 / (target + 1)`, and
+//! its sum over a chain, the chainwork that nodes use to choose between forks.
+use crate::target::{Target, TargetError};
+use crate::utils::from_hex;
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign};
+use std::str::FromStr;
+
+/// A 256-bit amount of work.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Work {
+    // Little-endian 64-bit limbs.
+    limbs: [u64; 4],
+}
+
+impl Work {
+    /// No work.
+    pub const ZERO: Work = Work { limbs: [0; 4] };
+
+    /// Returns the work of a block with `target`, `2^256 / (target + 1)` rounded down, as
+    /// `GetBlockProof()` of nodes computes it. A zero target has no work.
+    /// # Examples
+    ///
+    /// ```
+    /// use lyra2::target::Target;
+    /// use lyra2::work::Work;
+    ///
+    /// assert_eq!(Work::from_target(&Target::DIFF1_NODE).to_string(), format!("{:064x}", 0x100010001u64));
+    /// ```
+    pub fn from_target(target: &Target) -> Work {
+        if *target == Target::ZERO {
+            return Work::ZERO;
+        }
+        if *target == Target::MAX {
+            return Work::from_u64(1);
+        }
+        // 2^256 does not fit, but 2^256 / (t + 1) == ~t / (t + 1) + 1.
+        let target = Work::from_le_bytes(target.to_le_bytes());
+        let mut not = target;
+        for limb in not.limbs.iter_mut() {
+            *limb = !*limb;
+        }
+        not.div(&(target + Work::from_u64(1))) + Work::from_u64(1)
+    }
+
+    /// Returns the work of a block with compact target `bits`.
+    pub fn from_bits(bits: u32) -> Result<Work, TargetError> {
+        Target::from_compact(bits).map(|target| Work::from_target(&target))
+    }
+
+    /// Returns the work from 32 little-endian bytes.
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Work {
+        let mut limbs = [0u64; 4];
+        for (limb, chunk) in limbs.iter_mut().zip(bytes.chunks_exact(8)) {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            *limb = u64::from_le_bytes(word);
+        }
+        Work { limbs }
+    }
+
+    /// Returns the work as 32 big-endian bytes, the order of `chainwork` in node RPCs.
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (chunk, limb) in bytes.chunks_exact_mut(8).zip(self.limbs.iter().rev()) {
+            chunk.copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Returns the work as a float, e.g. to show it as a number of hashes.
+    pub fn to_f64(&self) -> f64 {
+        self.limbs.iter().rev().fold(0.0, |acc, &limb| {
+            acc * 18_446_744_073_709_551_616.0 + limb as f64
+        })
+    }
+
+    fn from_u64(value: u64) -> Work {
+        Work {
+            limbs: [value, 0, 0, 0],
+        }
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.limbs[i / 64] >> (i % 64) & 1 == 1
+    }
+
+    fn shl1(&self) -> Work {
+        let mut limbs = [0u64; 4];
+        for i in (0..4).rev() {
+            limbs[i] = self.limbs[i] << 1 | if i > 0 { self.limbs[i - 1] >> 63 } else { 0 };
+        }
+        Work { limbs }
+    }
+
+    fn wrapping_sub(&self, other: &Work) -> Work {
+        let mut limbs = [0u64; 4];
+        let mut borrow = false;
+        for (out, (a, b)) in limbs.iter_mut().zip(self.limbs.iter().zip(&other.limbs)) {
+            let (diff, b1) = a.overflowing_sub(*b);
+            let (diff, b2) = diff.overflowing_sub(u64::from(borrow));
+            *out = diff;
+            borrow = b1 || b2;
+        }
+        Work { limbs }
+    }
+
+    // div divides by a non-zero `divisor` bit by bit, rounding down.
+    fn div(&self, divisor: &Work) -> Work {
+        let mut quotient = Work::ZERO;
+        let mut rem = Work::ZERO;
+        for i in (0..256).rev() {
+            rem = rem.shl1();
+            rem.limbs[0] |= u64::from(self.bit(i));
+            if rem >= *divisor {
+                rem = rem.wrapping_sub(divisor);
+                quotient.limbs[i / 64] |= 1 << (i % 64);
+            }
+        }
+        quotient
+    }
+}
+
+impl Add for Work {
+    type Output = Work;
+
+    /// Adds two amounts of work, saturating at `2^256 - 1`.
+    fn add(self, other: Work) -> Work {
+        let mut limbs = [0u64; 4];
+        let mut carry = false;
+        for (out, (a, b)) in limbs.iter_mut().zip(self.limbs.iter().zip(&other.limbs)) {
+            let (sum, c1) = a.overflowing_add(*b);
+            let (sum, c2) = sum.overflowing_add(u64::from(carry));
+            *out = sum;
+            carry = c1 || c2;
+        }
+        if carry {
+            return Work {
+                limbs: [u64::MAX; 4],
+            };
+        }
+        Work { limbs }
+    }
+}
+
+impl AddAssign for Work {
+    fn add_assign(&mut self, other: Work) {
+        *self = *self + other;
+    }
+}
+
+impl Sum for Work {
+    fn sum<I: Iterator<Item = Work>>(iter: I) -> Work {
+        iter.fold(Work::ZERO, Add::add)
+    }
+}
+
+impl Ord for Work {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs.iter().rev().cmp(other.limbs.iter().rev())
+    }
+}
+
+impl PartialOrd for Work {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Work {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for limb in self.limbs.iter().rev() {
+            write!(f, "{:016x}", limb)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Work {
+    type Err = TargetError;
+
+    /// Parses a big-endian hex amount of at most 64 digits, such as `chainwork` of
+    /// `getblockheader`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        if s.len() > 64 {
+            return Err(TargetError::InvalidHex(s.to_string()));
+        }
+        let mut bytes = from_hex(&format!("{:0>64}", s))
+            .ok_or_else(|| TargetError::InvalidHex(s.to_string()))?;
+        bytes.reverse();
+        let mut le = [0u8; 32];
+        le.copy_from_slice(&bytes);
+        Ok(Work::from_le_bytes(le))
+    }
+}
+
+#[test]
+fn work_cal() {
+    // Difficulty 1 and regtest blocks, as `chainwork` of nodes counts them.
+    assert_eq!(
+        Work::from_bits(0x1d00ffff).unwrap(),
+        Work::from_u64(0x100010001)
+    );
+    assert_eq!(Work::from_bits(0x207fffff).unwrap(), Work::from_u64(2));
+    assert_eq!(
+        Work::from_bits(0x1e0ffff0).unwrap(),
+        Work::from_u64(0x100010)
+    );
+    assert_eq!(
+        Work::from_bits(0x1b0404cb).unwrap().to_string(),
+        "00000000000000000000000000000000000000000000000000003fb3ab764c00"
+    );
+    assert!(Work::from_bits(0x04923456).is_err());
+    assert_eq!(Work::from_target(&Target::ZERO), Work::ZERO);
+    assert_eq!(Work::from_target(&Target::MAX), Work::from_u64(1));
+    assert_eq!(
+        Work::from_target(&Target::from_compact(0x01010000).unwrap()).to_be_bytes()[0],
+        0x80
+    );
+
+    let work: Work = [0x1d00ffff, 0x1d00ffff]
+        .iter()
+        .map(|&bits| Work::from_bits(bits).unwrap())
+        .sum();
+    assert_eq!(work, "200020002".parse().unwrap());
+    assert_eq!(work.to_f64(), 8590065666.0);
+    assert!(work > Work::from_bits(0x1d00ffff).unwrap());
+    assert!("zz".parse::<Work>().is_err());
+}